@@ -0,0 +1,201 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detection and reporting of AuRa authorities that seal two different blocks of the same slot.
+
+use codec::Codec;
+use cumulus_primitives_core::{AuraEquivocationApi, AuraEquivocationProof, AuraExtApi};
+use sc_client_api::backend::AuxStore;
+use sp_api::{ApiExt, ProvideRuntimeApi};
+use sp_consensus::{
+	import_queue::CacheKeyId, BlockCheckParams, BlockImport, BlockImportParams, ImportResult,
+};
+use sp_consensus_aura::{digests::CompatibleDigestItem, AuraApi};
+use sp_core::crypto::Pair;
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, DigestItemFor, Header as HeaderT},
+};
+use std::{collections::HashMap, fmt::Debug, marker::PhantomData, sync::Arc};
+
+use crate::LOG_TARGET;
+
+/// A block import that reports the authors of imported blocks that equivocated.
+///
+/// The sealed headers of the imported blocks are stored per slot and author. When a block of the
+/// same slot and author but with another hash is imported, the equivocation is reported to the
+/// runtime by [`AuraEquivocationApi`], which submits an unsigned extrinsic to the transaction
+/// pool. The block is imported anyway.
+///
+/// Only the blocks sealed by the primary author of their slot are checked, so the blocks of the
+/// secondary authors are not mistaken for equivocations. The headers are stored in the same place
+/// as by the equivocation check of the verifier, so the verifier must be built with
+/// `CheckForEquivocation::No`, as by [`import_queue`](crate::import_queue) and
+/// [`migration_import_queue`](crate::migration_import_queue).
+pub struct EquivocationReportingBlockImport<I, C, P> {
+	inner: I,
+	client: Arc<C>,
+	_phantom: PhantomData<P>,
+}
+
+impl<I, C, P> EquivocationReportingBlockImport<I, C, P> {
+	/// Create a new instance that wraps the `inner` block import.
+	pub fn new(inner: I, client: Arc<C>) -> Self {
+		Self {
+			inner,
+			client,
+			_phantom: PhantomData,
+		}
+	}
+}
+
+impl<I: Clone, C, P> Clone for EquivocationReportingBlockImport<I, C, P> {
+	fn clone(&self) -> Self {
+		Self {
+			inner: self.inner.clone(),
+			client: self.client.clone(),
+			_phantom: PhantomData,
+		}
+	}
+}
+
+impl<I, C, P> EquivocationReportingBlockImport<I, C, P>
+where
+	C: AuxStore,
+	P: Pair,
+	P::Public: Codec + Debug,
+	P::Signature: Codec,
+{
+	/// Check if the author of the block equivocated and report it.
+	fn check_equivocation<Block, T>(
+		&self,
+		block: &BlockImportParams<Block, T>,
+	) -> sp_blockchain::Result<()>
+	where
+		Block: BlockT,
+		DigestItemFor<Block>: CompatibleDigestItem<P::Signature>,
+		C: ProvideRuntimeApi<Block>,
+		C::Api: AuraApi<Block, P::Public>
+			+ AuraExtApi<Block, P::Public>
+			+ AuraEquivocationApi<Block, P::Public>,
+	{
+		let slot = match sc_consensus_aura::find_pre_digest::<Block, P::Signature>(&block.header) {
+			Ok(slot) => slot,
+			Err(_) => return Ok(()),
+		};
+
+		let parent = BlockId::Hash(*block.header.parent_hash());
+		let runtime_api = self.client.runtime_api();
+		// `aura-ext` knows the authorities of the next block, like the runtime uses them to check
+		// the seal.
+		let authorities = if runtime_api.has_api::<dyn AuraExtApi<Block, P::Public>>(&parent)? {
+			runtime_api.aura_slot_info(&parent)?.authorities
+		} else {
+			runtime_api.authorities(&parent)?
+		};
+		if authorities.is_empty() {
+			return Ok(());
+		}
+		let author = &authorities[*slot as usize % authorities.len()];
+
+		let sealed_by_author = block
+			.post_digests
+			.last()
+			.and_then(|seal| CompatibleDigestItem::<P::Signature>::as_aura_seal(seal))
+			.map_or(false, |signature| P::verify(&signature, block.header.hash(), author));
+		if !sealed_by_author {
+			return Ok(());
+		}
+
+		let proof = match sc_consensus_slots::check_equivocation(
+			&*self.client,
+			slot,
+			slot,
+			&block.post_header(),
+			author,
+		)? {
+			Some(proof) => proof,
+			None => return Ok(()),
+		};
+
+		tracing::warn!(
+			target: LOG_TARGET,
+			slot = *proof.slot,
+			offender = ?proof.offender,
+			"Detected an AuRa equivocation, reporting it.",
+		);
+
+		let proof = AuraEquivocationProof {
+			slot: *proof.slot,
+			offender: proof.offender,
+			first_header: proof.first_header,
+			second_header: proof.second_header,
+		};
+
+		if self
+			.client
+			.runtime_api()
+			.submit_report_equivocation_unsigned_extrinsic(&parent, proof)?
+			.is_none()
+		{
+			tracing::warn!(target: LOG_TARGET, "Failed to submit the equivocation report.");
+		}
+
+		Ok(())
+	}
+}
+
+#[async_trait::async_trait]
+impl<Block, I, C, P> BlockImport<Block> for EquivocationReportingBlockImport<I, C, P>
+where
+	Block: BlockT,
+	DigestItemFor<Block>: CompatibleDigestItem<P::Signature>,
+	I: BlockImport<Block> + Send,
+	I::Transaction: Send,
+	C: ProvideRuntimeApi<Block> + AuxStore + Send + Sync,
+	C::Api: AuraApi<Block, P::Public>
+		+ AuraExtApi<Block, P::Public>
+		+ AuraEquivocationApi<Block, P::Public>,
+	P: Pair + Send + Sync,
+	P::Public: Codec + Debug,
+	P::Signature: Codec,
+{
+	type Error = I::Error;
+	type Transaction = I::Transaction;
+
+	async fn check_block(
+		&mut self,
+		block: BlockCheckParams<Block>,
+	) -> Result<ImportResult, Self::Error> {
+		self.inner.check_block(block).await
+	}
+
+	async fn import_block(
+		&mut self,
+		block: BlockImportParams<Block, Self::Transaction>,
+		cache: HashMap<CacheKeyId, Vec<u8>>,
+	) -> Result<ImportResult, Self::Error> {
+		if let Err(e) = self.check_equivocation(&block) {
+			tracing::warn!(
+				target: LOG_TARGET,
+				error = ?e,
+				"Failed to check the imported block for an equivocation.",
+			);
+		}
+
+		self.inner.import_block(block, cache).await
+	}
+}
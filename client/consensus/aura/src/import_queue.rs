@@ -17,18 +17,23 @@
 //! Parachain specific wrapper for the AuRa import queue.
 
 use codec::Codec;
+use cumulus_primitives_core::AuraExtApi;
 use sc_client_api::{backend::AuxStore, BlockOf};
 use sc_consensus_slots::InherentDataProviderExt;
 use sp_api::{ApiExt, ProvideRuntimeApi};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
 use sp_blockchain::{HeaderBackend, ProvideCache};
 use sp_consensus::{
-	import_queue::DefaultImportQueue, BlockImport, CanAuthorWith, Error as ConsensusError,
+	import_queue::{BasicQueue, DefaultImportQueue, Verifier},
+	BlockImport, CanAuthorWith, Error as ConsensusError, NeverCanAuthor,
 };
 use sp_consensus_aura::{digests::CompatibleDigestItem, AuraApi};
 use sp_core::crypto::Pair;
 use sp_inherents::CreateInherentDataProviders;
-use sp_runtime::traits::{Block as BlockT, DigestItemFor};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, DigestItemFor},
+};
 use std::{fmt::Debug, hash::Hash, sync::Arc};
 use substrate_prometheus_endpoint::Registry;
 use sc_telemetry::TelemetryHandle;
@@ -65,7 +70,10 @@ pub fn import_queue<'a, P, Block, I, C, S, CAW, IDP>(
 ) -> Result<DefaultImportQueue<Block, C>, sp_consensus::Error>
 where
 	Block: BlockT,
-	C::Api: BlockBuilderApi<Block> + AuraApi<Block, P::Public> + ApiExt<Block>,
+	C::Api: BlockBuilderApi<Block>
+		+ AuraApi<Block, P::Public>
+		+ AuraExtApi<Block, P::Public>
+		+ ApiExt<Block>,
 	C: 'static
 		+ ProvideRuntimeApi<Block>
 		+ BlockOf
@@ -87,15 +95,135 @@ where
 	IDP: CreateInherentDataProviders<Block, ()> + Sync + Send + 'static,
 	IDP::InherentDataProviders: InherentDataProviderExt + Send + Sync,
 {
-	sc_consensus_aura::import_queue::<P, _, _, _, _, _, _>(sc_consensus_aura::ImportQueueParams {
-		block_import: crate::ParachainBlockImport(block_import),
-		justification_import: None,
+	let verifier =
+		sc_consensus_aura::build_verifier::<P, _, _, _>(sc_consensus_aura::BuildVerifierParams {
+			client: client.clone(),
+			create_inherent_data_providers,
+			can_author_with,
+			check_for_equivocation: sc_consensus_aura::CheckForEquivocation::No,
+			telemetry,
+		});
+
+	secondary_author_queue::<P, _, _, _, _>(block_import, client, verifier, spawner, registry)
+}
+
+/// Parameters of [`verify_only_import_queue`].
+pub struct VerifyOnlyImportQueueParams<'a, I, C, S> {
+	/// The block import to use.
+	pub block_import: I,
+	/// The client to interact with the chain.
+	pub client: Arc<C>,
+	/// The spawner to spawn background tasks.
+	pub spawner: &'a S,
+	/// The prometheus registry.
+	pub registry: Option<&'a Registry>,
+	/// Should equivocations of the block authors be checked and reported?
+	pub check_for_equivocation: sc_consensus_aura::CheckForEquivocation,
+	/// The telemetry handle.
+	pub telemetry: Option<TelemetryHandle>,
+}
+
+/// Start an import queue for the Aura consensus algorithm that only verifies the blocks.
+///
+/// This is meant for nodes that don't author blocks, like RPC or archive nodes. It doesn't need
+/// a keystore or any inherent data providers. The seal and the slot of each block are verified
+/// against the authorities and the slot duration of the runtime at the parent, while the inherents
+/// are not checked. The inherents are checked by the relay chain validators anyway.
+pub fn verify_only_import_queue<'a, P, Block, I, C, S>(
+	VerifyOnlyImportQueueParams {
+		block_import,
 		client,
-		create_inherent_data_providers,
 		spawner,
 		registry,
-		can_author_with,
-		check_for_equivocation: sc_consensus_aura::CheckForEquivocation::No,
+		check_for_equivocation,
 		telemetry,
-	})
+	}: VerifyOnlyImportQueueParams<'a, I, C, S>,
+) -> Result<DefaultImportQueue<Block, C>, sp_consensus::Error>
+where
+	Block: BlockT,
+	C::Api: BlockBuilderApi<Block>
+		+ AuraApi<Block, P::Public>
+		+ AuraExtApi<Block, P::Public>
+		+ ApiExt<Block>,
+	C: 'static
+		+ ProvideRuntimeApi<Block>
+		+ BlockOf
+		+ ProvideCache<Block>
+		+ Send
+		+ Sync
+		+ AuxStore
+		+ HeaderBackend<Block>,
+	I: BlockImport<Block, Error = ConsensusError, Transaction = sp_api::TransactionFor<C, Block>>
+		+ Send
+		+ Sync
+		+ 'static,
+	DigestItemFor<Block>: CompatibleDigestItem<P::Signature>,
+	P: Pair + Send + Sync + 'static,
+	P::Public: Clone + Eq + Send + Sync + Hash + Debug + Codec,
+	P::Signature: Codec,
+	S: sp_core::traits::SpawnEssentialNamed,
+{
+	let create_inherent_data_providers = {
+		let client = client.clone();
+		move |parent: Block::Hash, _: ()| {
+			// The slot duration may be changed by a runtime upgrade of the parent.
+			let slot_duration =
+				crate::slot_duration_at::<_, _, P::Public>(&*client, &BlockId::Hash(parent));
+
+			async move {
+				let time = sp_timestamp::InherentDataProvider::from_system_time();
+				let slot =
+					sp_consensus_aura::inherents::InherentDataProvider::from_timestamp_and_duration(
+						*time,
+						slot_duration?,
+					);
+
+				Ok::<_, Box<dyn std::error::Error + Send + Sync>>((time, slot))
+			}
+		}
+	};
+
+	let verifier =
+		sc_consensus_aura::build_verifier::<P, _, _, _>(sc_consensus_aura::BuildVerifierParams {
+			client: client.clone(),
+			create_inherent_data_providers,
+			can_author_with: NeverCanAuthor,
+			check_for_equivocation,
+			telemetry,
+		});
+
+	secondary_author_queue::<P, _, _, _, _>(block_import, client, verifier, spawner, registry)
+}
+
+/// Start an import queue that accepts the blocks of the secondary authors and verifies all other
+/// blocks by the AuRa `verifier`.
+fn secondary_author_queue<P, Block, I, C, S>(
+	block_import: I,
+	client: Arc<C>,
+	verifier: impl Verifier<Block> + 'static,
+	spawner: &S,
+	registry: Option<&Registry>,
+) -> Result<DefaultImportQueue<Block, C>, sp_consensus::Error>
+where
+	Block: BlockT,
+	C: ProvideRuntimeApi<Block> + Send + Sync + 'static,
+	C::Api: AuraExtApi<Block, P::Public>,
+	I: BlockImport<Block, Error = ConsensusError, Transaction = sp_api::TransactionFor<C, Block>>
+		+ Send
+		+ Sync
+		+ 'static,
+	P: Pair + Send + Sync + 'static,
+	P::Public: Codec,
+	P::Signature: Codec,
+	S: sp_core::traits::SpawnEssentialNamed,
+{
+	let verifier = crate::SecondaryAuthorVerifier::<_, _, P>::new(client, Box::new(verifier));
+
+	Ok(BasicQueue::new(
+		verifier,
+		Box::new(crate::ParachainBlockImport(block_import)),
+		None,
+		spawner,
+		registry,
+	))
 }
@@ -18,22 +18,39 @@
 //!
 //! This extends the Substrate provided AuRa consensus implementation to make it compatible for
 //! parachains. The main entry points for of this consensus algorithm are [`build_aura_consensus`]
-//! and [`import_queue`].
+//! and [`import_queue`]. Nodes that don't author blocks can use the lighter
+//! [`verify_only_import_queue`].
+//!
+//! The proposers can be customized by passing a
+//! [`WrappedProposerFactory`](cumulus_client_consensus_common::WrappedProposerFactory) as the
+//! proposer factory, e.g. to add pre-runtime digests to the proposals.
+//!
+//! A parachain that switches to AuRa by a runtime upgrade can use the [`MigrationConsensus`] and
+//! the [`migration_import_queue`] to keep building and verifying the old blocks under the old
+//! rules.
+//!
+//! Authors that seal two different blocks of the same slot are reported to the runtime by the
+//! [`EquivocationReportingBlockImport`].
+//!
+//! When the runtime allows it, the secondary author of a slot authors the block of the slot if the
+//! parachain didn't progress for a while, and the import queues accept these blocks. See the
+//! [`SecondaryAuthorVerifier`].
 //!
 //! For more information about AuRa, the Substrate crate should be checked.
 
-use codec::{Decode, Encode};
+use codec::{Codec, Decode, Encode};
 use cumulus_client_consensus_common::{ParachainCandidate, ParachainConsensus};
 use cumulus_primitives_core::{
+	extract_reserved_pov_size,
 	relay_chain::v1::{Block as PBlock, Hash as PHash, ParachainHost},
-	PersistedValidationData,
+	AuraExtApi, PersistedValidationData, UnincludedSegmentApi,
 };
 use futures::lock::Mutex;
 use polkadot_service::ClientHandle;
 use sc_client_api::{backend::AuxStore, Backend, BlockOf};
 use sc_consensus_slots::{BackoffAuthoringBlocksStrategy, SlotInfo};
 use sc_telemetry::TelemetryHandle;
-use sp_api::ProvideRuntimeApi;
+use sp_api::{ApiExt, ProvideRuntimeApi};
 use sp_application_crypto::AppPublic;
 use sp_blockchain::{HeaderBackend, ProvideCache};
 use sp_consensus::{
@@ -43,12 +60,28 @@ use sp_consensus_aura::AuraApi;
 use sp_core::crypto::Pair;
 use sp_inherents::{CreateInherentDataProviders, InherentData, InherentDataProvider};
 use sp_keystore::SyncCryptoStorePtr;
-use sp_runtime::traits::{Block as BlockT, HashFor, Header as HeaderT, Member, NumberFor};
-use std::{convert::TryFrom, hash::Hash, marker::PhantomData, sync::Arc};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, HashFor, Header as HeaderT, Member, NumberFor},
+};
+use std::{convert::TryFrom, hash::Hash, marker::PhantomData, sync::Arc, time::Duration};
 
+mod equivocation;
 mod import_queue;
+mod migration;
+mod secondary;
 
-pub use import_queue::{import_queue, ImportQueueParams};
+pub use equivocation::EquivocationReportingBlockImport;
+pub use import_queue::{
+	import_queue, verify_only_import_queue, ImportQueueParams, VerifyOnlyImportQueueParams,
+};
+pub use migration::{
+	consensus_migration_block_at, migration_import_queue, uses_aura_at, ConsensusMigrationBlockAt,
+	MigrationConsensus, MigrationImportQueueParams, MigrationVerifier, UsesAuraAt,
+};
+pub use secondary::{
+	secondary_author_index, secondary_author_window_passed, SecondaryAuthorVerifier,
+};
 pub use sc_consensus_aura::{
 	slot_duration, AuraBlockImport, BuildAuraWorkerParams, SlotDuration, SlotProportion,
 };
@@ -57,7 +90,7 @@ pub use sc_consensus_slots::InherentDataProviderExt;
 const LOG_TARGET: &str = "aura::cumulus";
 
 /// The implementation of the AURA consensus for parachains.
-pub struct AuraConsensus<B, RClient, RBackend, CIDP> {
+pub struct AuraConsensus<B: BlockT, RClient, RBackend, CIDP> {
 	create_inherent_data_providers: Arc<CIDP>,
 	relay_chain_client: Arc<RClient>,
 	relay_chain_backend: Arc<RBackend>,
@@ -68,17 +101,24 @@ pub struct AuraConsensus<B, RClient, RBackend, CIDP> {
 				+ 'static,
 		>,
 	>,
-	slot_duration: SlotDuration,
+	secondary_worker: Arc<Mutex<dyn secondary::SecondarySlotWorker<B> + 'static>>,
+	slot_duration_at: Arc<dyn Fn(B::Hash) -> sp_blockchain::Result<Duration> + Send + Sync>,
+	unincluded_segment_capacity_at:
+		Arc<dyn Fn(B::Hash, B::Hash) -> sp_blockchain::Result<u32> + Send + Sync>,
+	block_proposal_slot_portion: f32,
 }
 
-impl<B, RClient, RBackend, CIDP> Clone for AuraConsensus<B, RClient, RBackend, CIDP> {
+impl<B: BlockT, RClient, RBackend, CIDP> Clone for AuraConsensus<B, RClient, RBackend, CIDP> {
 	fn clone(&self) -> Self {
 		Self {
 			create_inherent_data_providers: self.create_inherent_data_providers.clone(),
 			relay_chain_backend: self.relay_chain_backend.clone(),
 			relay_chain_client: self.relay_chain_client.clone(),
 			aura_worker: self.aura_worker.clone(),
-			slot_duration: self.slot_duration,
+			secondary_worker: self.secondary_worker.clone(),
+			slot_duration_at: self.slot_duration_at.clone(),
+			unincluded_segment_capacity_at: self.unincluded_segment_capacity_at.clone(),
+			block_proposal_slot_portion: self.block_proposal_slot_portion,
 		}
 	}
 }
@@ -104,7 +144,6 @@ where
 		create_inherent_data_providers: CIDP,
 		polkadot_client: Arc<RClient>,
 		polkadot_backend: Arc<RBackend>,
-		slot_duration: SlotDuration,
 		telemetry: Option<TelemetryHandle>,
 		block_proposal_slot_portion: SlotProportion,
 	) -> Self
@@ -117,7 +156,7 @@ where
 			+ Send
 			+ Sync
 			+ 'static,
-		Client::Api: AuraApi<B, P::Public>,
+		Client::Api: AuraApi<B, P::Public> + AuraExtApi<B, P::Public> + UnincludedSegmentApi<B>,
 		BI: BlockImport<B, Transaction = sp_api::TransactionFor<Client, B>> + Send + Sync + 'static,
 		SO: SyncOracle + Send + Sync + Clone + 'static,
 		BS: BackoffAuthoringBlocksStrategy<NumberFor<B>> + Send + 'static,
@@ -134,6 +173,26 @@ where
 		P::Public: AppPublic + Hash + Member + Encode + Decode,
 		P::Signature: TryFrom<Vec<u8>> + Hash + Member + Encode + Decode,
 	{
+		let client = para_client.clone();
+		let slot_duration_at = Arc::new(move |parent: B::Hash| {
+			crate::slot_duration_at::<B, _, P::Public>(&*client, &BlockId::Hash(parent))
+		});
+		let client = para_client.clone();
+		let unincluded_segment_capacity_at = Arc::new(move |parent: B::Hash, included: B::Hash| {
+			crate::unincluded_segment_capacity_at::<B, _>(&*client, parent, included)
+		});
+
+		// The secondary author of a slot builds with the same proposer factory and block import.
+		let proposer_factory = secondary::SharedProposerFactory::new(proposer_factory);
+		let block_import = secondary::SharedBlockImport::new(block_import);
+		let secondary_worker = secondary::SecondaryAuthorWorker::<B, _, _, _, P>::new(
+			para_client.clone(),
+			proposer_factory.clone(),
+			ParachainBlockImport(block_import.clone()),
+			keystore.clone(),
+		);
+		let block_proposal_slot_portion_value = block_proposal_slot_portion.get();
+
 		let worker =
 			sc_consensus_aura::build_aura_worker::<P, _, _, _, _, _, _, _>(BuildAuraWorkerParams {
 				client: para_client,
@@ -152,7 +211,10 @@ where
 			relay_chain_backend: polkadot_backend,
 			relay_chain_client: polkadot_client,
 			aura_worker: Arc::new(Mutex::new(worker)),
-			slot_duration,
+			secondary_worker: Arc::new(Mutex::new(secondary_worker)),
+			slot_duration_at,
+			unincluded_segment_capacity_at,
+			block_proposal_slot_portion: block_proposal_slot_portion_value,
 		}
 	}
 
@@ -208,32 +270,146 @@ where
 		relay_parent: PHash,
 		validation_data: &PersistedValidationData,
 	) -> Option<ParachainCandidate<B>> {
+		// Even with the slot, a block on top of a full unincluded segment would be rejected.
+		let included = B::Header::decode(&mut &validation_data.parent_head.0[..])
+			.map_err(|e| {
+				tracing::error!(
+					target: LOG_TARGET,
+					error = ?e,
+					"Failed to decode the included parachain head.",
+				)
+			})
+			.ok()?;
+		let capacity = (self.unincluded_segment_capacity_at)(parent.hash(), included.hash())
+			.map_err(|e| {
+				tracing::error!(
+					target: LOG_TARGET,
+					error = ?e,
+					"Failed to fetch the capacity of the unincluded segment.",
+				)
+			})
+			.ok()?;
+		if capacity == 0 {
+			tracing::debug!(
+				target: LOG_TARGET,
+				parent = ?parent.hash(),
+				included = ?included.hash(),
+				"The unincluded segment is full, skipping the slot.",
+			);
+
+			return None;
+		}
+
 		let (inherent_data, inherent_data_providers) = self
 			.inherent_data(parent.hash(), validation_data, relay_parent)
 			.await?;
 
+		let slot_duration = (self.slot_duration_at)(parent.hash())
+			.map_err(|e| {
+				tracing::error!(
+					target: LOG_TARGET,
+					error = ?e,
+					"Failed to fetch the slot duration.",
+				)
+			})
+			.ok()?;
+
+		// Set the block limit to 50% of the maximum PoV size, minus the space the runtime
+		// asked us to keep free.
+		//
+		// TODO: If we got benchmarking that includes the proof size,
+		// we should be able to use the maximum pov size.
+		let block_size_limit = Some(
+			(validation_data.max_pov_size / 2)
+				.saturating_sub(extract_reserved_pov_size(parent.digest())) as usize,
+		);
+		let slot = inherent_data_providers.slot();
+
 		let info = SlotInfo::new(
-			inherent_data_providers.slot(),
+			slot,
 			inherent_data_providers.timestamp(),
-			inherent_data,
-			self.slot_duration.slot_duration(),
+			inherent_data.clone(),
+			slot_duration,
 			parent.clone(),
-			// Set the block limit to 50% of the maximum PoV size.
-			//
-			// TODO: If we got benchmarking that includes the proof size,
-			// we should be able to use the maximum pov size.
-			Some((validation_data.max_pov_size / 2) as usize),
+			block_size_limit,
 		);
 
-		let res = self.aura_worker.lock().await.on_slot(info).await?;
+		let res = self.aura_worker.lock().await.on_slot(info).await;
+		let res = match res {
+			Some(res) => res,
+			// The slot may still be claimed by this node as the secondary author of the slot.
+			None => self
+				.secondary_worker
+				.lock()
+				.await
+				.on_slot(
+					parent,
+					slot,
+					validation_data.relay_parent_number,
+					inherent_data,
+					slot_duration.mul_f32(self.block_proposal_slot_portion),
+					block_size_limit,
+				)
+				.await?,
+		};
 
 		Some(ParachainCandidate {
 			block: res.block,
 			proof: res.storage_proof,
+			aux_data: None,
 		})
 	}
 }
 
+/// Returns the slot duration of the runtime at the block `at`.
+///
+/// Unlike [`slot_duration`], this doesn't cache the slot duration. So, it follows the changes of
+/// the slot duration by runtime upgrades, when called with the parent of the block to build or to
+/// verify.
+///
+/// The slot duration is taken from the [`AuraExtApi`] if the runtime provides it, and from the
+/// [`AuraApi`] otherwise.
+pub fn slot_duration_at<B, C, A>(client: &C, at: &BlockId<B>) -> sp_blockchain::Result<Duration>
+where
+	B: BlockT,
+	C: ProvideRuntimeApi<B>,
+	C::Api: AuraApi<B, A> + AuraExtApi<B, A>,
+	A: Codec,
+{
+	let runtime_api = client.runtime_api();
+
+	if runtime_api.has_api::<dyn AuraExtApi<B, A>>(at)? {
+		let slot_info = runtime_api.aura_slot_info(at)?;
+		return Ok(Duration::from_millis(slot_info.slot_duration));
+	}
+
+	Ok(runtime_api.slot_duration(at)?.slot_duration())
+}
+
+/// Returns the number of blocks that can be built on top of the block `at` before the unincluded
+/// segment is full, if the block `included` is the last block included on the relay chain.
+///
+/// Runtimes without the [`UnincludedSegmentApi`] don't limit the unincluded segment.
+pub fn unincluded_segment_capacity_at<B, C>(
+	client: &C,
+	at: B::Hash,
+	included: B::Hash,
+) -> sp_blockchain::Result<u32>
+where
+	B: BlockT,
+	C: ProvideRuntimeApi<B>,
+	C::Api: UnincludedSegmentApi<B>,
+{
+	let runtime_api = client.runtime_api();
+	let block_id = BlockId::Hash(at);
+
+	if !runtime_api.has_api::<dyn UnincludedSegmentApi<B>>(&block_id)? {
+		return Ok(u32::max_value());
+	}
+
+	Ok(runtime_api.unincluded_segment_capacity(&block_id, at, included)?)
+}
+
 /// Parachain specific block import.
 ///
 /// This is used to set `block_import_params.fork_choice` to `false` as long as the block origin is
@@ -280,10 +456,13 @@ pub struct BuildAuraConsensusParams<PF, BI, RBackend, CIDP, Client, BS, SO> {
 	pub relay_chain_backend: Arc<RBackend>,
 	pub para_client: Arc<Client>,
 	pub backoff_authoring_blocks: Option<BS>,
+	/// The sync oracle that decides whether a slot is claimed.
+	///
+	/// This should also follow the relay chain node, see
+	/// [`CombinedSyncOracle`](cumulus_client_consensus_common::CombinedSyncOracle).
 	pub sync_oracle: SO,
 	pub keystore: SyncCryptoStorePtr,
 	pub force_authoring: bool,
-	pub slot_duration: SlotDuration,
 	pub telemetry: Option<TelemetryHandle>,
 	pub block_proposal_slot_portion: SlotProportion,
 }
@@ -303,7 +482,6 @@ pub fn build_aura_consensus<P, Block, PF, BI, RBackend, CIDP, Client, SO, BS, Er
 		sync_oracle,
 		keystore,
 		force_authoring,
-		slot_duration,
 		telemetry,
 		block_proposal_slot_portion,
 	}: BuildAuraConsensusParams<PF, BI, RBackend, CIDP, Client, BS, SO>,
@@ -326,7 +504,9 @@ where
 		+ Send
 		+ Sync
 		+ 'static,
-	Client::Api: AuraApi<Block, P::Public>,
+	Client::Api: AuraApi<Block, P::Public>
+		+ AuraExtApi<Block, P::Public>
+		+ UnincludedSegmentApi<Block>,
 	BI: BlockImport<Block, Transaction = sp_api::TransactionFor<Client, Block>>
 		+ Send
 		+ Sync
@@ -357,7 +537,6 @@ where
 		sync_oracle,
 		force_authoring,
 		keystore,
-		slot_duration,
 		telemetry,
 		block_proposal_slot_portion,
 	)
@@ -382,7 +561,6 @@ struct AuraConsensusBuilder<P, Block, PF, BI, RBackend, CIDP, Client, SO, BS, Er
 	sync_oracle: SO,
 	force_authoring: bool,
 	keystore: SyncCryptoStorePtr,
-	slot_duration: SlotDuration,
 	telemetry: Option<TelemetryHandle>,
 	block_proposal_slot_portion: SlotProportion,
 }
@@ -407,7 +585,9 @@ where
 		+ Send
 		+ Sync
 		+ 'static,
-	Client::Api: AuraApi<Block, P::Public>,
+	Client::Api: AuraApi<Block, P::Public>
+		+ AuraExtApi<Block, P::Public>
+		+ UnincludedSegmentApi<Block>,
 	BI: BlockImport<Block, Transaction = sp_api::TransactionFor<Client, Block>>
 		+ Send
 		+ Sync
@@ -439,7 +619,6 @@ where
 		sync_oracle: SO,
 		force_authoring: bool,
 		keystore: SyncCryptoStorePtr,
-		slot_duration: SlotDuration,
 		telemetry: Option<TelemetryHandle>,
 		block_proposal_slot_portion: SlotProportion,
 	) -> Self {
@@ -455,7 +634,6 @@ where
 			sync_oracle,
 			force_authoring,
 			keystore,
-			slot_duration,
 			telemetry,
 			block_proposal_slot_portion,
 		}
@@ -487,7 +665,9 @@ where
 		+ Send
 		+ Sync
 		+ 'static,
-	Client::Api: AuraApi<Block, P::Public>,
+	Client::Api: AuraApi<Block, P::Public>
+		+ AuraExtApi<Block, P::Public>
+		+ UnincludedSegmentApi<Block>,
 	BI: BlockImport<Block, Transaction = sp_api::TransactionFor<Client, Block>>
 		+ Send
 		+ Sync
@@ -528,7 +708,6 @@ where
 			self.create_inherent_data_providers,
 			client.clone(),
 			self.relay_chain_backend,
-			self.slot_duration,
 			self.telemetry,
 			self.block_proposal_slot_portion,
 		))
@@ -0,0 +1,496 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Support for parachains that switch to AuRa from another consensus, e.g. the relay-chain
+//! provided consensus, by a runtime upgrade.
+//!
+//! The runtime upgrade adds AuRa and initializes the AuRa authorities. The first block of the new
+//! runtime is still built and verified under the old rules, as its parent doesn't know about the
+//! authorities yet. All later blocks are built and verified by AuRa. Once the runtime notes the
+//! migration block, the old consensus doesn't verify any later block.
+
+use codec::Codec;
+use cumulus_client_consensus_common::{ParachainCandidate, ParachainConsensus};
+use cumulus_primitives_core::{relay_chain::v1::Hash as PHash, AuraExtApi, PersistedValidationData};
+use sc_client_api::{backend::AuxStore, BlockOf};
+use sc_consensus_slots::InherentDataProviderExt;
+use sc_telemetry::TelemetryHandle;
+use sp_api::{ApiExt, ProvideRuntimeApi};
+use sp_block_builder::BlockBuilder as BlockBuilderApi;
+use sp_blockchain::{HeaderBackend, ProvideCache};
+use sp_consensus::{
+	import_queue::{BasicQueue, CacheKeyId, DefaultImportQueue, Verifier as VerifierT},
+	BlockImport, BlockImportParams, BlockOrigin, CanAuthorWith, Error as ConsensusError,
+};
+use sp_consensus_aura::{digests::CompatibleDigestItem, AuraApi};
+use sp_core::crypto::Pair;
+use sp_inherents::CreateInherentDataProviders;
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, DigestItemFor, Header as HeaderT, NumberFor},
+	Justifications,
+};
+use std::{fmt::Debug, hash::Hash, sync::Arc};
+use substrate_prometheus_endpoint::Registry;
+
+use crate::LOG_TARGET;
+
+/// Returns whether the children of the block are built and verified by AuRa.
+pub type UsesAuraAt<B> =
+	Arc<dyn Fn(<B as BlockT>::Hash) -> sp_blockchain::Result<bool> + Send + Sync>;
+
+/// Returns the migration block that is known to the block, see [`consensus_migration_block_at`].
+pub type ConsensusMigrationBlockAt<B> = Arc<
+	dyn Fn(<B as BlockT>::Hash) -> sp_blockchain::Result<Option<NumberFor<B>>> + Send + Sync,
+>;
+
+/// Returns whether the children of the block `at` are built and verified by AuRa.
+///
+/// This is the case when the runtime at `at` provides the [`AuraApi`] and has AuRa authorities.
+pub fn uses_aura_at<B, C, A>(client: &C, at: &BlockId<B>) -> sp_blockchain::Result<bool>
+where
+	B: BlockT,
+	C: ProvideRuntimeApi<B>,
+	C::Api: AuraApi<B, A> + ApiExt<B>,
+	A: Codec,
+{
+	let runtime_api = client.runtime_api();
+
+	if !runtime_api.has_api::<dyn AuraApi<B, A>>(at)? {
+		return Ok(false);
+	}
+
+	Ok(!runtime_api.authorities(at)?.is_empty())
+}
+
+/// Returns the first block of the runtime upgrade that switched to AuRa, as noted by the runtime
+/// at `at`.
+///
+/// Runtimes without the [`AuraExtApi`] didn't switch to AuRa.
+pub fn consensus_migration_block_at<B, C, A>(
+	client: &C,
+	at: &BlockId<B>,
+) -> sp_blockchain::Result<Option<NumberFor<B>>>
+where
+	B: BlockT,
+	C: ProvideRuntimeApi<B>,
+	C::Api: AuraExtApi<B, A> + ApiExt<B>,
+	A: Codec,
+{
+	let runtime_api = client.runtime_api();
+
+	if !runtime_api.has_api::<dyn AuraExtApi<B, A>>(at)? {
+		return Ok(None);
+	}
+
+	Ok(runtime_api.consensus_migration_block(at)?)
+}
+
+/// A verifier that verifies the blocks by AuRa once the chain switched to AuRa, and by the
+/// `fallback` verifier before.
+///
+/// Blocks after the migration block are never verified by the `fallback` verifier.
+pub struct MigrationVerifier<B: BlockT> {
+	uses_aura_at: UsesAuraAt<B>,
+	consensus_migration_block_at: ConsensusMigrationBlockAt<B>,
+	aura: Box<dyn VerifierT<B>>,
+	fallback: Box<dyn VerifierT<B>>,
+}
+
+impl<B: BlockT> MigrationVerifier<B> {
+	/// Create a new instance.
+	pub fn new(
+		uses_aura_at: UsesAuraAt<B>,
+		consensus_migration_block_at: ConsensusMigrationBlockAt<B>,
+		aura: Box<dyn VerifierT<B>>,
+		fallback: Box<dyn VerifierT<B>>,
+	) -> Self {
+		Self {
+			uses_aura_at,
+			consensus_migration_block_at,
+			aura,
+			fallback,
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl<B: BlockT> VerifierT<B> for MigrationVerifier<B> {
+	async fn verify(
+		&mut self,
+		origin: BlockOrigin,
+		header: B::Header,
+		justifications: Option<Justifications>,
+		body: Option<Vec<B::Extrinsic>>,
+	) -> Result<(BlockImportParams<B, ()>, Option<Vec<(CacheKeyId, Vec<u8>)>>), String> {
+		let parent = *header.parent_hash();
+		let uses_aura = (self.uses_aura_at)(parent)
+			.map_err(|e| format!("Could not check if the parent uses AuRa: {:?}", e))?;
+		if uses_aura {
+			return self.aura.verify(origin, header, justifications, body).await;
+		}
+
+		let migration_block = (self.consensus_migration_block_at)(parent)
+			.map_err(|e| format!("Could not fetch the consensus migration block: {:?}", e))?;
+		if let Some(migration_block) = migration_block {
+			if *header.number() >= migration_block {
+				return Err(format!(
+					"Block {} is not verified by AuRa, but the chain switched to AuRa in block {}",
+					header.number(),
+					migration_block,
+				));
+			}
+		}
+
+		self.fallback.verify(origin, header, justifications, body).await
+	}
+}
+
+/// Parameters of [`migration_import_queue`].
+pub struct MigrationImportQueueParams<'a, Block: BlockT, I, C, IDP, S, CAW> {
+	/// The block import to use.
+	pub block_import: I,
+	/// The client to interact with the chain.
+	pub client: Arc<C>,
+	/// The inherent data providers of AuRa, to create the inherent data.
+	pub create_inherent_data_providers: IDP,
+	/// The verifier of the blocks before the switch to AuRa.
+	pub fallback_verifier: Box<dyn VerifierT<Block>>,
+	/// The spawner to spawn background tasks.
+	pub spawner: &'a S,
+	/// The prometheus registry.
+	pub registry: Option<&'a Registry>,
+	/// Can we author with the current node?
+	pub can_author_with: CAW,
+	/// The telemetry handle.
+	pub telemetry: Option<TelemetryHandle>,
+}
+
+/// Start an import queue for a parachain that switches to AuRa by a runtime upgrade.
+pub fn migration_import_queue<'a, P, Block, I, C, S, CAW, IDP>(
+	MigrationImportQueueParams {
+		block_import,
+		client,
+		create_inherent_data_providers,
+		fallback_verifier,
+		spawner,
+		registry,
+		can_author_with,
+		telemetry,
+	}: MigrationImportQueueParams<'a, Block, I, C, IDP, S, CAW>,
+) -> Result<DefaultImportQueue<Block, C>, sp_consensus::Error>
+where
+	Block: BlockT,
+	C::Api: BlockBuilderApi<Block>
+		+ AuraApi<Block, P::Public>
+		+ AuraExtApi<Block, P::Public>
+		+ ApiExt<Block>,
+	C: 'static
+		+ ProvideRuntimeApi<Block>
+		+ BlockOf
+		+ ProvideCache<Block>
+		+ Send
+		+ Sync
+		+ AuxStore
+		+ HeaderBackend<Block>,
+	I: BlockImport<Block, Error = ConsensusError, Transaction = sp_api::TransactionFor<C, Block>>
+		+ Send
+		+ Sync
+		+ 'static,
+	DigestItemFor<Block>: CompatibleDigestItem<P::Signature>,
+	P: Pair + Send + Sync + 'static,
+	P::Public: Clone + Eq + Send + Sync + Hash + Debug + Codec,
+	P::Signature: Codec,
+	S: sp_core::traits::SpawnEssentialNamed,
+	CAW: CanAuthorWith<Block> + Send + Sync + 'static,
+	IDP: CreateInherentDataProviders<Block, ()> + Sync + Send + 'static,
+	IDP::InherentDataProviders: InherentDataProviderExt + Send + Sync,
+{
+	let aura_verifier =
+		sc_consensus_aura::build_verifier::<P, _, _, _>(sc_consensus_aura::BuildVerifierParams {
+			client: client.clone(),
+			create_inherent_data_providers,
+			can_author_with,
+			check_for_equivocation: sc_consensus_aura::CheckForEquivocation::No,
+			telemetry,
+		});
+	let aura_verifier =
+		crate::SecondaryAuthorVerifier::<_, _, P>::new(client.clone(), Box::new(aura_verifier));
+
+	let uses_aura = {
+		let client = client.clone();
+		Arc::new(move |parent: Block::Hash| {
+			uses_aura_at::<Block, _, P::Public>(&*client, &BlockId::Hash(parent))
+		})
+	};
+	let migration_block = {
+		let client = client.clone();
+		Arc::new(move |parent: Block::Hash| {
+			consensus_migration_block_at::<Block, _, P::Public>(&*client, &BlockId::Hash(parent))
+		})
+	};
+
+	let verifier = MigrationVerifier::new(
+		uses_aura,
+		migration_block,
+		Box::new(aura_verifier),
+		fallback_verifier,
+	);
+
+	Ok(BasicQueue::new(
+		verifier,
+		Box::new(crate::ParachainBlockImport(block_import)),
+		None,
+		spawner,
+		registry,
+	))
+}
+
+/// A [`ParachainConsensus`] that builds the blocks by AuRa once the chain switched to AuRa, and by
+/// the `fallback` consensus before.
+pub struct MigrationConsensus<B: BlockT> {
+	uses_aura_at: UsesAuraAt<B>,
+	aura: Box<dyn ParachainConsensus<B>>,
+	fallback: Box<dyn ParachainConsensus<B>>,
+}
+
+impl<B: BlockT> Clone for MigrationConsensus<B> {
+	fn clone(&self) -> Self {
+		Self {
+			uses_aura_at: self.uses_aura_at.clone(),
+			aura: self.aura.clone(),
+			fallback: self.fallback.clone(),
+		}
+	}
+}
+
+impl<B: BlockT> MigrationConsensus<B> {
+	/// Create a new instance.
+	pub fn new(
+		uses_aura_at: UsesAuraAt<B>,
+		aura: Box<dyn ParachainConsensus<B>>,
+		fallback: Box<dyn ParachainConsensus<B>>,
+	) -> Self {
+		Self {
+			uses_aura_at,
+			aura,
+			fallback,
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl<B: BlockT> ParachainConsensus<B> for MigrationConsensus<B> {
+	async fn produce_candidate(
+		&mut self,
+		parent: &B::Header,
+		relay_parent: PHash,
+		validation_data: &PersistedValidationData,
+	) -> Option<ParachainCandidate<B>> {
+		let uses_aura = (self.uses_aura_at)(parent.hash())
+			.map_err(|e| {
+				tracing::error!(
+					target: LOG_TARGET,
+					error = ?e,
+					"Could not check if the parent uses AuRa.",
+				)
+			})
+			.ok()?;
+
+		if uses_aura {
+			self.aura
+				.produce_candidate(parent, relay_parent, validation_data)
+				.await
+		} else {
+			self.fallback
+				.produce_candidate(parent, relay_parent, validation_data)
+				.await
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::H256;
+	use sp_runtime::testing::{Block as RawBlock, ExtrinsicWrapper, Header};
+
+	type Block = RawBlock<ExtrinsicWrapper<u64>>;
+
+	/// A verifier that rejects every block with its name.
+	struct NamedVerifier(&'static str);
+
+	#[async_trait::async_trait]
+	impl VerifierT<Block> for NamedVerifier {
+		async fn verify(
+			&mut self,
+			_: BlockOrigin,
+			_: Header,
+			_: Option<Justifications>,
+			_: Option<Vec<ExtrinsicWrapper<u64>>>,
+		) -> Result<(BlockImportParams<Block, ()>, Option<Vec<(CacheKeyId, Vec<u8>)>>), String> {
+			Err(self.0.into())
+		}
+	}
+
+	/// A consensus that notes its name for every candidate it is asked to produce.
+	#[derive(Clone)]
+	struct NamedConsensus(&'static str, Arc<parking_lot::Mutex<Vec<&'static str>>>);
+
+	#[async_trait::async_trait]
+	impl ParachainConsensus<Block> for NamedConsensus {
+		async fn produce_candidate(
+			&mut self,
+			_: &Header,
+			_: PHash,
+			_: &PersistedValidationData,
+		) -> Option<ParachainCandidate<Block>> {
+			self.1.lock().push(self.0);
+			None
+		}
+	}
+
+	/// Only the children of the block with the hash `aura` use AuRa.
+	fn uses_aura(aura: H256) -> UsesAuraAt<Block> {
+		Arc::new(move |parent| Ok(parent == aura))
+	}
+
+	/// The block with the hash `migrated` knows of the migration block `number`.
+	fn migration_block(migrated: H256, number: u64) -> ConsensusMigrationBlockAt<Block> {
+		Arc::new(move |parent| Ok(Some(number).filter(|_| parent == migrated)))
+	}
+
+	fn unknown_block(parent: H256) -> sp_blockchain::Error {
+		sp_blockchain::Error::UnknownBlock(format!("{:?}", parent))
+	}
+
+	fn verify(verifier: &mut MigrationVerifier<Block>, parent: H256) -> Result<(), String> {
+		futures::executor::block_on(verifier.verify(
+			BlockOrigin::NetworkBroadcast,
+			header_on(parent),
+			None,
+			None,
+		))
+		.map(|_| ())
+	}
+
+	fn header_on(parent: H256) -> Header {
+		let mut header = Header::new_from_number(2);
+		header.parent_hash = parent;
+		header
+	}
+
+	#[test]
+	fn blocks_are_verified_by_the_consensus_of_their_parent() {
+		let aura_parent = H256::from_low_u64_be(1);
+		let mut verifier = MigrationVerifier::new(
+			uses_aura(aura_parent),
+			migration_block(aura_parent, 1),
+			Box::new(NamedVerifier("aura")),
+			Box::new(NamedVerifier("fallback")),
+		);
+		let mut verify = |parent| {
+			futures::executor::block_on(verifier.verify(
+				BlockOrigin::NetworkBroadcast,
+				header_on(parent),
+				None,
+				None,
+			))
+			.map(|_| ())
+		};
+
+		assert_eq!(verify(aura_parent), Err("aura".into()));
+		assert_eq!(verify(H256::from_low_u64_be(2)), Err("fallback".into()));
+	}
+
+	#[test]
+	fn blocks_after_the_migration_block_are_not_verified_by_the_fallback() {
+		// The parent noted the migration block, but has no AuRa authorities.
+		let migrated = H256::from_low_u64_be(1);
+		let mut verifier = MigrationVerifier::new(
+			uses_aura(H256::zero()),
+			migration_block(migrated, 2),
+			Box::new(NamedVerifier("aura")),
+			Box::new(NamedVerifier("fallback")),
+		);
+
+		assert_eq!(
+			verify(&mut verifier, migrated).unwrap_err(),
+			"Block 2 is not verified by AuRa, but the chain switched to AuRa in block 2",
+		);
+		assert_eq!(verify(&mut verifier, H256::from_low_u64_be(2)), Err("fallback".into()));
+	}
+
+	#[test]
+	fn failing_runtime_apis_fail_the_verification() {
+		let mut verifier = MigrationVerifier::new(
+			Arc::new(|parent| Err(unknown_block(parent))),
+			Arc::new(|_| Ok(None)),
+			Box::new(NamedVerifier("aura")),
+			Box::new(NamedVerifier("fallback")),
+		);
+		let parent = H256::from_low_u64_be(1);
+		let error = verify(&mut verifier, parent).unwrap_err();
+		assert!(error.starts_with("Could not check if the parent uses AuRa"));
+
+		verifier.uses_aura_at = uses_aura(H256::zero());
+		verifier.consensus_migration_block_at = Arc::new(|parent| Err(unknown_block(parent)));
+		let error = verify(&mut verifier, parent).unwrap_err();
+		assert!(error.starts_with("Could not fetch the consensus migration block"));
+	}
+
+	#[test]
+	fn blocks_are_built_by_the_consensus_of_their_parent() {
+		// The chain switched to AuRa in the block `after`, so its children use AuRa.
+		let before = header_on(H256::from_low_u64_be(1));
+		let after = header_on(before.hash());
+		let produced = Arc::new(parking_lot::Mutex::new(Vec::new()));
+		let mut consensus = MigrationConsensus::new(
+			uses_aura(after.hash()),
+			Box::new(NamedConsensus("aura", produced.clone())),
+			Box::new(NamedConsensus("fallback", produced.clone())),
+		);
+
+		for parent in [before, after].iter() {
+			futures::executor::block_on(consensus.produce_candidate(
+				parent,
+				Default::default(),
+				&Default::default(),
+			));
+		}
+
+		assert_eq!(*produced.lock(), vec!["fallback", "aura"]);
+	}
+
+	#[test]
+	fn no_candidate_is_built_if_the_consensus_is_unknown() {
+		let produced = Arc::new(parking_lot::Mutex::new(Vec::new()));
+		let mut consensus = MigrationConsensus::new(
+			Arc::new(|parent| Err(unknown_block(parent))),
+			Box::new(NamedConsensus("aura", produced.clone())),
+			Box::new(NamedConsensus("fallback", produced.clone())),
+		);
+
+		let candidate = futures::executor::block_on(consensus.produce_candidate(
+			&header_on(H256::from_low_u64_be(1)),
+			Default::default(),
+			&Default::default(),
+		));
+
+		assert!(candidate.is_none());
+		assert!(produced.lock().is_empty());
+	}
+}
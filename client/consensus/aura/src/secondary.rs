@@ -0,0 +1,494 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Authoring and verification of the blocks of the secondary AuRa author of a slot.
+//!
+//! When the primary author of a slot misses it, the runtime may allow the secondary author of the
+//! slot to author the block of the slot instead. The runtime announces this by the
+//! `secondary_author_from` of the [`AuraSlotInfo`], which it provides by the [`AuraExtApi`].
+//! Runtimes without the [`AuraExtApi`] only allow the primary author.
+
+use codec::Codec;
+use cumulus_primitives_core::{
+	relay_chain::BlockNumber as RelayBlockNumber, secondary_aura_author_index, AuraExtApi,
+	AuraSlotInfo,
+};
+use sc_consensus_slots::SlotResult;
+use sp_api::{ApiExt, ProvideRuntimeApi};
+use sp_application_crypto::{AppKey, AppPublic};
+use sp_consensus::{
+	import_queue::{CacheKeyId, Verifier as VerifierT},
+	BlockCheckParams, BlockImport, BlockImportParams, BlockOrigin, EnableProofRecording,
+	Environment, ImportResult, ProofRecording, Proposal, Proposer,
+};
+use sp_consensus_aura::{digests::CompatibleDigestItem, Slot};
+use sp_core::crypto::{Pair, Public};
+use sp_inherents::InherentData;
+use sp_keystore::{SyncCryptoStore, SyncCryptoStorePtr};
+use sp_runtime::{
+	generic::{BlockId, Digest},
+	traits::{Block as BlockT, DigestItemFor, Header as HeaderT},
+	Justifications,
+};
+use std::{collections::HashMap, convert::TryFrom, marker::PhantomData, sync::Arc, time::Duration};
+
+use crate::LOG_TARGET;
+
+/// The index of the secondary author of `slot` in the authorities of the `slot_info`.
+///
+/// Returns `None` if the runtime doesn't allow secondary authors or if the secondary author is
+/// also the primary author of the slot.
+pub fn secondary_author_index<A>(slot_info: &AuraSlotInfo<A>, slot: u64) -> Option<usize> {
+	let authorities = slot_info.authorities.len();
+	if slot_info.secondary_author_from.is_none() || authorities == 0 {
+		return None;
+	}
+
+	let primary = (slot % authorities as u64) as usize;
+	let secondary = secondary_aura_author_index(primary as u32, authorities);
+
+	if secondary == primary {
+		None
+	} else {
+		Some(secondary)
+	}
+}
+
+/// Returns whether the secondary author of a slot may author a block on top of a relay parent
+/// with the given number.
+pub fn secondary_author_window_passed<A>(
+	slot_info: &AuraSlotInfo<A>,
+	relay_parent_number: RelayBlockNumber,
+) -> bool {
+	slot_info.secondary_author_from.map_or(false, |from| relay_parent_number >= from)
+}
+
+/// A verifier that accepts the blocks sealed by the secondary author of their slot, and verifies
+/// all other blocks by the `inner` verifier.
+///
+/// The relay chain window after which the secondary author may author the block of a slot is not
+/// checked, as the relay parent of a block is only known by executing it. It is checked when the
+/// relay chain validates the block. The inherents of these blocks are not checked either.
+pub struct SecondaryAuthorVerifier<B: BlockT, C, P> {
+	client: Arc<C>,
+	inner: Box<dyn VerifierT<B>>,
+	_phantom: PhantomData<P>,
+}
+
+impl<B: BlockT, C, P> SecondaryAuthorVerifier<B, C, P> {
+	/// Create a new instance.
+	pub fn new(client: Arc<C>, inner: Box<dyn VerifierT<B>>) -> Self {
+		Self {
+			client,
+			inner,
+			_phantom: PhantomData,
+		}
+	}
+}
+
+impl<B, C, P> SecondaryAuthorVerifier<B, C, P>
+where
+	B: BlockT,
+	C: ProvideRuntimeApi<B>,
+	C::Api: AuraExtApi<B, P::Public>,
+	P: Pair,
+	P::Public: Codec,
+	P::Signature: Codec,
+{
+	/// Returns the header without the seal and the seal, if the `header` was sealed by the
+	/// secondary author of its slot.
+	fn secondary_seal(
+		&self,
+		header: &B::Header,
+	) -> Result<Option<(B::Header, DigestItemFor<B>)>, String> {
+		let mut pre_header = header.clone();
+		let seal = match pre_header.digest_mut().pop() {
+			Some(seal) => seal,
+			None => return Ok(None),
+		};
+		let signature = match CompatibleDigestItem::<P::Signature>::as_aura_seal(&seal) {
+			Some(signature) => signature,
+			None => return Ok(None),
+		};
+		let slot = match sc_consensus_aura::find_pre_digest::<B, P::Signature>(&pre_header) {
+			Ok(slot) => slot,
+			Err(_) => return Ok(None),
+		};
+
+		let parent = BlockId::Hash(*header.parent_hash());
+		let runtime_api = self.client.runtime_api();
+		if !runtime_api
+			.has_api::<dyn AuraExtApi<B, P::Public>>(&parent)
+			.map_err(|e| format!("Failed to check for the AuraExtApi: {:?}", e))?
+		{
+			return Ok(None);
+		}
+		let slot_info = runtime_api
+			.aura_slot_info(&parent)
+			.map_err(|e| format!("Failed to fetch the AuRa slot info: {:?}", e))?;
+
+		let secondary = match secondary_author_index(&slot_info, *slot) {
+			Some(secondary) => &slot_info.authorities[secondary],
+			None => return Ok(None),
+		};
+
+		if P::verify(&signature, pre_header.hash().as_ref(), secondary) {
+			Ok(Some((pre_header, seal)))
+		} else {
+			Ok(None)
+		}
+	}
+}
+
+#[async_trait::async_trait]
+impl<B, C, P> VerifierT<B> for SecondaryAuthorVerifier<B, C, P>
+where
+	B: BlockT,
+	C: ProvideRuntimeApi<B> + Send + Sync,
+	C::Api: AuraExtApi<B, P::Public>,
+	P: Pair + Send + Sync,
+	P::Public: Codec,
+	P::Signature: Codec,
+{
+	async fn verify(
+		&mut self,
+		origin: BlockOrigin,
+		header: B::Header,
+		justifications: Option<Justifications>,
+		body: Option<Vec<B::Extrinsic>>,
+	) -> Result<(BlockImportParams<B, ()>, Option<Vec<(CacheKeyId, Vec<u8>)>>), String> {
+		let (pre_header, seal) = match self.secondary_seal(&header)? {
+			Some(sealed) => sealed,
+			None => return self.inner.verify(origin, header, justifications, body).await,
+		};
+
+		tracing::debug!(
+			target: LOG_TARGET,
+			hash = ?header.hash(),
+			"Importing a block of the secondary author of its slot.",
+		);
+
+		let mut import_params = BlockImportParams::new(origin, pre_header);
+		import_params.post_digests.push(seal);
+		import_params.body = body;
+		import_params.justifications = justifications;
+		import_params.post_hash = Some(header.hash());
+
+		Ok((import_params, None))
+	}
+}
+
+/// Authors the block of a slot as the secondary author of the slot.
+#[async_trait::async_trait]
+pub(crate) trait SecondarySlotWorker<B: BlockT>: Send {
+	/// Author the block of the `slot` on top of `parent`, if this node is the secondary author
+	/// of the slot and the relay chain window passed.
+	async fn on_slot(
+		&mut self,
+		parent: &B::Header,
+		slot: Slot,
+		relay_parent_number: RelayBlockNumber,
+		inherent_data: InherentData,
+		proposing_duration: Duration,
+		block_size_limit: Option<usize>,
+	) -> Option<SlotResult<B, <EnableProofRecording as ProofRecording>::Proof>>;
+}
+
+/// The [`SecondarySlotWorker`] that uses the keys of the `keystore`.
+pub(crate) struct SecondaryAuthorWorker<B, C, PF, BI, P> {
+	client: Arc<C>,
+	proposer_factory: PF,
+	block_import: BI,
+	keystore: SyncCryptoStorePtr,
+	_phantom: PhantomData<(B, P)>,
+}
+
+impl<B, C, PF, BI, P> SecondaryAuthorWorker<B, C, PF, BI, P>
+where
+	B: BlockT,
+	C: ProvideRuntimeApi<B>,
+	C::Api: AuraExtApi<B, P::Public>,
+	P: Pair,
+	P::Public: AppPublic + Codec,
+	P::Signature: TryFrom<Vec<u8>> + Codec,
+{
+	/// Create a new instance.
+	pub fn new(
+		client: Arc<C>,
+		proposer_factory: PF,
+		block_import: BI,
+		keystore: SyncCryptoStorePtr,
+	) -> Self {
+		Self {
+			client,
+			proposer_factory,
+			block_import,
+			keystore,
+			_phantom: PhantomData,
+		}
+	}
+
+	/// Returns the key of the secondary author of the `slot`, if it is in the keystore and the
+	/// secondary author may author a block on top of `parent` and the relay parent.
+	fn claim_slot(
+		&self,
+		parent: &B::Header,
+		slot: Slot,
+		relay_parent_number: RelayBlockNumber,
+	) -> Option<P::Public> {
+		let at = BlockId::Hash(parent.hash());
+		let runtime_api = self.client.runtime_api();
+		if !runtime_api.has_api::<dyn AuraExtApi<B, P::Public>>(&at).unwrap_or(false) {
+			return None;
+		}
+		let slot_info = runtime_api
+			.aura_slot_info(&at)
+			.map_err(|e| {
+				tracing::error!(
+					target: LOG_TARGET,
+					error = ?e,
+					"Failed to fetch the AuRa slot info.",
+				)
+			})
+			.ok()?;
+
+		if !secondary_author_window_passed(&slot_info, relay_parent_number) {
+			return None;
+		}
+		let author = slot_info.authorities.get(secondary_author_index(&slot_info, *slot)?)?;
+
+		if SyncCryptoStore::has_keys(&*self.keystore, &[(author.to_raw_vec(), P::Public::ID)]) {
+			Some(author.clone())
+		} else {
+			None
+		}
+	}
+
+	/// Seal the header with the given `hash` by the key of the `author`.
+	fn seal(&self, author: &P::Public, hash: &B::Hash) -> Option<DigestItemFor<B>> {
+		let signature = SyncCryptoStore::sign_with(
+			&*self.keystore,
+			P::Public::ID,
+			&author.to_public_crypto_pair(),
+			hash.as_ref(),
+		)
+		.map_err(|e| {
+			tracing::error!(target: LOG_TARGET, error = ?e, "Failed to seal the block.")
+		})
+		.ok()??;
+		let signature = P::Signature::try_from(signature)
+			.map_err(|_| tracing::error!(target: LOG_TARGET, "Invalid signature of the seal."))
+			.ok()?;
+
+		Some(CompatibleDigestItem::<P::Signature>::aura_seal(signature))
+	}
+}
+
+#[async_trait::async_trait]
+impl<B, C, PF, BI, P> SecondarySlotWorker<B> for SecondaryAuthorWorker<B, C, PF, BI, P>
+where
+	B: BlockT,
+	C: ProvideRuntimeApi<B> + Send + Sync,
+	C::Api: AuraExtApi<B, P::Public>,
+	PF: Environment<B> + Send,
+	PF::Proposer: Proposer<
+		B,
+		Transaction = BI::Transaction,
+		ProofRecording = EnableProofRecording,
+		Proof = <EnableProofRecording as ProofRecording>::Proof,
+	>,
+	BI: BlockImport<B> + Send,
+	BI::Transaction: Send,
+	P: Pair + Send + Sync,
+	P::Public: AppPublic + Codec,
+	P::Signature: TryFrom<Vec<u8>> + Codec,
+{
+	async fn on_slot(
+		&mut self,
+		parent: &B::Header,
+		slot: Slot,
+		relay_parent_number: RelayBlockNumber,
+		inherent_data: InherentData,
+		proposing_duration: Duration,
+		block_size_limit: Option<usize>,
+	) -> Option<SlotResult<B, <EnableProofRecording as ProofRecording>::Proof>> {
+		let author = self.claim_slot(parent, slot, relay_parent_number)?;
+
+		tracing::debug!(
+			target: LOG_TARGET,
+			slot = *slot,
+			"Authoring the block of the slot as its secondary author.",
+		);
+
+		let proposer = self
+			.proposer_factory
+			.init(parent)
+			.await
+			.map_err(
+				|e| tracing::error!(target: LOG_TARGET, error = ?e, "Could not create proposer."),
+			)
+			.ok()?;
+
+		let pre_digest: DigestItemFor<B> =
+			CompatibleDigestItem::<P::Signature>::aura_pre_digest(slot);
+		let Proposal {
+			block,
+			storage_changes,
+			proof,
+		} = proposer
+			.propose(
+				inherent_data,
+				Digest {
+					logs: vec![pre_digest],
+				},
+				proposing_duration,
+				block_size_limit,
+			)
+			.await
+			.map_err(|e| tracing::error!(target: LOG_TARGET, error = ?e, "Proposing failed."))
+			.ok()?;
+
+		let (header, body) = block.deconstruct();
+		let seal = self.seal(&author, &header.hash())?;
+
+		let mut import_params = BlockImportParams::new(BlockOrigin::Own, header);
+		import_params.post_digests.push(seal);
+		import_params.body = Some(body.clone());
+		import_params.storage_changes = Some(storage_changes);
+		let post_header = import_params.post_header();
+
+		if let Err(err) = self
+			.block_import
+			.import_block(import_params, Default::default())
+			.await
+		{
+			tracing::error!(
+				target: LOG_TARGET,
+				at = ?parent.hash(),
+				error = ?err,
+				"Error importing build block.",
+			);
+
+			return None;
+		}
+
+		Some(SlotResult {
+			block: B::new(post_header, body),
+			storage_proof: proof,
+		})
+	}
+}
+
+/// A proposer factory that is shared by the AuRa worker and the [`SecondaryAuthorWorker`].
+pub(crate) struct SharedProposerFactory<PF>(Arc<parking_lot::Mutex<PF>>);
+
+impl<PF> SharedProposerFactory<PF> {
+	pub fn new(proposer_factory: PF) -> Self {
+		Self(Arc::new(parking_lot::Mutex::new(proposer_factory)))
+	}
+}
+
+impl<PF> Clone for SharedProposerFactory<PF> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone())
+	}
+}
+
+impl<B: BlockT, PF: Environment<B>> Environment<B> for SharedProposerFactory<PF> {
+	type Proposer = PF::Proposer;
+	type CreateProposer = PF::CreateProposer;
+	type Error = PF::Error;
+
+	fn init(&mut self, parent_header: &B::Header) -> Self::CreateProposer {
+		self.0.lock().init(parent_header)
+	}
+}
+
+/// A block import that is shared by the AuRa worker and the [`SecondaryAuthorWorker`].
+pub(crate) struct SharedBlockImport<I>(Arc<futures::lock::Mutex<I>>);
+
+impl<I> SharedBlockImport<I> {
+	pub fn new(block_import: I) -> Self {
+		Self(Arc::new(futures::lock::Mutex::new(block_import)))
+	}
+}
+
+impl<I> Clone for SharedBlockImport<I> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone())
+	}
+}
+
+#[async_trait::async_trait]
+impl<B, I> BlockImport<B> for SharedBlockImport<I>
+where
+	B: BlockT,
+	I: BlockImport<B> + Send,
+	I::Transaction: Send,
+{
+	type Error = I::Error;
+	type Transaction = I::Transaction;
+
+	async fn check_block(
+		&mut self,
+		block: BlockCheckParams<B>,
+	) -> Result<ImportResult, Self::Error> {
+		self.0.lock().await.check_block(block).await
+	}
+
+	async fn import_block(
+		&mut self,
+		block: BlockImportParams<B, Self::Transaction>,
+		cache: HashMap<CacheKeyId, Vec<u8>>,
+	) -> Result<ImportResult, Self::Error> {
+		self.0.lock().await.import_block(block, cache).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn slot_info(authorities: u32, secondary_author_from: Option<u32>) -> AuraSlotInfo<u32> {
+		AuraSlotInfo {
+			slot: 0,
+			slot_duration: 6000,
+			authorities: (0..authorities).collect(),
+			secondary_author_from,
+		}
+	}
+
+	#[test]
+	fn secondary_authors_need_to_be_allowed() {
+		assert_eq!(secondary_author_index(&slot_info(4, None), 5), None);
+		assert_eq!(secondary_author_index(&slot_info(4, Some(10)), 5), Some(3));
+		assert_eq!(secondary_author_index(&slot_info(4, Some(10)), 6), Some(0));
+	}
+
+	#[test]
+	fn single_authorities_have_no_secondary_author() {
+		assert_eq!(secondary_author_index(&slot_info(0, Some(10)), 5), None);
+		assert_eq!(secondary_author_index(&slot_info(1, Some(10)), 5), None);
+	}
+
+	#[test]
+	fn secondary_authors_wait_for_the_window() {
+		assert!(!secondary_author_window_passed(&slot_info(4, None), 20));
+		assert!(!secondary_author_window_passed(&slot_info(4, Some(10)), 9));
+		assert!(secondary_author_window_passed(&slot_info(4, Some(10)), 10));
+		assert!(secondary_author_window_passed(&slot_info(4, Some(10)), 11));
+	}
+}
@@ -0,0 +1,292 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bounded buffering of the relay chain head streams.
+
+use crate::RelaychainClient;
+use futures::{Stream, StreamExt};
+use polkadot_primitives::v1::{
+	Block as PBlock, CoreState, Id as ParaId, InboundDownwardMessage, InboundHrmpMessage,
+	OccupiedCoreAssumption, PersistedValidationData, SessionIndex, ValidatorId, ValidatorIndex,
+};
+use sp_blockchain::Result as ClientResult;
+use sp_runtime::generic::BlockId;
+use sp_trie::StorageProof;
+use std::{
+	collections::{BTreeMap, VecDeque},
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+const LOG_TARGET: &str = "cumulus-consensus";
+
+/// What to do when the buffer of a [`BoundedStream`] is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+	/// Drop the oldest buffered item to make room for the new one.
+	///
+	/// Useful for streams where only the latest items are of interest, like new best heads.
+	DropOldest,
+	/// Stop pulling items from the wrapped stream until there is room in the buffer again.
+	///
+	/// No item is ever dropped, which is required for streams like finalized heads.
+	NeverDrop,
+}
+
+/// The buffer configuration of a [`BoundedStream`].
+#[derive(Clone, Copy, Debug)]
+pub struct StreamBounds {
+	/// The maximum number of buffered items.
+	pub capacity: usize,
+	/// What to do when the buffer is full.
+	pub policy: OverflowPolicy,
+}
+
+/// A stream that eagerly pulls all ready items from the wrapped stream into a bounded buffer.
+///
+/// When the consumer falls behind, the buffer fills up and the [`OverflowPolicy`] decides if the
+/// oldest items are dropped or if the wrapped stream is not polled anymore until the consumer
+/// caught up.
+pub struct BoundedStream<S: Stream> {
+	inner: S,
+	buffer: VecDeque<S::Item>,
+	bounds: StreamBounds,
+	terminated: bool,
+}
+
+impl<S: Stream> BoundedStream<S> {
+	/// Create a new instance wrapping the given `stream`.
+	pub fn new(stream: S, bounds: StreamBounds) -> Self {
+		Self {
+			inner: stream,
+			buffer: VecDeque::new(),
+			bounds: StreamBounds {
+				// A buffer without any capacity would not yield anything.
+				capacity: bounds.capacity.max(1),
+				..bounds
+			},
+			terminated: false,
+		}
+	}
+}
+
+impl<S: Stream + Unpin> Stream for BoundedStream<S>
+where
+	S::Item: Unpin,
+{
+	type Item = S::Item;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+		let this = &mut *self;
+
+		while !this.terminated {
+			if this.bounds.policy == OverflowPolicy::NeverDrop &&
+				this.buffer.len() >= this.bounds.capacity
+			{
+				break
+			}
+
+			match this.inner.poll_next_unpin(cx) {
+				Poll::Ready(Some(item)) => {
+					this.buffer.push_back(item);
+
+					if this.buffer.len() > this.bounds.capacity {
+						this.buffer.pop_front();
+						tracing::trace!(
+							target: LOG_TARGET,
+							capacity = this.bounds.capacity,
+							"Dropping the oldest item of a full relay chain stream.",
+						);
+					}
+				}
+				Poll::Ready(None) => this.terminated = true,
+				Poll::Pending => break,
+			}
+		}
+
+		match this.buffer.pop_front() {
+			Some(item) => Poll::Ready(Some(item)),
+			None if this.terminated => Poll::Ready(None),
+			None => Poll::Pending,
+		}
+	}
+}
+
+/// The buffer configuration of the streams of a [`BoundedRelaychainClient`].
+#[derive(Clone, Copy, Debug)]
+pub struct BoundedStreamsConfig {
+	/// The bounds of [`RelaychainClient::new_best_heads`].
+	pub new_best_heads: StreamBounds,
+	/// The bounds of [`RelaychainClient::finalized_heads`].
+	pub finalized_heads: StreamBounds,
+}
+
+impl Default for BoundedStreamsConfig {
+	fn default() -> Self {
+		Self {
+			new_best_heads: StreamBounds {
+				capacity: 16,
+				policy: OverflowPolicy::DropOldest,
+			},
+			finalized_heads: StreamBounds {
+				capacity: 64,
+				policy: OverflowPolicy::NeverDrop,
+			},
+		}
+	}
+}
+
+/// A [`RelaychainClient`] that bounds the buffering of the head streams of the wrapped client.
+///
+/// All other methods are directly forwarded to the wrapped client.
+#[derive(Clone)]
+pub struct BoundedRelaychainClient<R> {
+	inner: R,
+	config: BoundedStreamsConfig,
+}
+
+impl<R> BoundedRelaychainClient<R> {
+	/// Create a new instance using the given stream `config`.
+	pub fn new(inner: R, config: BoundedStreamsConfig) -> Self {
+		Self { inner, config }
+	}
+}
+
+impl<R: RelaychainClient> RelaychainClient for BoundedRelaychainClient<R> {
+	type Error = R::Error;
+
+	type HeadStream = BoundedStream<R::HeadStream>;
+
+	type StorageChangesStream = R::StorageChangesStream;
+
+	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.inner
+			.new_best_heads(para_id)
+			.map(|s| BoundedStream::new(s, self.config.new_best_heads))
+	}
+
+	fn finalized_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.inner
+			.finalized_heads(para_id)
+			.map(|s| BoundedStream::new(s, self.config.finalized_heads))
+	}
+
+	fn parachain_head_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<u8>>> {
+		self.inner.parachain_head_at(at, para_id)
+	}
+
+	fn parachain_heads_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+	) -> ClientResult<Vec<Option<Vec<u8>>>> {
+		self.inner.parachain_heads_at(at, para_ids)
+	}
+
+	fn persisted_validation_data(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>> {
+		self.inner.persisted_validation_data(at, para_id, assumption)
+	}
+
+	fn persisted_validation_data_for_paras(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Vec<Option<PersistedValidationData>>> {
+		self.inner.persisted_validation_data_for_paras(at, para_ids, assumption)
+	}
+
+	fn dmq_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>> {
+		self.inner.dmq_contents(at, para_id)
+	}
+
+	fn inbound_hrmp_channels_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>> {
+		self.inner.inbound_hrmp_channels_contents(at, para_id)
+	}
+
+	fn session_index_for_child(&self, at: &BlockId<PBlock>) -> ClientResult<SessionIndex> {
+		self.inner.session_index_for_child(at)
+	}
+
+	fn validators(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>> {
+		self.inner.validators(at)
+	}
+
+	fn availability_cores(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>> {
+		self.inner.availability_cores(at)
+	}
+
+	fn backing_group(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<ValidatorIndex>>> {
+		self.inner.backing_group(at, para_id)
+	}
+
+	fn storage_changes(&self, keys: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream> {
+		self.inner.storage_changes(keys)
+	}
+
+	fn prove_read(&self, at: &BlockId<PBlock>, keys: &[Vec<u8>]) -> ClientResult<StorageProof> {
+		self.inner.prove_read(at, keys)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{executor::block_on, stream};
+
+	#[test]
+	fn drop_oldest_keeps_the_latest_items() {
+		let bounds = StreamBounds {
+			capacity: 2,
+			policy: OverflowPolicy::DropOldest,
+		};
+		let s = BoundedStream::new(stream::iter(1..=5), bounds);
+
+		assert_eq!(block_on(s.collect::<Vec<_>>()), vec![4, 5]);
+	}
+
+	#[test]
+	fn never_drop_yields_all_items() {
+		let bounds = StreamBounds {
+			capacity: 2,
+			policy: OverflowPolicy::NeverDrop,
+		};
+		let s = BoundedStream::new(stream::iter(1..=5), bounds);
+
+		assert_eq!(block_on(s.collect::<Vec<_>>()), vec![1, 2, 3, 4, 5]);
+	}
+}
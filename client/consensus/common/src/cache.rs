@@ -0,0 +1,304 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A caching wrapper around a [`RelaychainClient`].
+
+use crate::RelaychainClient;
+use codec::{Decode, Encode};
+use lru::LruCache;
+use parking_lot::Mutex;
+use polkadot_primitives::v1::{
+	Block as PBlock, CoreState, Hash as PHash, Id as ParaId, InboundDownwardMessage,
+	InboundHrmpMessage, OccupiedCoreAssumption, PersistedValidationData, SessionIndex, ValidatorId,
+	ValidatorIndex,
+};
+use sp_blockchain::Result as ClientResult;
+use sp_runtime::generic::BlockId;
+use sp_trie::StorageProof;
+use std::{collections::BTreeMap, sync::Arc};
+
+/// The default number of entries kept by [`CachingRelaychainClient`].
+pub const DEFAULT_CACHE_SIZE: usize = 256;
+
+/// The query that was answered by the relay chain.
+///
+/// Together with the relay chain block hash this identifies a cached entry.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Query {
+	ParachainHead(ParaId),
+	/// The `u8` is the encoded [`OccupiedCoreAssumption`].
+	PersistedValidationData(ParaId, u8),
+	SessionIndexForChild,
+	Validators,
+	AvailabilityCores,
+	BackingGroup(ParaId),
+}
+
+/// A [`RelaychainClient`] that caches the answers of the wrapped client.
+///
+/// The same relay chain state is queried repeatedly by different subsystems, while the answer for
+/// a given relay chain block never changes. Queries against a [`BlockId::Hash`] are therefore
+/// cached by `(block hash, query)` in an LRU cache. Queries against a [`BlockId::Number`] are
+/// always forwarded, as the block a number refers to may change.
+///
+/// The notification streams and the message queue contents are not cached and are directly
+/// forwarded to the wrapped client.
+pub struct CachingRelaychainClient<R> {
+	inner: R,
+	cache: Arc<Mutex<LruCache<(PHash, Query), Vec<u8>>>>,
+}
+
+impl<R> CachingRelaychainClient<R> {
+	/// Create a new instance that caches up to `cache_size` entries.
+	pub fn new(inner: R, cache_size: usize) -> Self {
+		Self {
+			inner,
+			cache: Arc::new(Mutex::new(LruCache::new(cache_size))),
+		}
+	}
+
+	/// Returns the cached value for `query` at `at` or calls `f` to fetch it.
+	///
+	/// Only successful answers end up in the cache.
+	fn cached<T: Encode + Decode>(
+		&self,
+		at: &BlockId<PBlock>,
+		query: Query,
+		f: impl FnOnce() -> ClientResult<T>,
+	) -> ClientResult<T> {
+		let hash = match at {
+			BlockId::Hash(hash) => *hash,
+			BlockId::Number(_) => return f(),
+		};
+
+		if let Some(value) = self
+			.cache
+			.lock()
+			.get(&(hash, query))
+			.and_then(|v| T::decode(&mut &v[..]).ok())
+		{
+			return Ok(value);
+		}
+
+		let value = f()?;
+		self.cache.lock().put((hash, query), value.encode());
+		Ok(value)
+	}
+}
+
+impl<R: Clone> Clone for CachingRelaychainClient<R> {
+	fn clone(&self) -> Self {
+		Self {
+			inner: self.inner.clone(),
+			cache: self.cache.clone(),
+		}
+	}
+}
+
+impl<R: RelaychainClient> RelaychainClient for CachingRelaychainClient<R> {
+	type Error = R::Error;
+
+	type HeadStream = R::HeadStream;
+
+	type StorageChangesStream = R::StorageChangesStream;
+
+	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.inner.new_best_heads(para_id)
+	}
+
+	fn finalized_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.inner.finalized_heads(para_id)
+	}
+
+	fn parachain_head_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<u8>>> {
+		self.cached(at, Query::ParachainHead(para_id), || {
+			self.inner.parachain_head_at(at, para_id)
+		})
+	}
+
+	fn persisted_validation_data(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>> {
+		self.cached(
+			at,
+			Query::PersistedValidationData(para_id, assumption as u8),
+			|| self.inner.persisted_validation_data(at, para_id, assumption),
+		)
+	}
+
+	fn dmq_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>> {
+		self.inner.dmq_contents(at, para_id)
+	}
+
+	fn inbound_hrmp_channels_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>> {
+		self.inner.inbound_hrmp_channels_contents(at, para_id)
+	}
+
+	fn session_index_for_child(&self, at: &BlockId<PBlock>) -> ClientResult<SessionIndex> {
+		self.cached(at, Query::SessionIndexForChild, || self.inner.session_index_for_child(at))
+	}
+
+	fn validators(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>> {
+		self.cached(at, Query::Validators, || self.inner.validators(at))
+	}
+
+	fn availability_cores(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>> {
+		self.cached(at, Query::AvailabilityCores, || self.inner.availability_cores(at))
+	}
+
+	fn backing_group(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<ValidatorIndex>>> {
+		self.cached(at, Query::BackingGroup(para_id), || self.inner.backing_group(at, para_id))
+	}
+
+	fn storage_changes(&self, keys: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream> {
+		self.inner.storage_changes(keys)
+	}
+
+	fn prove_read(&self, at: &BlockId<PBlock>, keys: &[Vec<u8>]) -> ClientResult<StorageProof> {
+		self.inner.prove_read(at, keys)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::stream;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	#[derive(Clone, Default)]
+	struct CountingRelaychain {
+		calls: Arc<AtomicUsize>,
+	}
+
+	impl RelaychainClient for CountingRelaychain {
+		type Error = sp_blockchain::Error;
+
+		type HeadStream = stream::Empty<Vec<u8>>;
+
+		type StorageChangesStream = stream::Empty<crate::RelayStorageChanges>;
+
+		fn new_best_heads(&self, _: ParaId) -> ClientResult<Self::HeadStream> {
+			Ok(stream::empty())
+		}
+
+		fn finalized_heads(&self, _: ParaId) -> ClientResult<Self::HeadStream> {
+			Ok(stream::empty())
+		}
+
+		fn parachain_head_at(
+			&self,
+			_: &BlockId<PBlock>,
+			para_id: ParaId,
+		) -> ClientResult<Option<Vec<u8>>> {
+			self.calls.fetch_add(1, Ordering::SeqCst);
+			Ok(Some(para_id.encode()))
+		}
+
+		fn persisted_validation_data(
+			&self,
+			_: &BlockId<PBlock>,
+			_: ParaId,
+			_: OccupiedCoreAssumption,
+		) -> ClientResult<Option<PersistedValidationData>> {
+			unimplemented!("Not required for tests")
+		}
+
+		fn dmq_contents(
+			&self,
+			_: &BlockId<PBlock>,
+			_: ParaId,
+		) -> ClientResult<Vec<InboundDownwardMessage>> {
+			unimplemented!("Not required for tests")
+		}
+
+		fn inbound_hrmp_channels_contents(
+			&self,
+			_: &BlockId<PBlock>,
+			_: ParaId,
+		) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>> {
+			unimplemented!("Not required for tests")
+		}
+
+		fn session_index_for_child(&self, _: &BlockId<PBlock>) -> ClientResult<SessionIndex> {
+			unimplemented!("Not required for tests")
+		}
+
+		fn validators(&self, _: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>> {
+			unimplemented!("Not required for tests")
+		}
+
+		fn availability_cores(&self, _: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>> {
+			unimplemented!("Not required for tests")
+		}
+
+		fn backing_group(
+			&self,
+			_: &BlockId<PBlock>,
+			_: ParaId,
+		) -> ClientResult<Option<Vec<ValidatorIndex>>> {
+			unimplemented!("Not required for tests")
+		}
+
+		fn storage_changes(&self, _: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream> {
+			Ok(stream::empty())
+		}
+	}
+
+	#[test]
+	fn caches_queries_by_hash() {
+		let inner = CountingRelaychain::default();
+		let client = CachingRelaychainClient::new(inner.clone(), DEFAULT_CACHE_SIZE);
+		let at = BlockId::Hash(PHash::repeat_byte(1));
+
+		assert_eq!(
+			client.parachain_head_at(&at, 100.into()).unwrap(),
+			Some(ParaId::from(100).encode()),
+		);
+		assert_eq!(
+			client.parachain_head_at(&at, 100.into()).unwrap(),
+			Some(ParaId::from(100).encode()),
+		);
+		assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+
+		// A different para id is a different query.
+		client.parachain_head_at(&at, 200.into()).unwrap();
+		assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+
+		// Queries by number are never cached.
+		client.parachain_head_at(&BlockId::Number(1), 100.into()).unwrap();
+		client.parachain_head_at(&BlockId::Number(1), 100.into()).unwrap();
+		assert_eq!(inner.calls.load(Ordering::SeqCst), 4);
+	}
+}
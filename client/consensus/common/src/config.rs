@@ -0,0 +1,310 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Configuration of the wrappers that are applied to the relay chain client of a node.
+
+use crate::{
+	BoundedRelaychainClient, BoundedStreamsConfig, CachingRelaychainClient,
+	HealthTrackingRelaychainClient, MetricsRelaychainClient, RateLimit,
+	RateLimitedRelaychainClient, RelayChainHealthHandle, RelaychainClient, RetryPolicy,
+	RetryingRelaychainClient,
+};
+use polkadot_primitives::v1::{
+	Block as PBlock, CoreState, Id as ParaId, InboundDownwardMessage, InboundHrmpMessage,
+	OccupiedCoreAssumption, PersistedValidationData, SessionIndex, ValidatorId, ValidatorIndex,
+};
+use sp_blockchain::Result as ClientResult;
+use sp_runtime::generic::BlockId;
+use sp_trie::StorageProof;
+use std::collections::BTreeMap;
+use substrate_prometheus_endpoint::{PrometheusError, Registry};
+
+/// A [`RelaychainClient`] that is either wrapped by `W` or used directly.
+///
+/// Used by [`RelayChainClientConfig::build`] for the wrappers that are not enabled.
+#[derive(Clone)]
+pub enum MaybeWrapped<W, R> {
+	/// The client is wrapped by `W`.
+	Wrapped(W),
+	/// The client is used directly.
+	Direct(R),
+}
+
+impl<W, R> MaybeWrapped<W, R> {
+	/// Wrap `client` using `wrap` if `param` is given.
+	fn new<P>(client: R, param: Option<P>, wrap: impl FnOnce(R, P) -> W) -> Self {
+		match param {
+			Some(param) => Self::Wrapped(wrap(client, param)),
+			None => Self::Direct(client),
+		}
+	}
+}
+
+/// Call the given method on the wrapped or the direct client.
+macro_rules! dispatch {
+	($self:ident, $client:ident => $call:expr) => {
+		match $self {
+			Self::Wrapped($client) => $call,
+			Self::Direct($client) => $call,
+		}
+	};
+}
+
+impl<W, R> RelaychainClient for MaybeWrapped<W, R>
+where
+	R: RelaychainClient,
+	W: RelaychainClient<
+		Error = R::Error,
+		HeadStream = R::HeadStream,
+		StorageChangesStream = R::StorageChangesStream,
+	>,
+{
+	type Error = R::Error;
+
+	type HeadStream = R::HeadStream;
+
+	type StorageChangesStream = R::StorageChangesStream;
+
+	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		dispatch!(self, c => c.new_best_heads(para_id))
+	}
+
+	fn finalized_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		dispatch!(self, c => c.finalized_heads(para_id))
+	}
+
+	fn parachain_head_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<u8>>> {
+		dispatch!(self, c => c.parachain_head_at(at, para_id))
+	}
+
+	fn parachain_heads_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+	) -> ClientResult<Vec<Option<Vec<u8>>>> {
+		dispatch!(self, c => c.parachain_heads_at(at, para_ids))
+	}
+
+	fn persisted_validation_data(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>> {
+		dispatch!(self, c => c.persisted_validation_data(at, para_id, assumption))
+	}
+
+	fn persisted_validation_data_for_paras(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Vec<Option<PersistedValidationData>>> {
+		dispatch!(self, c => c.persisted_validation_data_for_paras(at, para_ids, assumption))
+	}
+
+	fn dmq_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>> {
+		dispatch!(self, c => c.dmq_contents(at, para_id))
+	}
+
+	fn inbound_hrmp_channels_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>> {
+		dispatch!(self, c => c.inbound_hrmp_channels_contents(at, para_id))
+	}
+
+	fn session_index_for_child(&self, at: &BlockId<PBlock>) -> ClientResult<SessionIndex> {
+		dispatch!(self, c => c.session_index_for_child(at))
+	}
+
+	fn validators(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>> {
+		dispatch!(self, c => c.validators(at))
+	}
+
+	fn availability_cores(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>> {
+		dispatch!(self, c => c.availability_cores(at))
+	}
+
+	fn backing_group(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<ValidatorIndex>>> {
+		dispatch!(self, c => c.backing_group(at, para_id))
+	}
+
+	fn storage_changes(&self, keys: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream> {
+		dispatch!(self, c => c.storage_changes(keys))
+	}
+
+	fn prove_read(&self, at: &BlockId<PBlock>, keys: &[Vec<u8>]) -> ClientResult<StorageProof> {
+		dispatch!(self, c => c.prove_read(at, keys))
+	}
+}
+
+type Bounded<R> = BoundedRelaychainClient<R>;
+type Retried<R> = MaybeWrapped<RetryingRelaychainClient<Bounded<R>>, Bounded<R>>;
+type Limited<R> = MaybeWrapped<RateLimitedRelaychainClient<Retried<R>>, Retried<R>>;
+type Cached<R> = MaybeWrapped<CachingRelaychainClient<Limited<R>>, Limited<R>>;
+type Measured<R> = MaybeWrapped<MetricsRelaychainClient<Cached<R>>, Cached<R>>;
+
+/// The relay chain client that is returned by [`RelayChainClientConfig::build`].
+pub type ConfiguredRelaychainClient<R> = HealthTrackingRelaychainClient<Measured<R>>;
+
+/// The configuration of the wrappers that are applied to the relay chain client that is followed
+/// by the parachain consensus.
+///
+/// All wrappers except for the health tracking and the bounded head streams are opt-in. The
+/// wrappers are applied in the following order, from the outermost to the innermost:
+/// [`HealthTrackingRelaychainClient`], [`MetricsRelaychainClient`], [`CachingRelaychainClient`],
+/// [`RateLimitedRelaychainClient`], [`RetryingRelaychainClient`] and [`BoundedRelaychainClient`].
+/// So cached answers are not rate limited and only the requests that reach the relay chain are
+/// retried.
+#[derive(Clone, Default)]
+pub struct RelayChainClientConfig {
+	/// The bounds of the head streams.
+	pub stream_bounds: BoundedStreamsConfig,
+	/// Retry requests that failed with a transient error using this policy.
+	pub retry_policy: Option<RetryPolicy>,
+	/// Limit the rate of requests to this default limit.
+	pub rate_limit: Option<RateLimit>,
+	/// The limits of individual methods, only used when [`Self::rate_limit`] is set.
+	pub method_rate_limits: Vec<(&'static str, RateLimit)>,
+	/// Cache up to this number of answers.
+	pub cache_size: Option<usize>,
+	/// Record the metrics of the requests in this registry.
+	pub metrics_registry: Option<Registry>,
+	/// Report the health of the relay chain backend to this handle.
+	pub health: RelayChainHealthHandle,
+}
+
+impl RelayChainClientConfig {
+	/// Wrap the given `client` according to this configuration.
+	pub fn build<R: RelaychainClient>(
+		self,
+		client: R,
+	) -> Result<ConfiguredRelaychainClient<R>, PrometheusError> {
+		let method_rate_limits = self.method_rate_limits;
+
+		let client = BoundedRelaychainClient::new(client, self.stream_bounds);
+		let client = MaybeWrapped::new(client, self.retry_policy, RetryingRelaychainClient::new);
+		let client = MaybeWrapped::new(client, self.rate_limit, |client, limit| {
+			method_rate_limits.into_iter().fold(
+				RateLimitedRelaychainClient::new(client, limit),
+				|client, (method, limit)| client.with_limit(method, limit),
+			)
+		});
+		let client = MaybeWrapped::new(client, self.cache_size, CachingRelaychainClient::new);
+
+		let client = match self.metrics_registry {
+			Some(registry) => {
+				MaybeWrapped::Wrapped(MetricsRelaychainClient::new(client, &registry)?)
+			}
+			None => MaybeWrapped::Direct(client),
+		};
+
+		Ok(HealthTrackingRelaychainClient::with_handle(client, self.health))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::MockRelaychainClient;
+
+	#[test]
+	fn only_enabled_wrappers_are_applied() {
+		let mock = MockRelaychainClient::new();
+		let hash = Default::default();
+		let at = BlockId::Hash(hash);
+
+		let direct = RelayChainClientConfig::default().build(mock.clone()).unwrap();
+		let cached = RelayChainClientConfig {
+			cache_size: Some(16),
+			..Default::default()
+		}
+		.build(mock.clone())
+		.unwrap();
+
+		mock.set_session_index_for_child(hash, 1);
+		assert_eq!(direct.session_index_for_child(&at).unwrap(), 1);
+		assert_eq!(cached.session_index_for_child(&at).unwrap(), 1);
+
+		mock.set_session_index_for_child(hash, 2);
+		assert_eq!(direct.session_index_for_child(&at).unwrap(), 2);
+		assert_eq!(cached.session_index_for_child(&at).unwrap(), 1);
+	}
+
+	#[test]
+	fn metrics_are_registered() {
+		let registry = Registry::new();
+
+		let client = RelayChainClientConfig {
+			metrics_registry: Some(registry.clone()),
+			..Default::default()
+		}
+		.build(MockRelaychainClient::new())
+		.unwrap();
+		client.validators(&BlockId::Hash(Default::default())).unwrap();
+
+		assert!(!registry.gather().is_empty());
+	}
+
+	#[test]
+	fn read_proofs_pass_through_the_wrappers() {
+		let mock = MockRelaychainClient::new();
+		let hash = Default::default();
+		let keys = vec![vec![1, 2, 3]];
+		let proof = StorageProof::new(vec![vec![4, 5, 6]]);
+		mock.set_read_proof(hash, keys.clone(), proof.clone());
+
+		let client = RelayChainClientConfig {
+			retry_policy: Some(Default::default()),
+			rate_limit: Some(Default::default()),
+			cache_size: Some(16),
+			..Default::default()
+		}
+		.build(mock)
+		.unwrap();
+
+		assert_eq!(client.prove_read(&BlockId::Hash(hash), &keys).unwrap(), proof);
+	}
+
+	#[test]
+	fn health_is_reported_to_the_handle() {
+		let health = RelayChainHealthHandle::default();
+
+		let client = RelayChainClientConfig {
+			health: health.clone(),
+			..Default::default()
+		}
+		.build(MockRelaychainClient::new())
+		.unwrap();
+
+		let _heads = client.new_best_heads(100.into()).unwrap();
+		assert_eq!(health.health().active_head_streams, 1);
+	}
+}
@@ -0,0 +1,390 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Health tracking of the relay chain backend.
+
+use crate::RelaychainClient;
+use futures::{Stream, StreamExt};
+use parking_lot::Mutex;
+use polkadot_primitives::v1::{
+	Block as PBlock, CoreState, Id as ParaId, InboundDownwardMessage, InboundHrmpMessage,
+	OccupiedCoreAssumption, PersistedValidationData, SessionIndex, ValidatorId, ValidatorIndex,
+};
+use sp_blockchain::Result as ClientResult;
+use sp_consensus::SyncOracle;
+use sp_runtime::generic::BlockId;
+use sp_trie::StorageProof;
+use std::{
+	collections::BTreeMap,
+	pin::Pin,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+	task::{Context, Poll},
+	time::{Duration, Instant},
+};
+
+/// The health of the relay chain backend as seen by a [`HealthTrackingRelaychainClient`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelayChainHealth {
+	/// Is the relay chain node major syncing?
+	///
+	/// `None` if no sync status was provided.
+	pub is_major_syncing: Option<bool>,
+	/// The number of head streams that are still alive.
+	pub active_head_streams: usize,
+	/// The time since the last relay chain head was received by any head stream.
+	///
+	/// `None` if no head was received yet.
+	pub last_head_age: Option<Duration>,
+}
+
+impl RelayChainHealth {
+	/// Returns `true` if the relay chain backend is considered to be ready.
+	///
+	/// This requires that the relay chain is not major syncing, at least one head stream is alive
+	/// and the last head was received at most `max_head_age` ago.
+	pub fn is_ready(&self, max_head_age: Duration) -> bool {
+		self.is_major_syncing != Some(true) &&
+			self.active_head_streams > 0 &&
+			self.last_head_age.map_or(false, |age| age <= max_head_age)
+	}
+}
+
+#[derive(Default)]
+struct HealthState {
+	active_head_streams: AtomicUsize,
+	last_head: Mutex<Option<Instant>>,
+}
+
+/// A handle to the health that is tracked by a [`HealthTrackingRelaychainClient`].
+///
+/// The handle can be created before the client, to share it with the components of the node that
+/// need to know the health of the relay chain backend.
+#[derive(Clone, Default)]
+pub struct RelayChainHealthHandle {
+	state: Arc<HealthState>,
+	is_major_syncing: Option<Arc<dyn Fn() -> bool + Send + Sync>>,
+}
+
+impl RelayChainHealthHandle {
+	/// Use the given function to determine if the relay chain node is major syncing.
+	pub fn with_sync_status(
+		mut self,
+		is_major_syncing: impl Fn() -> bool + Send + Sync + 'static,
+	) -> Self {
+		self.is_major_syncing = Some(Arc::new(is_major_syncing));
+		self
+	}
+
+	/// Returns the current health of the relay chain backend.
+	pub fn health(&self) -> RelayChainHealth {
+		RelayChainHealth {
+			is_major_syncing: self.is_major_syncing.as_ref().map(|f| f()),
+			active_head_streams: self.state.active_head_streams.load(Ordering::Relaxed),
+			last_head_age: self.state.last_head.lock().map(|i| i.elapsed()),
+		}
+	}
+
+	/// Returns `true` if the relay chain backend is ready.
+	///
+	/// See [`RelayChainHealth::is_ready`] for more information.
+	pub fn is_ready(&self, max_head_age: Duration) -> bool {
+		self.health().is_ready(max_head_age)
+	}
+}
+
+/// A [`SyncOracle`] that reports the health of the relay chain backend.
+///
+/// The relay chain is reported as major syncing while the backend is not ready and as offline
+/// while no head stream is alive.
+#[derive(Clone)]
+pub struct RelayChainHealthOracle {
+	handle: RelayChainHealthHandle,
+	max_head_age: Duration,
+}
+
+impl RelayChainHealthOracle {
+	/// Create a new instance that requires a relay chain head that is at most `max_head_age` old.
+	pub fn new(handle: RelayChainHealthHandle, max_head_age: Duration) -> Self {
+		Self {
+			handle,
+			max_head_age,
+		}
+	}
+}
+
+impl SyncOracle for RelayChainHealthOracle {
+	fn is_major_syncing(&mut self) -> bool {
+		!self.handle.is_ready(self.max_head_age)
+	}
+
+	fn is_offline(&mut self) -> bool {
+		self.handle.health().active_head_streams == 0
+	}
+}
+
+/// A [`RelaychainClient`] that tracks the health of the wrapped client.
+///
+/// The health is derived from the head streams returned by the client. Every head that is
+/// yielded by any of the streams is recorded, as well as the number of streams that are alive.
+#[derive(Clone)]
+pub struct HealthTrackingRelaychainClient<R> {
+	inner: R,
+	handle: RelayChainHealthHandle,
+}
+
+impl<R> HealthTrackingRelaychainClient<R> {
+	/// Create a new instance.
+	pub fn new(inner: R) -> Self {
+		Self::with_handle(inner, Default::default())
+	}
+
+	/// Create a new instance that reports its health to the given `handle`.
+	pub fn with_handle(inner: R, handle: RelayChainHealthHandle) -> Self {
+		Self { inner, handle }
+	}
+
+	/// Use the given function to determine if the relay chain node is major syncing.
+	pub fn with_sync_status(
+		mut self,
+		is_major_syncing: impl Fn() -> bool + Send + Sync + 'static,
+	) -> Self {
+		self.handle = self.handle.with_sync_status(is_major_syncing);
+		self
+	}
+
+	/// Returns a handle to the health of this client.
+	pub fn handle(&self) -> RelayChainHealthHandle {
+		self.handle.clone()
+	}
+
+	/// Returns the current health of the relay chain backend.
+	pub fn health(&self) -> RelayChainHealth {
+		self.handle.health()
+	}
+
+	/// Returns `true` if the relay chain backend is ready.
+	///
+	/// See [`RelayChainHealth::is_ready`] for more information.
+	pub fn is_ready(&self, max_head_age: Duration) -> bool {
+		self.handle.is_ready(max_head_age)
+	}
+}
+
+/// A head stream that reports its items and its liveness to a [`HealthTrackingRelaychainClient`].
+pub struct HealthTrackingStream<S> {
+	inner: S,
+	state: Option<Arc<HealthState>>,
+}
+
+impl<S> HealthTrackingStream<S> {
+	fn new(inner: S, state: Arc<HealthState>) -> Self {
+		state.active_head_streams.fetch_add(1, Ordering::Relaxed);
+
+		Self {
+			inner,
+			state: Some(state),
+		}
+	}
+
+	/// Stop reporting this stream as alive.
+	fn terminate(&mut self) {
+		if let Some(state) = self.state.take() {
+			state.active_head_streams.fetch_sub(1, Ordering::Relaxed);
+		}
+	}
+}
+
+impl<S> Drop for HealthTrackingStream<S> {
+	fn drop(&mut self) {
+		self.terminate();
+	}
+}
+
+impl<S: Stream + Unpin> Stream for HealthTrackingStream<S> {
+	type Item = S::Item;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+		let res = self.inner.poll_next_unpin(cx);
+
+		match res {
+			Poll::Ready(Some(_)) => {
+				if let Some(state) = self.state.as_ref() {
+					*state.last_head.lock() = Some(Instant::now());
+				}
+			}
+			Poll::Ready(None) => self.terminate(),
+			Poll::Pending => {}
+		}
+
+		res
+	}
+}
+
+impl<R: RelaychainClient> RelaychainClient for HealthTrackingRelaychainClient<R> {
+	type Error = R::Error;
+
+	type HeadStream = HealthTrackingStream<R::HeadStream>;
+
+	type StorageChangesStream = R::StorageChangesStream;
+
+	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.inner
+			.new_best_heads(para_id)
+			.map(|s| HealthTrackingStream::new(s, self.handle.state.clone()))
+	}
+
+	fn finalized_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.inner
+			.finalized_heads(para_id)
+			.map(|s| HealthTrackingStream::new(s, self.handle.state.clone()))
+	}
+
+	fn parachain_head_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<u8>>> {
+		self.inner.parachain_head_at(at, para_id)
+	}
+
+	fn parachain_heads_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+	) -> ClientResult<Vec<Option<Vec<u8>>>> {
+		self.inner.parachain_heads_at(at, para_ids)
+	}
+
+	fn persisted_validation_data(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>> {
+		self.inner.persisted_validation_data(at, para_id, assumption)
+	}
+
+	fn persisted_validation_data_for_paras(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Vec<Option<PersistedValidationData>>> {
+		self.inner.persisted_validation_data_for_paras(at, para_ids, assumption)
+	}
+
+	fn dmq_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>> {
+		self.inner.dmq_contents(at, para_id)
+	}
+
+	fn inbound_hrmp_channels_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>> {
+		self.inner.inbound_hrmp_channels_contents(at, para_id)
+	}
+
+	fn session_index_for_child(&self, at: &BlockId<PBlock>) -> ClientResult<SessionIndex> {
+		self.inner.session_index_for_child(at)
+	}
+
+	fn validators(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>> {
+		self.inner.validators(at)
+	}
+
+	fn availability_cores(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>> {
+		self.inner.availability_cores(at)
+	}
+
+	fn backing_group(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<ValidatorIndex>>> {
+		self.inner.backing_group(at, para_id)
+	}
+
+	fn storage_changes(&self, keys: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream> {
+		self.inner.storage_changes(keys)
+	}
+
+	fn prove_read(&self, at: &BlockId<PBlock>, keys: &[Vec<u8>]) -> ClientResult<StorageProof> {
+		self.inner.prove_read(at, keys)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::MockRelaychainClient;
+	use futures::executor::block_on;
+
+	#[test]
+	fn tracks_head_streams() {
+		let mock = MockRelaychainClient::new();
+		let client = HealthTrackingRelaychainClient::new(mock.clone());
+		assert!(!client.is_ready(Duration::from_secs(60)));
+
+		let mut heads = client.new_best_heads(100.into()).unwrap();
+		assert_eq!(client.health().active_head_streams, 1);
+		assert_eq!(client.health().last_head_age, None);
+
+		mock.push_new_best_head(100.into(), vec![1]);
+		assert_eq!(block_on(heads.next()), Some(vec![1]));
+		assert!(client.is_ready(Duration::from_secs(60)));
+
+		drop(heads);
+		assert_eq!(client.health().active_head_streams, 0);
+		assert!(!client.is_ready(Duration::from_secs(60)));
+	}
+
+	#[test]
+	fn not_ready_while_syncing() {
+		let client = HealthTrackingRelaychainClient::new(MockRelaychainClient::new())
+			.with_sync_status(|| true);
+		let _heads = client.new_best_heads(100.into()).unwrap();
+		*client.handle.state.last_head.lock() = Some(Instant::now());
+
+		assert_eq!(client.health().is_major_syncing, Some(true));
+		assert!(!client.is_ready(Duration::from_secs(60)));
+	}
+
+	#[test]
+	fn oracle_follows_the_shared_handle() {
+		let mock = MockRelaychainClient::new();
+		let handle = RelayChainHealthHandle::default();
+		let client = HealthTrackingRelaychainClient::with_handle(mock.clone(), handle.clone());
+		let mut oracle = RelayChainHealthOracle::new(handle, Duration::from_secs(60));
+		assert!(oracle.is_offline());
+		assert!(oracle.is_major_syncing());
+
+		let mut heads = client.new_best_heads(100.into()).unwrap();
+		assert!(!oracle.is_offline());
+		assert!(oracle.is_major_syncing());
+
+		mock.push_new_best_head(100.into(), vec![1]);
+		assert_eq!(block_on(heads.next()), Some(vec![1]));
+		assert!(!oracle.is_major_syncing());
+	}
+}
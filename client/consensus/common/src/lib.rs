@@ -17,8 +17,10 @@
 use sc_client_api::{
 	Backend, BlockBackend, BlockImportNotification, BlockchainEvents, Finalizer, UsageProvider,
 };
-use sp_api::ProvideRuntimeApi;
+use sp_api::{ApiExt, ApiRef, ProvideRuntimeApi};
 use sp_blockchain::{Error as ClientError, Result as ClientResult};
+use sp_core::storage::StorageKey;
+use sp_trie::StorageProof;
 use sp_consensus::{
 	BlockImport, BlockImportParams, BlockOrigin, BlockStatus, Error as ConsensusError,
 	ForkChoiceStrategy, SelectChain as SelectChainT,
@@ -29,14 +31,53 @@ use sp_runtime::{
 };
 
 use polkadot_primitives::v1::{
-	Block as PBlock, Hash as PHash, Id as ParaId, OccupiedCoreAssumption, ParachainHost,
-	PersistedValidationData,
+	Block as PBlock, CoreIndex, CoreState, Hash as PHash, Id as ParaId, InboundDownwardMessage,
+	InboundHrmpMessage, OccupiedCoreAssumption, ParachainHost, PersistedValidationData,
+	ScheduledCore, SessionIndex, ValidatorId, ValidatorIndex,
 };
 
 use codec::Decode;
 use futures::{future, select, FutureExt, Stream, StreamExt};
 
-use std::{marker::PhantomData, sync::Arc};
+use std::{
+	cell::Cell,
+	collections::BTreeMap,
+	marker::PhantomData,
+	sync::{
+		atomic::{AtomicU32, Ordering},
+		Arc,
+	},
+};
+
+mod bounded;
+mod cache;
+mod config;
+mod health;
+mod metrics;
+#[cfg(any(test, feature = "test-helpers"))]
+mod mock;
+mod proof;
+mod proposer;
+mod rate_limit;
+mod retry;
+mod sync_oracle;
+pub use bounded::{
+	BoundedRelaychainClient, BoundedStream, BoundedStreamsConfig, OverflowPolicy, StreamBounds,
+};
+pub use cache::{CachingRelaychainClient, DEFAULT_CACHE_SIZE};
+pub use config::{ConfiguredRelaychainClient, MaybeWrapped, RelayChainClientConfig};
+pub use health::{
+	HealthTrackingRelaychainClient, HealthTrackingStream, RelayChainHealth, RelayChainHealthHandle,
+	RelayChainHealthOracle,
+};
+pub use metrics::MetricsRelaychainClient;
+#[cfg(any(test, feature = "test-helpers"))]
+pub use mock::MockRelaychainClient;
+pub use proof::ProvingRelaychainClient;
+pub use proposer::{ProposerWrapper, WrappedProposerFactory};
+pub use rate_limit::{RateLimit, RateLimitedRelaychainClient};
+pub use retry::{is_transient_error, RetryPolicy, RetryingRelaychainClient};
+pub use sync_oracle::CombinedSyncOracle;
 
 /// Errors that can occur while following the polkadot relay-chain.
 #[derive(Debug)]
@@ -55,6 +96,9 @@ pub trait RelaychainClient: Clone + 'static {
 	/// A stream that yields head-data for a parachain.
 	type HeadStream: Stream<Item = Vec<u8>> + Send + Unpin;
 
+	/// A stream that yields changes of relay chain storage entries.
+	type StorageChangesStream: Stream<Item = RelayStorageChanges> + Send + Unpin;
+
 	/// Get a stream of new best heads for the given parachain.
 	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream>;
 
@@ -67,6 +111,132 @@ pub trait RelaychainClient: Clone + 'static {
 		at: &BlockId<PBlock>,
 		para_id: ParaId,
 	) -> ClientResult<Option<Vec<u8>>>;
+
+	/// Returns the [`PersistedValidationData`] of the given `para_id` at the given block id.
+	///
+	/// The `assumption` is used when the availability core of the parachain is occupied.
+	fn persisted_validation_data(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>>;
+
+	/// Returns the parachain heads for all the given `para_ids` at the given block id.
+	///
+	/// The heads are returned in the order of `para_ids`.
+	fn parachain_heads_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+	) -> ClientResult<Vec<Option<Vec<u8>>>> {
+		para_ids
+			.iter()
+			.map(|para_id| self.parachain_head_at(at, *para_id))
+			.collect()
+	}
+
+	/// Returns the [`PersistedValidationData`] for all the given `para_ids` at the given block id.
+	///
+	/// The validation data is returned in the order of `para_ids`.
+	fn persisted_validation_data_for_paras(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Vec<Option<PersistedValidationData>>> {
+		para_ids
+			.iter()
+			.map(|para_id| self.persisted_validation_data(at, *para_id, assumption))
+			.collect()
+	}
+
+	/// Returns the contents of the downward message queue of the given `para_id` at the given
+	/// block id.
+	fn dmq_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>>;
+
+	/// Returns the contents of all inbound HRMP channels of the given `para_id` at the given block
+	/// id.
+	///
+	/// Empty channels are also included.
+	fn inbound_hrmp_channels_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>>;
+
+	/// Returns the session index that is expected at the child of the given block id.
+	fn session_index_for_child(&self, at: &BlockId<PBlock>) -> ClientResult<SessionIndex>;
+
+	/// Returns the current validator set at the given block id.
+	fn validators(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>>;
+
+	/// Returns the state of all availability cores at the given block id.
+	///
+	/// See [`scheduled_core`] for checking if a parachain is scheduled on one of the cores.
+	fn availability_cores(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>>;
+
+	/// Returns the validators of the backing group assigned to the given `para_id` at the given
+	/// block id.
+	///
+	/// Returns `None` if the parachain is not assigned to any availability core.
+	fn backing_group(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<ValidatorIndex>>>;
+
+	/// Get a stream of changes to the given relay chain storage `keys`.
+	///
+	/// An item is yielded for every imported relay chain block that changed at least one of the
+	/// `keys`.
+	fn storage_changes(&self, keys: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream>;
+
+	/// Generate a read proof of the given relay chain storage `keys` at the given block id.
+	///
+	/// The proof can be put into the parachain inherent, so that the runtime can read these
+	/// entries of the relay chain state. Not every client has access to the relay chain state, so
+	/// the default implementation fails. See [`ProvingRelaychainClient`] for adding the state of a
+	/// relay chain backend.
+	fn prove_read(&self, at: &BlockId<PBlock>, keys: &[Vec<u8>]) -> ClientResult<StorageProof> {
+		let _ = keys;
+
+		Err(ClientError::Msg(format!(
+			"The relay chain client can not prove the relay chain state at {:?}",
+			at,
+		)))
+	}
+}
+
+/// Returns the index of the availability core the given `para_id` is scheduled on.
+///
+/// A parachain is scheduled when a core is free and assigned to it, or when a core is occupied and
+/// the parachain is up next once the occupying candidate is made available. Returns `None` if the
+/// parachain is not scheduled, e.g. because it is an on-demand parachain without a claim.
+pub fn scheduled_core(cores: &[CoreState], para_id: ParaId) -> Option<CoreIndex> {
+	let is_para = |core: &Option<ScheduledCore>| core.as_ref().map(|c| c.para_id) == Some(para_id);
+
+	cores
+		.iter()
+		.position(|core| match core {
+			CoreState::Scheduled(core) => core.para_id == para_id,
+			CoreState::Occupied(core) => is_para(&core.next_up_on_available),
+			CoreState::Free => false,
+		})
+		.map(|index| CoreIndex(index as u32))
+}
+
+/// The changes of relay chain storage entries in a relay chain block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RelayStorageChanges {
+	/// The hash of the relay chain block that changed the storage entries.
+	pub block_hash: PHash,
+	/// The changed keys with their new values. `None` means the entry was removed.
+	pub changes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
 }
 
 /// Follow the finalized head of the given parachain.
@@ -383,6 +553,62 @@ where
 	}
 }
 
+/// The newest version of the `ParachainHost` runtime api this client knows about.
+const KNOWN_PARACHAIN_HOST_VERSION: u32 = 1;
+
+/// The newest `ParachainHost` version a warning was logged for, see [`parachain_host_at`].
+static WARNED_PARACHAIN_HOST_VERSION: AtomicU32 = AtomicU32::new(KNOWN_PARACHAIN_HOST_VERSION);
+
+/// Returns the `ParachainHost` runtime api at the given block id.
+///
+/// Returns an error if the relay chain runtime at `at` does not provide the `ParachainHost`
+/// runtime api. Newer versions of the api are accepted and only lead to a warning, once per
+/// version, as new versions are expected to keep the calls used by Cumulus working.
+///
+/// There is no per-version dispatch, as version 1 is the only version of the api. When a new
+/// version changes a call used by Cumulus, the call needs to check the returned version and use
+/// the `*_before_version_*` method generated by `decl_runtime_apis!` for older relay chains.
+fn parachain_host_at<'a, T>(
+	client: &'a T,
+	at: &BlockId<PBlock>,
+) -> ClientResult<ApiRef<'a, T::Api>>
+where
+	T: ProvideRuntimeApi<PBlock>,
+	T::Api: ParachainHost<PBlock>,
+{
+	let runtime_api = client.runtime_api();
+
+	let version = Cell::new(None);
+	let has_api = runtime_api.has_api_with::<dyn ParachainHost<PBlock>, _>(at, |v| {
+		version.set(Some(v));
+		true
+	})?;
+
+	match version.get() {
+		Some(version) if has_api => {
+			if WARNED_PARACHAIN_HOST_VERSION.fetch_max(version, Ordering::Relaxed) < version {
+				tracing::warn!(
+					target: "cumulus-consensus",
+					?at,
+					version,
+					known_version = KNOWN_PARACHAIN_HOST_VERSION,
+					"Relay chain runtime provides a newer `ParachainHost` api than known. \
+					 Please upgrade the node.",
+				);
+			}
+
+			Ok(runtime_api)
+		}
+		_ => Err(ClientError::Msg(format!(
+			"The relay chain runtime at {:?} does not provide the `ParachainHost` api",
+			at,
+		))),
+	}
+}
+
+/// The relay chain client of an embedded relay chain node.
+///
+/// The head streams are not bounded, see [`RelayChainClientConfig::stream_bounds`].
 impl<T> RelaychainClient for Arc<T>
 where
 	T: sc_client_api::BlockchainEvents<PBlock> + ProvideRuntimeApi<PBlock> + 'static + Send + Sync,
@@ -392,6 +618,8 @@ where
 
 	type HeadStream = Box<dyn Stream<Item = Vec<u8>> + Send + Unpin>;
 
+	type StorageChangesStream = Box<dyn Stream<Item = RelayStorageChanges> + Send + Unpin>;
+
 	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
 		let polkadot = self.clone();
 
@@ -429,11 +657,123 @@ where
 		at: &BlockId<PBlock>,
 		para_id: ParaId,
 	) -> ClientResult<Option<Vec<u8>>> {
-		self.runtime_api()
+		parachain_host_at(&**self, at)?
 			.persisted_validation_data(at, para_id, OccupiedCoreAssumption::TimedOut)
 			.map(|s| s.map(|s| s.parent_head.0))
 			.map_err(Into::into)
 	}
+
+	fn persisted_validation_data(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>> {
+		parachain_host_at(&**self, at)?
+			.persisted_validation_data(at, para_id, assumption)
+			.map_err(Into::into)
+	}
+
+	fn parachain_heads_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+	) -> ClientResult<Vec<Option<Vec<u8>>>> {
+		Ok(self
+			.persisted_validation_data_for_paras(at, para_ids, OccupiedCoreAssumption::TimedOut)?
+			.into_iter()
+			.map(|d| d.map(|d| d.parent_head.0))
+			.collect())
+	}
+
+	fn persisted_validation_data_for_paras(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Vec<Option<PersistedValidationData>>> {
+		// Use the same runtime api instance for all requests, to share the storage cache.
+		let runtime_api = parachain_host_at(&**self, at)?;
+
+		para_ids
+			.iter()
+			.map(|para_id| {
+				runtime_api
+					.persisted_validation_data(at, *para_id, assumption)
+					.map_err(Into::into)
+			})
+			.collect()
+	}
+
+	fn dmq_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>> {
+		parachain_host_at(&**self, at)?.dmq_contents(at, para_id).map_err(Into::into)
+	}
+
+	fn inbound_hrmp_channels_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>> {
+		parachain_host_at(&**self, at)?
+			.inbound_hrmp_channels_contents(at, para_id)
+			.map_err(Into::into)
+	}
+
+	fn session_index_for_child(&self, at: &BlockId<PBlock>) -> ClientResult<SessionIndex> {
+		parachain_host_at(&**self, at)?.session_index_for_child(at).map_err(Into::into)
+	}
+
+	fn validators(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>> {
+		parachain_host_at(&**self, at)?.validators(at).map_err(Into::into)
+	}
+
+	fn availability_cores(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>> {
+		parachain_host_at(&**self, at)?.availability_cores(at).map_err(Into::into)
+	}
+
+	fn backing_group(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<ValidatorIndex>>> {
+		let runtime_api = parachain_host_at(&**self, at)?;
+
+		let cores = runtime_api.availability_cores(at)?;
+		let core_index = match cores.iter().position(|c| c.para_id() == Some(para_id)) {
+			Some(index) => CoreIndex(index as u32),
+			None => return Ok(None),
+		};
+
+		let (mut groups, rotation_info) = runtime_api.validator_groups(at)?;
+		let group = rotation_info.group_for_core(core_index, cores.len());
+
+		Ok(if (group.0 as usize) < groups.len() {
+			Some(groups.swap_remove(group.0 as usize))
+		} else {
+			None
+		})
+	}
+
+	fn storage_changes(&self, keys: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream> {
+		let keys = keys.iter().cloned().map(StorageKey).collect::<Vec<_>>();
+
+		let s = self
+			.storage_changes_notification_stream(Some(&keys), None)?
+			.map(|(block_hash, changes)| RelayStorageChanges {
+				block_hash,
+				changes: changes
+					.iter()
+					.filter(|(child, _, _)| child.is_none())
+					.map(|(_, key, value)| (key.0.clone(), value.map(|v| v.0.clone())))
+					.collect(),
+			});
+
+		Ok(Box::new(s))
+	}
 }
 
 /// Select chain implementation for parachains.
@@ -518,7 +858,12 @@ pub struct ParachainCandidate<B> {
 	/// The block that was built for this candidate.
 	pub block: B,
 	/// The proof that was recorded while building the block.
-	pub proof: sp_trie::StorageProof,
+	pub proof: StorageProof,
+	/// Auxiliary data of the consensus, e.g. a signature over the head data.
+	///
+	/// This is attached to the announcement of the block, where it is checked by the
+	/// `AuxDataChecker` of the block announce validator.
+	pub aux_data: Option<Vec<u8>>,
 }
 
 /// A specific parachain consensus implementation that can be used by a collator to produce candidates.
@@ -570,79 +915,21 @@ mod tests {
 		runtime::{Block, Header},
 		Client, InitBlockBuilder, TestClientBuilder, TestClientBuilderExt,
 	};
-	use futures::{channel::mpsc, executor::block_on};
-	use futures_timer::Delay;
-	use std::{sync::Mutex, time::Duration};
-
-	struct RelaychainInner {
-		new_best_heads: Option<mpsc::UnboundedReceiver<Header>>,
-		finalized_heads: Option<mpsc::UnboundedReceiver<Header>>,
-		new_best_heads_sender: mpsc::UnboundedSender<Header>,
-		finalized_heads_sender: mpsc::UnboundedSender<Header>,
-	}
-
-	impl RelaychainInner {
-		fn new() -> Self {
-			let (new_best_heads_sender, new_best_heads) = mpsc::unbounded();
-			let (finalized_heads_sender, finalized_heads) = mpsc::unbounded();
-
-			Self {
-				new_best_heads_sender,
-				finalized_heads_sender,
-				new_best_heads: Some(new_best_heads),
-				finalized_heads: Some(finalized_heads),
-			}
-		}
-	}
-
-	#[derive(Clone)]
-	struct Relaychain {
-		inner: Arc<Mutex<RelaychainInner>>,
-	}
-
-	impl Relaychain {
-		fn new() -> Self {
-			Self {
-				inner: Arc::new(Mutex::new(RelaychainInner::new())),
+	use futures::executor::block_on;
+	use std::task::Poll;
+
+	/// Let the consensus, which runs on the same thread, make progress.
+	async fn yield_to_consensus() {
+		let mut yielded = false;
+		future::poll_fn(|cx| {
+			if yielded {
+				return Poll::Ready(());
 			}
-		}
-	}
-
-	impl RelaychainClient for Relaychain {
-		type Error = ClientError;
-
-		type HeadStream = Box<dyn Stream<Item = Vec<u8>> + Send + Unpin>;
-		fn new_best_heads(&self, _: ParaId) -> ClientResult<Self::HeadStream> {
-			let stream = self
-				.inner
-				.lock()
-				.unwrap()
-				.new_best_heads
-				.take()
-				.expect("Should only be called once");
-
-			Ok(Box::new(stream.map(|v| v.encode())))
-		}
-
-		fn finalized_heads(&self, _: ParaId) -> ClientResult<Self::HeadStream> {
-			let stream = self
-				.inner
-				.lock()
-				.unwrap()
-				.finalized_heads
-				.take()
-				.expect("Should only be called once");
-
-			Ok(Box::new(stream.map(|v| v.encode())))
-		}
-
-		fn parachain_head_at(
-			&self,
-			_: &BlockId<PBlock>,
-			_: ParaId,
-		) -> ClientResult<Option<Vec<u8>>> {
-			unimplemented!("Not required for tests")
-		}
+			yielded = true;
+			cx.waker().wake_by_ref();
+			Poll::Pending
+		})
+		.await
 	}
 
 	fn build_and_import_block(mut client: Arc<Client>) -> Block {
@@ -668,23 +955,19 @@ mod tests {
 		let client = Arc::new(TestClientBuilder::default().build());
 
 		let block = build_and_import_block(client.clone());
-		let relay_chain = Relaychain::new();
-		let new_best_heads_sender = relay_chain
-			.inner
-			.lock()
-			.unwrap()
-			.new_best_heads_sender
-			.clone();
+		let relay_chain = MockRelaychainClient::new();
 
-		let consensus =
-			run_parachain_consensus(100.into(), client.clone(), relay_chain, Arc::new(|_, _| {}));
+		let consensus = run_parachain_consensus(
+			100.into(),
+			client.clone(),
+			relay_chain.clone(),
+			Arc::new(|_, _| {}),
+		);
 
 		let work = async move {
-			new_best_heads_sender
-				.unbounded_send(block.header().clone())
-				.unwrap();
+			relay_chain.push_new_best_head(100.into(), block.header().encode());
 			loop {
-				Delay::new(Duration::from_millis(100)).await;
+				yield_to_consensus().await;
 				if block.hash() == client.usage_info().chain.best_hash {
 					break;
 				}
@@ -709,23 +992,19 @@ mod tests {
 		let client = Arc::new(TestClientBuilder::default().build());
 
 		let block = build_and_import_block(client.clone());
-		let relay_chain = Relaychain::new();
-		let finalized_sender = relay_chain
-			.inner
-			.lock()
-			.unwrap()
-			.finalized_heads_sender
-			.clone();
+		let relay_chain = MockRelaychainClient::new();
 
-		let consensus =
-			run_parachain_consensus(100.into(), client.clone(), relay_chain, Arc::new(|_, _| {}));
+		let consensus = run_parachain_consensus(
+			100.into(),
+			client.clone(),
+			relay_chain.clone(),
+			Arc::new(|_, _| {}),
+		);
 
 		let work = async move {
-			finalized_sender
-				.unbounded_send(block.header().clone())
-				.unwrap();
+			relay_chain.push_finalized_head(100.into(), block.header().encode());
 			loop {
-				Delay::new(Duration::from_millis(100)).await;
+				yield_to_consensus().await;
 				if block.hash() == client.usage_info().chain.finalized_hash {
 					break;
 				}
@@ -760,31 +1039,25 @@ mod tests {
 			block_builder.build().unwrap().block
 		};
 
-		let relay_chain = Relaychain::new();
-		let finalized_sender = relay_chain
-			.inner
-			.lock()
-			.unwrap()
-			.finalized_heads_sender
-			.clone();
+		let relay_chain = MockRelaychainClient::new();
 
-		let consensus =
-			run_parachain_consensus(100.into(), client.clone(), relay_chain, Arc::new(|_, _| {}));
+		let consensus = run_parachain_consensus(
+			100.into(),
+			client.clone(),
+			relay_chain.clone(),
+			Arc::new(|_, _| {}),
+		);
 
 		let work = async move {
 			for _ in 0..3usize {
-				finalized_sender
-					.unbounded_send(unknown_block.header().clone())
-					.unwrap();
+				relay_chain.push_finalized_head(100.into(), unknown_block.header().encode());
 
-				Delay::new(Duration::from_millis(100)).await;
+				yield_to_consensus().await;
 			}
 
-			finalized_sender
-				.unbounded_send(block.header().clone())
-				.unwrap();
+			relay_chain.push_finalized_head(100.into(), block.header().encode());
 			loop {
-				Delay::new(Duration::from_millis(100)).await;
+				yield_to_consensus().await;
 				if block.hash() == client.usage_info().chain.finalized_hash {
 					break;
 				}
@@ -822,38 +1095,32 @@ mod tests {
 			block_builder.build().unwrap().block
 		};
 
-		let relay_chain = Relaychain::new();
-		let new_best_heads_sender = relay_chain
-			.inner
-			.lock()
-			.unwrap()
-			.new_best_heads_sender
-			.clone();
+		let relay_chain = MockRelaychainClient::new();
 
-		let consensus =
-			run_parachain_consensus(100.into(), client.clone(), relay_chain, Arc::new(|_, _| {}));
+		let consensus = run_parachain_consensus(
+			100.into(),
+			client.clone(),
+			relay_chain.clone(),
+			Arc::new(|_, _| {}),
+		);
 
 		let work = async move {
-			new_best_heads_sender
-				.unbounded_send(block.header().clone())
-				.unwrap();
+			relay_chain.push_new_best_head(100.into(), block.header().encode());
 
 			loop {
-				Delay::new(Duration::from_millis(100)).await;
+				yield_to_consensus().await;
 				if block.hash() == client.usage_info().chain.best_hash {
 					break;
 				}
 			}
 
 			// Announce the unknown block
-			new_best_heads_sender
-				.unbounded_send(unknown_block.header().clone())
-				.unwrap();
+			relay_chain.push_new_best_head(100.into(), unknown_block.header().encode());
 
 			// Do some iterations. As this is a local task executor, only one task can run at a time.
 			// Meaning that it should already have processed the unknown block.
 			for _ in 0..3usize {
-				Delay::new(Duration::from_millis(100)).await;
+				yield_to_consensus().await;
 			}
 
 			let (header, body) = unknown_block.clone().deconstruct();
@@ -869,7 +1136,7 @@ mod tests {
 				.unwrap();
 
 			loop {
-				Delay::new(Duration::from_millis(100)).await;
+				yield_to_consensus().await;
 				if unknown_block.hash() == client.usage_info().chain.best_hash {
 					break;
 				}
@@ -886,4 +1153,19 @@ mod tests {
 			}
 		});
 	}
+
+	#[test]
+	fn scheduled_core_works() {
+		let scheduled = |para_id: u32| {
+			CoreState::Scheduled(ScheduledCore {
+				para_id: para_id.into(),
+				collator: None,
+			})
+		};
+		let cores = vec![CoreState::Free, scheduled(200), scheduled(100)];
+
+		assert_eq!(scheduled_core(&cores, 100.into()), Some(CoreIndex(2)));
+		assert_eq!(scheduled_core(&cores, 200.into()), Some(CoreIndex(1)));
+		assert_eq!(scheduled_core(&cores, 300.into()), None);
+	}
 }
@@ -0,0 +1,190 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A wrapper around a [`RelaychainClient`] that records Prometheus metrics.
+
+use crate::RelaychainClient;
+use polkadot_primitives::v1::{
+	Block as PBlock, CoreState, Id as ParaId, InboundDownwardMessage, InboundHrmpMessage,
+	OccupiedCoreAssumption, PersistedValidationData, SessionIndex, ValidatorId, ValidatorIndex,
+};
+use sp_blockchain::Result as ClientResult;
+use sp_runtime::generic::BlockId;
+use sp_trie::StorageProof;
+use std::collections::BTreeMap;
+use substrate_prometheus_endpoint::{
+	register, CounterVec, HistogramOpts, HistogramVec, Opts, PrometheusError, Registry, U64,
+};
+
+#[derive(Clone)]
+struct Metrics {
+	calls: CounterVec<U64>,
+	errors: CounterVec<U64>,
+	duration: HistogramVec,
+}
+
+impl Metrics {
+	fn register(registry: &Registry) -> Result<Self, PrometheusError> {
+		Ok(Self {
+			calls: register(
+				CounterVec::new(
+					Opts::new(
+						"cumulus_relay_chain_calls_total",
+						"Number of calls to the relay chain client per method.",
+					),
+					&["method"],
+				)?,
+				registry,
+			)?,
+			errors: register(
+				CounterVec::new(
+					Opts::new(
+						"cumulus_relay_chain_errors_total",
+						"Number of failed calls to the relay chain client per method.",
+					),
+					&["method"],
+				)?,
+				registry,
+			)?,
+			duration: register(
+				HistogramVec::new(
+					HistogramOpts::new(
+						"cumulus_relay_chain_call_duration_seconds",
+						"Time it took to answer a call to the relay chain client per method.",
+					),
+					&["method"],
+				)?,
+				registry,
+			)?,
+		})
+	}
+}
+
+/// A [`RelaychainClient`] that records the number of calls, the number of errors and the call
+/// duration of every method of the wrapped client.
+///
+/// For the methods returning a stream, only the creation of the stream is measured.
+#[derive(Clone)]
+pub struct MetricsRelaychainClient<R> {
+	inner: R,
+	metrics: Metrics,
+}
+
+impl<R> MetricsRelaychainClient<R> {
+	/// Create a new instance that registers its metrics in the given `registry`.
+	pub fn new(inner: R, registry: &Registry) -> Result<Self, PrometheusError> {
+		Ok(Self {
+			inner,
+			metrics: Metrics::register(registry)?,
+		})
+	}
+
+	/// Call `f` and record the metrics for the given `method`.
+	fn measure<T>(&self, method: &str, f: impl FnOnce() -> ClientResult<T>) -> ClientResult<T> {
+		self.metrics.calls.with_label_values(&[method]).inc();
+
+		let timer = self.metrics.duration.with_label_values(&[method]).start_timer();
+		let res = f();
+		timer.observe_duration();
+
+		if res.is_err() {
+			self.metrics.errors.with_label_values(&[method]).inc();
+		}
+
+		res
+	}
+}
+
+impl<R: RelaychainClient> RelaychainClient for MetricsRelaychainClient<R> {
+	type Error = R::Error;
+
+	type HeadStream = R::HeadStream;
+
+	type StorageChangesStream = R::StorageChangesStream;
+
+	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.measure("new_best_heads", || self.inner.new_best_heads(para_id))
+	}
+
+	fn finalized_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.measure("finalized_heads", || self.inner.finalized_heads(para_id))
+	}
+
+	fn parachain_head_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<u8>>> {
+		self.measure("parachain_head_at", || self.inner.parachain_head_at(at, para_id))
+	}
+
+	fn persisted_validation_data(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>> {
+		self.measure("persisted_validation_data", || {
+			self.inner.persisted_validation_data(at, para_id, assumption)
+		})
+	}
+
+	fn dmq_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>> {
+		self.measure("dmq_contents", || self.inner.dmq_contents(at, para_id))
+	}
+
+	fn inbound_hrmp_channels_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>> {
+		self.measure("inbound_hrmp_channels_contents", || {
+			self.inner.inbound_hrmp_channels_contents(at, para_id)
+		})
+	}
+
+	fn session_index_for_child(&self, at: &BlockId<PBlock>) -> ClientResult<SessionIndex> {
+		self.measure("session_index_for_child", || self.inner.session_index_for_child(at))
+	}
+
+	fn validators(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>> {
+		self.measure("validators", || self.inner.validators(at))
+	}
+
+	fn availability_cores(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>> {
+		self.measure("availability_cores", || self.inner.availability_cores(at))
+	}
+
+	fn backing_group(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<ValidatorIndex>>> {
+		self.measure("backing_group", || self.inner.backing_group(at, para_id))
+	}
+
+	fn storage_changes(&self, keys: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream> {
+		self.measure("storage_changes", || self.inner.storage_changes(keys))
+	}
+
+	fn prove_read(&self, at: &BlockId<PBlock>, keys: &[Vec<u8>]) -> ClientResult<StorageProof> {
+		self.measure("prove_read", || self.inner.prove_read(at, keys))
+	}
+}
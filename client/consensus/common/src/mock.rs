@@ -0,0 +1,449 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! An in-memory [`RelaychainClient`] for testing.
+
+use crate::{RelaychainClient, RelayStorageChanges};
+use futures::channel::mpsc;
+use parking_lot::Mutex;
+use polkadot_primitives::v1::{
+	Block as PBlock, CoreState, Hash as PHash, Id as ParaId, InboundDownwardMessage,
+	InboundHrmpMessage, OccupiedCoreAssumption, PersistedValidationData, SessionIndex, ValidatorId,
+	ValidatorIndex,
+};
+use sp_blockchain::{Error as ClientError, Result as ClientResult};
+use sp_runtime::generic::BlockId;
+use sp_trie::StorageProof;
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::Arc,
+};
+
+#[derive(Default)]
+struct MockState {
+	parachain_heads: HashMap<(PHash, ParaId), Vec<u8>>,
+	validation_data: HashMap<(PHash, ParaId), PersistedValidationData>,
+	dmq_contents: HashMap<(PHash, ParaId), Vec<InboundDownwardMessage>>,
+	hrmp_contents: HashMap<(PHash, ParaId), BTreeMap<ParaId, Vec<InboundHrmpMessage>>>,
+	session_indices: HashMap<PHash, SessionIndex>,
+	validators: HashMap<PHash, Vec<ValidatorId>>,
+	availability_cores: HashMap<PHash, Vec<CoreState>>,
+	backing_groups: HashMap<(PHash, ParaId), Vec<ValidatorIndex>>,
+	read_proofs: HashMap<(PHash, Vec<Vec<u8>>), StorageProof>,
+	failures: HashMap<&'static str, fn() -> ClientError>,
+	new_best_heads: HeadStreams,
+	finalized_heads: HeadStreams,
+	storage_changes: Vec<(Vec<Vec<u8>>, mpsc::UnboundedSender<RelayStorageChanges>)>,
+}
+
+/// The heads that were pushed so far and the streams they are pushed to.
+#[derive(Default)]
+struct HeadStreams {
+	pushed: Vec<(ParaId, Vec<u8>)>,
+	senders: Vec<(ParaId, mpsc::UnboundedSender<Vec<u8>>)>,
+}
+
+impl HeadStreams {
+	fn push(&mut self, para_id: ParaId, head: Vec<u8>) {
+		self.senders
+			.retain(|(id, sender)| *id != para_id || sender.unbounded_send(head.clone()).is_ok());
+		self.pushed.push((para_id, head));
+	}
+
+	/// Returns a stream that replays the heads of `para_id` that were pushed before.
+	fn subscribe(&mut self, para_id: ParaId) -> mpsc::UnboundedReceiver<Vec<u8>> {
+		let (sender, receiver) = mpsc::unbounded();
+		for (_, head) in self.pushed.iter().filter(|(id, _)| *id == para_id) {
+			let _ = sender.unbounded_send(head.clone());
+		}
+		self.senders.push((para_id, sender));
+		receiver
+	}
+}
+
+/// A scriptable in-memory [`RelaychainClient`].
+///
+/// All answers of the client need to be set up front. Queries are only answered for relay chain
+/// blocks given by [`BlockId::Hash`]. The head streams first yield the heads that were pushed
+/// before they were created, so a subscriber doesn't miss any head. The storage changes streams
+/// only yield the changes that are pushed after the stream was created.
+///
+/// Every method can be made to fail by calling [`MockRelaychainClient::fail`] with the name of the
+/// method.
+#[derive(Clone, Default)]
+pub struct MockRelaychainClient {
+	state: Arc<Mutex<MockState>>,
+}
+
+impl MockRelaychainClient {
+	/// Create a new instance without any data.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Push a new best head of the given `para_id` to the [`RelaychainClient::new_best_heads`]
+	/// streams.
+	pub fn push_new_best_head(&self, para_id: ParaId, head: Vec<u8>) {
+		self.state.lock().new_best_heads.push(para_id, head);
+	}
+
+	/// Push a new finalized head of the given `para_id` to the
+	/// [`RelaychainClient::finalized_heads`] streams.
+	pub fn push_finalized_head(&self, para_id: ParaId, head: Vec<u8>) {
+		self.state.lock().finalized_heads.push(para_id, head);
+	}
+
+	/// Push the given storage `changes` of the relay chain block `block_hash` to the
+	/// [`RelaychainClient::storage_changes`] streams.
+	///
+	/// Every stream only receives the changes of the keys it was created for.
+	pub fn push_storage_changes(
+		&self,
+		block_hash: PHash,
+		changes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+	) {
+		self.state.lock().storage_changes.retain(|(keys, sender)| {
+			let changes = changes
+				.iter()
+				.filter(|(key, _)| keys.contains(key))
+				.cloned()
+				.collect::<Vec<_>>();
+
+			changes.is_empty() ||
+				sender
+					.unbounded_send(RelayStorageChanges {
+						block_hash,
+						changes,
+					})
+					.is_ok()
+		});
+	}
+
+	/// Set the parachain head of the given `para_id` at the relay chain block `at`.
+	///
+	/// If no head is set, the parent head of the [`PersistedValidationData`] is returned.
+	pub fn set_parachain_head(&self, at: PHash, para_id: ParaId, head: Vec<u8>) {
+		self.state.lock().parachain_heads.insert((at, para_id), head);
+	}
+
+	/// Set the [`PersistedValidationData`] of the given `para_id` at the relay chain block `at`.
+	///
+	/// The same data is returned for every [`OccupiedCoreAssumption`].
+	pub fn set_persisted_validation_data(
+		&self,
+		at: PHash,
+		para_id: ParaId,
+		data: PersistedValidationData,
+	) {
+		self.state.lock().validation_data.insert((at, para_id), data);
+	}
+
+	/// Set the downward messages of the given `para_id` at the relay chain block `at`.
+	pub fn set_dmq_contents(
+		&self,
+		at: PHash,
+		para_id: ParaId,
+		messages: Vec<InboundDownwardMessage>,
+	) {
+		self.state.lock().dmq_contents.insert((at, para_id), messages);
+	}
+
+	/// Set the inbound HRMP messages of the given `para_id` at the relay chain block `at`.
+	pub fn set_inbound_hrmp_channels_contents(
+		&self,
+		at: PHash,
+		para_id: ParaId,
+		messages: BTreeMap<ParaId, Vec<InboundHrmpMessage>>,
+	) {
+		self.state.lock().hrmp_contents.insert((at, para_id), messages);
+	}
+
+	/// Set the session index for the child of the relay chain block `at`.
+	pub fn set_session_index_for_child(&self, at: PHash, session_index: SessionIndex) {
+		self.state.lock().session_indices.insert(at, session_index);
+	}
+
+	/// Set the validator set at the relay chain block `at`.
+	pub fn set_validators(&self, at: PHash, validators: Vec<ValidatorId>) {
+		self.state.lock().validators.insert(at, validators);
+	}
+
+	/// Set the state of the availability cores at the relay chain block `at`.
+	pub fn set_availability_cores(&self, at: PHash, cores: Vec<CoreState>) {
+		self.state.lock().availability_cores.insert(at, cores);
+	}
+
+	/// Set the backing group of the given `para_id` at the relay chain block `at`.
+	pub fn set_backing_group(&self, at: PHash, para_id: ParaId, group: Vec<ValidatorIndex>) {
+		self.state.lock().backing_groups.insert((at, para_id), group);
+	}
+
+	/// Set the read proof of the given `keys` at the relay chain block `at`.
+	pub fn set_read_proof(&self, at: PHash, keys: Vec<Vec<u8>>, proof: StorageProof) {
+		self.state.lock().read_proofs.insert((at, keys), proof);
+	}
+
+	/// Make all calls to the given `method` fail with the error returned by `error`.
+	pub fn fail(&self, method: &'static str, error: fn() -> ClientError) {
+		self.state.lock().failures.insert(method, error);
+	}
+
+	/// Stop failing the calls to the given `method`.
+	pub fn stop_failing(&self, method: &'static str) {
+		self.state.lock().failures.remove(method);
+	}
+
+	/// Returns the error `method` should fail with or the `hash` that `at` refers to.
+	fn check(&self, method: &'static str, at: &BlockId<PBlock>) -> ClientResult<PHash> {
+		self.check_failure(method)?;
+
+		match at {
+			BlockId::Hash(hash) => Ok(*hash),
+			BlockId::Number(number) => Err(ClientError::UnknownBlock(format!(
+				"The mock only supports queries by hash, got number {}",
+				number,
+			))),
+		}
+	}
+
+	fn check_failure(&self, method: &'static str) -> ClientResult<()> {
+		match self.state.lock().failures.get(method) {
+			Some(error) => Err(error()),
+			None => Ok(()),
+		}
+	}
+}
+
+impl RelaychainClient for MockRelaychainClient {
+	type Error = ClientError;
+
+	type HeadStream = mpsc::UnboundedReceiver<Vec<u8>>;
+
+	type StorageChangesStream = mpsc::UnboundedReceiver<RelayStorageChanges>;
+
+	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.check_failure("new_best_heads")?;
+
+		Ok(self.state.lock().new_best_heads.subscribe(para_id))
+	}
+
+	fn finalized_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.check_failure("finalized_heads")?;
+
+		Ok(self.state.lock().finalized_heads.subscribe(para_id))
+	}
+
+	fn parachain_head_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<u8>>> {
+		let hash = self.check("parachain_head_at", at)?;
+		let state = self.state.lock();
+
+		Ok(state.parachain_heads.get(&(hash, para_id)).cloned().or_else(|| {
+			state
+				.validation_data
+				.get(&(hash, para_id))
+				.map(|d| d.parent_head.0.clone())
+		}))
+	}
+
+	fn persisted_validation_data(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+		_: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>> {
+		let hash = self.check("persisted_validation_data", at)?;
+		Ok(self.state.lock().validation_data.get(&(hash, para_id)).cloned())
+	}
+
+	fn dmq_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>> {
+		let hash = self.check("dmq_contents", at)?;
+		Ok(self
+			.state
+			.lock()
+			.dmq_contents
+			.get(&(hash, para_id))
+			.cloned()
+			.unwrap_or_default())
+	}
+
+	fn inbound_hrmp_channels_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>> {
+		let hash = self.check("inbound_hrmp_channels_contents", at)?;
+		Ok(self
+			.state
+			.lock()
+			.hrmp_contents
+			.get(&(hash, para_id))
+			.cloned()
+			.unwrap_or_default())
+	}
+
+	fn session_index_for_child(&self, at: &BlockId<PBlock>) -> ClientResult<SessionIndex> {
+		let hash = self.check("session_index_for_child", at)?;
+		Ok(self
+			.state
+			.lock()
+			.session_indices
+			.get(&hash)
+			.copied()
+			.unwrap_or_default())
+	}
+
+	fn validators(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>> {
+		let hash = self.check("validators", at)?;
+		Ok(self
+			.state
+			.lock()
+			.validators
+			.get(&hash)
+			.cloned()
+			.unwrap_or_default())
+	}
+
+	fn availability_cores(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>> {
+		let hash = self.check("availability_cores", at)?;
+		Ok(self
+			.state
+			.lock()
+			.availability_cores
+			.get(&hash)
+			.cloned()
+			.unwrap_or_default())
+	}
+
+	fn backing_group(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<ValidatorIndex>>> {
+		let hash = self.check("backing_group", at)?;
+		Ok(self.state.lock().backing_groups.get(&(hash, para_id)).cloned())
+	}
+
+	fn storage_changes(&self, keys: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream> {
+		self.check_failure("storage_changes")?;
+
+		let (sender, receiver) = mpsc::unbounded();
+		self.state.lock().storage_changes.push((keys.to_vec(), sender));
+		Ok(receiver)
+	}
+
+	fn prove_read(&self, at: &BlockId<PBlock>, keys: &[Vec<u8>]) -> ClientResult<StorageProof> {
+		let hash = self.check("prove_read", at)?;
+
+		self.state
+			.lock()
+			.read_proofs
+			.get(&(hash, keys.to_vec()))
+			.cloned()
+			.ok_or_else(|| ClientError::Msg(format!("No read proof set for {:?}", keys)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{executor::block_on, StreamExt};
+
+	#[test]
+	fn heads_are_pushed_to_the_streams_of_the_para() {
+		let client = MockRelaychainClient::new();
+		let heads = client.new_best_heads(100.into()).unwrap();
+		let mut other_heads = client.new_best_heads(200.into()).unwrap();
+
+		client.push_new_best_head(100.into(), vec![1]);
+		client.push_new_best_head(200.into(), vec![2]);
+		drop(client);
+
+		assert_eq!(block_on(heads.collect::<Vec<_>>()), vec![vec![1]]);
+		assert_eq!(block_on(other_heads.next()), Some(vec![2]));
+	}
+
+	#[test]
+	fn pushed_heads_are_replayed_to_late_streams() {
+		let client = MockRelaychainClient::new();
+		client.push_finalized_head(100.into(), vec![1]);
+		client.push_finalized_head(200.into(), vec![2]);
+
+		let heads = client.finalized_heads(100.into()).unwrap();
+		client.push_finalized_head(100.into(), vec![3]);
+		drop(client);
+
+		assert_eq!(block_on(heads.collect::<Vec<_>>()), vec![vec![1], vec![3]]);
+	}
+
+	#[test]
+	fn storage_changes_are_filtered_by_key() {
+		let client = MockRelaychainClient::new();
+		let mut changes = client.storage_changes(&[vec![1]]).unwrap();
+		let block_hash = PHash::repeat_byte(1);
+
+		client.push_storage_changes(block_hash, vec![(vec![2], None)]);
+		client.push_storage_changes(block_hash, vec![(vec![1], Some(vec![3])), (vec![2], None)]);
+		drop(client);
+
+		assert_eq!(
+			block_on(changes.next()),
+			Some(RelayStorageChanges {
+				block_hash,
+				changes: vec![(vec![1], Some(vec![3]))],
+			}),
+		);
+		assert_eq!(block_on(changes.next()), None);
+	}
+
+	#[test]
+	fn batched_heads_are_returned_in_order() {
+		let client = MockRelaychainClient::new();
+		let hash = PHash::repeat_byte(1);
+		client.set_parachain_head(hash, 100.into(), vec![1]);
+		client.set_parachain_head(hash, 300.into(), vec![3]);
+
+		assert_eq!(
+			client
+				.parachain_heads_at(&BlockId::Hash(hash), &[300.into(), 200.into(), 100.into()])
+				.unwrap(),
+			vec![Some(vec![3]), None, Some(vec![1])],
+		);
+	}
+
+	#[test]
+	fn failing_calls_work() {
+		let client = MockRelaychainClient::new();
+		let at = BlockId::Hash(PHash::repeat_byte(1));
+		client.set_parachain_head(PHash::repeat_byte(1), 100.into(), vec![1]);
+
+		client.fail("parachain_head_at", || ClientError::RemoteFetchFailed);
+		assert!(matches!(
+			client.parachain_head_at(&at, 100.into()),
+			Err(ClientError::RemoteFetchFailed),
+		));
+
+		client.stop_failing("parachain_head_at");
+		assert_eq!(client.parachain_head_at(&at, 100.into()).unwrap(), Some(vec![1]));
+	}
+}
@@ -0,0 +1,158 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A wrapper around a [`RelaychainClient`] that proves the relay chain state of a backend.
+
+use crate::RelaychainClient;
+use polkadot_primitives::v1::{
+	Block as PBlock, CoreState, Id as ParaId, InboundDownwardMessage, InboundHrmpMessage,
+	OccupiedCoreAssumption, PersistedValidationData, SessionIndex, ValidatorId, ValidatorIndex,
+};
+use sc_client_api::Backend;
+use sp_blockchain::{Error as ClientError, Result as ClientResult};
+use sp_runtime::generic::BlockId;
+use sp_trie::StorageProof;
+use std::{collections::BTreeMap, sync::Arc};
+
+/// A [`RelaychainClient`] that implements [`RelaychainClient::prove_read`] using the state of the
+/// given relay chain backend.
+///
+/// All other methods are directly forwarded to the wrapped client.
+pub struct ProvingRelaychainClient<R, B> {
+	inner: R,
+	backend: Arc<B>,
+}
+
+impl<R, B> ProvingRelaychainClient<R, B> {
+	/// Create a new instance that proves the state of the given `backend`.
+	pub fn new(inner: R, backend: Arc<B>) -> Self {
+		Self { inner, backend }
+	}
+}
+
+impl<R: Clone, B> Clone for ProvingRelaychainClient<R, B> {
+	fn clone(&self) -> Self {
+		Self {
+			inner: self.inner.clone(),
+			backend: self.backend.clone(),
+		}
+	}
+}
+
+impl<R, B> RelaychainClient for ProvingRelaychainClient<R, B>
+where
+	R: RelaychainClient,
+	B: Backend<PBlock> + 'static,
+{
+	type Error = R::Error;
+
+	type HeadStream = R::HeadStream;
+
+	type StorageChangesStream = R::StorageChangesStream;
+
+	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.inner.new_best_heads(para_id)
+	}
+
+	fn finalized_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.inner.finalized_heads(para_id)
+	}
+
+	fn parachain_head_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<u8>>> {
+		self.inner.parachain_head_at(at, para_id)
+	}
+
+	fn parachain_heads_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+	) -> ClientResult<Vec<Option<Vec<u8>>>> {
+		self.inner.parachain_heads_at(at, para_ids)
+	}
+
+	fn persisted_validation_data(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>> {
+		self.inner.persisted_validation_data(at, para_id, assumption)
+	}
+
+	fn persisted_validation_data_for_paras(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Vec<Option<PersistedValidationData>>> {
+		self.inner.persisted_validation_data_for_paras(at, para_ids, assumption)
+	}
+
+	fn dmq_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>> {
+		self.inner.dmq_contents(at, para_id)
+	}
+
+	fn inbound_hrmp_channels_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>> {
+		self.inner.inbound_hrmp_channels_contents(at, para_id)
+	}
+
+	fn session_index_for_child(&self, at: &BlockId<PBlock>) -> ClientResult<SessionIndex> {
+		self.inner.session_index_for_child(at)
+	}
+
+	fn validators(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>> {
+		self.inner.validators(at)
+	}
+
+	fn availability_cores(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>> {
+		self.inner.availability_cores(at)
+	}
+
+	fn backing_group(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<ValidatorIndex>>> {
+		self.inner.backing_group(at, para_id)
+	}
+
+	fn storage_changes(&self, keys: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream> {
+		self.inner.storage_changes(keys)
+	}
+
+	fn prove_read(&self, at: &BlockId<PBlock>, keys: &[Vec<u8>]) -> ClientResult<StorageProof> {
+		let state = self.backend.state_at(*at)?;
+
+		sp_state_machine::prove_read(state, keys).map_err(|e| {
+			ClientError::Msg(format!(
+				"Failed to prove the relay chain state at {:?}: {:?}",
+				at, e,
+			))
+		})
+	}
+}
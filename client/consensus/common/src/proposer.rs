@@ -0,0 +1,153 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A wrapper around a proposer factory that wraps every proposer it creates.
+//!
+//! The parachain consensus implementations are generic over the proposer factory, so a
+//! [`WrappedProposerFactory`] can be passed to them to, for example, add pre-runtime digests to
+//! every proposal or to record metrics per proposal, without reimplementing the consensus.
+
+use futures::{Future, FutureExt};
+use sp_consensus::{Environment, Proposer};
+use sp_runtime::traits::Block as BlockT;
+use std::{pin::Pin, sync::Arc};
+
+/// Something that wraps the proposers created by a proposer factory.
+pub trait ProposerWrapper<B: BlockT, P>: Send + Sync + 'static {
+	/// The wrapped proposer.
+	type Proposer: Proposer<B> + Send + 'static;
+
+	/// Wrap the `proposer` that was created to build on top of `parent`.
+	fn wrap(&self, parent: &B::Header, proposer: P) -> Self::Proposer;
+}
+
+impl<B, P, W, F> ProposerWrapper<B, P> for F
+where
+	B: BlockT,
+	W: Proposer<B> + Send + 'static,
+	F: Fn(&B::Header, P) -> W + Send + Sync + 'static,
+{
+	type Proposer = W;
+
+	fn wrap(&self, parent: &B::Header, proposer: P) -> W {
+		(self)(parent, proposer)
+	}
+}
+
+/// A proposer factory that wraps the proposers of the `inner` factory with a [`ProposerWrapper`].
+pub struct WrappedProposerFactory<PF, W> {
+	inner: PF,
+	wrapper: Arc<W>,
+}
+
+impl<PF, W> WrappedProposerFactory<PF, W> {
+	/// Create a new instance that wraps the proposers of `inner` with `wrapper`.
+	pub fn new(inner: PF, wrapper: W) -> Self {
+		Self {
+			inner,
+			wrapper: Arc::new(wrapper),
+		}
+	}
+}
+
+impl<B, PF, W> Environment<B> for WrappedProposerFactory<PF, W>
+where
+	B: BlockT,
+	PF: Environment<B>,
+	W: ProposerWrapper<B, PF::Proposer>,
+{
+	type Proposer = W::Proposer;
+	type CreateProposer =
+		Pin<Box<dyn Future<Output = Result<Self::Proposer, Self::Error>> + Send + 'static>>;
+	type Error = PF::Error;
+
+	fn init(&mut self, parent_header: &B::Header) -> Self::CreateProposer {
+		let wrapper = self.wrapper.clone();
+		let parent = parent_header.clone();
+
+		self.inner
+			.init(parent_header)
+			.map(move |proposer| proposer.map(|proposer| wrapper.wrap(&parent, proposer)))
+			.boxed()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use cumulus_test_client::runtime::{Block, Header};
+	use futures::{executor::block_on, future};
+	use sp_consensus::{DisableProofRecording, Proposal};
+	use sp_inherents::InherentData;
+	use sp_runtime::{generic::Digest, traits::Header as HeaderT};
+	use std::time::Duration;
+
+	/// A proposer that remembers the number of its parent.
+	struct DummyProposer(u32);
+
+	impl Proposer<Block> for DummyProposer {
+		type Error = sp_consensus::Error;
+		type Transaction = ();
+		type Proposal = future::Ready<Result<Proposal<Block, (), ()>, sp_consensus::Error>>;
+		type ProofRecording = DisableProofRecording;
+		type Proof = ();
+
+		fn propose(
+			self,
+			_: InherentData,
+			_: Digest<<Block as BlockT>::Hash>,
+			_: Duration,
+			_: Option<usize>,
+		) -> Self::Proposal {
+			future::ready(Err(sp_consensus::Error::CannotPropose))
+		}
+	}
+
+	struct DummyFactory;
+
+	impl Environment<Block> for DummyFactory {
+		type Proposer = DummyProposer;
+		type CreateProposer = future::Ready<Result<DummyProposer, sp_consensus::Error>>;
+		type Error = sp_consensus::Error;
+
+		fn init(&mut self, parent_header: &Header) -> Self::CreateProposer {
+			future::ready(Ok(DummyProposer(*parent_header.number())))
+		}
+	}
+
+	#[test]
+	fn proposers_are_wrapped() {
+		let mut factory = WrappedProposerFactory::new(
+			DummyFactory,
+			|parent: &Header, proposer: DummyProposer| {
+				assert_eq!(*parent.number(), proposer.0);
+				DummyProposer(proposer.0 + 100)
+			},
+		);
+
+		let parent = Header::new(
+			5,
+			Default::default(),
+			Default::default(),
+			Default::default(),
+			Default::default(),
+		);
+		let proposer = block_on(factory.init(&parent)).unwrap();
+
+		assert_eq!(proposer.0, 105);
+	}
+}
@@ -0,0 +1,327 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A rate limiting wrapper around a [`RelaychainClient`].
+
+use crate::RelaychainClient;
+use parking_lot::Mutex;
+use polkadot_primitives::v1::{
+	Block as PBlock, CoreState, Id as ParaId, InboundDownwardMessage, InboundHrmpMessage,
+	OccupiedCoreAssumption, PersistedValidationData, SessionIndex, ValidatorId, ValidatorIndex,
+};
+use sp_blockchain::{Error as ClientError, Result as ClientResult};
+use sp_runtime::generic::BlockId;
+use sp_trie::StorageProof;
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+/// The budget of a token bucket.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+	/// The number of requests that can be done in a burst.
+	pub burst: u32,
+	/// The number of requests per second that are added to the budget.
+	pub requests_per_second: u32,
+}
+
+impl Default for RateLimit {
+	fn default() -> Self {
+		Self {
+			burst: 50,
+			requests_per_second: 20,
+		}
+	}
+}
+
+struct TokenBucket {
+	limit: RateLimit,
+	tokens: f64,
+	last_refill: Instant,
+}
+
+impl TokenBucket {
+	fn new(limit: RateLimit) -> Self {
+		Self {
+			limit,
+			tokens: limit.burst as f64,
+			last_refill: Instant::now(),
+		}
+	}
+
+	/// Take a token from the bucket.
+	///
+	/// Returns the time to wait until a token is available if the bucket is empty.
+	fn try_take(&mut self) -> Result<(), Duration> {
+		let now = Instant::now();
+		let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+		self.last_refill = now;
+
+		let rate = self.limit.requests_per_second.max(1) as f64;
+		self.tokens = (self.tokens + elapsed * rate).min(self.limit.burst.max(1) as f64);
+
+		if self.tokens >= 1.0 {
+			self.tokens -= 1.0;
+			Ok(())
+		} else {
+			Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+		}
+	}
+}
+
+/// A [`RelaychainClient`] that limits the rate of requests to the wrapped client.
+///
+/// Every method has its own token bucket. Methods without an explicit [`RateLimit`] use the
+/// default limit. As the methods of [`RelaychainClient`] are synchronous and called from async
+/// code, requests that exceed their budget fail instead of waiting for a token. Async callers can
+/// use [`Self::when_ready`] to wait for a token without blocking the current thread.
+///
+/// # Note
+///
+/// The creation of the streams is also rate limited, but not the items of the streams.
+#[derive(Clone)]
+pub struct RateLimitedRelaychainClient<R> {
+	inner: R,
+	default_limit: RateLimit,
+	limits: HashMap<&'static str, RateLimit>,
+	buckets: Arc<Mutex<HashMap<&'static str, TokenBucket>>>,
+}
+
+impl<R> RateLimitedRelaychainClient<R> {
+	/// Create a new instance that uses `default_limit` for every method.
+	pub fn new(inner: R, default_limit: RateLimit) -> Self {
+		Self {
+			inner,
+			default_limit,
+			limits: Default::default(),
+			buckets: Default::default(),
+		}
+	}
+
+	/// Use the given `limit` for the given `method`.
+	pub fn with_limit(mut self, method: &'static str, limit: RateLimit) -> Self {
+		self.limits.insert(method, limit);
+		self
+	}
+
+	/// Wait until a request to `method` is allowed and then call `f` with the wrapped client.
+	///
+	/// Waiting for a token doesn't block the current thread.
+	pub async fn when_ready<T>(&self, method: &'static str, f: impl FnOnce(&R) -> T) -> T {
+		while let Err(wait) = self.try_take(method) {
+			tracing::trace!(
+				target: "cumulus-consensus",
+				?wait,
+				"Relay chain request `{}` is rate limited, waiting.",
+				method,
+			);
+			futures_timer::Delay::new(wait).await;
+		}
+
+		f(&self.inner)
+	}
+
+	/// Take a token from the bucket of `method`.
+	fn try_take(&self, method: &'static str) -> Result<(), Duration> {
+		self.buckets
+			.lock()
+			.entry(method)
+			.or_insert_with(|| {
+				TokenBucket::new(self.limits.get(method).copied().unwrap_or(self.default_limit))
+			})
+			.try_take()
+	}
+
+	/// Call `f` if a request to `method` is allowed, fail otherwise.
+	fn limited<T>(
+		&self,
+		method: &'static str,
+		f: impl FnOnce() -> ClientResult<T>,
+	) -> ClientResult<T> {
+		match self.try_take(method) {
+			Ok(()) => f(),
+			Err(wait) => {
+				tracing::debug!(
+					target: "cumulus-consensus",
+					?wait,
+					"Relay chain request `{}` is rate limited.",
+					method,
+				);
+
+				Err(ClientError::Msg(format!(
+					"Relay chain request `{}` is rate limited, retry in {:?}",
+					method, wait,
+				)))
+			}
+		}
+	}
+}
+
+impl<R: RelaychainClient> RelaychainClient for RateLimitedRelaychainClient<R> {
+	type Error = R::Error;
+
+	type HeadStream = R::HeadStream;
+
+	type StorageChangesStream = R::StorageChangesStream;
+
+	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.limited("new_best_heads", || self.inner.new_best_heads(para_id))
+	}
+
+	fn finalized_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.limited("finalized_heads", || self.inner.finalized_heads(para_id))
+	}
+
+	fn parachain_head_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<u8>>> {
+		self.limited("parachain_head_at", || self.inner.parachain_head_at(at, para_id))
+	}
+
+	fn parachain_heads_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+	) -> ClientResult<Vec<Option<Vec<u8>>>> {
+		self.limited("parachain_heads_at", || self.inner.parachain_heads_at(at, para_ids))
+	}
+
+	fn persisted_validation_data(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>> {
+		self.limited("persisted_validation_data", || {
+			self.inner.persisted_validation_data(at, para_id, assumption)
+		})
+	}
+
+	fn persisted_validation_data_for_paras(
+		&self,
+		at: &BlockId<PBlock>,
+		para_ids: &[ParaId],
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Vec<Option<PersistedValidationData>>> {
+		self.limited("persisted_validation_data_for_paras", || {
+			self.inner.persisted_validation_data_for_paras(at, para_ids, assumption)
+		})
+	}
+
+	fn dmq_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>> {
+		self.limited("dmq_contents", || self.inner.dmq_contents(at, para_id))
+	}
+
+	fn inbound_hrmp_channels_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>> {
+		self.limited("inbound_hrmp_channels_contents", || {
+			self.inner.inbound_hrmp_channels_contents(at, para_id)
+		})
+	}
+
+	fn session_index_for_child(&self, at: &BlockId<PBlock>) -> ClientResult<SessionIndex> {
+		self.limited("session_index_for_child", || self.inner.session_index_for_child(at))
+	}
+
+	fn validators(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>> {
+		self.limited("validators", || self.inner.validators(at))
+	}
+
+	fn availability_cores(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>> {
+		self.limited("availability_cores", || self.inner.availability_cores(at))
+	}
+
+	fn backing_group(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<ValidatorIndex>>> {
+		self.limited("backing_group", || self.inner.backing_group(at, para_id))
+	}
+
+	fn storage_changes(&self, keys: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream> {
+		self.limited("storage_changes", || self.inner.storage_changes(keys))
+	}
+
+	fn prove_read(&self, at: &BlockId<PBlock>, keys: &[Vec<u8>]) -> ClientResult<StorageProof> {
+		self.limited("prove_read", || self.inner.prove_read(at, keys))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn token_bucket_allows_bursts() {
+		let mut bucket = TokenBucket::new(RateLimit {
+			burst: 3,
+			requests_per_second: 1,
+		});
+
+		assert!(bucket.try_take().is_ok());
+		assert!(bucket.try_take().is_ok());
+		assert!(bucket.try_take().is_ok());
+
+		let wait = bucket.try_take().unwrap_err();
+		assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+	}
+
+	fn limited_client() -> RateLimitedRelaychainClient<crate::MockRelaychainClient> {
+		RateLimitedRelaychainClient::new(
+			crate::MockRelaychainClient::new(),
+			RateLimit {
+				burst: 1,
+				requests_per_second: 1000,
+			},
+		)
+	}
+
+	#[test]
+	fn requests_over_budget_fail_without_waiting() {
+		let client = limited_client();
+		let at = BlockId::Hash(Default::default());
+
+		assert!(client.validators(&at).is_ok());
+
+		let start = Instant::now();
+		assert!(client.validators(&at).is_err());
+		assert!(start.elapsed() < Duration::from_millis(100));
+	}
+
+	#[test]
+	fn when_ready_waits_for_a_token() {
+		let client = limited_client();
+		let at = BlockId::Hash(Default::default());
+
+		assert!(client.validators(&at).is_ok());
+		let res =
+			futures::executor::block_on(client.when_ready("validators", |c| c.validators(&at)));
+
+		assert!(res.is_ok());
+	}
+}
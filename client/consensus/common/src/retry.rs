@@ -0,0 +1,305 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A retrying wrapper around a [`RelaychainClient`].
+
+use crate::RelaychainClient;
+use polkadot_primitives::v1::{
+	Block as PBlock, CoreState, Id as ParaId, InboundDownwardMessage, InboundHrmpMessage,
+	OccupiedCoreAssumption, PersistedValidationData, SessionIndex, ValidatorId, ValidatorIndex,
+};
+use sp_blockchain::{Error as ClientError, Result as ClientResult};
+use sp_runtime::generic::BlockId;
+use sp_trie::StorageProof;
+use std::{collections::BTreeMap, future::Future, time::Duration};
+
+const LOG_TARGET: &str = "cumulus-consensus";
+
+/// Returns `true` if the given error is expected to go away when the request is retried.
+///
+/// Only failures of fetching remote data are considered transient. Everything else, e.g. an
+/// unknown block or a failing runtime api call, will fail again on retry.
+pub fn is_transient_error(error: &ClientError) -> bool {
+	matches!(error, ClientError::RemoteFetchCancelled | ClientError::RemoteFetchFailed)
+}
+
+/// The policy used by [`RetryingRelaychainClient`].
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+	/// The maximum number of retries after the initial attempt failed.
+	pub max_retries: u32,
+	/// The time to wait before the first retry.
+	pub initial_backoff: Duration,
+	/// The upper bound for the time to wait between two retries.
+	///
+	/// The backoff is doubled after every retry until it reaches this value.
+	pub max_backoff: Duration,
+	/// Decides if an error is transient and thus, if the request should be retried.
+	pub is_transient: fn(&ClientError) -> bool,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self {
+			max_retries: 3,
+			initial_backoff: Duration::from_millis(100),
+			max_backoff: Duration::from_secs(2),
+			is_transient: is_transient_error,
+		}
+	}
+}
+
+impl RetryPolicy {
+	/// Run `f` until it succeeds, fails with a permanent error or all retries are used up.
+	///
+	/// # Note
+	///
+	/// The relay chain client is called from async code, so this doesn't wait between the
+	/// retries. Use [`Self::run_async`] to back off between the retries.
+	pub fn run<T>(&self, what: &str, mut f: impl FnMut() -> ClientResult<T>) -> ClientResult<T> {
+		let mut retries = 0;
+
+		loop {
+			match f() {
+				Err(e) if self.should_retry(what, retries, &e) => retries += 1,
+				res => return res,
+			}
+		}
+	}
+
+	/// Run the future returned by `f` until it succeeds, fails with a permanent error or all
+	/// retries are used up.
+	///
+	/// Between two retries this waits for the backoff without blocking the current thread.
+	pub async fn run_async<T, F, Fut>(&self, what: &str, mut f: F) -> ClientResult<T>
+	where
+		F: FnMut() -> Fut,
+		Fut: Future<Output = ClientResult<T>>,
+	{
+		let mut backoff = self.initial_backoff;
+		let mut retries = 0;
+
+		loop {
+			match f().await {
+				Err(e) if self.should_retry(what, retries, &e) => {
+					futures_timer::Delay::new(backoff).await;
+					backoff = std::cmp::min(backoff * 2, self.max_backoff);
+					retries += 1;
+				}
+				res => return res,
+			}
+		}
+	}
+
+	/// Returns `true` if the request `what` that failed with `error` should be retried.
+	fn should_retry(&self, what: &str, retries: u32, error: &ClientError) -> bool {
+		if retries >= self.max_retries || !(self.is_transient)(error) {
+			return false;
+		}
+
+		tracing::debug!(
+			target: LOG_TARGET,
+			error = ?error,
+			retry = retries + 1,
+			max_retries = self.max_retries,
+			"Relay chain request `{}` failed with a transient error, retrying.",
+			what,
+		);
+
+		true
+	}
+}
+
+/// A [`RelaychainClient`] that retries requests failing with a transient error.
+///
+/// The errors that are considered transient are controlled by the [`RetryPolicy`]. As the
+/// methods of [`RelaychainClient`] are synchronous, the requests are retried without a backoff.
+#[derive(Clone)]
+pub struct RetryingRelaychainClient<R> {
+	inner: R,
+	policy: RetryPolicy,
+}
+
+impl<R> RetryingRelaychainClient<R> {
+	/// Create a new instance that uses the given `policy`.
+	pub fn new(inner: R, policy: RetryPolicy) -> Self {
+		Self { inner, policy }
+	}
+}
+
+impl<R: RelaychainClient> RelaychainClient for RetryingRelaychainClient<R> {
+	type Error = R::Error;
+
+	type HeadStream = R::HeadStream;
+
+	type StorageChangesStream = R::StorageChangesStream;
+
+	fn new_best_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.policy.run("new_best_heads", || self.inner.new_best_heads(para_id))
+	}
+
+	fn finalized_heads(&self, para_id: ParaId) -> ClientResult<Self::HeadStream> {
+		self.policy.run("finalized_heads", || self.inner.finalized_heads(para_id))
+	}
+
+	fn parachain_head_at(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<u8>>> {
+		self.policy.run("parachain_head_at", || self.inner.parachain_head_at(at, para_id))
+	}
+
+	fn persisted_validation_data(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+		assumption: OccupiedCoreAssumption,
+	) -> ClientResult<Option<PersistedValidationData>> {
+		self.policy.run("persisted_validation_data", || {
+			self.inner.persisted_validation_data(at, para_id, assumption)
+		})
+	}
+
+	fn dmq_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Vec<InboundDownwardMessage>> {
+		self.policy.run("dmq_contents", || self.inner.dmq_contents(at, para_id))
+	}
+
+	fn inbound_hrmp_channels_contents(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<BTreeMap<ParaId, Vec<InboundHrmpMessage>>> {
+		self.policy.run("inbound_hrmp_channels_contents", || {
+			self.inner.inbound_hrmp_channels_contents(at, para_id)
+		})
+	}
+
+	fn session_index_for_child(&self, at: &BlockId<PBlock>) -> ClientResult<SessionIndex> {
+		self.policy.run("session_index_for_child", || self.inner.session_index_for_child(at))
+	}
+
+	fn validators(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<ValidatorId>> {
+		self.policy.run("validators", || self.inner.validators(at))
+	}
+
+	fn availability_cores(&self, at: &BlockId<PBlock>) -> ClientResult<Vec<CoreState>> {
+		self.policy.run("availability_cores", || self.inner.availability_cores(at))
+	}
+
+	fn backing_group(
+		&self,
+		at: &BlockId<PBlock>,
+		para_id: ParaId,
+	) -> ClientResult<Option<Vec<ValidatorIndex>>> {
+		self.policy.run("backing_group", || self.inner.backing_group(at, para_id))
+	}
+
+	fn storage_changes(&self, keys: &[Vec<u8>]) -> ClientResult<Self::StorageChangesStream> {
+		self.policy.run("storage_changes", || self.inner.storage_changes(keys))
+	}
+
+	fn prove_read(&self, at: &BlockId<PBlock>, keys: &[Vec<u8>]) -> ClientResult<StorageProof> {
+		self.policy.run("prove_read", || self.inner.prove_read(at, keys))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn policy(max_retries: u32) -> RetryPolicy {
+		RetryPolicy {
+			max_retries,
+			initial_backoff: Duration::from_millis(1),
+			max_backoff: Duration::from_millis(1),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn retries_transient_errors() {
+		let mut attempts = 0;
+		let res = policy(3).run("test", || {
+			attempts += 1;
+			if attempts < 3 {
+				Err(ClientError::RemoteFetchFailed)
+			} else {
+				Ok(attempts)
+			}
+		});
+
+		assert_eq!(res.unwrap(), 3);
+	}
+
+	#[test]
+	fn gives_up_after_max_retries() {
+		let mut attempts = 0;
+		let res: ClientResult<()> = policy(2).run("test", || {
+			attempts += 1;
+			Err(ClientError::RemoteFetchFailed)
+		});
+
+		assert!(res.is_err());
+		assert_eq!(attempts, 3);
+	}
+
+	#[test]
+	fn does_not_retry_permanent_errors() {
+		let mut attempts = 0;
+		let res: ClientResult<()> = policy(3).run("test", || {
+			attempts += 1;
+			Err(ClientError::UnknownBlock("test".into()))
+		});
+
+		assert!(res.is_err());
+		assert_eq!(attempts, 1);
+	}
+
+	#[test]
+	fn run_async_retries_transient_errors() {
+		let mut attempts = 0;
+		let res = futures::executor::block_on(policy(3).run_async("test", || {
+			attempts += 1;
+			let attempt = attempts;
+			async move {
+				if attempt < 3 {
+					Err(ClientError::RemoteFetchCancelled)
+				} else {
+					Ok(attempt)
+				}
+			}
+		}));
+
+		assert_eq!(res.unwrap(), 3);
+	}
+
+	#[test]
+	fn run_async_gives_up_after_max_retries() {
+		let mut attempts = 0;
+		let res: ClientResult<()> = futures::executor::block_on(policy(2).run_async("test", || {
+			attempts += 1;
+			async { Err(ClientError::RemoteFetchFailed) }
+		}));
+
+		assert!(res.is_err());
+		assert_eq!(attempts, 3);
+	}
+}
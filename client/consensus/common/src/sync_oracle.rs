@@ -0,0 +1,86 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A [`SyncOracle`] that combines the sync state of the parachain and of the relay chain.
+
+use sp_consensus::SyncOracle;
+
+/// A [`SyncOracle`] that is syncing or offline if the parachain or the relay chain is.
+///
+/// A collator should only author blocks when both of its nodes are synced. While the relay chain
+/// node is syncing, the relay parent of a new block would be stale and the block would be rejected
+/// by the validators.
+#[derive(Clone)]
+pub struct CombinedSyncOracle<P, R> {
+	parachain: P,
+	relay_chain: R,
+}
+
+impl<P, R> CombinedSyncOracle<P, R> {
+	/// Create a new instance that combines the `parachain` and the `relay_chain` sync oracles.
+	pub fn new(parachain: P, relay_chain: R) -> Self {
+		Self {
+			parachain,
+			relay_chain,
+		}
+	}
+}
+
+impl<P: SyncOracle, R: SyncOracle> SyncOracle for CombinedSyncOracle<P, R> {
+	fn is_major_syncing(&mut self) -> bool {
+		self.parachain.is_major_syncing() || self.relay_chain.is_major_syncing()
+	}
+
+	fn is_offline(&mut self) -> bool {
+		self.parachain.is_offline() || self.relay_chain.is_offline()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[derive(Clone, Copy)]
+	struct DummyOracle {
+		syncing: bool,
+		offline: bool,
+	}
+
+	impl SyncOracle for DummyOracle {
+		fn is_major_syncing(&mut self) -> bool {
+			self.syncing
+		}
+
+		fn is_offline(&mut self) -> bool {
+			self.offline
+		}
+	}
+
+	#[test]
+	fn either_oracle_blocks_authoring() {
+		let synced = DummyOracle { syncing: false, offline: false };
+		let syncing = DummyOracle { syncing: true, offline: false };
+		let offline = DummyOracle { syncing: false, offline: true };
+
+		assert!(!CombinedSyncOracle::new(synced, synced).is_major_syncing());
+		assert!(!CombinedSyncOracle::new(synced, synced).is_offline());
+
+		assert!(CombinedSyncOracle::new(syncing, synced).is_major_syncing());
+		assert!(CombinedSyncOracle::new(synced, syncing).is_major_syncing());
+		assert!(CombinedSyncOracle::new(offline, synced).is_offline());
+		assert!(CombinedSyncOracle::new(synced, offline).is_offline());
+	}
+}
@@ -32,9 +32,25 @@
 //! block producer to be added as backed candidate on chain.
 //!
 //! 5. After the parachain candidate got backed and included, all collators start at 1.
+//!
+//! As every collator builds a candidate on every relay block, most of the work is wasted. With an
+//! [`AuthoringBackoff`], each collator waits a delay before building that is derived from the relay
+//! chain epoch randomness, the relay parent and its own identity. The delays differ per relay
+//! block, so typically one collator builds first while the others act as fallbacks if its
+//! candidate doesn't make it.
+//!
+//! Alternatively, the [`RelayRandomnessConsensus`] lets only one collator of a known collator set
+//! build on each relay parent. The collator is selected by the relay chain epoch randomness, so
+//! the selection doesn't depend on the clocks of the collators.
+//!
+//! The proposers can be customized by passing a
+//! [`WrappedProposerFactory`](cumulus_client_consensus_common::WrappedProposerFactory) as the
+//! proposer factory.
 
+use codec::Encode;
 use cumulus_client_consensus_common::{ParachainCandidate, ParachainConsensus};
 use cumulus_primitives_core::{
+	extract_reserved_pov_size,
 	relay_chain::v1::{Block as PBlock, Hash as PHash, ParachainHost},
 	ParaId, PersistedValidationData,
 };
@@ -51,10 +67,41 @@ use sp_runtime::traits::{Block as BlockT, HashFor, Header as HeaderT};
 use std::{marker::PhantomData, sync::Arc, time::Duration};
 
 mod import_queue;
-pub use import_queue::import_queue;
+mod relay_randomness;
+pub use import_queue::{import_queue, Verifier};
+use relay_randomness::epoch_randomness;
+pub use relay_randomness::{eligible_collator, CollatorSetAt, RelayRandomnessConsensus};
 
 const LOG_TARGET: &str = "cumulus-consensus-relay-chain";
 
+/// The backoff of a collator before building a candidate.
+///
+/// The delay is one of `positions` steps, chosen by hashing the BABE randomness of the current
+/// relay chain epoch and the relay parent together with the `collator_id`. The randomness isn't
+/// known before the epoch and can't be ground by the collators, unlike the relay parent hash
+/// alone, so no collator can position itself in advance. Every node can still verify the position
+/// of any other.
+#[derive(Clone, Debug)]
+pub struct AuthoringBackoff {
+	/// The identity of this collator, e.g. the encoded public key of its collator key.
+	pub collator_id: Vec<u8>,
+	/// The number of different positions, usually the expected number of collators.
+	pub positions: u32,
+	/// The delay between two positions.
+	pub step: Duration,
+}
+
+impl AuthoringBackoff {
+	/// The delay before building a candidate on top of the given relay parent, with the given
+	/// `randomness` of the relay chain epoch of the relay parent.
+	pub fn delay(&self, randomness: &[u8; 32], relay_parent: &PHash) -> Duration {
+		let hash = sp_core::blake2_256(&(randomness, relay_parent, &self.collator_id).encode());
+		let position = u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]);
+
+		self.step * (position % self.positions.max(1))
+	}
+}
+
 /// The implementation of the relay-chain provided consensus for parachains.
 pub struct RelayChainConsensus<B, PF, BI, RClient, RBackend, CIDP> {
 	para_id: ParaId,
@@ -64,6 +111,7 @@ pub struct RelayChainConsensus<B, PF, BI, RClient, RBackend, CIDP> {
 	block_import: Arc<futures::lock::Mutex<BI>>,
 	relay_chain_client: Arc<RClient>,
 	relay_chain_backend: Arc<RBackend>,
+	authoring_backoff: Option<AuthoringBackoff>,
 }
 
 impl<B, PF, BI, RClient, RBackend, CIDP> Clone
@@ -78,6 +126,7 @@ impl<B, PF, BI, RClient, RBackend, CIDP> Clone
 			block_import: self.block_import.clone(),
 			relay_chain_backend: self.relay_chain_backend.clone(),
 			relay_chain_client: self.relay_chain_client.clone(),
+			authoring_backoff: self.authoring_backoff.clone(),
 		}
 	}
 }
@@ -106,10 +155,17 @@ where
 			block_import: Arc::new(futures::lock::Mutex::new(block_import)),
 			relay_chain_backend: polkadot_backend,
 			relay_chain_client: polkadot_client,
+			authoring_backoff: None,
 			_phantom: PhantomData,
 		}
 	}
 
+	/// Wait for the given [`AuthoringBackoff`] before building a candidate.
+	pub fn with_authoring_backoff(mut self, authoring_backoff: AuthoringBackoff) -> Self {
+		self.authoring_backoff = Some(authoring_backoff);
+		self
+	}
+
 	/// Get the inherent data with validation function parameters injected
 	async fn inherent_data(
 		&self,
@@ -151,6 +207,8 @@ where
 	RClient: ProvideRuntimeApi<PBlock> + Send + Sync,
 	RClient::Api: ParachainHost<PBlock>,
 	RBackend: Backend<PBlock>,
+	// Rust bug: https://github.com/rust-lang/rust/issues/24159
+	sc_client_api::StateBackendFor<RBackend, PBlock>: sc_client_api::StateBackend<HashFor<PBlock>>,
 	BI: BlockImport<B> + Send + Sync,
 	PF: Environment<B> + Send + Sync,
 	PF::Proposer: Proposer<
@@ -167,6 +225,22 @@ where
 		relay_parent: PHash,
 		validation_data: &PersistedValidationData,
 	) -> Option<ParachainCandidate<B>> {
+		if let Some(backoff) = &self.authoring_backoff {
+			// Without the randomness, the candidate is built right away.
+			let delay = epoch_randomness(&*self.relay_chain_backend, relay_parent)
+				.map_or(Duration::from_secs(0), |r| backoff.delay(&r, &relay_parent));
+			if delay > Duration::from_secs(0) {
+				tracing::debug!(
+					target: LOG_TARGET,
+					?relay_parent,
+					?delay,
+					"Backing off before building a candidate.",
+				);
+
+				futures_timer::Delay::new(delay).await;
+			}
+		}
+
 		let proposer_future = self.proposer_factory.lock().init(&parent);
 
 		let proposer = proposer_future
@@ -190,11 +264,15 @@ where
 				Default::default(),
 				//TODO: Fix this.
 				Duration::from_millis(500),
-				// Set the block limit to 50% of the maximum PoV size.
+				// Set the block limit to 50% of the maximum PoV size, minus the space the runtime
+				// asked us to keep free.
 				//
 				// TODO: If we got benchmarking that includes that encapsulates the proof size,
 				// we should be able to use the maximum pov size.
-				Some((validation_data.max_pov_size / 2) as usize),
+				Some(
+					(validation_data.max_pov_size / 2)
+						.saturating_sub(extract_reserved_pov_size(parent.digest())) as usize,
+				),
 			)
 			.await
 			.map_err(|e| tracing::error!(target: LOG_TARGET, error = ?e, "Proposing failed."))
@@ -225,7 +303,11 @@ where
 			return None;
 		}
 
-		Some(ParachainCandidate { block, proof })
+		Some(ParachainCandidate {
+			block,
+			proof,
+			aux_data: None,
+		})
 	}
 }
 
@@ -237,6 +319,8 @@ pub struct BuildRelayChainConsensusParams<PF, BI, RBackend, CIDP> {
 	pub block_import: BI,
 	pub relay_chain_client: polkadot_service::Client,
 	pub relay_chain_backend: Arc<RBackend>,
+	/// The backoff before building a candidate, `None` to build immediately.
+	pub authoring_backoff: Option<AuthoringBackoff>,
 }
 
 /// Build the [`RelayChainConsensus`].
@@ -250,6 +334,7 @@ pub fn build_relay_chain_consensus<Block, PF, BI, RBackend, CIDP>(
 		block_import,
 		relay_chain_client,
 		relay_chain_backend,
+		authoring_backoff,
 	}: BuildRelayChainConsensusParams<PF, BI, RBackend, CIDP>,
 ) -> Box<dyn ParachainConsensus<Block>>
 where
@@ -274,6 +359,7 @@ where
 		create_inherent_data_providers,
 		relay_chain_client,
 		relay_chain_backend,
+		authoring_backoff,
 	)
 	.build()
 }
@@ -292,6 +378,7 @@ struct RelayChainConsensusBuilder<Block, PF, BI, RBackend, CIDP> {
 	block_import: BI,
 	relay_chain_backend: Arc<RBackend>,
 	relay_chain_client: polkadot_service::Client,
+	authoring_backoff: Option<AuthoringBackoff>,
 }
 
 impl<Block, PF, BI, RBackend, CIDP> RelayChainConsensusBuilder<Block, PF, BI, RBackend, CIDP>
@@ -318,6 +405,7 @@ where
 		create_inherent_data_providers: CIDP,
 		relay_chain_client: polkadot_service::Client,
 		relay_chain_backend: Arc<RBackend>,
+		authoring_backoff: Option<AuthoringBackoff>,
 	) -> Self {
 		Self {
 			para_id,
@@ -327,6 +415,7 @@ where
 			create_inherent_data_providers,
 			relay_chain_backend,
 			relay_chain_client,
+			authoring_backoff,
 		}
 	}
 
@@ -363,13 +452,93 @@ where
 		Api: polkadot_service::RuntimeApiCollection<StateBackend = PBackend::State>,
 		PClient: polkadot_service::AbstractClient<PBlock, PBackend, Api = Api> + 'static,
 	{
-		Box::new(RelayChainConsensus::new(
+		let mut consensus = RelayChainConsensus::new(
 			self.para_id,
 			self.proposer_factory,
 			self.create_inherent_data_providers,
 			self.block_import,
 			client.clone(),
 			self.relay_chain_backend,
-		))
+		);
+		consensus.authoring_backoff = self.authoring_backoff;
+
+		Box::new(consensus)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn backoff(collator: u8) -> AuthoringBackoff {
+		AuthoringBackoff {
+			collator_id: vec![collator],
+			positions: 4,
+			step: Duration::from_millis(100),
+		}
+	}
+
+	fn relay_parents() -> impl Iterator<Item = PHash> {
+		(0..1000u64).map(PHash::from_low_u64_be)
+	}
+
+	#[test]
+	fn delays_are_evenly_distributed() {
+		let backoff = backoff(1);
+		let randomness = [7; 32];
+
+		let mut positions = [0; 4];
+		for relay_parent in relay_parents() {
+			let delay = backoff.delay(&randomness, &relay_parent);
+			assert!(delay < Duration::from_millis(400));
+			positions[(delay.as_millis() / 100) as usize] += 1;
+		}
+
+		// Each of the positions is expected 250 times.
+		for count in &positions {
+			assert!((180..320).contains(count), "{:?}", positions);
+		}
+	}
+
+	#[test]
+	fn colliding_collators_are_rare() {
+		let randomness = [7; 32];
+		let (first, second) = (backoff(1), backoff(2));
+
+		let collisions = relay_parents()
+			.filter(|p| first.delay(&randomness, p) == second.delay(&randomness, p))
+			.count();
+
+		// Two collators share a position for a quarter of the relay parents.
+		assert!((180..320).contains(&collisions), "{}", collisions);
+	}
+
+	#[test]
+	fn delays_depend_on_the_randomness() {
+		let backoff = backoff(1);
+
+		let changed = relay_parents()
+			.filter(|p| backoff.delay(&[1; 32], p) != backoff.delay(&[2; 32], p))
+			.count();
+
+		// A new randomness keeps the position for a quarter of the relay parents.
+		assert!((680..820).contains(&changed), "{}", changed);
+	}
+
+	#[test]
+	fn single_position_means_no_delay() {
+		let no_positions = AuthoringBackoff {
+			positions: 0,
+			..backoff(1)
+		};
+		let one_position = AuthoringBackoff {
+			positions: 1,
+			..backoff(1)
+		};
+
+		for relay_parent in relay_parents().take(10) {
+			assert_eq!(no_positions.delay(&[0; 32], &relay_parent), Duration::from_secs(0));
+			assert_eq!(one_position.delay(&[0; 32], &relay_parent), Duration::from_secs(0));
+		}
 	}
 }
@@ -0,0 +1,227 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Selection of the collator of each relay parent by the relay chain randomness.
+//!
+//! In contrast to AuRa, the collator is not selected by a slot that is derived from the local
+//! clock. The collators of a parachain select the same collator for a relay parent, no matter how
+//! far their clocks are apart.
+//!
+//! The selection is advisory. The blocks don't carry the identity of their collator, so neither
+//! the import queue nor the relay chain can check that a block was built by the eligible collator
+//! of its relay parent. It only keeps honest collators from building competing blocks.
+
+use codec::{Decode, Encode};
+use cumulus_client_consensus_common::{ParachainCandidate, ParachainConsensus};
+use cumulus_primitives_core::{
+	relay_chain::v1::{Block as PBlock, Hash as PHash},
+	relay_well_known_keys, PersistedValidationData,
+};
+use sc_client_api::{Backend, StateBackend};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, HashFor, Header as HeaderT},
+};
+use std::sync::Arc;
+
+use crate::LOG_TARGET;
+
+/// Returns the encoded ids of the collator set at the given parachain block.
+pub type CollatorSetAt<B> =
+	Arc<dyn Fn(<B as BlockT>::Hash) -> Option<Vec<Vec<u8>>> + Send + Sync>;
+
+/// The index of the collator in a set of `collators` that is selected for the relay parent.
+///
+/// The selection is keyed to the BABE randomness of the current relay chain epoch and the relay
+/// parent, so it changes with every relay parent and can't be predicted before the epoch.
+pub fn eligible_collator(randomness: &[u8; 32], relay_parent: &PHash, collators: usize) -> usize {
+	let hash = sp_core::blake2_256(&(randomness, relay_parent).encode());
+	let index = u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]);
+
+	index as usize % collators.max(1)
+}
+
+/// A [`ParachainConsensus`] that only builds a candidate when this collator is the
+/// [`eligible_collator`] of the relay parent.
+///
+/// The candidate is built by the `inner` consensus, e.g. a
+/// [`RelayChainConsensus`](crate::RelayChainConsensus). The blocks of other collators are imported
+/// as usual, see the [module docs](self).
+pub struct RelayRandomnessConsensus<B: BlockT, RBackend> {
+	inner: Box<dyn ParachainConsensus<B>>,
+	relay_chain_backend: Arc<RBackend>,
+	collator_set_at: CollatorSetAt<B>,
+	collator_id: Vec<u8>,
+}
+
+impl<B: BlockT, RBackend> Clone for RelayRandomnessConsensus<B, RBackend> {
+	fn clone(&self) -> Self {
+		Self {
+			inner: self.inner.clone(),
+			relay_chain_backend: self.relay_chain_backend.clone(),
+			collator_set_at: self.collator_set_at.clone(),
+			collator_id: self.collator_id.clone(),
+		}
+	}
+}
+
+impl<B, RBackend> RelayRandomnessConsensus<B, RBackend>
+where
+	B: BlockT,
+	RBackend: Backend<PBlock>,
+	// Rust bug: https://github.com/rust-lang/rust/issues/24159
+	sc_client_api::StateBackendFor<RBackend, PBlock>: StateBackend<HashFor<PBlock>>,
+{
+	/// Create a new instance.
+	///
+	/// `collator_set_at` returns the collator set at a parachain block and `collator_id` is the
+	/// id of this collator in the set.
+	pub fn new(
+		inner: Box<dyn ParachainConsensus<B>>,
+		relay_chain_backend: Arc<RBackend>,
+		collator_set_at: CollatorSetAt<B>,
+		collator_id: Vec<u8>,
+	) -> Self {
+		Self {
+			inner,
+			relay_chain_backend,
+			collator_set_at,
+			collator_id,
+		}
+	}
+}
+
+/// Read the randomness of the current relay chain epoch at the given relay parent.
+pub(crate) fn epoch_randomness<RBackend>(
+	relay_chain_backend: &RBackend,
+	relay_parent: PHash,
+) -> Option<[u8; 32]>
+where
+	RBackend: Backend<PBlock>,
+	// Rust bug: https://github.com/rust-lang/rust/issues/24159
+	sc_client_api::StateBackendFor<RBackend, PBlock>: StateBackend<HashFor<PBlock>>,
+{
+	let state = relay_chain_backend
+		.state_at(BlockId::Hash(relay_parent))
+		.map_err(|e| {
+			tracing::error!(
+				target: LOG_TARGET,
+				?relay_parent,
+				error = ?e,
+				"Cannot obtain the state of the relay chain.",
+			)
+		})
+		.ok()?;
+
+	let randomness = state
+		.storage(relay_well_known_keys::CURRENT_EPOCH_RANDOMNESS)
+		.map_err(|e| {
+			tracing::error!(
+				target: LOG_TARGET,
+				error = ?e,
+				"Cannot obtain the relay chain epoch randomness.",
+			)
+		})
+		.ok()??;
+
+	Decode::decode(&mut &randomness[..]).ok()
+}
+
+#[async_trait::async_trait]
+impl<B, RBackend> ParachainConsensus<B> for RelayRandomnessConsensus<B, RBackend>
+where
+	B: BlockT,
+	RBackend: Backend<PBlock>,
+	// Rust bug: https://github.com/rust-lang/rust/issues/24159
+	sc_client_api::StateBackendFor<RBackend, PBlock>: StateBackend<HashFor<PBlock>>,
+{
+	async fn produce_candidate(
+		&mut self,
+		parent: &B::Header,
+		relay_parent: PHash,
+		validation_data: &PersistedValidationData,
+	) -> Option<ParachainCandidate<B>> {
+		let randomness = epoch_randomness(&*self.relay_chain_backend, relay_parent)?;
+		let collators = (self.collator_set_at)(parent.hash())?;
+		let index = eligible_collator(&randomness, &relay_parent, collators.len());
+		let eligible = collators.get(index)?;
+
+		if *eligible != self.collator_id {
+			tracing::debug!(
+				target: LOG_TARGET,
+				?relay_parent,
+				"Not the eligible collator of the relay parent.",
+			);
+
+			return None;
+		}
+
+		self.inner
+			.produce_candidate(parent, relay_parent, validation_data)
+			.await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn relay_parents() -> impl Iterator<Item = PHash> {
+		(0..1000u64).map(PHash::from_low_u64_be)
+	}
+
+	#[test]
+	fn selection_is_deterministic() {
+		for relay_parent in relay_parents() {
+			assert_eq!(
+				eligible_collator(&[7; 32], &relay_parent, 5),
+				eligible_collator(&[7; 32], &relay_parent, 5),
+			);
+		}
+	}
+
+	#[test]
+	fn collators_are_evenly_selected() {
+		let mut selected = [0; 4];
+		for relay_parent in relay_parents() {
+			selected[eligible_collator(&[7; 32], &relay_parent, 4)] += 1;
+		}
+
+		// Each of the collators is expected to be selected 250 times.
+		for count in &selected {
+			assert!((180..320).contains(count), "{:?}", selected);
+		}
+	}
+
+	#[test]
+	fn selection_depends_on_the_randomness() {
+		let changed = relay_parents()
+			.filter(|p| eligible_collator(&[1; 32], p, 4) != eligible_collator(&[2; 32], p, 4))
+			.count();
+
+		// A new randomness keeps the collator for a quarter of the relay parents.
+		assert!((680..820).contains(&changed), "{}", changed);
+	}
+
+	#[test]
+	fn single_collator_is_always_selected() {
+		for relay_parent in relay_parents() {
+			assert_eq!(eligible_collator(&[7; 32], &relay_parent, 1), 0);
+			// An empty collator set doesn't panic, the index just selects no collator.
+			assert_eq!(eligible_collator(&[7; 32], &relay_parent, 0), 0);
+		}
+	}
+}
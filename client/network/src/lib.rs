@@ -33,10 +33,11 @@ use sp_runtime::{
 	traits::{Block as BlockT, HashFor, Header as HeaderT},
 };
 
+use cumulus_primitives_core::{build_head_data, decode_head_data, HeadDataExtension};
 use polkadot_node_primitives::{SignedFullStatement, Statement};
 use polkadot_parachain::primitives::HeadData;
 use polkadot_primitives::v1::{
-	Block as PBlock, Hash as PHash, CandidateReceipt, CompactStatement, Id as ParaId,
+	Block as PBlock, CandidateReceipt, CompactStatement, Id as ParaId,
 	OccupiedCoreAssumption, ParachainHost, UncheckedSigned, SigningContext,
 };
 use polkadot_service::ClientHandle;
@@ -74,13 +75,106 @@ impl fmt::Display for BlockAnnounceError {
 ///
 /// This will be used to prove that a header belongs to a block that is probably being backed by
 /// the relay chain.
-#[derive(Encode, Decode, Debug)]
+///
+/// The [`HeadDataExtension`] of the candidate is encoded after the other fields, if there is one.
+/// Nodes that don't know about it ignore the trailing bytes. The auxiliary data of the consensus is
+/// encoded after that, prefixed by [`AUX_DATA_TAG`]. Nodes that don't know about the auxiliary data
+/// only accept an announcement with auxiliary data if it also has a [`HeadDataExtension`].
+#[derive(Debug)]
 pub struct BlockAnnounceData {
 	receipt: CandidateReceipt,
 	statement: UncheckedSigned<CompactStatement>,
+	/// The extension of the head data of the candidate.
+	///
+	/// The validator only knows the announced header, but it needs the complete head data to
+	/// compare it with the para head of the receipt.
+	head_data_extension: Option<HeadDataExtension>,
+	/// The auxiliary data that the consensus attached to the candidate.
+	///
+	/// This is checked by the [`AuxDataChecker`] of the validator.
+	aux_data: Option<Vec<u8>>,
+}
+
+/// The tag that precedes the auxiliary data in an encoded [`BlockAnnounceData`].
+///
+/// This is distinct from the index of [`HeadDataExtension::V1`], so the trailing items can be
+/// told apart.
+const AUX_DATA_TAG: u8 = 1;
+
+impl Encode for BlockAnnounceData {
+	fn encode_to<T: codec::Output + ?Sized>(&self, dest: &mut T) {
+		self.receipt.encode_to(dest);
+		self.statement.encode_to(dest);
+		if let Some(ref extension) = self.head_data_extension {
+			extension.encode_to(dest);
+		}
+		if let Some(ref aux_data) = self.aux_data {
+			AUX_DATA_TAG.encode_to(dest);
+			aux_data.encode_to(dest);
+		}
+	}
+}
+
+impl Decode for BlockAnnounceData {
+	fn decode<I: codec::Input>(input: &mut I) -> Result<Self, codec::Error> {
+		let receipt = Decode::decode(input)?;
+		let statement = Decode::decode(input)?;
+
+		let mut head_data_extension = None;
+		let mut aux_data = None;
+		while input.remaining_len()? != Some(0) {
+			match input.read_byte()? {
+				// The index of `HeadDataExtension::V1`, the extension comes first.
+				0 if head_data_extension.is_none() && aux_data.is_none() => {
+					head_data_extension = Some(HeadDataExtension::V1(Decode::decode(input)?));
+				},
+				AUX_DATA_TAG if aux_data.is_none() => aux_data = Some(Decode::decode(input)?),
+				_ => return Err("Invalid trailing data of the `BlockAnnounceData`".into()),
+			}
+		}
+
+		Ok(Self {
+			receipt,
+			statement,
+			head_data_extension,
+			aux_data,
+		})
+	}
+}
+
+/// Something that checks the auxiliary data that the consensus attached to a block announcement.
+///
+/// The auxiliary data is provided by the parachain consensus of the collator with the candidate.
+pub trait AuxDataChecker<Block: BlockT>: Send + Sync {
+	/// Check the auxiliary data attached to the announcement of `header`.
+	///
+	/// `aux_data` is `None` if the announcement doesn't carry auxiliary data. Returns `false` if
+	/// the announcement should be rejected.
+	fn check_aux_data(&self, header: &Block::Header, aux_data: Option<&[u8]>) -> bool;
+}
+
+/// Accepts any auxiliary data.
+impl<Block: BlockT> AuxDataChecker<Block> for () {
+	fn check_aux_data(&self, _: &Block::Header, _: Option<&[u8]>) -> bool {
+		true
+	}
 }
 
 impl BlockAnnounceData {
+	/// Create the [`BlockAnnounceData`] of a block with the given `Header` type.
+	///
+	/// In contrast to the `TryFrom` implementation, this attaches the [`HeadDataExtension`] of the
+	/// candidate.
+	fn from_statement<Header: Decode>(stmt: &SignedFullStatement) -> Result<Self, ()> {
+		let mut data = Self::try_from(stmt)?;
+		if let Statement::Seconded(receipt) = stmt.payload() {
+			data.head_data_extension =
+				decode_head_data::<Header>(&receipt.commitments.head_data.0).map_err(|_| ())?.1;
+		}
+
+		Ok(data)
+	}
+
 	/// Validate that the receipt, statement and announced header match.
 	///
 	/// This will not check the signature, for this you should use [`BlockAnnounceData::check_signature`].
@@ -103,7 +197,8 @@ impl BlockAnnounceData {
 			return Err(Validation::Failure { disconnect: true });
 		}
 
-		if HeadData(encoded_header).hash() != self.receipt.descriptor.para_head {
+		let head_data = build_head_data(encoded_header, self.head_data_extension.as_ref());
+		if head_data.hash() != self.receipt.descriptor.para_head {
 			tracing::debug!(
 				target: LOG_TARGET,
 				"Receipt para head hash doesn't match the hash of the header in the block announcement",
@@ -191,6 +286,8 @@ impl TryFrom<&'_ SignedFullStatement> for BlockAnnounceData {
 		Ok(BlockAnnounceData {
 			receipt,
 			statement: stmt.convert_payload().into(),
+			head_data_extension: None,
+			aux_data: None,
 		})
 	}
 }
@@ -222,16 +319,20 @@ impl TryFrom<&'_ SignedFullStatement> for BlockAnnounceData {
 /// chain. If it is at the tip, it is required to provide a justification or otherwise we reject
 /// it. However, if the announcement is for a block below the tip the announcement is accepted
 /// as it probably comes from a node that is currently syncing the chain.
-pub struct BlockAnnounceValidator<Block, R, B, BCE> {
+///
+/// The auxiliary data of an announcement with a justification is checked by the
+/// [`AuxDataChecker`], which accepts anything by default.
+pub struct BlockAnnounceValidator<Block: BlockT, R, B, BCE> {
 	phantom: PhantomData<Block>,
 	relay_chain_client: Arc<R>,
 	relay_chain_backend: Arc<B>,
 	para_id: ParaId,
 	relay_chain_sync_oracle: Box<dyn SyncOracle + Send>,
 	wait_on_relay_chain_block: WaitOnRelayChainBlock<B, BCE>,
+	aux_data_checker: Box<dyn AuxDataChecker<Block>>,
 }
 
-impl<Block, R, B, BCE> BlockAnnounceValidator<Block, R, B, BCE> {
+impl<Block: BlockT, R, B, BCE> BlockAnnounceValidator<Block, R, B, BCE> {
 	/// Create a new [`BlockAnnounceValidator`].
 	pub fn new(
 		relay_chain_client: Arc<R>,
@@ -250,8 +351,15 @@ impl<Block, R, B, BCE> BlockAnnounceValidator<Block, R, B, BCE> {
 				relay_chain_backend,
 				relay_chain_blockchain_events,
 			),
+			aux_data_checker: Box::new(()),
 		}
 	}
+
+	/// Check the auxiliary data of the announcements with the given [`AuxDataChecker`].
+	pub fn with_aux_data_checker(mut self, checker: Box<dyn AuxDataChecker<Block>>) -> Self {
+		self.aux_data_checker = checker;
+		self
+	}
 }
 
 impl<Block: BlockT, R, B, BCE> BlockAnnounceValidator<Block, R, B, BCE>
@@ -288,18 +396,29 @@ where
 		Ok(para_head)
 	}
 
-	/// Get the backed block hash of the given parachain in the relay chain.
-	fn backed_block_hash(
+	/// Check if the given `header` is the header of the backed block of the given parachain in
+	/// the relay chain.
+	fn is_backed_block(
 		relay_chain_client: &R,
 		block_id: &BlockId<PBlock>,
 		para_id: ParaId,
-	) -> Result<Option<PHash>, BoxedError> {
+		header: &Block::Header,
+	) -> Result<bool, BoxedError> {
 		let candidate_receipt = relay_chain_client
 			.runtime_api()
 			.candidate_pending_availability(block_id, para_id)
 			.map_err(|e| Box::new(BlockAnnounceError(format!("{:?}", e))) as Box<_>)?;
 
-		Ok(candidate_receipt.map(|cr| cr.descriptor.para_head))
+		Ok(candidate_receipt.map_or(false, |cr| {
+			let para_head = cr.descriptor.para_head;
+			let head_data = &cr.commitments.head_data;
+
+			// Without a `HeadDataExtension` the head data is just the encoded header. Otherwise,
+			// the header needs to be decoded from the head data of the candidate.
+			HeadData(header.encode()).hash() == para_head ||
+				(head_data.hash() == para_head &&
+					Block::Header::decode(&mut &head_data.0[..]).ok().as_ref() == Some(header))
+		}))
 	}
 
 	/// Handle a block announcement with empty data (no statement) attached to it.
@@ -320,8 +439,14 @@ where
 			let best_head =
 				Self::included_block(&*relay_chain_client, &runtime_api_block_id, para_id)?;
 			let known_best_number = best_head.number();
-			let backed_block = ||
-				Self::backed_block_hash(&*relay_chain_client, &runtime_api_block_id, para_id);
+			let is_backed_block = || {
+				Self::is_backed_block(
+					&*relay_chain_client,
+					&runtime_api_block_id,
+					para_id,
+					&header,
+				)
+			};
 
 			if best_head == header {
 				tracing::debug!(
@@ -330,7 +455,7 @@ where
 				);
 
 				Ok(Validation::Success { is_new_best: true })
-			} else if Some(HeadData(header.encode()).hash()) == backed_block()? {
+			} else if is_backed_block()? {
 				tracing::debug!(
 					target: LOG_TARGET,
 					"Announced block matches latest backed block.",
@@ -386,6 +511,18 @@ where
 			}
 		};
 
+		if !self
+			.aux_data_checker
+			.check_aux_data(header, block_announce_data.aux_data.as_deref())
+		{
+			tracing::debug!(
+				target: LOG_TARGET,
+				"Auxiliary data of the block announcement was rejected.",
+			);
+
+			return ready(Ok(Validation::Failure { disconnect: true })).boxed();
+		}
+
 		let relay_chain_client = self.relay_chain_client.clone();
 		let header_encoded = header.encode();
 		let wait_on_relay_chain_block = self.wait_on_relay_chain_block.clone();
@@ -418,6 +555,7 @@ pub fn build_block_announce_validator<Block: BlockT, B>(
 	para_id: ParaId,
 	relay_chain_sync_oracle: Box<dyn SyncOracle + Send>,
 	relay_chain_backend: Arc<B>,
+	aux_data_checker: Box<dyn AuxDataChecker<Block>>,
 ) -> Box<dyn BlockAnnounceValidatorT<Block> + Send>
 where
 	B: Backend<PBlock> + Send + 'static,
@@ -429,6 +567,7 @@ where
 		para_id,
 		relay_chain_sync_oracle,
 		relay_chain_backend,
+		aux_data_checker,
 	)
 	.build()
 }
@@ -439,12 +578,13 @@ where
 /// a concrete relay chain client instance, the builder takes a [`polkadot_service::Client`]
 /// that wraps this concrete instanace. By using [`polkadot_service::ExecuteWithClient`]
 /// the builder gets access to this concrete instance.
-struct BlockAnnounceValidatorBuilder<Block, B> {
+struct BlockAnnounceValidatorBuilder<Block: BlockT, B> {
 	phantom: PhantomData<Block>,
 	relay_chain_client: polkadot_service::Client,
 	para_id: ParaId,
 	relay_chain_sync_oracle: Box<dyn SyncOracle + Send>,
 	relay_chain_backend: Arc<B>,
+	aux_data_checker: Box<dyn AuxDataChecker<Block>>,
 }
 
 impl<Block: BlockT, B> BlockAnnounceValidatorBuilder<Block, B>
@@ -459,12 +599,14 @@ where
 		para_id: ParaId,
 		relay_chain_sync_oracle: Box<dyn SyncOracle + Send>,
 		relay_chain_backend: Arc<B>,
+		aux_data_checker: Box<dyn AuxDataChecker<Block>>,
 	) -> Self {
 		Self {
 			relay_chain_client,
 			para_id,
 			relay_chain_sync_oracle,
 			relay_chain_backend,
+			aux_data_checker,
 			phantom: PhantomData,
 		}
 	}
@@ -499,7 +641,8 @@ where
 			self.relay_chain_sync_oracle,
 			self.relay_chain_backend,
 			client,
-		))
+		)
+		.with_aux_data_checker(self.aux_data_checker))
 	}
 }
 
@@ -526,11 +669,13 @@ impl<Block: BlockT> WaitToAnnounce<Block> {
 	}
 
 	/// Wait for a candidate message for the block, then announce the block. The candidate
-	/// message will be added as justification to the block announcement.
+	/// message will be added as justification to the block announcement, together with the
+	/// auxiliary data of the consensus, if any.
 	pub fn wait_to_announce(
 		&mut self,
 		block_hash: <Block as BlockT>::Hash,
 		signed_stmt_recv: oneshot::Receiver<SignedFullStatement>,
+		aux_data: Option<Vec<u8>>,
 	) {
 		let announce_block = self.announce_block.clone();
 
@@ -542,7 +687,8 @@ impl<Block: BlockT> WaitToAnnounce<Block> {
 					"waiting for announce block in a background task...",
 				);
 
-				wait_to_announce::<Block>(block_hash, announce_block, signed_stmt_recv).await;
+				wait_to_announce::<Block>(block_hash, announce_block, signed_stmt_recv, aux_data)
+					.await;
 
 				tracing::debug!(
 					target: "cumulus-network",
@@ -558,6 +704,7 @@ async fn wait_to_announce<Block: BlockT>(
 	block_hash: <Block as BlockT>::Hash,
 	announce_block: Arc<dyn Fn(Block::Hash, Option<Vec<u8>>) + Send + Sync>,
 	signed_stmt_recv: oneshot::Receiver<SignedFullStatement>,
+	aux_data: Option<Vec<u8>>,
 ) {
 	let statement = match signed_stmt_recv.await {
 		Ok(s) => s,
@@ -571,7 +718,8 @@ async fn wait_to_announce<Block: BlockT>(
 		}
 	};
 
-	if let Ok(data) = BlockAnnounceData::try_from(&statement) {
+	if let Ok(mut data) = BlockAnnounceData::from_statement::<Block::Header>(&statement) {
+		data.aux_data = aux_data;
 		announce_block(block_hash, Some(data.encode()));
 	} else {
 		tracing::debug!(
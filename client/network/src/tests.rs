@@ -109,6 +109,20 @@ async fn make_gossip_message_and_header(
 	relay_parent: H256,
 	validator_index: u32,
 ) -> (SignedFullStatement, Header) {
+	let header = default_header();
+	let signed =
+		make_gossip_message(api, relay_parent, validator_index, HeadData(header.encode())).await;
+
+	(signed, header)
+}
+
+/// Make a gossip message for a candidate with the given `head_data`.
+async fn make_gossip_message(
+	api: Arc<TestApi>,
+	relay_parent: H256,
+	validator_index: u32,
+	head_data: HeadData,
+) -> SignedFullStatement {
 	let keystore: SyncCryptoStorePtr = Arc::new(KeyStore::new());
 	let alice_public = SyncCryptoStore::sr25519_generate_new(
 		&*keystore,
@@ -125,15 +139,14 @@ async fn make_gossip_message_and_header(
 		session_index,
 	};
 
-	let header = default_header();
 	let candidate_receipt = CommittedCandidateReceipt {
-		commitments: CandidateCommitments {
-			head_data: header.encode().into(),
-			..Default::default()
-		},
 		descriptor: CandidateDescriptor {
 			relay_parent,
-			para_head: polkadot_parachain::primitives::HeadData(header.encode()).hash(),
+			para_head: head_data.hash(),
+			..Default::default()
+		},
+		commitments: CandidateCommitments {
+			head_data,
 			..Default::default()
 		},
 	};
@@ -148,9 +161,7 @@ async fn make_gossip_message_and_header(
 	.await
 	.ok()
 	.flatten()
-	.expect("Signing statement");
-
-	(signed, header)
+	.expect("Signing statement")
 }
 
 #[test]
@@ -283,6 +294,8 @@ fn check_statement_seconded() {
 	let data = BlockAnnounceData {
 		receipt: Default::default(),
 		statement: signed_statement.convert_payload().into(),
+		head_data_extension: None,
+		aux_data: None,
 	}
 	.encode();
 
@@ -305,6 +318,87 @@ fn check_header_match_candidate_receipt_header() {
 	assert_eq!(Validation::Failure { disconnect: true }, res.unwrap());
 }
 
+#[test]
+fn check_header_match_candidate_receipt_head_data_with_extension() {
+	let (mut validator, api) = make_validator_and_api();
+	let relay_parent = api.relay_client.hash(0).ok().flatten().expect("Genesis hash exists");
+
+	let header = default_header();
+	let extension = HeadDataExtension::V1(vec![1; 32]);
+	let head_data = build_head_data(header.encode(), Some(&extension));
+	let signed_statement = block_on(make_gossip_message(api, relay_parent, 0, head_data));
+
+	let data = BlockAnnounceData::from_statement::<Header>(&signed_statement).unwrap();
+	assert_eq!(data.head_data_extension, Some(extension));
+	let data = data.encode();
+	assert!(BlockAnnounceData::decode(&mut &data[..]).unwrap().head_data_extension.is_some());
+
+	let res = block_on(validator.validate(&header, &data));
+	assert_eq!(Validation::Success { is_new_best: true }, res.unwrap());
+
+	// The hash of the head data doesn't match without the extension.
+	let data = BlockAnnounceData::try_from(&signed_statement).unwrap().encode();
+	let res = block_on(validator.validate(&header, &data));
+	assert_eq!(Validation::Failure { disconnect: true }, res.unwrap());
+}
+
+#[test]
+fn aux_data_is_encoded_after_the_extension() {
+	let api = Arc::new(TestApi::new());
+	let relay_parent = api.relay_client.hash(0).ok().flatten().expect("Genesis hash exists");
+
+	let header = default_header();
+	let extension = HeadDataExtension::V1(vec![1; 32]);
+	let head_data = build_head_data(header.encode(), Some(&extension));
+	let signed_statement = block_on(make_gossip_message(api, relay_parent, 0, head_data));
+
+	let mut data = BlockAnnounceData::from_statement::<Header>(&signed_statement).unwrap();
+	data.aux_data = Some(vec![2; 16]);
+	let decoded = BlockAnnounceData::decode(&mut &data.encode()[..]).unwrap();
+	assert_eq!(decoded.head_data_extension, Some(extension.clone()));
+	assert_eq!(decoded.aux_data, Some(vec![2; 16]));
+
+	data.head_data_extension = None;
+	let decoded = BlockAnnounceData::decode(&mut &data.encode()[..]).unwrap();
+	assert_eq!(decoded.head_data_extension, None);
+	assert_eq!(decoded.aux_data, Some(vec![2; 16]));
+
+	// The auxiliary data can't come before the extension.
+	let mut encoded = data.encode();
+	extension.encode_to(&mut encoded);
+	assert!(BlockAnnounceData::decode(&mut &encoded[..]).is_err());
+}
+
+/// Only accepts the auxiliary data `b"valid"`.
+struct ValidAuxData;
+
+impl AuxDataChecker<Block> for ValidAuxData {
+	fn check_aux_data(&self, _: &Header, aux_data: Option<&[u8]>) -> bool {
+		aux_data == Some(&b"valid"[..])
+	}
+}
+
+#[test]
+fn aux_data_is_checked() {
+	let (validator, api) = make_validator_and_api();
+	let mut validator = validator.with_aux_data_checker(Box::new(ValidAuxData));
+
+	let (signed_statement, header) =
+		block_on(make_gossip_message_and_header_using_genesis(api, 0));
+	let mut data = BlockAnnounceData::try_from(&signed_statement).unwrap();
+
+	let res = block_on(validator.validate(&header, &data.encode()));
+	assert_eq!(Validation::Failure { disconnect: true }, res.unwrap());
+
+	data.aux_data = Some(b"invalid".to_vec());
+	let res = block_on(validator.validate(&header, &data.encode()));
+	assert_eq!(Validation::Failure { disconnect: true }, res.unwrap());
+
+	data.aux_data = Some(b"valid".to_vec());
+	let res = block_on(validator.validate(&header, &data.encode()));
+	assert_eq!(Validation::Success { is_new_best: true }, res.unwrap());
+}
+
 /// Test that ensures that we postpone the block announce verification until
 /// a relay chain block is imported. This is important for when we receive a
 /// block announcement before we have imported the associated relay chain block
@@ -18,8 +18,10 @@
 //!
 //! Provides functions for starting a collator node or a normal full node.
 
-use cumulus_client_consensus_common::ParachainConsensus;
-use cumulus_primitives_core::ParaId;
+use cumulus_client_consensus_common::{
+	ParachainConsensus, ProvingRelaychainClient, RelayChainClientConfig,
+};
+use cumulus_primitives_core::{CollectCollationInfo, ParaId};
 use futures::FutureExt;
 use polkadot_primitives::v1::{Block as PBlock, CollatorPair};
 use polkadot_service::{AbstractClient, Client as PClient, ClientHandle, RuntimeApiCollection};
@@ -28,6 +30,7 @@ use sc_client_api::{
 };
 use sc_service::{error::Result as ServiceResult, Configuration, Role, TaskManager};
 use sc_telemetry::TelemetryWorkerHandle;
+use sp_api::ProvideRuntimeApi;
 use sp_blockchain::HeaderBackend;
 use sp_consensus::BlockImport;
 use sp_core::traits::SpawnNamed;
@@ -51,6 +54,7 @@ pub struct StartCollatorParams<'a, Block: BlockT, BS, Client, Backend, Spawner,
 	pub relay_chain_full_node: RFullNode<RClient>,
 	pub task_manager: &'a mut TaskManager,
 	pub parachain_consensus: Box<dyn ParachainConsensus<Block>>,
+	pub relay_chain_client_config: RelayChainClientConfig,
 }
 
 /// Start a collator node for a parachain.
@@ -60,7 +64,7 @@ pub struct StartCollatorParams<'a, Block: BlockT, BS, Client, Backend, Spawner,
 /// parachain validator for validation and inclusion into the relay chain.
 pub async fn start_collator<'a, Block, BS, Client, Backend, Spawner, RClient>(
 	StartCollatorParams {
-		backend,
+		backend: _,
 		block_status,
 		client,
 		announce_block,
@@ -70,6 +74,7 @@ pub async fn start_collator<'a, Block, BS, Client, Backend, Spawner, RClient>(
 		task_manager,
 		relay_chain_full_node,
 		parachain_consensus,
+		relay_chain_client_config,
 	}: StartCollatorParams<'a, Block, BS, Client, Backend, Spawner, RClient>,
 ) -> sc_service::error::Result<()>
 where
@@ -82,7 +87,9 @@ where
 		+ Sync
 		+ BlockBackend<Block>
 		+ BlockchainEvents<Block>
+		+ ProvideRuntimeApi<Block>
 		+ 'static,
+	Client::Api: CollectCollationInfo<Block>,
 	for<'b> &'b Client: BlockImport<Block>,
 	Backend: BackendT<Block> + 'static,
 	Spawner: SpawnNamed + Clone + Send + Sync + 'static,
@@ -93,11 +100,13 @@ where
 		announce_block: announce_block.clone(),
 		client: client.clone(),
 		task_manager,
+		relay_chain_backend: relay_chain_full_node.backend.clone(),
+		relay_chain_client_config,
 		_phantom: PhantomData,
 	})?;
 
 	cumulus_client_collator::start_collator(cumulus_client_collator::StartCollatorParams {
-		backend,
+		runtime_api: client.clone(),
 		block_status,
 		announce_block,
 		overseer_handler: relay_chain_full_node
@@ -122,6 +131,7 @@ pub struct StartFullNodeParams<'a, Block: BlockT, Client, PClient> {
 	pub polkadot_full_node: RFullNode<PClient>,
 	pub task_manager: &'a mut TaskManager,
 	pub announce_block: Arc<dyn Fn(Block::Hash, Option<Vec<u8>>) + Send + Sync>,
+	pub relay_chain_client_config: RelayChainClientConfig,
 }
 
 /// Start a full node for a parachain.
@@ -135,6 +145,7 @@ pub fn start_full_node<Block, Client, Backend, PClient>(
 		task_manager,
 		polkadot_full_node,
 		para_id,
+		relay_chain_client_config,
 	}: StartFullNodeParams<Block, Client, PClient>,
 ) -> sc_service::error::Result<()>
 where
@@ -155,6 +166,8 @@ where
 		para_id,
 		client,
 		task_manager,
+		relay_chain_backend: polkadot_full_node.backend.clone(),
+		relay_chain_client_config,
 		_phantom: PhantomData,
 	})?;
 
@@ -168,6 +181,8 @@ struct StartConsensus<'a, Block: BlockT, Client, Backend> {
 	announce_block: Arc<dyn Fn(Block::Hash, Option<Vec<u8>>) + Send + Sync>,
 	client: Arc<Client>,
 	task_manager: &'a mut TaskManager,
+	relay_chain_backend: Arc<polkadot_service::FullBackend>,
+	relay_chain_client_config: RelayChainClientConfig,
 	_phantom: PhantomData<Backend>,
 }
 
@@ -195,10 +210,14 @@ where
 		Api: RuntimeApiCollection<StateBackend = PBackend::State>,
 		PClient: AbstractClient<PBlock, PBackend, Api = Api> + 'static,
 	{
+		let relay_chain_client = self
+			.relay_chain_client_config
+			.build(ProvingRelaychainClient::new(client, self.relay_chain_backend))?;
+
 		let consensus = cumulus_client_consensus_common::run_parachain_consensus(
 			self.para_id,
 			self.client,
-			client,
+			relay_chain_client,
 			self.announce_block,
 		);
 
@@ -30,17 +30,74 @@
 //!     cumulus_pallet_aura_ext::BlockExecutor::<Runtime, Executive>,
 //! );
 //! ```
+//!
+//! The slot duration may be decreased by a runtime upgrade, e.g. to go from 12s to 6s blocks. The
+//! block at which this happened is recorded in [`LastSlotDurationChange`]. Increasing the slot
+//! duration is not supported, as the slot numbers would go backwards. The `try-runtime` checks of
+//! an upgrade reject an increase, and an increase that is enacted anyway isn't recorded.
+//!
+//! Blocks of a development node that are sealed manually or instantly don't carry an AuRa seal.
+//! With the `manual-seal` feature, the [`BlockExecutor`] doesn't check the seal and the author of
+//! the blocks, so they can be validated. This feature must never be enabled for a production
+//! runtime, as anyone could author blocks then.
+//!
+//! If the author of a slot misses it, the block for the slot may be authored by a deterministic
+//! secondary author, see [`Config::SecondaryAuthorWindow`]. This keeps an outage of a single
+//! collator from stalling the parachain until the next slot of an online collator.
+//!
+//! A parachain that launched with another consensus, e.g. the relay-chain provided consensus, can
+//! switch to AuRa by a runtime upgrade that adds this pallet and initializes the AuRa authorities.
+//! The first block of the new runtime was built under the rules of the old consensus, so it is
+//! executed without a seal. The upgrade notes this block in [`ConsensusMigrationBlock`], which the
+//! [`BlockExecutor`] checks after executing an unsealed block. All later blocks need to be sealed.
+//!
+//! The slot information that the node needs to build and to verify blocks is provided by
+//! [`Pallet::slot_info`], which should be exposed by the runtime as
+//! [`AuraExtApi`](cumulus_primitives_core::AuraExtApi), along with
+//! [`Pallet::consensus_migration_block`]. The node doesn't verify any block after the migration
+//! block by the old consensus.
+//!
+//! An authority that seals two different blocks of the same slot can be reported by anyone with
+//! an [`AuraEquivocationProof`]. The nodes submit these reports as unsigned extrinsics by
+//! [`Pallet::submit_unsigned_equivocation_report`], exposed by the runtime as
+//! [`AuraEquivocationApi`](cumulus_primitives_core::AuraEquivocationApi). The offender is handed to
+//! [`Config::HandleEquivocation`], e.g. to slash the bond of the collator. Equivocations can be
+//! reported for [`Config::ReportLongevity`] slots, the reports of older slots are pruned.
+//!
+//! The [`RelaySlotVelocityHook`] ties the parachain blocks to the relay chain slots, for
+//! parachains that build more than one block per relay chain block.
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
-use frame_support::traits::{ExecuteBlock, FindAuthor};
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
+pub mod weights;
+
+use codec::{Decode, Encode};
+use cumulus_pallet_parachain_system::{ConsensusHook, ConsensusHookContext};
+use cumulus_primitives_core::{
+	relay_chain::BlockNumber as RelayChainBlockNumber, AuraEquivocationProof, AuraSlotInfo,
+};
+use frame_support::traits::{ExecuteBlock, FindAuthor, Get};
+use frame_system::offchain::{SendTransactionTypes, SubmitTransaction};
 use sp_application_crypto::RuntimeAppPublic;
-use sp_consensus_aura::digests::CompatibleDigestItem;
-use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
+use sp_consensus_aura::{digests::CompatibleDigestItem, AURA_ENGINE_ID};
+use sp_runtime::{
+	traits::{Block as BlockT, Header as HeaderT, UniqueSaturatedInto, Zero},
+	transaction_validity::{
+		InvalidTransaction, TransactionLongevity, TransactionPriority, TransactionSource,
+		TransactionValidity, ValidTransaction,
+	},
+};
+use sp_std::{boxed::Box, vec};
 
 type Aura<T> = pallet_aura::Pallet<T>;
 
+pub use cumulus_primitives_core::secondary_aura_author_index as secondary_author_index;
 pub use pallet::*;
+pub use weights::WeightInfo;
 
 #[frame_support::pallet]
 pub mod pallet {
@@ -51,7 +108,30 @@ pub mod pallet {
 
 	/// The configuration trait.
 	#[pallet::config]
-	pub trait Config: pallet_aura::Config + frame_system::Config {}
+	pub trait Config: pallet_aura::Config + frame_system::Config {
+		/// The overarching event type.
+		type Event: From<Event<Self>> + IsType<<Self as frame_system::Config>::Event>;
+
+		/// Handles the authorities that were reported for an equivocation.
+		type HandleEquivocation: HandleEquivocation<Self::AuthorityId>;
+
+		/// The number of relay chain blocks after which the secondary author of a slot may author
+		/// the block of the slot.
+		///
+		/// The window starts at the relay parent of the parent block, so the secondary author
+		/// can only step in when the parachain didn't progress for this number of relay chain
+		/// blocks. `None` only allows the primary author.
+		type SecondaryAuthorWindow: Get<Option<u32>>;
+
+		/// The number of slots for which an equivocation can be reported.
+		///
+		/// The reports of older slots are pruned, so older equivocations can't be reported
+		/// anymore.
+		type ReportLongevity: Get<u64>;
+
+		/// Weight information for the extrinsics and the pruning of this pallet.
+		type WeightInfo: WeightInfo;
+	}
 
 	#[pallet::pallet]
 	pub struct Pallet<T>(_);
@@ -67,12 +147,106 @@ pub mod pallet {
 			// Fetch the authorities once to get them into the storage proof of the PoV.
 			Authorities::<T>::get();
 
-			T::DbWeight::get().reads_writes(2, 1)
+			let pruned = Self::prune_equivocation_reports();
+
+			T::DbWeight::get()
+				.reads_writes(2, 1)
+				.saturating_add(T::WeightInfo::prune_equivocation_reports(pruned))
+		}
+
+		fn on_runtime_upgrade() -> Weight {
+			// Without authorities, the upgrade added this pallet to switch to AuRa from another
+			// consensus.
+			let mut weight = T::DbWeight::get().reads(1);
+			if !Authorities::<T>::exists() {
+				ConsensusMigrationBlock::<T>::put(frame_system::Pallet::<T>::block_number());
+				weight = weight.saturating_add(T::DbWeight::get().writes(1));
+			}
+
+			// Note a change of the slot duration by the upgrade. This runs before the
+			// `on_initialize` of `pallet_aura` checks the slot of the first block of the new
+			// runtime.
+			let slot_duration = Aura::<T>::slot_duration();
+			let last_slot_duration = SlotDuration::<T>::get();
+			if slot_duration == last_slot_duration {
+				return weight.saturating_add(T::DbWeight::get().reads(1));
+			}
+
+			// The slot duration wasn't tracked before, so there is no change to note.
+			if slot_duration > last_slot_duration && !last_slot_duration.is_zero() {
+				log::error!(
+					target: "runtime::aura-ext",
+					"The slot duration was increased from {:?} to {:?}, the slots go backwards",
+					last_slot_duration,
+					slot_duration,
+				);
+			} else if !last_slot_duration.is_zero() {
+				let now = frame_system::Pallet::<T>::block_number();
+				LastSlotDurationChange::<T>::put((now, last_slot_duration, slot_duration));
+			}
+			SlotDuration::<T>::put(slot_duration);
+			weight.saturating_add(T::DbWeight::get().reads_writes(2, 2))
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<(), &'static str> {
+			let last_slot_duration = SlotDuration::<T>::get();
+			if Aura::<T>::slot_duration() > last_slot_duration && !last_slot_duration.is_zero() {
+				return Err(
+					"Increasing the slot duration is not supported, the slots would go backwards",
+				);
+			}
+			Ok(())
 		}
 	}
 
 	#[pallet::call]
-	impl<T: Config> Pallet<T> {}
+	impl<T: Config> Pallet<T> {
+		/// Report an authority that sealed two different blocks of the same slot.
+		///
+		/// This is submitted as an unsigned extrinsic by the nodes that observed the
+		/// equivocation. An equivocation is only handled once.
+		#[pallet::weight(T::WeightInfo::report_equivocation_unsigned())]
+		pub fn report_equivocation_unsigned(
+			origin: OriginFor<T>,
+			equivocation_proof: Box<AuraEquivocationProof<T::Header, T::AuthorityId>>,
+		) -> DispatchResult {
+			ensure_none(origin)?;
+
+			Self::check_equivocation_proof(&equivocation_proof)?;
+
+			let AuraEquivocationProof { slot, offender, .. } = *equivocation_proof;
+			ReportedEquivocations::<T>::insert(slot, &offender, ());
+			ReportedSlots::<T>::mutate(|slots| {
+				if let Err(index) = slots.binary_search(&slot) {
+					slots.insert(index, slot);
+				}
+			});
+			T::HandleEquivocation::handle_equivocation(&offender, slot);
+
+			Self::deposit_event(Event::EquivocationReported(offender, slot));
+			Ok(())
+		}
+	}
+
+	#[pallet::event]
+	#[pallet::generate_deposit(pub(super) fn deposit_event)]
+	#[pallet::metadata(T::AuthorityId = "AuthorityId")]
+	pub enum Event<T: Config> {
+		/// An authority sealed two different blocks of the same slot.
+		/// \[ offender, slot \]
+		EquivocationReported(T::AuthorityId, u64),
+	}
+
+	#[pallet::error]
+	pub enum Error<T> {
+		/// The equivocation proof is not valid.
+		InvalidEquivocationProof,
+		/// The equivocation was already reported.
+		DuplicateEquivocationReport,
+		/// The equivocation is older than [`Config::ReportLongevity`] slots.
+		StaleEquivocationReport,
+	}
 
 	/// Serves as cache for the authorities.
 	///
@@ -82,6 +256,46 @@ pub mod pallet {
 	#[pallet::storage]
 	pub(crate) type Authorities<T: Config> = StorageValue<_, Vec<T::AuthorityId>, ValueQuery>;
 
+	/// The slot duration of the current block.
+	///
+	/// This is updated by the first block of a runtime upgrade.
+	#[pallet::storage]
+	pub(crate) type SlotDuration<T: Config> = StorageValue<_, T::Moment, ValueQuery>;
+
+	/// The equivocations that were reported, by the slot and the offender.
+	#[pallet::storage]
+	pub(crate) type ReportedEquivocations<T: Config> =
+		StorageDoubleMap<_, Twox64Concat, u64, Blake2_128Concat, T::AuthorityId, (), OptionQuery>;
+
+	/// The slots with [`ReportedEquivocations`], in ascending order.
+	#[pallet::storage]
+	pub(crate) type ReportedSlots<T: Config> = StorageValue<_, Vec<u64>, ValueQuery>;
+
+	/// The relay chain slot of the current block and the number of blocks built in it, including
+	/// the current block.
+	///
+	/// This is only tracked by the [`RelaySlotVelocityHook`].
+	#[pallet::storage]
+	pub(crate) type RelaySlotInfo<T: Config> = StorageValue<_, (u64, u32), OptionQuery>;
+
+	/// The last change of the slot duration by a runtime upgrade.
+	///
+	/// This is the number of the first block with the new slot duration, the old slot duration and
+	/// the new slot duration.
+	#[pallet::storage]
+	#[pallet::getter(fn last_slot_duration_change)]
+	pub type LastSlotDurationChange<T: Config> =
+		StorageValue<_, (T::BlockNumber, T::Moment, T::Moment), OptionQuery>;
+
+	/// The first block of the runtime upgrade that switched to AuRa from another consensus.
+	///
+	/// This block may come without a seal. The node doesn't verify any later block by the old
+	/// consensus.
+	#[pallet::storage]
+	#[pallet::getter(fn consensus_migration_block)]
+	pub(crate) type ConsensusMigrationBlock<T: Config> =
+		StorageValue<_, T::BlockNumber, OptionQuery>;
+
 	#[pallet::genesis_config]
 	#[derive(Default)]
 	pub struct GenesisConfig;
@@ -97,24 +311,207 @@ pub mod pallet {
 			);
 
 			Authorities::<T>::put(authorities);
+			SlotDuration::<T>::put(Aura::<T>::slot_duration());
+		}
+	}
+}
+
+impl<T> Pallet<T>
+where
+	T: Config + cumulus_pallet_parachain_system::Config,
+{
+	/// The slot of the current block, the slot duration and the authorities of the next block.
+	pub fn slot_info() -> AuraSlotInfo<T::AuthorityId> {
+		AuraSlotInfo {
+			slot: *Aura::<T>::current_slot(),
+			slot_duration: Aura::<T>::slot_duration().unique_saturated_into(),
+			authorities: Authorities::<T>::get(),
+			secondary_author_from: Self::secondary_author_from(),
+		}
+	}
+
+	/// The relay parent number from which on the secondary author of a slot may author the next
+	/// block.
+	///
+	/// This is [`Config::SecondaryAuthorWindow`] relay chain blocks after the relay parent of the
+	/// current block.
+	pub fn secondary_author_from() -> Option<RelayChainBlockNumber> {
+		let window = T::SecondaryAuthorWindow::get()?;
+		let relay_chain_state = cumulus_pallet_parachain_system::Pallet::<T>::relay_chain_state()?;
+
+		Some(relay_chain_state.number.saturating_add(window))
+	}
+}
+
+impl<T: Config> Pallet<T> {
+	/// Check that the `equivocation_proof` is valid and wasn't reported yet.
+	///
+	/// The offender needs to be one of the current authorities.
+	fn check_equivocation_proof(
+		equivocation_proof: &AuraEquivocationProof<T::Header, T::AuthorityId>,
+	) -> Result<(), Error<T>> {
+		let AuraEquivocationProof { slot, offender, first_header, second_header } =
+			equivocation_proof;
+
+		// The reports of older slots were pruned, so they can't be checked for duplicates.
+		if *slot < Self::oldest_reportable_slot() {
+			return Err(Error::<T>::StaleEquivocationReport);
+		}
+
+		if ReportedEquivocations::<T>::contains_key(slot, offender) {
+			return Err(Error::<T>::DuplicateEquivocationReport);
+		}
+
+		if !Authorities::<T>::get().contains(offender) ||
+			first_header.hash() == second_header.hash() ||
+			!Self::is_sealed_by(first_header, *slot, offender) ||
+			!Self::is_sealed_by(second_header, *slot, offender)
+		{
+			return Err(Error::<T>::InvalidEquivocationProof);
+		}
+
+		Ok(())
+	}
+
+	/// The oldest slot for which an equivocation can be reported.
+	fn oldest_reportable_slot() -> u64 {
+		(*Aura::<T>::current_slot()).saturating_sub(T::ReportLongevity::get())
+	}
+
+	/// Remove the equivocation reports of the slots that can't be reported anymore.
+	///
+	/// This needs to run after `pallet_aura` updated the current slot, so the pallet needs to come
+	/// after `pallet_aura` in `construct_runtime!`. Returns the number of removed reports.
+	fn prune_equivocation_reports() -> u32 {
+		let oldest = Self::oldest_reportable_slot();
+		let mut slots = ReportedSlots::<T>::get();
+		let stale = slots.iter().take_while(|slot| **slot < oldest).count();
+		if stale == 0 {
+			return 0;
+		}
+
+		let mut pruned = 0;
+		for slot in slots.drain(..stale) {
+			pruned += ReportedEquivocations::<T>::drain_prefix(slot).count() as u32;
 		}
+		ReportedSlots::<T>::put(slots);
+
+		pruned
 	}
+
+	/// Returns whether the `header` is of the `slot` and sealed by the `authority`.
+	fn is_sealed_by(header: &T::Header, slot: u64, authority: &T::AuthorityId) -> bool {
+		let mut header = header.clone();
+		let seal = match header.digest_mut().pop() {
+			Some(seal) => seal,
+			None => return false,
+		};
+		let seal: Option<<T::AuthorityId as RuntimeAppPublic>::Signature> = seal.as_aura_seal();
+		let seal = match seal {
+			Some(seal) => seal,
+			None => return false,
+		};
+
+		let header_slot = header
+			.digest()
+			.logs()
+			.iter()
+			.filter_map(|d| d.as_pre_runtime())
+			.find_map(|(id, mut data)| {
+				if id == AURA_ENGINE_ID {
+					u64::decode(&mut data).ok()
+				} else {
+					None
+				}
+			});
+
+		header_slot == Some(slot) && authority.verify(&header.hash(), &seal)
+	}
+}
+
+impl<T> Pallet<T>
+where
+	T: Config + SendTransactionTypes<Call<T>>,
+{
+	/// Submit an unsigned extrinsic that reports the equivocation of `equivocation_proof`.
+	///
+	/// This needs to be called by the node with the transaction pool extension registered.
+	pub fn submit_unsigned_equivocation_report(
+		equivocation_proof: AuraEquivocationProof<T::Header, T::AuthorityId>,
+	) -> Option<()> {
+		let call = Call::report_equivocation_unsigned(Box::new(equivocation_proof));
+
+		SubmitTransaction::<T, Call<T>>::submit_unsigned_transaction(call.into()).ok()
+	}
+}
+
+impl<T: Config> sp_runtime::traits::ValidateUnsigned for Pallet<T> {
+	type Call = Call<T>;
+
+	fn validate_unsigned(source: TransactionSource, call: &Self::Call) -> TransactionValidity {
+		if let Call::report_equivocation_unsigned(ref equivocation_proof) = call {
+			// Only accept the reports of the local node, or included in a block.
+			match source {
+				TransactionSource::Local | TransactionSource::InBlock => {},
+				_ => return Err(InvalidTransaction::Call.into()),
+			}
+
+			match Self::check_equivocation_proof(equivocation_proof) {
+				Ok(()) => {},
+				Err(Error::<T>::DuplicateEquivocationReport) |
+				Err(Error::<T>::StaleEquivocationReport) =>
+					return Err(InvalidTransaction::Stale.into()),
+				Err(_) => return Err(InvalidTransaction::BadProof.into()),
+			}
+
+			return Ok(ValidTransaction {
+				priority: TransactionPriority::max_value(),
+				requires: vec![],
+				provides: vec![(&equivocation_proof.offender, equivocation_proof.slot).encode()],
+				longevity: TransactionLongevity::max_value(),
+				propagate: false,
+			});
+		}
+		Err(InvalidTransaction::Call.into())
+	}
+}
+
+/// Handles an authority that sealed two different blocks of the same slot.
+pub trait HandleEquivocation<AuthorityId> {
+	/// Handle the equivocation of the `offender` in the `slot`, e.g. by slashing its bond.
+	fn handle_equivocation(offender: &AuthorityId, slot: u64);
+}
+
+impl<AuthorityId> HandleEquivocation<AuthorityId> for () {
+	fn handle_equivocation(_: &AuthorityId, _: u64) {}
 }
 
 /// The block executor used when validating a PoV at the relay chain.
 ///
 /// When executing the block it will verify the block seal to ensure that the correct author created
-/// the block.
+/// the block. This is either the author of the slot, or the secondary author of the slot when the
+/// [`Config::SecondaryAuthorWindow`] passed.
 pub struct BlockExecutor<T, I>(sp_std::marker::PhantomData<(T, I)>);
 
 impl<Block, T, I> ExecuteBlock<Block> for BlockExecutor<T, I>
 where
 	Block: BlockT,
-	T: Config,
+	T: Config + cumulus_pallet_parachain_system::Config,
 	I: ExecuteBlock<Block>,
 {
 	fn execute_block(block: Block) {
 		let (mut header, extrinsics) = block.deconstruct();
+
+		if cfg!(feature = "manual-seal") {
+			// Remove the seal, if any, as it is not part of the block that was built.
+			header.digest_mut().logs.retain(|s| {
+				let seal: Option<<T::AuthorityId as RuntimeAppPublic>::Signature> =
+					s.as_aura_seal();
+				seal.is_none()
+			});
+			return I::execute_block(Block::new(header, extrinsics));
+		}
+
 		// We need to fetch the authorities before we execute the block, to get the authorities
 		// before any potential update.
 		let authorities = Authorities::<T>::get();
@@ -133,7 +530,21 @@ where
 			}
 		});
 
-		let seal = seal.expect("Could not find an AuRa seal digest!");
+		let seal = match seal {
+			Some(seal) => seal,
+			None => {
+				I::execute_block(Block::new(header, extrinsics));
+
+				// Only the first block after switching to AuRa from another consensus may come
+				// without a seal, as it was built under the rules of the old consensus.
+				let now = frame_system::Pallet::<T>::block_number();
+				assert!(
+					ConsensusMigrationBlock::<T>::get() == Some(now),
+					"Could not find an AuRa seal digest!",
+				);
+				return;
+			},
+		};
 
 		let author = Aura::<T>::find_author(
 			header
@@ -146,16 +557,91 @@ where
 
 		let pre_hash = header.hash();
 
-		if !authorities
+		if authorities
 			.get(author as usize)
 			.unwrap_or_else(||
 				panic!("Invalid AuRa author index {} for authorities: {:?}", author, authorities)
 			)
 			.verify(&pre_hash, &seal)
 		{
+			return I::execute_block(Block::new(header, extrinsics));
+		}
+
+		// The block may still be authored by the secondary author of the slot.
+		if T::SecondaryAuthorWindow::get().is_none() {
+			panic!("Invalid AuRa seal");
+		}
+		let secondary = secondary_author_index(author, authorities.len());
+		if !authorities[secondary].verify(&pre_hash, &seal) {
 			panic!("Invalid AuRa seal");
 		}
 
+		let secondary_author_from = Pallet::<T>::secondary_author_from();
+
 		I::execute_block(Block::new(header, extrinsics));
+
+		let relay_number = cumulus_pallet_parachain_system::Pallet::<T>::relay_chain_state()
+			.map(|s| s.number)
+			.expect("The relay chain state is set by every block; qed");
+		match secondary_author_from {
+			Some(from) if relay_number >= from => {},
+			_ => panic!("Secondary AuRa author {} before the window passed", secondary),
+		}
+	}
+}
+
+/// A [`ConsensusHook`] that allows at most `Velocity` blocks per relay chain slot and at most
+/// `Capacity` blocks that are not yet included on the relay chain, including the block itself.
+///
+/// The relay chain slot is read from the relay chain state proof in `set_validation_data`, so
+/// this is enforced when validating a block on the relay chain as well. If the relay chain doesn't
+/// use BABE, at most `Velocity` blocks per relay parent are allowed instead.
+///
+/// The number of blocks per relay chain slot is tracked in [`RelaySlotInfo`] by
+/// [`ConsensusHook::on_block_accepted`].
+pub struct RelaySlotVelocityHook<T, Velocity, Capacity>(
+	sp_std::marker::PhantomData<(T, Velocity, Capacity)>,
+);
+
+impl<T: Config, Velocity, Capacity> RelaySlotVelocityHook<T, Velocity, Capacity> {
+	/// The number of blocks in `relay_slot` including the current block, or `None` if the relay
+	/// chain slot went backwards.
+	fn blocks_in_relay_slot(relay_slot: u64) -> Option<u32> {
+		match RelaySlotInfo::<T>::get() {
+			Some((slot, blocks)) if slot == relay_slot => Some(blocks.saturating_add(1)),
+			Some((slot, _)) if slot > relay_slot => None,
+			_ => Some(1),
+		}
+	}
+}
+
+impl<T, Velocity, Capacity> ConsensusHook for RelaySlotVelocityHook<T, Velocity, Capacity>
+where
+	T: Config,
+	Velocity: Get<u32>,
+	Capacity: Get<u32>,
+{
+	fn can_build_block(context: &ConsensusHookContext) -> bool {
+		if context.unincluded_segment_len >= Capacity::get() {
+			return false;
+		}
+
+		match context.relay_slot {
+			Some(relay_slot) => Self::blocks_in_relay_slot(relay_slot)
+				.map_or(false, |blocks| blocks <= Velocity::get()),
+			None => context.blocks_on_relay_parent <= Velocity::get(),
+		}
+	}
+
+	fn on_block_accepted(context: &ConsensusHookContext) {
+		if let Some(relay_slot) = context.relay_slot {
+			if let Some(blocks) = Self::blocks_in_relay_slot(relay_slot) {
+				RelaySlotInfo::<T>::put((relay_slot, blocks));
+			}
+		}
+	}
+
+	fn unincluded_segment_capacity() -> u32 {
+		Capacity::get()
 	}
 }
@@ -0,0 +1,240 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::*;
+use crate as aura_ext;
+
+use cumulus_pallet_parachain_system::{ParachainSetCode, RelayNumberStrictlyIncreases};
+use cumulus_primitives_core::{ParaId, RelayChainState};
+use frame_support::{parameter_types, storage, traits::GenesisBuild};
+use sp_consensus_aura::Slot;
+use sp_core::H256;
+use sp_io::hashing::twox_128;
+use sp_runtime::{
+	testing::{Digest, DigestItem, Header, UintAuthorityId},
+	traits::{BlakeTwo256, IdentityLookup},
+};
+use std::cell::RefCell;
+
+type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
+type Block = frame_system::mocking::MockBlock<Test>;
+
+frame_support::construct_runtime!(
+	pub enum Test where
+		Block = Block,
+		NodeBlock = Block,
+		UncheckedExtrinsic = UncheckedExtrinsic,
+	{
+		System: frame_system::{Pallet, Call, Config, Storage, Event<T>},
+		ParachainSystem: cumulus_pallet_parachain_system::{Pallet, Call, Storage, Event<T>},
+		Timestamp: pallet_timestamp::{Pallet, Call, Storage, Inherent},
+		Aura: pallet_aura::{Pallet, Config<T>},
+		AuraExt: aura_ext::{Pallet, Call, Storage, Config, Event<T>, ValidateUnsigned},
+	}
+);
+
+parameter_types! {
+	pub const BlockHashCount: u64 = 250;
+	pub const ParachainId: ParaId = ParaId::new(200);
+}
+
+impl frame_system::Config for Test {
+	type Origin = Origin;
+	type Call = Call;
+	type Index = u64;
+	type BlockNumber = u64;
+	type Hash = H256;
+	type Hashing = BlakeTwo256;
+	type AccountId = u64;
+	type Lookup = IdentityLookup<Self::AccountId>;
+	type Header = Header;
+	type Event = Event;
+	type BlockHashCount = BlockHashCount;
+	type BlockLength = ();
+	type BlockWeights = ();
+	type Version = ();
+	type PalletInfo = PalletInfo;
+	type AccountData = ();
+	type OnNewAccount = ();
+	type OnKilledAccount = ();
+	type DbWeight = ();
+	type BaseCallFilter = ();
+	type SystemWeightInfo = ();
+	type SS58Prefix = ();
+	type OnSetCode = ParachainSetCode<Self>;
+}
+
+impl cumulus_pallet_parachain_system::Config for Test {
+	type Event = Event;
+	type OnSystemEvent = ();
+	type OnRelaySessionChange = ();
+	type OnOffboarding = ();
+	type SelfParaId = ParachainId;
+	type CheckAssociatedRelayNumber = RelayNumberStrictlyIncreases;
+	type ValidationDataGapThreshold = ();
+	type ConsensusHook = ();
+	type OutboundXcmpMessageSource = ();
+	type DmpMessageHandler = ();
+	type ReservedDmpWeight = ();
+	type XcmpMessageHandler = ();
+	type ReservedXcmpWeight = ();
+	type MaxHorizontalMessagesPerBlock = ();
+	type MaxPausedMessages = ();
+	type MaxDeferredHorizontalMessages = ();
+	type LenientMqcHeadCheck = ();
+	type InboundMessagesOrder = ();
+	type AdditionalRelayStateKeys = ();
+	type HrmpChannelManagerOrigin = frame_system::EnsureRoot<u64>;
+	type RelayHrmpPalletIndex = ();
+	type RelayHrmpCallWeight = ();
+	type UpgradePovReserve = ();
+	type HeadDataExtensionProvider = ();
+	type QueuedMessageDeposit = ();
+	type WeightInfo = ();
+}
+
+/// The slot duration that is used at genesis.
+pub const SLOT_DURATION: u64 = 12_000;
+
+thread_local! {
+	/// The slot duration of the runtime, as changed by a runtime upgrade.
+	pub static RUNTIME_SLOT_DURATION: RefCell<u64> = RefCell::new(SLOT_DURATION);
+	/// The `SecondaryAuthorWindow` of the runtime.
+	pub static SECONDARY_AUTHOR_WINDOW: RefCell<Option<u32>> = RefCell::new(None);
+	/// The relay parent number that the next executed block sets, `None` to not set any.
+	pub static NEXT_RELAY_PARENT: RefCell<Option<u32>> = RefCell::new(None);
+	/// The headers of the blocks that were executed by the `MockExecutor`.
+	pub static EXECUTED: RefCell<Vec<Header>> = RefCell::new(Vec::new());
+}
+
+/// Change the slot duration, like a runtime upgrade would. The change is only noted by the next
+/// `on_runtime_upgrade`.
+pub fn set_slot_duration(slot_duration: u64) {
+	RUNTIME_SLOT_DURATION.with(|d| *d.borrow_mut() = slot_duration);
+}
+
+/// Half of the slot duration, as AuRa uses twice the minimum period as its slot duration.
+pub struct MinimumPeriod;
+impl Get<u64> for MinimumPeriod {
+	fn get() -> u64 {
+		RUNTIME_SLOT_DURATION.with(|d| *d.borrow() / 2)
+	}
+}
+
+pub struct SecondaryAuthorWindow;
+impl Get<Option<u32>> for SecondaryAuthorWindow {
+	fn get() -> Option<u32> {
+		SECONDARY_AUTHOR_WINDOW.with(|w| *w.borrow())
+	}
+}
+
+impl pallet_timestamp::Config for Test {
+	type Moment = u64;
+	type OnTimestampSet = ();
+	type MinimumPeriod = MinimumPeriod;
+	type WeightInfo = ();
+}
+
+impl pallet_aura::Config for Test {
+	type AuthorityId = UintAuthorityId;
+}
+
+parameter_types! {
+	pub const ReportLongevity: u64 = 10;
+}
+
+impl Config for Test {
+	type Event = Event;
+	type HandleEquivocation = ();
+	type SecondaryAuthorWindow = SecondaryAuthorWindow;
+	type ReportLongevity = ReportLongevity;
+	type WeightInfo = ();
+}
+
+/// Set the relay chain state of the current block to the given relay parent number.
+pub fn set_relay_parent(number: u32) {
+	let key = [twox_128(b"ParachainSystem"), twox_128(b"LastRelayChainState")].concat();
+	storage::unhashed::put(&key, &RelayChainState { number, state_root: Default::default() });
+}
+
+/// Records the executed blocks and sets the relay chain state like `set_validation_data` would.
+pub struct MockExecutor;
+impl ExecuteBlock<Block> for MockExecutor {
+	fn execute_block(block: Block) {
+		if let Some(number) = NEXT_RELAY_PARENT.with(|n| n.borrow_mut().take()) {
+			set_relay_parent(number);
+		}
+		EXECUTED.with(|e| e.borrow_mut().push(block.header));
+	}
+}
+
+/// A header of the given AuRa `slot` that is sealed by `author`, if any.
+pub fn sealed_header(slot: u64, author: Option<u64>) -> Header {
+	let pre_digest = DigestItem::PreRuntime(AURA_ENGINE_ID, Slot::from(slot).encode());
+	let mut header = Header::new(
+		1,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+		Digest { logs: vec![pre_digest] },
+	);
+	if let Some(author) = author {
+		let seal = UintAuthorityId(author).sign(&header.hash()).expect("Test keys always sign");
+		header.digest_mut().push(DigestItem::aura_seal(seal));
+	}
+	header
+}
+
+/// Validate a block with the given `header` like the relay chain validators do.
+pub fn validate_block(header: Header) {
+	BlockExecutor::<Test, MockExecutor>::execute_block(Block::new(header, vec![]));
+}
+
+/// Returns the headers of the blocks that were executed since the last call.
+pub fn take_executed() -> Vec<Header> {
+	EXECUTED.with(|e| std::mem::take(&mut *e.borrow_mut()))
+}
+
+/// The AuRa authorities at genesis.
+pub fn authorities() -> Vec<UintAuthorityId> {
+	vec![UintAuthorityId(1), UintAuthorityId(2), UintAuthorityId(3)]
+}
+
+/// Start the block `number` of the given AuRa `slot`, without executing any hook.
+pub fn start_block(number: u64, slot: u64) {
+	let pre_digest = DigestItem::PreRuntime(AURA_ENGINE_ID, Slot::from(slot).encode());
+	System::initialize(
+		&number,
+		&Default::default(),
+		&Digest { logs: vec![pre_digest] },
+		Default::default(),
+	);
+}
+
+pub fn new_test_ext() -> sp_io::TestExternalities {
+	set_slot_duration(SLOT_DURATION);
+	SECONDARY_AUTHOR_WINDOW.with(|w| *w.borrow_mut() = None);
+	NEXT_RELAY_PARENT.with(|n| *n.borrow_mut() = None);
+	EXECUTED.with(|e| e.borrow_mut().clear());
+
+	let mut storage = frame_system::GenesisConfig::default().build_storage::<Test>().unwrap();
+	pallet_aura::GenesisConfig::<Test> { authorities: authorities() }
+		.assimilate_storage(&mut storage)
+		.unwrap();
+	GenesisBuild::<Test>::assimilate_storage(&aura_ext::GenesisConfig, &mut storage).unwrap();
+
+	storage.into()
+}
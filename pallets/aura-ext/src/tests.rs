@@ -0,0 +1,512 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::*;
+use crate::mock::*;
+
+use frame_support::{
+	assert_noop, assert_ok, parameter_types,
+	traits::{OnFinalize, OnInitialize, OnRuntimeUpgrade},
+};
+use sp_core::H256;
+use sp_runtime::{
+	testing::{DigestItem, Header, UintAuthorityId},
+	traits::ValidateUnsigned,
+	DispatchResult,
+};
+
+/// Run the first block of a runtime upgrade, in the order of the `Executive`.
+fn upgrade_block(number: u64, slot: u64) {
+	start_block(number, slot);
+	AuraExt::on_runtime_upgrade();
+	Aura::on_initialize(number);
+	AuraExt::on_initialize(number);
+}
+
+#[test]
+fn slot_duration_is_set_at_genesis() {
+	new_test_ext().execute_with(|| {
+		assert_eq!(SlotDuration::<Test>::get(), SLOT_DURATION);
+		assert_eq!(AuraExt::last_slot_duration_change(), None);
+	});
+}
+
+#[test]
+fn decreasing_the_slot_duration_is_noted() {
+	new_test_ext().execute_with(|| {
+		start_block(1, 100);
+		Aura::on_initialize(1);
+		assert_eq!(AuraExt::slot_info().slot_duration, SLOT_DURATION);
+
+		// The same point in time has twice the slot number with half the slot duration.
+		set_slot_duration(SLOT_DURATION / 2);
+		upgrade_block(2, 202);
+
+		assert_eq!(SlotDuration::<Test>::get(), SLOT_DURATION / 2);
+		assert_eq!(
+			AuraExt::last_slot_duration_change(),
+			Some((2, SLOT_DURATION, SLOT_DURATION / 2)),
+		);
+		let slot_info = AuraExt::slot_info();
+		assert_eq!(slot_info.slot, 202);
+		assert_eq!(slot_info.slot_duration, SLOT_DURATION / 2);
+	});
+}
+
+#[test]
+fn unchanged_slot_duration_is_not_noted() {
+	new_test_ext().execute_with(|| {
+		upgrade_block(1, 100);
+
+		assert_eq!(SlotDuration::<Test>::get(), SLOT_DURATION);
+		assert_eq!(AuraExt::last_slot_duration_change(), None);
+	});
+}
+
+#[test]
+fn only_the_last_change_is_kept() {
+	new_test_ext().execute_with(|| {
+		set_slot_duration(SLOT_DURATION / 2);
+		upgrade_block(1, 200);
+		set_slot_duration(SLOT_DURATION / 4);
+		upgrade_block(2, 401);
+		upgrade_block(3, 402);
+
+		assert_eq!(
+			AuraExt::last_slot_duration_change(),
+			Some((2, SLOT_DURATION / 2, SLOT_DURATION / 4)),
+		);
+	});
+}
+
+#[test]
+fn increasing_the_slot_duration_is_not_noted() {
+	new_test_ext().execute_with(|| {
+		start_block(1, 100);
+		Aura::on_initialize(1);
+
+		set_slot_duration(SLOT_DURATION * 2);
+		start_block(2, 50);
+		AuraExt::on_runtime_upgrade();
+
+		assert_eq!(SlotDuration::<Test>::get(), SLOT_DURATION * 2);
+		assert_eq!(AuraExt::last_slot_duration_change(), None);
+	});
+}
+
+#[cfg(feature = "try-runtime")]
+#[test]
+fn increasing_the_slot_duration_fails_the_upgrade_checks() {
+	new_test_ext().execute_with(|| {
+		upgrade_block(1, 100);
+		assert_ok!(AuraExt::pre_upgrade());
+
+		set_slot_duration(SLOT_DURATION / 2);
+		assert_ok!(AuraExt::pre_upgrade());
+		set_slot_duration(SLOT_DURATION * 2);
+		assert_eq!(
+			AuraExt::pre_upgrade(),
+			Err("Increasing the slot duration is not supported, the slots would go backwards"),
+		);
+	});
+}
+
+parameter_types! {
+	pub const Velocity: u32 = 2;
+	pub const Capacity: u32 = 3;
+}
+
+type Hook = RelaySlotVelocityHook<Test, Velocity, Capacity>;
+
+fn context(relay_slot: Option<u64>, blocks_on_relay_parent: u32) -> ConsensusHookContext {
+	ConsensusHookContext {
+		relay_parent_number: 10,
+		relay_slot,
+		blocks_on_relay_parent,
+		unincluded_segment_len: 0,
+	}
+}
+
+/// Check a block with the `Hook` and note it if it's allowed, like `set_validation_data` does.
+fn build_block(context: &ConsensusHookContext) -> bool {
+	let allowed = Hook::can_build_block(context);
+	if allowed {
+		Hook::on_block_accepted(context);
+	}
+	allowed
+}
+
+#[test]
+fn blocks_per_relay_slot_are_limited() {
+	new_test_ext().execute_with(|| {
+		assert!(build_block(&context(Some(5), 1)));
+		assert!(build_block(&context(Some(5), 2)));
+		assert!(!build_block(&context(Some(5), 3)));
+		assert_eq!(RelaySlotInfo::<Test>::get(), Some((5, 2)));
+
+		// The relay parent doesn't matter, only the relay chain slot.
+		assert!(!build_block(&context(Some(5), 1)));
+		assert!(build_block(&context(Some(6), 1)));
+		assert_eq!(RelaySlotInfo::<Test>::get(), Some((6, 1)));
+	});
+}
+
+#[test]
+fn checking_a_block_does_not_change_the_storage() {
+	new_test_ext().execute_with(|| {
+		assert!(Hook::can_build_block(&context(Some(5), 1)));
+		assert!(Hook::can_build_block(&context(Some(5), 1)));
+		assert_eq!(RelaySlotInfo::<Test>::get(), None);
+
+		Hook::on_block_accepted(&context(Some(5), 1));
+		assert_eq!(RelaySlotInfo::<Test>::get(), Some((5, 1)));
+	});
+}
+
+#[test]
+fn relay_slot_must_not_go_backwards() {
+	new_test_ext().execute_with(|| {
+		assert!(build_block(&context(Some(5), 1)));
+		assert!(!build_block(&context(Some(4), 1)));
+		assert_eq!(RelaySlotInfo::<Test>::get(), Some((5, 1)));
+	});
+}
+
+#[test]
+fn blocks_per_relay_parent_are_limited_without_relay_slot() {
+	new_test_ext().execute_with(|| {
+		assert!(build_block(&context(None, 2)));
+		assert!(!build_block(&context(None, 3)));
+		assert_eq!(RelaySlotInfo::<Test>::get(), None);
+	});
+}
+
+#[test]
+fn unincluded_segment_is_limited() {
+	new_test_ext().execute_with(|| {
+		let mut context = context(Some(5), 1);
+		context.unincluded_segment_len = 2;
+		assert!(Hook::can_build_block(&context));
+
+		context.unincluded_segment_len = 3;
+		assert!(!Hook::can_build_block(&context));
+		assert_eq!(Hook::unincluded_segment_capacity(), 3);
+	});
+}
+
+/// Validate a block of slot 3 with the given author, on top of a parent at relay parent 10. The
+/// primary author of slot 3 is authority 1, its secondary author is authority 2.
+fn validate_at_relay_parent(author: u64, relay_parent: Option<u32>, window: Option<u32>) {
+	set_relay_parent(10);
+	SECONDARY_AUTHOR_WINDOW.with(|w| *w.borrow_mut() = window);
+	NEXT_RELAY_PARENT.with(|n| *n.borrow_mut() = relay_parent);
+
+	validate_block(sealed_header(3, Some(author)));
+}
+
+#[test]
+fn blocks_of_the_primary_author_are_accepted() {
+	new_test_ext().execute_with(|| {
+		validate_at_relay_parent(1, Some(11), None);
+
+		let executed = take_executed();
+		assert_eq!(executed.len(), 1);
+		// The seal is removed before the block is executed.
+		assert_eq!(executed[0], sealed_header(3, None));
+	});
+}
+
+#[test]
+fn blocks_of_the_secondary_author_are_accepted_after_the_window() {
+	new_test_ext().execute_with(|| {
+		validate_at_relay_parent(2, Some(15), Some(5));
+
+		assert_eq!(take_executed(), vec![sealed_header(3, None)]);
+	});
+}
+
+#[test]
+#[should_panic(expected = "Secondary AuRa author 1 before the window passed")]
+fn blocks_of_the_secondary_author_are_rejected_within_the_window() {
+	new_test_ext().execute_with(|| {
+		validate_at_relay_parent(2, Some(14), Some(5));
+	});
+}
+
+#[test]
+#[should_panic(expected = "Invalid AuRa seal")]
+fn blocks_of_the_secondary_author_are_rejected_without_a_window() {
+	new_test_ext().execute_with(|| {
+		validate_at_relay_parent(2, Some(15), None);
+	});
+}
+
+#[test]
+#[should_panic(expected = "Invalid AuRa seal")]
+fn blocks_of_other_authorities_are_rejected() {
+	new_test_ext().execute_with(|| {
+		validate_at_relay_parent(3, Some(15), Some(5));
+	});
+}
+
+#[test]
+#[should_panic(expected = "The relay chain state is set by every block")]
+fn blocks_of_the_secondary_author_need_the_relay_chain_state() {
+	new_test_ext().execute_with(|| {
+		// Executing the block sets the relay chain state, so it must already be set before.
+		SECONDARY_AUTHOR_WINDOW.with(|w| *w.borrow_mut() = Some(5));
+
+		validate_block(sealed_header(3, Some(2)));
+	});
+}
+
+#[test]
+#[should_panic(expected = "Secondary AuRa author 1 before the window passed")]
+fn blocks_of_the_secondary_author_need_the_relay_parent_of_the_parent() {
+	new_test_ext().execute_with(|| {
+		SECONDARY_AUTHOR_WINDOW.with(|w| *w.borrow_mut() = Some(5));
+		NEXT_RELAY_PARENT.with(|n| *n.borrow_mut() = Some(15));
+
+		validate_block(sealed_header(3, Some(2)));
+	});
+}
+
+#[test]
+fn slot_info_contains_the_start_of_the_secondary_author_window() {
+	new_test_ext().execute_with(|| {
+		assert_eq!(AuraExt::slot_info().secondary_author_from, None);
+
+		SECONDARY_AUTHOR_WINDOW.with(|w| *w.borrow_mut() = Some(5));
+		assert_eq!(AuraExt::slot_info().secondary_author_from, None);
+
+		set_relay_parent(10);
+		let slot_info = AuraExt::slot_info();
+		assert_eq!(slot_info.secondary_author_from, Some(15));
+		assert_eq!(slot_info.authorities, authorities());
+	});
+}
+
+#[test]
+fn secondary_authors_are_half_the_authority_set_away() {
+	assert_eq!(secondary_author_index(0, 1), 0);
+	assert_eq!(secondary_author_index(0, 3), 1);
+	assert_eq!(secondary_author_index(2, 3), 0);
+	assert_eq!(secondary_author_index(1, 4), 3);
+	assert_eq!(secondary_author_index(3, 4), 1);
+}
+
+/// Run the first block of the runtime upgrade that adds `aura-ext` to switch to AuRa.
+fn switch_to_aura(number: u64) {
+	Authorities::<Test>::kill();
+	upgrade_block(number, 100);
+}
+
+#[test]
+fn switching_to_aura_notes_the_migration_block() {
+	new_test_ext().execute_with(|| {
+		switch_to_aura(5);
+		assert_eq!(ConsensusMigrationBlock::<Test>::get(), Some(5));
+
+		// The `BlockExecutor` checks the migration block after executing it.
+		AuraExt::on_finalize(5);
+		assert_eq!(ConsensusMigrationBlock::<Test>::get(), Some(5));
+		assert_eq!(Authorities::<Test>::get(), authorities());
+
+		// The node reads it to reject later blocks of the old consensus.
+		start_block(6, 101);
+		AuraExt::on_finalize(6);
+		assert_eq!(AuraExt::consensus_migration_block(), Some(5));
+	});
+}
+
+#[test]
+fn upgrades_of_aura_chains_are_no_migration() {
+	new_test_ext().execute_with(|| {
+		upgrade_block(5, 100);
+
+		assert_eq!(ConsensusMigrationBlock::<Test>::get(), None);
+	});
+}
+
+#[test]
+fn unsealed_migration_block_is_accepted() {
+	new_test_ext().execute_with(|| {
+		switch_to_aura(1);
+
+		validate_block(sealed_header(3, None));
+
+		assert_eq!(take_executed(), vec![sealed_header(3, None)]);
+	});
+}
+
+#[test]
+#[should_panic(expected = "Could not find an AuRa seal digest!")]
+fn unsealed_blocks_are_rejected() {
+	new_test_ext().execute_with(|| {
+		System::set_block_number(1);
+
+		validate_block(sealed_header(3, None));
+	});
+}
+
+#[test]
+#[should_panic(expected = "Could not find an AuRa seal digest!")]
+fn unsealed_blocks_after_the_migration_block_are_rejected() {
+	new_test_ext().execute_with(|| {
+		switch_to_aura(1);
+		AuraExt::on_finalize(1);
+		System::set_block_number(2);
+
+		validate_block(sealed_header(3, None));
+	});
+}
+
+/// A header of the AuRa `slot` with the given state root, sealed by `author`.
+fn equivocating_header(slot: u64, author: u64, state_root: u64) -> Header {
+	let mut header = sealed_header(slot, None);
+	header.state_root = H256::from_low_u64_be(state_root);
+	let seal = UintAuthorityId(author).sign(&header.hash()).expect("Test keys always sign");
+	header.digest_mut().push(DigestItem::aura_seal(seal));
+	header
+}
+
+/// A proof that `author` sealed two different blocks of the `slot`.
+fn equivocation_proof(slot: u64, author: u64) -> AuraEquivocationProof<Header, UintAuthorityId> {
+	AuraEquivocationProof {
+		slot,
+		offender: UintAuthorityId(author),
+		first_header: equivocating_header(slot, author, 1),
+		second_header: equivocating_header(slot, author, 2),
+	}
+}
+
+/// Start a block of the AuRa `slot`, like the `Executive` would.
+fn start_slot(slot: u64) {
+	start_block(1, slot);
+	Aura::on_initialize(1);
+	AuraExt::on_initialize(1);
+}
+
+fn report(proof: AuraEquivocationProof<Header, UintAuthorityId>) -> DispatchResult {
+	AuraExt::report_equivocation_unsigned(Origin::none(), Box::new(proof))
+}
+
+#[test]
+fn valid_equivocations_are_reported() {
+	new_test_ext().execute_with(|| {
+		start_slot(100);
+
+		assert_ok!(AuraExt::check_equivocation_proof(&equivocation_proof(100, 1)));
+		assert_ok!(report(equivocation_proof(100, 1)));
+
+		assert!(ReportedEquivocations::<Test>::contains_key(100, UintAuthorityId(1)));
+		assert_eq!(ReportedSlots::<Test>::get(), vec![100]);
+		let event = crate::Event::EquivocationReported(UintAuthorityId(1), 100);
+		let event = crate::mock::Event::AuraExt(event);
+		assert!(System::events().iter().any(|record| record.event == event));
+	});
+}
+
+#[test]
+fn equivocations_need_different_headers() {
+	new_test_ext().execute_with(|| {
+		start_slot(100);
+		let mut proof = equivocation_proof(100, 1);
+		proof.second_header = proof.first_header.clone();
+
+		assert_eq!(
+			AuraExt::check_equivocation_proof(&proof),
+			Err(Error::<Test>::InvalidEquivocationProof),
+		);
+	});
+}
+
+#[test]
+fn equivocations_need_headers_of_the_slot() {
+	new_test_ext().execute_with(|| {
+		start_slot(100);
+		let mut proof = equivocation_proof(100, 1);
+		proof.second_header = equivocating_header(99, 1, 2);
+
+		assert_eq!(
+			AuraExt::check_equivocation_proof(&proof),
+			Err(Error::<Test>::InvalidEquivocationProof),
+		);
+	});
+}
+
+#[test]
+fn equivocations_need_headers_sealed_by_the_offender() {
+	new_test_ext().execute_with(|| {
+		start_slot(100);
+		let mut proof = equivocation_proof(100, 1);
+		proof.second_header = equivocating_header(100, 2, 2);
+
+		assert_eq!(
+			AuraExt::check_equivocation_proof(&proof),
+			Err(Error::<Test>::InvalidEquivocationProof),
+		);
+	});
+}
+
+#[test]
+fn equivocations_of_non_authorities_are_rejected() {
+	new_test_ext().execute_with(|| {
+		start_slot(100);
+
+		assert_noop!(report(equivocation_proof(100, 4)), Error::<Test>::InvalidEquivocationProof);
+	});
+}
+
+#[test]
+fn equivocations_are_only_reported_once() {
+	new_test_ext().execute_with(|| {
+		start_slot(100);
+		assert_ok!(report(equivocation_proof(100, 1)));
+
+		assert_noop!(
+			report(equivocation_proof(100, 1)),
+			Error::<Test>::DuplicateEquivocationReport,
+		);
+		let call = crate::Call::<Test>::report_equivocation_unsigned(Box::new(
+			equivocation_proof(100, 1),
+		));
+		assert_eq!(
+			AuraExt::validate_unsigned(TransactionSource::Local, &call),
+			Err(InvalidTransaction::Stale.into()),
+		);
+	});
+}
+
+#[test]
+fn reports_of_old_slots_are_pruned() {
+	new_test_ext().execute_with(|| {
+		start_slot(100);
+		assert_ok!(report(equivocation_proof(100, 1)));
+		assert_ok!(report(equivocation_proof(95, 2)));
+		assert_eq!(ReportedSlots::<Test>::get(), vec![95, 100]);
+
+		// The reports of slot 95 can be checked for duplicates until slot 105.
+		start_slot(105);
+		assert_eq!(ReportedSlots::<Test>::get(), vec![95, 100]);
+
+		start_slot(106);
+		assert_eq!(ReportedSlots::<Test>::get(), vec![100]);
+		assert!(!ReportedEquivocations::<Test>::contains_key(95, UintAuthorityId(2)));
+		assert!(ReportedEquivocations::<Test>::contains_key(100, UintAuthorityId(1)));
+		assert_noop!(report(equivocation_proof(95, 2)), Error::<Test>::StaleEquivocationReport);
+	});
+}
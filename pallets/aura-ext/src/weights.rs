@@ -0,0 +1,59 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The weights of the equivocation reports of this pallet.
+
+use frame_support::{
+	traits::Get,
+	weights::{constants::RocksDbWeight, Weight},
+};
+use sp_std::marker::PhantomData;
+
+/// The weight functions needed by this pallet.
+pub trait WeightInfo {
+	/// The weight of checking and noting an equivocation report, without handling the offender.
+	fn report_equivocation_unsigned() -> Weight;
+	/// The weight of pruning `r` equivocation reports of old slots.
+	fn prune_equivocation_reports(r: u32) -> Weight;
+}
+
+/// The weights of the equivocation reports.
+///
+/// These are estimates from the storage accesses and the two seal verifications of a report.
+pub struct SubstrateWeight<T>(PhantomData<T>);
+impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
+	fn report_equivocation_unsigned() -> Weight {
+		(110_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(4 as Weight))
+			.saturating_add(T::DbWeight::get().writes(2 as Weight))
+	}
+	fn prune_equivocation_reports(r: u32) -> Weight {
+		(1_000_000 as Weight)
+			.saturating_add((2_000_000 as Weight).saturating_mul(r as Weight))
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(r as Weight))
+	}
+}
+
+/// Only the storage accesses, without the cost of the seal verifications, e.g. for tests.
+impl WeightInfo for () {
+	fn report_equivocation_unsigned() -> Weight {
+		RocksDbWeight::get().reads_writes(4, 2)
+	}
+	fn prune_equivocation_reports(r: u32) -> Weight {
+		RocksDbWeight::get().reads(1).saturating_add(RocksDbWeight::get().writes(r as Weight))
+	}
+}
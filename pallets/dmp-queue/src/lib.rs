@@ -23,29 +23,77 @@
 
 use sp_std::{prelude::*, convert::TryFrom};
 use cumulus_primitives_core::relay_chain::BlockNumber as RelayBlockNumber;
-use cumulus_primitives_core::DmpMessageHandler;
+use cumulus_primitives_core::{DmpMessageHandler, DmpQueueStatus, QueuedDownwardMessage};
 use codec::{Encode, Decode};
 use sp_runtime::RuntimeDebug;
-use xcm::{VersionedXcm, v0::{Xcm, Junction, Outcome, ExecuteXcm, Error as XcmError}};
-use frame_support::{traits::EnsureOrigin, dispatch::Weight, weights::constants::WEIGHT_PER_MILLIS};
+use xcm::{VersionedXcm, v0::{Xcm, Junction, OriginKind, Outcome, ExecuteXcm, Error as XcmError}};
+use xcm_executor::traits::WeightBounds;
+use frame_support::{
+	traits::{Contains, EnsureOrigin}, dispatch::Weight, weights::constants::WEIGHT_PER_MILLIS,
+};
 pub use pallet::*;
 
+/// Handles the downward messages that can not be decoded, e.g. to keep them for later analysis.
+pub trait OnInvalidDownwardMessage {
+	/// Handle the message `data` that was sent at the relay chain block `sent_at`.
+	///
+	/// Returns the weight consumed.
+	fn on_invalid_downward_message(sent_at: RelayBlockNumber, data: &[u8]) -> Weight;
+}
+
+impl OnInvalidDownwardMessage for () {
+	fn on_invalid_downward_message(_sent_at: RelayBlockNumber, _data: &[u8]) -> Weight {
+		0
+	}
+}
+
+/// Matches the downward messages that `Transact` with the `Superuser` origin, i.e. the messages
+/// that are sent by the relay chain governance.
+pub struct SuperuserTransacts;
+impl<Call> Contains<Xcm<Call>> for SuperuserTransacts {
+	fn contains(xcm: &Xcm<Call>) -> bool {
+		matches!(xcm, Xcm::Transact { origin_type: OriginKind::Superuser, .. })
+	}
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Encode, Decode, RuntimeDebug)]
 pub struct ConfigData {
 	/// The maximum amount of weight any individual message may consume. Messages above this weight
 	/// go into the overweight queue and may only be serviced explicitly by the
 	/// `ExecuteOverweightOrigin`.
 	max_individual: Weight,
+	/// The maximum number of messages in a queue page.
+	max_page_messages: u32,
+	/// The maximum total size in bytes of the messages in a queue page. A message that is bigger
+	/// than this on its own is placed in a page of its own.
+	max_page_bytes: u32,
+	/// The maximum number of messages executed in a block. The messages beyond this remain in the
+	/// queue, even if there is weight left.
+	max_messages_per_block: u32,
 }
 
 impl Default for ConfigData {
 	fn default() -> Self {
 		Self {
 			max_individual: 10 * WEIGHT_PER_MILLIS,	// 10 ms of execution time maximum by default
+			max_page_messages: 128,
+			max_page_bytes: 64 * 1024,
+			max_messages_per_block: 1024,
 		}
 	}
 }
 
+impl ConfigData {
+	/// Returns `true` if a message of `size` bytes may be added to a page with `count` messages
+	/// of `bytes` bytes in total.
+	fn page_has_room(&self, count: usize, bytes: usize, size: usize) -> bool {
+		count == 0 || (
+			count < self.max_page_messages as usize &&
+				bytes.saturating_add(size) <= self.max_page_bytes as usize
+		)
+	}
+}
+
 /// Information concerning our message pages.
 #[derive(Copy, Clone, Eq, PartialEq, Default, Encode, Decode, RuntimeDebug)]
 pub struct PageIndexData {
@@ -57,6 +105,48 @@ pub struct PageIndexData {
 	overweight_count: OverweightIndex,
 }
 
+/// The aggregate size of the messages in the queue pages.
+#[derive(Copy, Clone, Eq, PartialEq, Default, Encode, Decode, RuntimeDebug)]
+pub struct QueueTotalsData {
+	/// The number of queued messages.
+	pub message_count: u32,
+	/// The total size in bytes of the queued messages.
+	pub total_bytes: u64,
+	/// The total weight of the queued messages, as estimated by the `Weigher`.
+	pub estimated_weight: Weight,
+}
+
+impl QueueTotalsData {
+	/// Account for a message of `size` bytes and `weight` that was added to the queue.
+	fn add(&mut self, size: usize, weight: Weight) {
+		self.message_count = self.message_count.saturating_add(1);
+		self.total_bytes = self.total_bytes.saturating_add(size as u64);
+		self.estimated_weight = self.estimated_weight.saturating_add(weight);
+	}
+
+	/// Account for a message of `size` bytes and `weight` that was removed from the queue.
+	fn remove(&mut self, size: usize, weight: Weight) {
+		self.message_count = self.message_count.saturating_sub(1);
+		self.total_bytes = self.total_bytes.saturating_sub(size as u64);
+		self.estimated_weight = self.estimated_weight.saturating_sub(weight);
+	}
+}
+
+/// The versions of the storage of this pallet.
+#[derive(Copy, Clone, Eq, PartialEq, Encode, Decode, RuntimeDebug)]
+pub enum Releases {
+	/// The `QueueTotals` are not tracked.
+	V0,
+	/// The `QueueTotals` are tracked.
+	V1,
+}
+
+impl Default for Releases {
+	fn default() -> Self {
+		Releases::V0
+	}
+}
+
 /// Simple type used to identify messages for the purpose of reporting events. Secure if and only
 /// if the message content is unique.
 pub type MessageId = [u8; 32];
@@ -67,10 +157,16 @@ pub type OverweightIndex = u64;
 /// Index used to identify normal pages.
 pub type PageCounter = u32;
 
+/// A downward message decoded into an XCM of the current version, if it is valid.
+type DecodedMessage<Call> = Result<Result<Xcm<Call>, ()>, codec::Error>;
+
+/// The maximum number of messages previewed by `Pallet::queue_status`.
+pub const MAX_QUEUE_PREVIEWS: u32 = 32;
+
 #[frame_support::pallet]
 pub mod pallet {
 	use super::*;
-	use frame_support::pallet_prelude::*;
+	use frame_support::{pallet_prelude::*, storage};
 	use frame_system::pallet_prelude::*;
 
 	#[pallet::pallet]
@@ -85,8 +181,20 @@ pub mod pallet {
 
 		type XcmExecutor: ExecuteXcm<Self::Call>;
 
+		/// Means of estimating the weight of the queued messages, for the `QueueTotals`.
+		type Weigher: WeightBounds<Self::Call>;
+
 		/// Origin which is allowed to execute overweight messages.
 		type ExecuteOverweightOrigin: EnsureOrigin<Self::Origin>;
+
+		/// The downward messages that are executed before the queued messages.
+		///
+		/// A priority message that doesn't fit into the remaining weight is handled like any other
+		/// message.
+		type PriorityMessages: Contains<Xcm<Self::Call>>;
+
+		/// Handler of the downward messages that can not be decoded, which are dropped otherwise.
+		type OnInvalidDownwardMessage: OnInvalidDownwardMessage;
 	}
 
 	/// The configuration.
@@ -97,6 +205,15 @@ pub mod pallet {
 	#[pallet::storage]
 	pub(super) type PageIndex<T> = StorageValue<_, PageIndexData, ValueQuery>;
 
+	/// The number of messages executed in the current block.
+	#[pallet::storage]
+	pub(super) type ExecutedCount<T> = StorageValue<_, u32, ValueQuery>;
+
+	/// The aggregate size of the messages in the queue pages.
+	#[pallet::storage]
+	#[pallet::getter(fn queue_totals)]
+	pub(super) type QueueTotals<T> = StorageValue<_, QueueTotalsData, ValueQuery>;
+
 	/// The queue pages.
 	#[pallet::storage]
 	pub(super) type Pages<T> = StorageMap<
@@ -117,20 +234,39 @@ pub mod pallet {
 		OptionQuery,
 	>;
 
+	/// The version of the storage, for the migrations by `on_runtime_upgrade`.
+	#[pallet::storage]
+	pub(super) type StorageVersion<T> = StorageValue<_, Releases, ValueQuery>;
+
 	#[pallet::error]
 	pub enum Error<T> {
 		/// The message index given is unknown.
 		Unknown,
 		/// The amount of weight given is possibly not enough for executing the message.
 		OverLimit,
+		/// The range of pages given is empty or not within the queued pages.
+		BadPageRange,
 	}
 
 	#[pallet::hooks]
 	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+		fn on_initialize(_now: T::BlockNumber) -> Weight {
+			ExecutedCount::<T>::kill();
+			T::DbWeight::get().writes(1)
+		}
+
 		fn on_idle(_now: T::BlockNumber, max_weight: Weight) -> Weight {
 			// on_idle processes additional messages with any remaining block weight.
 			Self::service_queue(max_weight)
 		}
+
+		fn on_runtime_upgrade() -> Weight {
+			if StorageVersion::<T>::get() == Releases::V0 {
+				Self::migrate_to_v1()
+			} else {
+				T::DbWeight::get().reads(1)
+			}
+		}
 	}
 
 	#[pallet::call]
@@ -162,6 +298,124 @@ pub mod pallet {
 			Self::deposit_event(Event::OverweightServiced(index, used));
 			Ok(Some(used.saturating_add(1_000_000)).into())
 		}
+
+		/// Remove the queue pages `begin..end` without executing their messages.
+		///
+		/// This is meant for recovering from a page that blocks the queue, e.g. because it can not
+		/// be decoded. The messages of such a page are lost without being reported, and as their
+		/// sizes are unknown, the `QueueTotals` are reset.
+		///
+		/// - `origin`: Must be root.
+		/// - `begin`: The counter of the first page to remove.
+		/// - `end`: The counter after the last page to remove.
+		///
+		/// Errors:
+		/// - `BadPageRange`: The range is empty or not within the queued pages.
+		///
+		/// Events:
+		/// - `PagePurged`: For each removed page.
+		/// - `UndecodablePagePurged`: For each removed page that could not be decoded.
+		#[pallet::weight(
+			T::DbWeight::get().reads_writes(2, 2)
+				.saturating_add(T::DbWeight::get().reads_writes(1, 1)
+					.saturating_mul(end.saturating_sub(*begin) as Weight))
+		)]
+		pub fn purge_pages(
+			origin: OriginFor<T>,
+			begin: PageCounter,
+			end: PageCounter,
+		) -> DispatchResult {
+			ensure_root(origin)?;
+
+			let mut page_index = PageIndex::<T>::get();
+			ensure!(
+				page_index.begin_used <= begin && begin < end && end <= page_index.end_used,
+				Error::<T>::BadPageRange,
+			);
+			let mut totals = QueueTotals::<T>::get();
+			let mut totals_known = true;
+			for page_counter in begin..end {
+				// The page is read raw, as it might not decode.
+				let key = Pages::<T>::hashed_key_for(page_counter);
+				let raw_page = match storage::unhashed::get_raw(&key) {
+					Some(raw_page) => raw_page,
+					None => {
+						Self::deposit_event(Event::PagePurged(page_counter, Vec::new()));
+						continue;
+					}
+				};
+				storage::unhashed::kill(&key);
+				match <Vec<(RelayBlockNumber, Vec<u8>)>>::decode(&mut &raw_page[..]) {
+					Ok(page) => {
+						let ids = page
+							.iter()
+							.map(|(_, data)| {
+								totals.remove(data.len(), Self::estimate_weight(&data[..]));
+								sp_io::hashing::blake2_256(&data[..])
+							})
+							.collect();
+						Self::deposit_event(Event::PagePurged(page_counter, ids));
+					},
+					Err(_) => {
+						totals_known = false;
+						let hash = sp_io::hashing::blake2_256(&raw_page[..]);
+						Self::deposit_event(Event::UndecodablePagePurged(page_counter, hash));
+					},
+				}
+			}
+			// Pages in the middle of the queue are left empty, and skipped when servicing it.
+			if begin == page_index.begin_used {
+				page_index.begin_used = end;
+			}
+			if page_index.begin_used == page_index.end_used {
+				page_index.begin_used = 0;
+				page_index.end_used = 0;
+				totals = QueueTotalsData::default();
+			} else if !totals_known {
+				log::warn!("Resetting the DMP queue totals after purging an undecodable page.");
+				totals = QueueTotalsData::default();
+			}
+			QueueTotals::<T>::put(totals);
+			PageIndex::<T>::put(page_index);
+			Ok(())
+		}
+
+		/// Overwrite the maximum number of messages in a queue page.
+		///
+		/// Only the pages that are filled from now on are affected.
+		///
+		/// - `origin`: Must be root.
+		/// - `new`: Desired value for `ConfigData.max_page_messages`.
+		#[pallet::weight((T::DbWeight::get().reads_writes(1, 1), DispatchClass::Operational))]
+		pub fn update_max_page_messages(origin: OriginFor<T>, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Configuration::<T>::mutate(|config| config.max_page_messages = new);
+			Ok(())
+		}
+
+		/// Overwrite the maximum total size in bytes of the messages in a queue page.
+		///
+		/// Only the pages that are filled from now on are affected.
+		///
+		/// - `origin`: Must be root.
+		/// - `new`: Desired value for `ConfigData.max_page_bytes`.
+		#[pallet::weight((T::DbWeight::get().reads_writes(1, 1), DispatchClass::Operational))]
+		pub fn update_max_page_bytes(origin: OriginFor<T>, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Configuration::<T>::mutate(|config| config.max_page_bytes = new);
+			Ok(())
+		}
+
+		/// Overwrite the maximum number of messages executed in a block.
+		///
+		/// - `origin`: Must be root.
+		/// - `new`: Desired value for `ConfigData.max_messages_per_block`.
+		#[pallet::weight((T::DbWeight::get().reads_writes(1, 1), DispatchClass::Operational))]
+		pub fn update_max_messages_per_block(origin: OriginFor<T>, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Configuration::<T>::mutate(|config| config.max_messages_per_block = new);
+			Ok(())
+		}
 	}
 
 	#[pallet::event]
@@ -169,51 +423,125 @@ pub mod pallet {
 	#[pallet::metadata(T::BlockNumber = "BlockNumber")]
 	pub enum Event<T: Config> {
 		/// Downward message is invalid XCM.
-		/// \[ id \]
-		InvalidFormat(MessageId),
+		/// \[ id, sent_at \]
+		InvalidFormat(MessageId, RelayBlockNumber),
 		/// Downward message is unsupported version of XCM.
-		/// \[ id \]
-		UnsupportedVersion(MessageId),
+		/// \[ id, sent_at \]
+		UnsupportedVersion(MessageId, RelayBlockNumber),
 		/// Downward message executed with the given outcome.
-		/// \[ id, outcome \]
-		ExecutedDownward(MessageId, Outcome),
+		/// \[ id, sent_at, used, outcome \]
+		ExecutedDownward(MessageId, RelayBlockNumber, Weight, Outcome),
 		/// The weight limit for handling downward messages was reached.
 		/// \[ id, remaining, required \]
 		WeightExhausted(MessageId, Weight, Weight),
 		/// Downward message is overweight and was placed in the overweight queue.
-		/// \[ id, index, required \]
-		OverweightEnqueued(MessageId, OverweightIndex, Weight),
+		/// \[ id, sent_at, index, required \]
+		OverweightEnqueued(MessageId, RelayBlockNumber, OverweightIndex, Weight),
 		/// Downward message from the overweight queue was executed.
 		/// \[ index, used \]
 		OverweightServiced(OverweightIndex, Weight),
+		/// Queue page was removed without executing its messages.
+		/// \[ page, ids \]
+		PagePurged(PageCounter, Vec<MessageId>),
+		/// Queue page that could not be decoded was removed.
+		/// \[ page, hash \]
+		UndecodablePagePurged(PageCounter, [u8; 32]),
 	}
 
 	impl<T: Config> Pallet<T> {
+		/// The depth of the queue and up to `max_previews` of its oldest messages, for the
+		/// `DmpQueueApi`.
+		///
+		/// At most `MAX_QUEUE_PREVIEWS` previews are returned. Only the pages of the previews and
+		/// the last page are read, the depth is taken from the `QueueTotals`.
+		pub fn queue_status(max_previews: u32) -> DmpQueueStatus {
+			let totals = QueueTotals::<T>::get();
+			let mut status = DmpQueueStatus {
+				message_count: totals.message_count,
+				total_bytes: totals.total_bytes,
+				..Default::default()
+			};
+			let max_previews = max_previews.min(MAX_QUEUE_PREVIEWS) as usize;
+			let page_index = PageIndex::<T>::get();
+			let pages = page_index.begin_used..page_index.end_used;
+			// Purged pages in the middle of the queue are empty.
+			for page_counter in pages.clone() {
+				let page = Pages::<T>::get(page_counter);
+				if let Some(&(sent_at, _)) = page.first() {
+					status.first_sent_at.get_or_insert(sent_at);
+				}
+				for (sent_at, data) in page.into_iter().take(max_previews - status.previews.len()) {
+					status.previews.push(QueuedDownwardMessage {
+						id: sp_io::hashing::blake2_256(&data[..]),
+						sent_at,
+						msg: data,
+					});
+				}
+				if status.first_sent_at.is_some() && status.previews.len() == max_previews {
+					break;
+				}
+			}
+			for page_counter in pages.rev() {
+				if let Some(&(sent_at, _)) = Pages::<T>::get(page_counter).last() {
+					status.last_sent_at = Some(sent_at);
+					break;
+				}
+			}
+			status
+		}
+
 		/// Service the message queue up to some given weight `limit`.
 		///
 		/// Returns the weight consumed by executing messages in the queue.
 		fn service_queue(limit: Weight) -> Weight {
-			PageIndex::<T>::mutate(|page_index| Self::do_service_queue(limit, page_index))
+			let mut executed = ExecutedCount::<T>::get();
+			let used = PageIndex::<T>::mutate(|page_index| {
+				Self::do_service_queue(limit, page_index, &mut executed)
+			});
+			ExecutedCount::<T>::put(executed);
+			used
 		}
 
-		/// Exactly equivalent to `service_queue` but expects a mutable `page_index` to be passed
-		/// in and any changes stored.
-		fn do_service_queue(limit: Weight, page_index: &mut PageIndexData) -> Weight {
+		/// Exactly equivalent to `service_queue` but expects a mutable `page_index` and the
+		/// number of messages `executed` in the block to be passed in and any changes stored.
+		fn do_service_queue(
+			limit: Weight,
+			page_index: &mut PageIndexData,
+			executed: &mut u32,
+		) -> Weight {
+			let config = Configuration::<T>::get();
 			let mut used = 0;
+			let mut totals = QueueTotals::<T>::get();
 			while page_index.begin_used < page_index.end_used {
 				let page = Pages::<T>::take(page_index.begin_used);
 				for (i, &(sent_at, ref data)) in page.iter().enumerate() {
-					match Self::try_service_message(limit.saturating_sub(used), sent_at, &data[..]) {
-						Ok(w) => used += w,
-						Err(..) => {
-							// Too much weight needed - put the remaining messages back and bail
+					let maybe_msg = Self::decode_message(&data[..]);
+					// Estimate before executing, as the execution may change the estimation.
+					let estimated_weight = Self::estimate_decoded_weight(&maybe_msg);
+					let result = if *executed < config.max_messages_per_block {
+						let remaining = limit.saturating_sub(used);
+						Self::try_service_decoded(remaining, sent_at, &data[..], &maybe_msg).ok()
+					} else {
+						None
+					};
+					match result {
+						Some(w) => {
+							used += w;
+							*executed += 1;
+							totals.remove(data.len(), estimated_weight);
+						},
+						None => {
+							// Too much weight or too many messages needed - put the remaining
+							// messages back and bail
 							Pages::<T>::insert(page_index.begin_used, &page[i..]);
+							QueueTotals::<T>::put(totals);
 							return used;
 						}
 					}
 				}
 				page_index.begin_used += 1;
 			}
+			QueueTotals::<T>::put(totals);
 			if page_index.begin_used == page_index.end_used {
 				// Reset if there's no pages left.
 				page_index.begin_used = 0;
@@ -222,6 +550,53 @@ pub mod pallet {
 			used
 		}
 
+		/// Decode the encoded message `data` into an XCM of the current version.
+		fn decode_message(data: &[u8]) -> DecodedMessage<T::Call> {
+			VersionedXcm::<T::Call>::decode(&mut &data[..]).map(Xcm::<T::Call>::try_from)
+		}
+
+		/// The weight of the encoded message `data`, as estimated by the `Weigher`.
+		///
+		/// Returns zero for a message that can not be decoded or weighed.
+		fn estimate_weight(data: &[u8]) -> Weight {
+			Self::estimate_decoded_weight(&Self::decode_message(data))
+		}
+
+		/// Sum up the messages of the queued pages into the `QueueTotals`, which weren't tracked
+		/// before `Releases::V1`.
+		fn migrate_to_v1() -> Weight {
+			let page_index = PageIndex::<T>::get();
+			let mut totals = QueueTotalsData::default();
+			for page_counter in page_index.begin_used..page_index.end_used {
+				for (_, data) in Pages::<T>::get(page_counter) {
+					totals.add(data.len(), Self::estimate_weight(&data[..]));
+				}
+			}
+			QueueTotals::<T>::put(totals);
+			StorageVersion::<T>::put(Releases::V1);
+
+			let pages = page_index.end_used.saturating_sub(page_index.begin_used) as Weight;
+			T::DbWeight::get().reads_writes(pages.saturating_add(2), 2)
+		}
+
+		/// The weight of the decoded message `maybe_msg`, as estimated by the `Weigher`.
+		fn estimate_decoded_weight(maybe_msg: &DecodedMessage<T::Call>) -> Weight {
+			match maybe_msg {
+				Ok(Ok(x)) => {
+					let mut x = x.clone();
+					let shallow = T::Weigher::shallow(&mut x).unwrap_or(0);
+					let deep = T::Weigher::deep(&mut x).unwrap_or(0);
+					shallow.saturating_add(deep)
+				},
+				_ => 0,
+			}
+		}
+
+		/// Returns `true` if the decoded message `maybe_msg` is matched by `PriorityMessages`.
+		fn is_priority(maybe_msg: &DecodedMessage<T::Call>) -> bool {
+			matches!(maybe_msg, Ok(Ok(x)) if T::PriorityMessages::contains(x))
+		}
+
 		/// Attempt to service an individual message. Will return `Ok` with the execution weight
 		/// consumed unless the message was found to need more weight than `limit`.
 		///
@@ -229,28 +604,40 @@ pub mod pallet {
 		/// the message. This is why it's called message "servicing" rather than "execution".
 		pub(crate) fn try_service_message(
 			limit: Weight,
-			_sent_at: RelayBlockNumber,
+			sent_at: RelayBlockNumber,
+			data: &[u8],
+		) -> Result<Weight, (MessageId, Weight)> {
+			Self::try_service_decoded(limit, sent_at, data, &Self::decode_message(data))
+		}
+
+		/// Exactly equivalent to `try_service_message`, but for a message `data` that was
+		/// decoded into `maybe_msg` already.
+		fn try_service_decoded(
+			limit: Weight,
+			sent_at: RelayBlockNumber,
 			data: &[u8],
+			maybe_msg: &DecodedMessage<T::Call>,
 		) -> Result<Weight, (MessageId, Weight)> {
 			let id = sp_io::hashing::blake2_256(&data[..]);
-			let maybe_msg = VersionedXcm::<T::Call>::decode(&mut &data[..])
-				.map(Xcm::<T::Call>::try_from);
 			match maybe_msg {
 				Err(_) => {
-					Self::deposit_event(Event::InvalidFormat(id));
-					Ok(0)
+					Self::deposit_event(Event::InvalidFormat(id, sent_at));
+					Ok(T::OnInvalidDownwardMessage::on_invalid_downward_message(sent_at, data))
 				},
 				Ok(Err(())) => {
-					Self::deposit_event(Event::UnsupportedVersion(id));
+					Self::deposit_event(Event::UnsupportedVersion(id, sent_at));
 					Ok(0)
 				},
 				Ok(Ok(x)) => {
-					let outcome = T::XcmExecutor::execute_xcm(Junction::Parent.into(), x, limit);
+					let outcome =
+						T::XcmExecutor::execute_xcm(Junction::Parent.into(), x.clone(), limit);
 					match outcome {
 						Outcome::Error(XcmError::WeightLimitReached(required)) => Err((id, required)),
 						outcome => {
 							let weight_used = outcome.weight_used();
-							Self::deposit_event(Event::ExecutedDownward(id, outcome));
+							Self::deposit_event(
+								Event::ExecutedDownward(id, sent_at, weight_used, outcome),
+							);
 							Ok(weight_used)
 						}
 					}
@@ -270,25 +657,65 @@ pub mod pallet {
 			let mut page_index = PageIndex::<T>::get();
 			let config = Configuration::<T>::get();
 
-			// First try to use `max_weight` to service the current queue.
-			let mut used = Self::do_service_queue(limit, &mut page_index);
+			// First execute the priority messages. The ones that don't fit are handled in the order
+			// of their arrival, before the other incoming messages.
+			let mut used: Weight = 0;
+			let mut executed = ExecutedCount::<T>::get();
+			let mut priority_left = Vec::new();
+			let mut others = Vec::with_capacity(iter.size_hint().0);
+			// Each message is decoded only once, and the decoded message is passed along with it.
+			for (sent_at, data) in iter {
+				let maybe_msg = Self::decode_message(&data[..]);
+				if Self::is_priority(&maybe_msg) {
+					if executed >= config.max_messages_per_block {
+						priority_left.push((sent_at, data, maybe_msg));
+						continue;
+					}
+					let remaining = limit.saturating_sub(used);
+					match Self::try_service_decoded(remaining, sent_at, &data[..], &maybe_msg) {
+						Ok(consumed) => {
+							used = used.saturating_add(consumed);
+							executed += 1;
+						},
+						Err(_) => priority_left.push((sent_at, data, maybe_msg)),
+					}
+				} else {
+					others.push((sent_at, data, maybe_msg));
+				}
+			}
+			priority_left.append(&mut others);
+			let iter = priority_left.into_iter();
+
+			// Then try to use the weight remaining to service the current queue.
+			used = used.saturating_add(
+				Self::do_service_queue(limit.saturating_sub(used), &mut page_index, &mut executed),
+			);
 
 			// Then if the queue is empty, use the weight remaining to service the incoming messages
 			// and once we run out of weight, place them in the queue.
-			let item_count = iter.size_hint().0;
+			let item_count = iter.size_hint().0.min(config.max_page_messages as usize);
 			let mut maybe_enqueue_page = if page_index.end_used > page_index.begin_used {
 				// queue is already non-empty - start a fresh page.
 				Some(Vec::with_capacity(item_count))
 			} else {
 				None
 			};
+			let mut enqueue_page_bytes = 0;
+			let mut totals = QueueTotals::<T>::get();
 
-			for (i, (sent_at, data)) in iter.enumerate() {
+			for (i, (sent_at, data, maybe_msg)) in iter.enumerate() {
+				if maybe_enqueue_page.is_none() && executed >= config.max_messages_per_block {
+					// Too many messages executed in this block - enqueue from here on.
+					maybe_enqueue_page = Some(Vec::with_capacity(item_count.saturating_sub(i)));
+				}
 				if maybe_enqueue_page.is_none() {
 					// We're not currently enqueuing - try to execute inline.
 					let remaining = limit.saturating_sub(used);
-					match Self::try_service_message(remaining, sent_at, &data[..]) {
-						Ok(consumed) => used += consumed,
+					match Self::try_service_decoded(remaining, sent_at, &data[..], &maybe_msg) {
+						Ok(consumed) => {
+							used += consumed;
+							executed += 1;
+						},
 						Err((id, required)) =>
 							// Too much weight required right now.
 							if required > config.max_individual {
@@ -296,7 +723,9 @@ pub mod pallet {
 								// message execution.
 								let index = page_index.overweight_count;
 								Overweight::<T>::insert(index, (sent_at, data));
-								Self::deposit_event(Event::OverweightEnqueued(id, index, required));
+								Self::deposit_event(
+									Event::OverweightEnqueued(id, sent_at, index, required),
+								);
 								page_index.overweight_count += 1;
 								// Not needed for control flow, but only to ensure that the compiler
 								// understands that we won't attempt to re-use `data` later.
@@ -312,6 +741,14 @@ pub mod pallet {
 				}
 				// Cannot be an `else` here since the `maybe_enqueue_page` may have changed.
 				if let Some(ref mut enqueue_page) = maybe_enqueue_page {
+					if !config.page_has_room(enqueue_page.len(), enqueue_page_bytes, data.len()) {
+						// The page is full - deposit it and start a fresh one.
+						Pages::<T>::insert(page_index.end_used, sp_std::mem::take(enqueue_page));
+						page_index.end_used += 1;
+						enqueue_page_bytes = 0;
+					}
+					enqueue_page_bytes += data.len();
+					totals.add(data.len(), Self::estimate_decoded_weight(&maybe_msg));
 					enqueue_page.push((sent_at, data));
 				}
 			}
@@ -322,6 +759,8 @@ pub mod pallet {
 				page_index.end_used += 1;
 			}
 			PageIndex::<T>::put(page_index);
+			QueueTotals::<T>::put(totals);
+			ExecutedCount::<T>::put(executed);
 
 			used
 		}
@@ -336,7 +775,9 @@ mod tests {
 	use std::cell::RefCell;
 	use codec::Encode;
 	use cumulus_primitives_core::ParaId;
-	use frame_support::{parameter_types, assert_noop, traits::OnIdle};
+	use frame_support::{
+		parameter_types, assert_noop, assert_ok, traits::{OnIdle, OnInitialize, OnRuntimeUpgrade},
+	};
 	use sp_core::H256;
 	use sp_runtime::{testing::Header, traits::{IdentityLookup, BlakeTwo256}};
 	use sp_runtime::DispatchError::BadOrigin;
@@ -404,6 +845,7 @@ mod tests {
 
 	thread_local! {
 		pub static TRACE: RefCell<Vec<(Xcm, Outcome)>> = RefCell::new(Vec::new());
+		pub static INVALID: RefCell<Vec<(RelayBlockNumber, Vec<u8>)>> = RefCell::new(Vec::new());
 	}
 	pub fn take_trace() -> Vec<(Xcm, Outcome)> {
 		TRACE.with(|q| {
@@ -438,10 +880,34 @@ mod tests {
 		}
 	}
 
+	pub struct MockWeigher;
+	impl WeightBounds<Call> for MockWeigher {
+		fn shallow(message: &mut Xcm) -> Result<Weight, ()> {
+			match message {
+				Xcm::Transact { require_weight_at_most, .. } => Ok(*require_weight_at_most),
+				_ => Err(()),
+			}
+		}
+		fn deep(_message: &mut Xcm) -> Result<Weight, ()> {
+			Ok(0)
+		}
+	}
+
+	pub struct MockInvalidHandler;
+	impl OnInvalidDownwardMessage for MockInvalidHandler {
+		fn on_invalid_downward_message(sent_at: RelayBlockNumber, data: &[u8]) -> Weight {
+			INVALID.with(|q| q.borrow_mut().push((sent_at, data.to_vec())));
+			100
+		}
+	}
+
 	impl Config for Test {
 		type Event = Event;
 		type XcmExecutor = MockExec;
+		type Weigher = MockWeigher;
 		type ExecuteOverweightOrigin = frame_system::EnsureRoot<AccountId>;
+		type PriorityMessages = SuperuserTransacts;
+		type OnInvalidDownwardMessage = MockInvalidHandler;
 	}
 
 	pub(crate) fn new_test_ext() -> sp_io::TestExternalities {
@@ -451,10 +917,13 @@ mod tests {
 	fn enqueue(enqueued: &[Xcm]) {
 		if !enqueued.is_empty() {
 			let mut index = PageIndex::<Test>::get();
-			Pages::<Test>::insert(index.end_used, enqueued.iter()
+			let page = enqueued.iter()
 				.map(|m| (0, VersionedXcm::<Call>::from(m.clone()).encode()))
-				.collect::<Vec<_>>()
-			);
+				.collect::<Vec<_>>();
+			QueueTotals::<Test>::mutate(|totals| for (_, data) in &page {
+				totals.add(data.len(), DmpQueue::estimate_weight(&data[..]));
+			});
+			Pages::<Test>::insert(index.end_used, page);
 			index.end_used += 1;
 			PageIndex::<Test>::put(index);
 		}
@@ -481,6 +950,14 @@ mod tests {
 		(msg(weight), Outcome::Error(XcmError::WeightLimitReached(weight)))
 	}
 
+	fn superuser_msg(weight: Weight) -> Xcm {
+		Xcm::Transact {
+			origin_type: OriginKind::Superuser,
+			require_weight_at_most: weight,
+			call: vec![].into(),
+		}
+	}
+
 	fn pages_queued() -> PageCounter {
 		PageIndex::<Test>::get().end_used - PageIndex::<Test>::get().begin_used
 	}
@@ -722,7 +1199,7 @@ mod tests {
 	fn overweight_should_not_block_queue() {
 		new_test_ext().execute_with(|| {
 			// Set the overweight threshold to 9999.
-			Configuration::<Test>::put(ConfigData { max_individual: 9999 });
+			Configuration::<Test>::put(ConfigData { max_individual: 9999, ..Default::default() });
 
 			let incoming = vec![ msg(1000), msg(10001), msg(1002) ];
 			let weight_used = handle_messages(&incoming, 2500);
@@ -742,7 +1219,7 @@ mod tests {
 	fn overweights_should_be_manually_executable() {
 		new_test_ext().execute_with(|| {
 			// Set the overweight threshold to 9999.
-			Configuration::<Test>::put(ConfigData { max_individual: 9999 });
+			Configuration::<Test>::put(ConfigData { max_individual: 9999, ..Default::default() });
 
 			let incoming = vec![ msg(10000) ];
 			let weight_used = handle_messages(&incoming, 2500);
@@ -787,4 +1264,305 @@ mod tests {
 			assert_eq!(pages_queued(), 1);
 		});
 	}
+
+	#[test]
+	fn enqueue_splits_pages() {
+		new_test_ext().execute_with(|| {
+			Configuration::<Test>::put(ConfigData { max_page_messages: 2, ..Default::default() });
+
+			let incoming = vec![ msg(1000), msg(1001), msg(1002), msg(1003), msg(1004) ];
+			let weight_used = handle_messages(&incoming, 999);
+			assert_eq!(weight_used, 0);
+			assert_eq!(pages_queued(), 3);
+			assert_eq!(Pages::<Test>::get(0).len(), 2);
+			assert_eq!(Pages::<Test>::get(1).len(), 2);
+			assert_eq!(Pages::<Test>::get(2).len(), 1);
+			take_trace();
+
+			// A page holds at least one message, even if it exceeds the byte limit on its own.
+			let size = VersionedXcm::<Call>::from(msg(0)).encode().len() as u32;
+			Configuration::<Test>::put(ConfigData { max_page_bytes: size, ..Default::default() });
+			let weight_used = handle_messages(&[ msg(1005), msg(1006) ], 0);
+			assert_eq!(weight_used, 0);
+			assert_eq!(pages_queued(), 5);
+			assert_eq!(Pages::<Test>::get(3).len(), 1);
+			assert_eq!(Pages::<Test>::get(4).len(), 1);
+
+			let weight_used = handle_messages(&[], 20000);
+			assert_eq!(weight_used, 7021);
+			assert!(queue_is_empty());
+		});
+	}
+
+	#[test]
+	fn queue_status_works() {
+		new_test_ext().execute_with(|| {
+			assert_eq!(DmpQueue::queue_status(10), DmpQueueStatus::default());
+
+			enqueue(&[ msg(1000), msg(1001) ]);
+			enqueue(&[ msg(1002) ]);
+			let status = DmpQueue::queue_status(2);
+			assert_eq!(status.message_count, 3);
+			let size = VersionedXcm::<Call>::from(msg(1000)).encode().len() as u64;
+			assert_eq!(status.total_bytes, 3 * size);
+			assert_eq!(status.first_sent_at, Some(0));
+			assert_eq!(status.last_sent_at, Some(0));
+			assert_eq!(status.previews.len(), 2);
+			let data = VersionedXcm::<Call>::from(msg(1001)).encode();
+			assert_eq!(status.previews[1].id, sp_io::hashing::blake2_256(&data));
+			assert_eq!(status.previews[1].msg, data);
+
+			// The previews are capped.
+			for _ in 0..MAX_QUEUE_PREVIEWS {
+				enqueue(&[ msg(1003) ]);
+			}
+			let status = DmpQueue::queue_status(u32::max_value());
+			assert_eq!(status.message_count, MAX_QUEUE_PREVIEWS + 3);
+			assert_eq!(status.previews.len(), MAX_QUEUE_PREVIEWS as usize);
+
+			handle_messages(&[], 100_000);
+			assert_eq!(DmpQueue::queue_status(10), DmpQueueStatus::default());
+		});
+	}
+
+	#[test]
+	fn outcome_events_work() {
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			Configuration::<Test>::put(ConfigData { max_individual: 9999, ..Default::default() });
+
+			let executed = VersionedXcm::<Call>::from(msg(1000)).encode();
+			let overweight = VersionedXcm::<Call>::from(msg(10000)).encode();
+			let invalid = vec![0xff];
+			let incoming = vec![
+				(5, executed.clone()),
+				(6, overweight.clone()),
+				(7, invalid.clone()),
+			];
+			let weight_used = DmpQueue::handle_dmp_messages(incoming.into_iter(), 5000);
+			assert_eq!(weight_used, 1100);
+
+			let events = System::events().into_iter().map(|r| r.event).collect::<Vec<_>>();
+			assert_eq!(events, vec![
+				Event::DmpQueue(super::Event::ExecutedDownward(
+					sp_io::hashing::blake2_256(&executed),
+					5,
+					1000,
+					Outcome::Complete(1000),
+				)),
+				Event::DmpQueue(super::Event::OverweightEnqueued(
+					sp_io::hashing::blake2_256(&overweight),
+					6,
+					0,
+					10000,
+				)),
+				Event::DmpQueue(super::Event::InvalidFormat(
+					sp_io::hashing::blake2_256(&invalid),
+					7,
+				)),
+			]);
+		});
+	}
+
+	#[test]
+	fn purge_pages_works() {
+		new_test_ext().execute_with(|| {
+			enqueue(&[ msg(1000), msg(1001) ]);
+			enqueue(&[ msg(1002) ]);
+			enqueue(&[ msg(1003) ]);
+
+			assert_noop!(DmpQueue::purge_pages(Origin::signed(1), 0, 1), BadOrigin);
+			assert_noop!(DmpQueue::purge_pages(Origin::root(), 1, 1), Error::<Test>::BadPageRange);
+			assert_noop!(DmpQueue::purge_pages(Origin::root(), 2, 4), Error::<Test>::BadPageRange);
+
+			// A page in the middle of the queue is left empty.
+			assert_ok!(DmpQueue::purge_pages(Origin::root(), 1, 2));
+			assert_eq!(pages_queued(), 3);
+			assert!(Pages::<Test>::get(1).is_empty());
+
+			assert_ok!(DmpQueue::purge_pages(Origin::root(), 0, 1));
+			assert_eq!(pages_queued(), 2);
+
+			let weight_used = handle_messages(&[], 10000);
+			assert_eq!(weight_used, 1003);
+			assert_eq!(take_trace(), vec![ msg_complete(1003) ]);
+			assert!(queue_is_empty());
+		});
+	}
+
+	#[test]
+	fn undecodable_pages_can_be_purged() {
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			enqueue(&[ msg(1000) ]);
+			enqueue(&[ msg(1001) ]);
+			enqueue(&[ msg(1002) ]);
+			let raw_page = vec![0xff; 4];
+			frame_support::storage::unhashed::put_raw(&Pages::<Test>::hashed_key_for(0), &raw_page);
+
+			assert_ok!(DmpQueue::purge_pages(Origin::root(), 0, 1));
+			assert_eq!(pages_queued(), 2);
+			assert!(!Pages::<Test>::contains_key(0));
+			assert!(System::events().into_iter().any(|r| r.event == Event::DmpQueue(
+				super::Event::UndecodablePagePurged(0, sp_io::hashing::blake2_256(&raw_page)),
+			)));
+			// The size of the purged messages is unknown.
+			assert_eq!(DmpQueue::queue_totals(), QueueTotalsData::default());
+
+			// The totals are known again once the queue is empty.
+			assert_ok!(DmpQueue::purge_pages(Origin::root(), 1, 3));
+			assert!(queue_is_empty());
+			assert_eq!(DmpQueue::queue_totals(), QueueTotalsData::default());
+		});
+	}
+
+	#[test]
+	fn priority_messages_are_executed_first() {
+		new_test_ext().execute_with(|| {
+			enqueue(&[ msg(1000) ]);
+
+			let incoming = vec![ msg(1001), superuser_msg(1002), superuser_msg(1003) ];
+			let weight_used = handle_messages(&incoming, 1500);
+			assert_eq!(weight_used, 1002);
+			assert_eq!(take_trace(), vec![
+				(superuser_msg(1002), Outcome::Complete(1002)),
+				(superuser_msg(1003), Outcome::Error(XcmError::WeightLimitReached(1003))),
+				msg_limit_reached(1000),
+			]);
+			assert_eq!(pages_queued(), 2);
+			// The priority message that didn't fit is queued before the other incoming message.
+			let encode = |xcm: Xcm| (0, VersionedXcm::<Call>::from(xcm).encode());
+			assert_eq!(
+				Pages::<Test>::get(1),
+				vec![ encode(superuser_msg(1003)), encode(msg(1001)) ],
+			);
+
+			let weight_used = handle_messages(&[], 5000);
+			assert_eq!(weight_used, 3004);
+			assert_eq!(take_trace(), vec![
+				msg_complete(1000),
+				(superuser_msg(1003), Outcome::Complete(1003)),
+				msg_complete(1001),
+			]);
+			assert!(queue_is_empty());
+		});
+	}
+
+	#[test]
+	fn queue_totals_are_tracked() {
+		new_test_ext().execute_with(|| {
+			let size = VersionedXcm::<Call>::from(msg(1000)).encode().len() as u64;
+
+			let incoming = vec![ msg(1000), msg(1001), msg(1002) ];
+			handle_messages(&incoming, 999);
+			assert_eq!(DmpQueue::queue_totals(), QueueTotalsData {
+				message_count: 3,
+				total_bytes: 3 * size,
+				estimated_weight: 3003,
+			});
+
+			handle_messages(&[], 2001);
+			assert_eq!(DmpQueue::queue_totals(), QueueTotalsData {
+				message_count: 1,
+				total_bytes: size,
+				estimated_weight: 1002,
+			});
+
+			assert_ok!(DmpQueue::purge_pages(Origin::root(), 0, 1));
+			assert_eq!(DmpQueue::queue_totals(), QueueTotalsData::default());
+		});
+	}
+
+	#[test]
+	fn queue_totals_are_migrated() {
+		new_test_ext().execute_with(|| {
+			let size = VersionedXcm::<Call>::from(msg(1000)).encode().len() as u64;
+			// The pages of a chain that didn't track the totals.
+			enqueue(&[ msg(1000), msg(1001) ]);
+			enqueue(&[ msg(1002) ]);
+			QueueTotals::<Test>::kill();
+
+			DmpQueue::on_runtime_upgrade();
+			assert_eq!(StorageVersion::<Test>::get(), Releases::V1);
+			assert_eq!(DmpQueue::queue_totals(), QueueTotalsData {
+				message_count: 3,
+				total_bytes: 3 * size,
+				estimated_weight: 3003,
+			});
+
+			// Later upgrades don't sum up the pages again.
+			QueueTotals::<Test>::kill();
+			DmpQueue::on_runtime_upgrade();
+			assert_eq!(DmpQueue::queue_totals(), QueueTotalsData::default());
+		});
+	}
+
+	#[test]
+	fn messages_per_block_are_capped() {
+		new_test_ext().execute_with(|| {
+			Configuration::<Test>::put(ConfigData {
+				max_messages_per_block: 2,
+				..Default::default()
+			});
+
+			let incoming = vec![ msg(1000), msg(1001), msg(1002) ];
+			let weight_used = handle_messages(&incoming, 10000);
+			assert_eq!(weight_used, 2001);
+			assert_eq!(take_trace(), vec![ msg_complete(1000), msg_complete(1001) ]);
+			assert_eq!(pages_queued(), 1);
+
+			// The cap is reached for this block.
+			let weight_used = DmpQueue::on_idle(1, 10000);
+			assert_eq!(weight_used, 0);
+			assert!(take_trace().is_empty());
+
+			// The remaining message is executed in the next block.
+			DmpQueue::on_initialize(2);
+			let weight_used = DmpQueue::on_idle(2, 10000);
+			assert_eq!(weight_used, 1002);
+			assert_eq!(take_trace(), vec![ msg_complete(1002) ]);
+			assert!(queue_is_empty());
+		});
+	}
+
+	#[test]
+	fn config_can_be_updated_by_root() {
+		new_test_ext().execute_with(|| {
+			assert_noop!(DmpQueue::update_max_page_messages(Origin::signed(1), 2), BadOrigin);
+			assert_noop!(DmpQueue::update_max_page_bytes(Origin::signed(1), 2), BadOrigin);
+			assert_noop!(DmpQueue::update_max_messages_per_block(Origin::signed(1), 2), BadOrigin);
+
+			assert_ok!(DmpQueue::update_max_page_messages(Origin::root(), 2));
+			assert_ok!(DmpQueue::update_max_page_bytes(Origin::root(), 1024));
+			assert_ok!(DmpQueue::update_max_messages_per_block(Origin::root(), 3));
+			assert_eq!(Configuration::<Test>::get(), ConfigData {
+				max_page_messages: 2,
+				max_page_bytes: 1024,
+				max_messages_per_block: 3,
+				..Default::default()
+			});
+
+			let incoming = vec![ msg(1000), msg(1001), msg(1002) ];
+			handle_messages(&incoming, 999);
+			assert_eq!(pages_queued(), 2);
+		});
+	}
+
+	#[test]
+	fn invalid_messages_are_handled() {
+		new_test_ext().execute_with(|| {
+			let incoming = vec![
+				(5, vec![0xff]),
+				(6, VersionedXcm::<Call>::from(msg(1000)).encode()),
+				(7, vec![0xff, 0x01]),
+			];
+			let weight_used = DmpQueue::handle_dmp_messages(incoming.into_iter(), 5000);
+			assert_eq!(weight_used, 1200);
+			assert_eq!(take_trace(), vec![ msg_complete(1000) ]);
+			assert_eq!(
+				INVALID.with(|q| q.borrow().clone()),
+				vec![ (5, vec![0xff]), (7, vec![0xff, 0x01]) ],
+			);
+		});
+	}
 }
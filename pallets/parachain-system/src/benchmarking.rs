@@ -0,0 +1,200 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Benchmarks of the parachain-system pallet.
+//!
+//! The receiving of messages is benchmarked while the inbound message execution is paused. This
+//! measures the work of this pallet, the MQC hashing and the queueing of the messages, without
+//! the work of the message handlers. Queueing the messages is more expensive than passing them
+//! to a handler, so this is an upper bound.
+//!
+//! `set_validation_data` itself is not benchmarked, as this requires a relay chain state proof
+//! that can not be created in the runtime.
+
+use super::*;
+use frame_benchmarking::{benchmarks, impl_benchmark_test_suite};
+use frame_system::RawOrigin;
+
+/// The size of the messages used in the benchmarks.
+const MESSAGE_SIZE: usize = 1024;
+
+/// The maximum number of messages received in one block.
+const MAX_MESSAGES: u32 = 1000;
+
+/// The maximum size of the validation code.
+const MAX_CODE_SIZE: u32 = 3 * 1024 * 1024;
+
+/// Put the validation data and the host configuration that allow an upgrade and sending upward
+/// messages.
+fn set_up_upgrade<T: Config>() {
+	ValidationData::<T>::put(PersistedValidationData {
+		relay_parent_number: 100,
+		..Default::default()
+	});
+	HostConfiguration::<T>::put(AbridgedHostConfiguration {
+		max_code_size: MAX_CODE_SIZE,
+		max_head_data_size: 32 * 1024,
+		max_upward_queue_count: 8,
+		max_upward_queue_size: 1024 * 1024,
+		max_upward_message_size: 4 * 1024,
+		max_upward_message_num_per_candidate: 2,
+		hrmp_max_message_num_per_candidate: 2,
+		validation_upgrade_frequency: 10,
+		validation_upgrade_delay: 10,
+	});
+}
+
+benchmarks! {
+	authorize_upgrade {
+		let code_hash = T::Hashing::hash(&[1u8; 32]);
+	}: _(RawOrigin::Root, code_hash)
+	verify {
+		assert_eq!(AuthorizedUpgrade::<T>::get(), Some(code_hash));
+	}
+
+	enact_authorized_upgrade {
+		let c in 1 .. MAX_CODE_SIZE;
+		let code = vec![0; c as usize];
+		AuthorizedUpgrade::<T>::put(T::Hashing::hash(&code));
+		set_up_upgrade::<T>();
+	}: _(RawOrigin::None, code)
+	verify {
+		assert!(PendingValidationFunction::<T>::exists());
+	}
+
+	set_reserved_dmp_weight_override {
+	}: _(RawOrigin::Root, Some(1_000))
+	verify {
+		assert_eq!(ReservedDmpWeightOverride::<T>::get(), Some(1_000));
+	}
+
+	set_reserved_xcmp_weight_override {
+	}: _(RawOrigin::Root, Some(1_000))
+	verify {
+		assert_eq!(ReservedXcmpWeightOverride::<T>::get(), Some(1_000));
+	}
+
+	set_inbound_messages_paused {
+	}: _(RawOrigin::Root, true)
+	verify {
+		assert!(InboundMessagesPaused::<T>::get());
+	}
+
+	drop_paused_message {
+		// The horizontal messages of one sender need to be searched for the message, so this is
+		// more expensive than dropping a downward message.
+		let sender = ParaId::from(2000);
+		let messages = (0..MAX_MESSAGES)
+			.map(|i| {
+				(sender, InboundHrmpMessage { sent_at: i, data: vec![0; MESSAGE_SIZE] })
+			})
+			.collect::<Vec<_>>();
+		PausedHorizontalMessages::<T>::put(messages);
+	}: _(RawOrigin::Root, InboundChannel::Horizontal(sender), MAX_MESSAGES - 1)
+	verify {
+		assert_eq!(PausedHorizontalMessages::<T>::get().len(), MAX_MESSAGES as usize - 1);
+	}
+
+	force_set_mqc_head {
+		let sender = ParaId::from(2000);
+		let head = relay_chain::Hash::repeat_byte(1);
+	}: _(RawOrigin::Root, InboundChannel::Horizontal(sender), head)
+	verify {
+		assert_eq!(LastHrmpMqcHeads::<T>::get().get(&sender).map(|mqc| mqc.head()), Some(head));
+	}
+
+	force_open_hrmp_channel_request {
+		set_up_upgrade::<T>();
+		let origin = T::HrmpChannelManagerOrigin::successful_origin();
+	}: _<T::Origin>(origin, ParaId::from(2000), 8, 1024)
+	verify {
+		assert_eq!(PendingUpwardMessages::<T>::get().len(), 1);
+	}
+
+	accept_open_channel {
+		set_up_upgrade::<T>();
+		let origin = T::HrmpChannelManagerOrigin::successful_origin();
+	}: _<T::Origin>(origin, ParaId::from(2000))
+	verify {
+		assert_eq!(PendingUpwardMessages::<T>::get().len(), 1);
+	}
+
+	close_channel {
+		set_up_upgrade::<T>();
+		let origin = T::HrmpChannelManagerOrigin::successful_origin();
+	}: _<T::Origin>(origin, T::SelfParaId::get(), ParaId::from(2000))
+	verify {
+		assert_eq!(PendingUpwardMessages::<T>::get().len(), 1);
+	}
+
+	receive_downward_messages {
+		let n in 0 .. MAX_MESSAGES;
+		InboundMessagesPaused::<T>::put(true);
+
+		let messages = (0..n)
+			.map(|i| InboundDownwardMessage {
+				sent_at: i,
+				msg: vec![0; MESSAGE_SIZE],
+			})
+			.collect::<Vec<_>>();
+		let mut dmq_head = LastDmqMqcHead::<T>::get();
+		for message in &messages {
+			dmq_head.extend_downward(message);
+		}
+	}: {
+		Pallet::<T>::process_inbound_downward_messages(dmq_head.head(), messages);
+	}
+	verify {
+		assert_eq!(PausedDownwardMessages::<T>::get().len(), n as usize);
+	}
+
+	receive_horizontal_messages {
+		let n in 0 .. MAX_MESSAGES;
+		InboundMessagesPaused::<T>::put(true);
+
+		let sender = ParaId::from(2000);
+		let messages = (0..n)
+			.map(|i| InboundHrmpMessage {
+				sent_at: i,
+				data: vec![0; MESSAGE_SIZE],
+			})
+			.collect::<Vec<_>>();
+		let mut mqc = MessageQueueChain::default();
+		for message in &messages {
+			mqc.extend_hrmp(message);
+		}
+		let ingress_channels = vec![(
+			sender,
+			cumulus_primitives_core::AbridgedHrmpChannel {
+				max_capacity: MAX_MESSAGES,
+				max_total_size: MAX_MESSAGES * MESSAGE_SIZE as u32,
+				max_message_size: MESSAGE_SIZE as u32,
+				msg_count: n,
+				total_size: n * MESSAGE_SIZE as u32,
+				mqc_head: Some(mqc.head()),
+			},
+		)];
+		let mut horizontal_messages = BTreeMap::new();
+		horizontal_messages.insert(sender, messages);
+	}: {
+		Pallet::<T>::process_inbound_horizontal_messages(&ingress_channels, horizontal_messages);
+	}
+	verify {
+		assert_eq!(PausedHorizontalMessages::<T>::get().len(), n as usize);
+	}
+}
+
+impl_benchmark_test_suite!(Pallet, crate::tests::new_test_ext(), crate::tests::Test);
@@ -27,42 +27,56 @@
 //!
 //! Users must ensure that they register this pallet as an inherent provider.
 
+use codec::{Decode, Encode};
 use cumulus_primitives_core::{
 	relay_chain,
 	well_known_keys::{self, NEW_VALIDATION_CODE},
-	AbridgedHostConfiguration, ChannelStatus, DmpMessageHandler, GetChannelInfo,
-	InboundDownwardMessage, InboundHrmpMessage, MessageSendError, OnValidationData,
-	OutboundHrmpMessage, ParaId, PersistedValidationData, UpwardMessage, UpwardMessageSender,
-	XcmpMessageHandler, XcmpMessageSource,
+	AbridgedHostConfiguration, ChannelInfo, ChannelStatus, CollationInfo, CumulusDigestItem,
+	DmpMessageHandler, GetChannelInfo, HeadDataExtension, InboundDownwardMessage,
+	InboundHrmpMessage, MessageSendError, OutboundHrmpMessage, ParaId, ParaLifecycle,
+	PersistedValidationData, RelayChainState, RelaychainStateProvider, UpgradeGoAhead,
+	UpwardMessage, UpwardMessageFeeHint, UpwardMessageSender, XcmpMessageHandler,
+	XcmpMessageSource, MAX_HEAD_DATA_EXTENSION_SIZE,
 };
 use cumulus_primitives_parachain_inherent::ParachainInherentData;
 use frame_support::{
 	ensure,
 	dispatch::{DispatchError, DispatchResult},
 	storage,
-	traits::Get,
+	traits::{EnsureOrigin, Get, Randomness, ReservableCurrency},
 	weights::{PostDispatchInfo, Weight, Pays},
 	inherent::{InherentData, InherentIdentifier, ProvideInherent},
 };
 use frame_system::{ensure_none, ensure_root};
 use polkadot_parachain::primitives::RelayChainBlockNumber;
-use relay_state_snapshot::MessagingStateSnapshot;
+use relay_state_snapshot::{MessagingStateSnapshot, RelayChainStateProof};
+use unincluded_segment::{Ancestor, UsedBandwidth};
 use sp_runtime::{
-	traits::{BlakeTwo256, Hash},
+	traits::{BlakeTwo256, BlockNumberProvider, Hash, Header as HeaderT, Saturating},
 	transaction_validity::{
 		InvalidTransaction, TransactionLongevity, TransactionSource, TransactionValidity,
 		ValidTransaction,
 	},
+	RuntimeDebug,
 };
 use sp_std::{cmp, collections::btree_map::BTreeMap, prelude::*};
+use xcm::{
+	v0::{OriginKind, Xcm},
+	VersionedXcm,
+};
 
 mod relay_state_snapshot;
+mod unincluded_segment;
 #[macro_use]
 pub mod validate_block;
+pub mod weights;
 #[cfg(test)]
 mod tests;
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking;
 
 pub use pallet::*;
+pub use weights::WeightInfo;
 
 #[frame_support::pallet]
 pub mod pallet {
@@ -78,30 +92,151 @@ pub mod pallet {
 		/// The overarching event type.
 		type Event: From<Event<Self>> + IsType<<Self as frame_system::Config>::Event>;
 
-		/// Something which can be notified when the validation data is set.
-		type OnValidationData: OnValidationData;
+		/// Something which can be notified when the validation data is set or a validation code
+		/// upgrade was applied.
+		type OnSystemEvent: OnSystemEvent;
+
+		/// Something which is notified when the relay chain session changes.
+		type OnRelaySessionChange: OnRelaySessionChange;
+
+		/// Something which is notified when the relay chain starts to downgrade or to offboard
+		/// this parachain.
+		type OnOffboarding: OnOffboarding;
 
 		/// Returns the parachain ID we are running with.
 		type SelfParaId: Get<ParaId>;
 
+		/// Checks the relay parent number of a block against the relay parent number of its
+		/// parent block.
+		///
+		/// See [`CheckAssociatedRelayNumber`] for the provided implementations.
+		type CheckAssociatedRelayNumber: CheckAssociatedRelayNumber;
+
+		/// The number of relay chain blocks between the relay parents of two consecutive blocks
+		/// above which a gap in the validation data is reported.
+		///
+		/// A gap is reported with [`Event::ValidationDataGap`] and
+		/// [`OnSystemEvent::on_validation_data_gap`]. A threshold of zero disables the detection.
+		type ValidationDataGapThreshold: Get<RelayChainBlockNumber>;
+
+		/// Decides if a block may be built on its relay parent.
+		///
+		/// This controls the block velocity of the parachain, i.e. how many blocks can be built
+		/// on top of the same relay parent, and how many blocks can be built ahead of the last
+		/// block included on the relay chain. See [`FixedVelocityConsensusHook`]. When more than
+		/// one block per relay parent is allowed, `CheckAssociatedRelayNumber` must accept a
+		/// relay parent number that doesn't increase.
+		type ConsensusHook: ConsensusHook;
+
 		/// The place where outbound XCMP messages come from. This is queried in `finalize_block`.
 		type OutboundXcmpMessageSource: XcmpMessageSource;
 
 		/// The message handler that will be invoked when messages are received via DMP.
+		///
+		/// All downward messages of the inherent are passed to the handler, together with the
+		/// weight that is reserved for processing them. The MQC head is advanced for every message,
+		/// independent of the message being executed or not. So, the handler is responsible for
+		/// respecting the weight limit and for storing any message it doesn't execute right away.
+		/// See `cumulus-pallet-dmp-queue` for a handler that carries the remaining messages over to
+		/// the following blocks.
 		type DmpMessageHandler: DmpMessageHandler;
 
 		/// The weight we reserve at the beginning of the block for processing DMP messages.
+		///
+		/// This can be overridden at runtime by `ReservedDmpWeightOverride`.
 		type ReservedDmpWeight: Get<Weight>;
 
 		/// The message handler that will be invoked when messages are received via XCMP.
 		///
 		/// The messages are dispatched in the order they were relayed by the relay chain. If
 		/// multiple messages were relayed at one block, these will be dispatched in ascending
-		/// order of the sender's para ID.
+		/// order of the sender's para ID, unless `InboundMessagesOrder` changes the order.
 		type XcmpMessageHandler: XcmpMessageHandler;
 
 		/// The weight we reserve at the beginning of the block for processing XCMP messages.
+		///
+		/// This can be overridden at runtime by `ReservedXcmpWeightOverride`.
 		type ReservedXcmpWeight: Get<Weight>;
+
+		/// The maximum number of horizontal messages that are passed to the `XcmpMessageHandler`
+		/// in one block.
+		///
+		/// This keeps the work per block predictable when many cheap messages are received. The
+		/// messages above the limit are deferred and passed to the handler in the next blocks,
+		/// before the newly received messages. A limit of zero disables the limit.
+		type MaxHorizontalMessagesPerBlock: Get<u32>;
+
+		/// The maximum number of horizontal messages that are deferred because they exceeded
+		/// `MaxHorizontalMessagesPerBlock`.
+		///
+		/// If more messages would need to be deferred, more messages than
+		/// `MaxHorizontalMessagesPerBlock` are passed to the handler.
+		type MaxDeferredHorizontalMessages: Get<u32>;
+
+		/// The maximum number of downward messages and the maximum number of horizontal messages
+		/// that are queued while the inbound message execution is paused.
+		///
+		/// The messages can not be left on the relay chain, as every block needs to receive all
+		/// messages up to its relay parent. So the messages that don't fit into a full queue are
+		/// dropped without being executed, see [`Event::PausedMessagesOverflowed`].
+		type MaxPausedMessages: Get<u32>;
+
+		/// Whether a mismatch between the message queue chain heads of the relay chain and the
+		/// heads computed from the received messages is tolerated.
+		///
+		/// By default, a mismatch makes the block invalid. If this is `true`, the mismatch is
+		/// reported with [`Event::DownwardMqcHeadMismatch`] or
+		/// [`Event::HorizontalMqcHeadMismatch`] instead and the head of the relay chain is
+		/// adopted. The received messages are still passed to the message handlers, so this
+		/// should only be enabled to recover from a diverged head.
+		type LenientMqcHeadCheck: Get<bool>;
+
+		/// The order in which the inbound downward and horizontal messages are passed to their
+		/// message handlers. See [`InboundMessagesOrder`].
+		type InboundMessagesOrder: InboundMessagesOrder;
+
+		/// Additional relay chain storage keys that are read from the relay chain state proof of
+		/// every block.
+		///
+		/// The values can be accessed with [`Pallet::relay_state_entry`]. The collator queries the
+		/// keys through the `RelayStateKeysApi` at the parent block, so the keys should only depend
+		/// on the state of the parent block.
+		type AdditionalRelayStateKeys: Get<Vec<Vec<u8>>>;
+
+		/// The origin that is allowed to manage the HRMP channels of this parachain on the relay
+		/// chain.
+		type HrmpChannelManagerOrigin: EnsureOrigin<Self::Origin>;
+
+		/// The index of the HRMP pallet in the relay chain runtime.
+		type RelayHrmpPalletIndex: Get<u8>;
+
+		/// The maximum weight that the relay chain may use to dispatch an HRMP channel call.
+		type RelayHrmpCallWeight: Get<Weight>;
+
+		/// The number of bytes of the PoV the collator keeps free while a validation code upgrade
+		/// is authorized or pending.
+		///
+		/// The runtime signals the reserve through a [`CumulusDigestItem::ReservePovSize`] in the
+		/// header. This leaves room in the PoV for the validation code, which is not accounted for
+		/// by the transactions that are included next to it.
+		type UpgradePovReserve: Get<u32>;
+
+		/// The extension that is appended to the head data of every block, if any.
+		///
+		/// This is queried in `on_finalize`, so it can depend on everything that happened in the
+		/// block. Extensions with a payload larger than [`MAX_HEAD_DATA_EXTENSION_SIZE`] or that
+		/// don't fit into the head data are dropped.
+		type HeadDataExtensionProvider: Get<Option<HeadDataExtension>>;
+
+		/// The deposit that is taken from local senders for the upward messages they queue with
+		/// [`Pallet::send_upward_message_from`].
+		///
+		/// The deposit is returned once the message leaves the queue. See
+		/// [`ReserveDepositPerByte`].
+		type QueuedMessageDeposit: QueuedMessageDeposit<Self::AccountId>;
+
+		/// Weight information for the extrinsics and the message processing of this pallet.
+		type WeightInfo: WeightInfo;
 	}
 
 	#[pallet::hooks]
@@ -109,6 +244,19 @@ pub mod pallet {
 		fn on_finalize(_: T::BlockNumber) {
 			<DidSetValidationCode<T>>::kill();
 
+			let upgrade_pov_reserve = T::UpgradePovReserve::get();
+			if upgrade_pov_reserve > 0 &&
+				(<AuthorizedUpgrade<T>>::exists() || <PendingRelayChainBlockNumber<T>>::exists())
+			{
+				frame_system::Pallet::<T>::deposit_log(
+					CumulusDigestItem::ReservePovSize(upgrade_pov_reserve).to_digest_item(),
+				);
+			}
+
+			if let Some(extension) = T::HeadDataExtensionProvider::get() {
+				Self::store_head_data_extension(extension);
+			}
+
 			let host_config = match Self::host_configuration() {
 				Some(ok) => ok,
 				None => {
@@ -132,7 +280,31 @@ pub mod pallet {
 					}
 				};
 
-			<PendingUpwardMessages<T>>::mutate(|up| {
+			let (discarded, mut used_bandwidth) = <PendingUpwardMessages<T>>::mutate(|up| {
+				// Messages that were buffered under an older host configuration may not fit
+				// anymore. These can never be sent and would make the candidate invalid, so they
+				// are discarded instead of blocking all the messages behind them.
+				//
+				// TODO: #274 Return back messages that do not longer fit into the queue.
+				let max_upward_message_size = host_config.max_upward_message_size as usize;
+				let buffered = up.len();
+				// The indices of the messages that leave the queue and of those that are kept.
+				let mut next_index = <PendingUpwardMessagesFront<T>>::get();
+				let mut left_indices = Vec::new();
+				let mut kept_indices = Vec::new();
+				up.retain(|msg| {
+					let index = next_index;
+					next_index = next_index.wrapping_add(1);
+					if msg.len() <= max_upward_message_size {
+						kept_indices.push(index);
+						true
+					} else {
+						left_indices.push(index);
+						false
+					}
+				});
+				let discarded = (buffered - up.len()) as u32;
+
 				let (count, size) = relevant_messaging_state.relay_dispatch_queue_size;
 
 				let available_capacity = cmp::min(
@@ -160,11 +332,28 @@ pub mod pallet {
 					)
 					.count();
 
-				// TODO: #274 Return back messages that do not longer fit into the queue.
-
+				// The messages that don't fit are kept in order and sent with the next blocks.
 				storage::unhashed::put(well_known_keys::UPWARD_MESSAGES, &up[0..num]);
+				let used_bandwidth = UsedBandwidth::from_messages(&up[0..num], &[]);
 				*up = up.split_off(num);
+
+				<PendingUpwardMessagesSize<T>>::put((
+					up.len() as u32,
+					up.iter().map(|msg| msg.len() as u32).sum::<u32>(),
+				));
+				left_indices.extend(kept_indices.drain(..num));
+				Self::settle_upward_message_deposits(next_index, left_indices, kept_indices);
+
+				(discarded, used_bandwidth)
 			});
+			if discarded > 0 {
+				log::warn!(
+					target: "runtime::parachain-system",
+					"Discarded {} upward messages exceeding the maximum upward message size",
+					discarded,
+				);
+				Self::deposit_event(Event::UpwardMessagesDiscarded(discarded));
+			}
 
 			// Sending HRMP messages is a little bit more involved. There are the following
 			// constraints:
@@ -196,6 +385,17 @@ pub mod pallet {
 			};
 
 			storage::unhashed::put(well_known_keys::HRMP_OUTBOUND_MESSAGES, &outbound_messages);
+
+			// This block is not included on the relay chain yet. Its hash is added by the next
+			// block.
+			used_bandwidth.append(&UsedBandwidth::from_messages(&[], &outbound_messages));
+			if let Some(vfp) = Self::validation_data() {
+				<UnincludedSegment<T>>::append(Ancestor {
+					used_bandwidth,
+					para_head_hash: None,
+					relay_parent_number: vfp.relay_parent_number,
+				});
+			}
 		}
 
 		fn on_initialize(_n: T::BlockNumber) -> Weight {
@@ -209,10 +409,11 @@ pub mod pallet {
 			// Remove the validation from the old block.
 			<ValidationData<T>>::kill();
 
-			let mut weight = T::DbWeight::get().writes(3);
+			let mut weight = T::DbWeight::get().writes(4);
 			storage::unhashed::kill(well_known_keys::HRMP_WATERMARK);
 			storage::unhashed::kill(well_known_keys::UPWARD_MESSAGES);
 			storage::unhashed::kill(well_known_keys::HRMP_OUTBOUND_MESSAGES);
+			storage::unhashed::kill(well_known_keys::HEAD_DATA_EXTENSION);
 
 			// Here, in `on_initialize` we must report the weight for both `on_initialize` and
 			// `on_finalize`.
@@ -238,10 +439,17 @@ pub mod pallet {
 				.unwrap_or(0);
 			<AnnouncedHrmpMessagesPerCandidate<T>>::put(hrmp_max_message_num_per_candidate);
 
+			// Every sent upward message may have a deposit that is returned.
+			let ump_max_message_num_per_candidate = Self::host_configuration()
+				.map(|cfg| cfg.max_upward_message_num_per_candidate)
+				.unwrap_or(0);
+
 			// NOTE that the actual weight consumed by `on_finalize` may turn out lower.
 			weight += T::DbWeight::get().reads_writes(
-				3 + hrmp_max_message_num_per_candidate as u64,
-				4 + hrmp_max_message_num_per_candidate as u64,
+				7 + hrmp_max_message_num_per_candidate as u64 +
+					ump_max_message_num_per_candidate as u64,
+				6 + hrmp_max_message_num_per_candidate as u64 +
+					ump_max_message_num_per_candidate as u64,
 			);
 
 			weight
@@ -300,47 +508,207 @@ pub mod pallet {
 
 			Self::validate_validation_data(&vfp);
 
+			let previous_relay_parent_number = <LastRelayChainState<T>>::get().map(|s| s.number);
+			if let Some(previous) = previous_relay_parent_number {
+				T::CheckAssociatedRelayNumber::check_associated_relay_number(
+					vfp.relay_parent_number,
+					previous,
+				);
+			}
+
+			// Make the relay parent known to everyone that only has the header of the block.
+			frame_system::Pallet::<T>::deposit_log(
+				CumulusDigestItem::RelayParentInfo {
+					number: vfp.relay_parent_number,
+					storage_root: vfp.relay_parent_storage_root,
+				}
+				.to_digest_item(),
+			);
+
+			let relay_state_proof = RelayChainStateProof::new(
+				T::SelfParaId::get(),
+				vfp.relay_parent_storage_root,
+				relay_chain_state,
+			)
+			.expect("Invalid relay chain state proof");
+
+			let host_config = relay_state_proof
+				.read_abridged_host_configuration()
+				.expect("Invalid host configuration in relay chain state proof");
+
+			// The relay chain rejects candidates with a head data, aka the encoded header, that is
+			// larger than `max_head_data_size`. The roots are not known yet, but have a fixed size.
+			// The digest items that are added later, like the seal, are not accounted for. The
+			// collator needs to leave room for them.
+			ensure!(
+				Self::head_data_size_estimate() <= host_config.max_head_data_size as usize,
+				Error::<T>::HeadDataTooLarge,
+			);
+			let mut relevant_messaging_state = relay_state_proof
+				.read_messaging_state_snapshot()
+				.expect("Invalid messaging state in relay chain state proof");
+			let current_epoch_randomness = relay_state_proof
+				.read_current_epoch_randomness()
+				.expect("Invalid epoch randomness in relay chain state proof");
+			let current_epoch_start = relay_state_proof
+				.read_current_epoch_start()
+				.expect("Invalid epoch start in relay chain state proof");
+			let current_session_index = relay_state_proof
+				.read_current_session_index()
+				.expect("Invalid session index in relay chain state proof");
+			let relay_slot = relay_state_proof
+				.read_current_slot()
+				.expect("Invalid slot in relay chain state proof");
+			let relay_state_entries = T::AdditionalRelayStateKeys::get()
+				.into_iter()
+				.filter_map(|key| {
+					relay_state_proof
+						.read_raw_entry(&key)
+						.expect("Invalid additional entry in relay chain state proof")
+						.map(|value| (key, value))
+				})
+				.collect::<BTreeMap<_, _>>();
+			let upgrade_go_ahead_signal = relay_state_proof
+				.read_upgrade_go_ahead_signal()
+				.expect("Invalid upgrade go-ahead signal in relay chain state proof");
+			let included_para_head = relay_state_proof
+				.read_included_para_head()
+				.expect("Invalid para head in relay chain state proof");
+			let para_lifecycle = relay_state_proof
+				.read_para_lifecycle()
+				.expect("Invalid para lifecycle in relay chain state proof");
+
+			let mut unincluded_segment = <UnincludedSegment<T>>::get();
+			// The hash of the parent is only known now that its header is finalized.
+			if let Some(parent) = unincluded_segment.last_mut() {
+				parent.para_head_hash = Some(frame_system::Pallet::<T>::parent_hash());
+			}
+			if let Some(head) = included_para_head {
+				// The head data may contain a `HeadDataExtension` after the header, so the hash
+				// of the block is the hash of the decoded header.
+				let included_header = T::Header::decode(&mut &head.0[..])
+					.expect("Invalid para head in relay chain state proof");
+				unincluded_segment::remove_included(
+					&mut unincluded_segment,
+					&included_header.hash(),
+				);
+			}
+
+			let blocks_on_relay_parent = match previous_relay_parent_number {
+				Some(previous) if previous == vfp.relay_parent_number => {
+					<BlocksOnRelayParent<T>>::get().saturating_add(1)
+				}
+				_ => 1,
+			};
+			let consensus_context = ConsensusHookContext {
+				relay_parent_number: vfp.relay_parent_number,
+				relay_slot,
+				blocks_on_relay_parent,
+				unincluded_segment_len: unincluded_segment.len() as u32,
+			};
+			if !T::ConsensusHook::can_build_block(&consensus_context) {
+				panic!("Block is not allowed by the consensus hook: {:?}", consensus_context);
+			}
+			T::ConsensusHook::on_block_accepted(&consensus_context);
+			<BlocksOnRelayParent<T>>::put(blocks_on_relay_parent);
+
+			// The relay chain doesn't know about the messages of the unincluded ancestors yet.
+			unincluded_segment::total_used_bandwidth(&unincluded_segment)
+				.apply_to(&mut relevant_messaging_state);
+			<UnincludedSegment<T>>::put(unincluded_segment);
+
 			// initialization logic: we know that this runs exactly once every block,
 			// which means we can put the initialization logic here to remove the
 			// sequencing problem.
 			if let Some(apply_block) = <PendingRelayChainBlockNumber<T>>::get() {
-				if vfp.relay_parent_number >= apply_block {
-					<PendingRelayChainBlockNumber<T>>::kill();
-					let validation_function = <PendingValidationFunction<T>>::take();
-					<LastUpgrade<T>>::put(&apply_block);
-					Self::put_parachain_code(&validation_function);
-					Self::deposit_event(Event::ValidationFunctionApplied(vfp.relay_parent_number));
+				match upgrade_go_ahead_signal {
+					Some(UpgradeGoAhead::Abort) => {
+						<PendingRelayChainBlockNumber<T>>::kill();
+						<PendingValidationFunction<T>>::kill();
+						Self::deposit_event(Event::ValidationFunctionDiscarded);
+					}
+					Some(UpgradeGoAhead::GoAhead) => {
+						Self::apply_pending_validation_function(apply_block, vfp.relay_parent_number)
+					}
+					// Relay chains that don't send a go-ahead signal expect the upgrade to be
+					// applied as of `apply_block`.
+					None if vfp.relay_parent_number >= apply_block => {
+						Self::apply_pending_validation_function(apply_block, vfp.relay_parent_number)
+					}
+					None => {}
 				}
 			}
 
-			let (host_config, relevant_messaging_state) =
-				match relay_state_snapshot::extract_from_proof(
-					T::SelfParaId::get(),
-					vfp.relay_parent_storage_root,
-					relay_chain_state,
-				) {
-					Ok(r) => r,
-					Err(err) => {
-						panic!("invalid relay chain merkle proof: {:?}", err);
-					}
-				};
-
 			<ValidationData<T>>::put(&vfp);
+			<LastRelayChainState<T>>::put(RelayChainState {
+				number: vfp.relay_parent_number,
+				state_root: vfp.relay_parent_storage_root,
+			});
 			<RelevantMessagingState<T>>::put(relevant_messaging_state.clone());
 			<HostConfiguration<T>>::put(host_config);
+			<RelayEpochRandomness<T>>::set(current_epoch_randomness);
+			<RelayEpochStart<T>>::set(current_epoch_start);
+			<RelayStateEntries<T>>::put(relay_state_entries);
 
-			<T::OnValidationData as OnValidationData>::on_validation_data(&vfp);
+			<T::OnSystemEvent as OnSystemEvent>::on_validation_data(&vfp);
 
-			// TODO: This is more than zero, but will need benchmarking to figure out what.
-			let mut total_weight = 0;
-			total_weight += Self::process_inbound_downward_messages(
-				relevant_messaging_state.dmq_mqc_head,
-				downward_messages,
-			);
-			total_weight += Self::process_inbound_horizontal_messages(
-				&relevant_messaging_state.ingress_channels,
-				horizontal_messages,
-			);
+			if let Some(session_index) = current_session_index {
+				let previous_session_index = <LastRelaySessionIndex<T>>::get();
+				if previous_session_index.map_or(false, |previous| previous != session_index) {
+					T::OnRelaySessionChange::on_relay_session_change(session_index);
+				}
+				<LastRelaySessionIndex<T>>::put(session_index);
+			}
+
+			let previous_para_lifecycle = <RelayParaLifecycle<T>>::get();
+			if para_lifecycle != previous_para_lifecycle {
+				if let Some(lifecycle) = para_lifecycle {
+					if previous_para_lifecycle.is_some() {
+						Self::deposit_event(Event::ParaLifecycleChanged(lifecycle));
+					}
+					if lifecycle.is_leaving() &&
+						!previous_para_lifecycle.map_or(false, |l| l.is_leaving())
+					{
+						Self::deposit_event(Event::OffboardingStarted(lifecycle));
+						T::OnOffboarding::on_offboarding(lifecycle);
+					}
+				}
+				<RelayParaLifecycle<T>>::set(para_lifecycle);
+			}
+
+			if let Some(previous) = previous_relay_parent_number {
+				let gap = vfp.relay_parent_number.saturating_sub(previous);
+				let threshold = T::ValidationDataGapThreshold::get();
+				if threshold != 0 && gap > threshold {
+					Self::deposit_event(Event::ValidationDataGap(gap));
+					<T::OnSystemEvent as OnSystemEvent>::on_validation_data_gap(gap);
+				}
+			}
+
+			// TODO: The rest of the inherent is more than zero, but isn't benchmarked yet.
+			let dm_count = downward_messages.len() as u32;
+			let hm_count = horizontal_messages.values().map(|msgs| msgs.len() as u32).sum();
+			let mut total_weight = T::WeightInfo::receive_downward_messages(dm_count)
+				.saturating_add(T::WeightInfo::receive_horizontal_messages(hm_count));
+			if T::InboundMessagesOrder::downward_first() {
+				total_weight += Self::process_inbound_downward_messages(
+					relevant_messaging_state.dmq_mqc_head,
+					downward_messages,
+				);
+				total_weight += Self::process_inbound_horizontal_messages(
+					&relevant_messaging_state.ingress_channels,
+					horizontal_messages,
+				);
+			} else {
+				total_weight += Self::process_inbound_horizontal_messages(
+					&relevant_messaging_state.ingress_channels,
+					horizontal_messages,
+				);
+				total_weight += Self::process_inbound_downward_messages(
+					relevant_messaging_state.dmq_mqc_head,
+					downward_messages,
+				);
+			}
 
 			Ok(PostDispatchInfo {
 				actual_weight: Some(total_weight),
@@ -358,7 +726,7 @@ pub mod pallet {
 			Ok(())
 		}
 
-		#[pallet::weight((1_000_000, DispatchClass::Operational))]
+		#[pallet::weight((T::WeightInfo::authorize_upgrade(), DispatchClass::Operational))]
 		fn authorize_upgrade(origin: OriginFor<T>, code_hash: T::Hash) -> DispatchResult {
 			ensure_root(origin)?;
 
@@ -368,13 +736,195 @@ pub mod pallet {
 			Ok(())
 		}
 
-		#[pallet::weight(1_000_000)]
+		#[pallet::weight(T::WeightInfo::enact_authorized_upgrade(code.len() as u32))]
 		fn enact_authorized_upgrade(_: OriginFor<T>, code: Vec<u8>) -> DispatchResultWithPostInfo {
 			Self::validate_authorized_upgrade(&code[..])?;
 			Self::set_code_impl(code)?;
 			AuthorizedUpgrade::<T>::kill();
 			Ok(Pays::No.into())
 		}
+
+		/// Override the weight reserved for processing DMP messages.
+		///
+		/// `None` removes the override, so that `ReservedDmpWeight` of the `Config` is used again.
+		///
+		/// The dispatch origin for this call must be `Root`.
+		#[pallet::weight((
+			T::WeightInfo::set_reserved_dmp_weight_override(),
+			DispatchClass::Operational,
+		))]
+		pub fn set_reserved_dmp_weight_override(
+			origin: OriginFor<T>,
+			weight: Option<Weight>,
+		) -> DispatchResult {
+			ensure_root(origin)?;
+
+			ReservedDmpWeightOverride::<T>::set(weight);
+
+			Self::deposit_event(Event::ReservedDmpWeightOverrideSet(weight));
+			Ok(())
+		}
+
+		/// Override the weight reserved for processing XCMP messages.
+		///
+		/// `None` removes the override, so that `ReservedXcmpWeight` of the `Config` is used again.
+		///
+		/// The dispatch origin for this call must be `Root`.
+		#[pallet::weight((
+			T::WeightInfo::set_reserved_xcmp_weight_override(),
+			DispatchClass::Operational,
+		))]
+		pub fn set_reserved_xcmp_weight_override(
+			origin: OriginFor<T>,
+			weight: Option<Weight>,
+		) -> DispatchResult {
+			ensure_root(origin)?;
+
+			ReservedXcmpWeightOverride::<T>::set(weight);
+
+			Self::deposit_event(Event::ReservedXcmpWeightOverrideSet(weight));
+			Ok(())
+		}
+
+		/// Pause or resume the execution of inbound messages.
+		///
+		/// While paused, up to `MaxPausedMessages` inbound downward and horizontal messages are
+		/// queued and the others are dropped. The queued messages are passed to the message
+		/// handlers, in the order they were received, once the execution is resumed.
+		///
+		/// The dispatch origin for this call must be `Root`.
+		#[pallet::weight((T::WeightInfo::set_inbound_messages_paused(), DispatchClass::Operational))]
+		pub fn set_inbound_messages_paused(origin: OriginFor<T>, paused: bool) -> DispatchResult {
+			ensure_root(origin)?;
+
+			InboundMessagesPaused::<T>::put(paused);
+
+			Self::deposit_event(Event::InboundMessagesPausedSet(paused));
+			Ok(())
+		}
+
+		/// Drop a paused inbound message that can not be executed, e.g. because it is malformed.
+		///
+		/// The `index` is the position of the message among the paused messages of the `channel`,
+		/// starting at zero. Only the messages that were received while the inbound message
+		/// execution was paused can be dropped, not the messages that exceeded
+		/// `MaxHorizontalMessagesPerBlock`. The message is lost for good.
+		///
+		/// The dispatch origin for this call must be `Root`.
+		#[pallet::weight((T::WeightInfo::drop_paused_message(), DispatchClass::Operational))]
+		pub fn drop_paused_message(
+			origin: OriginFor<T>,
+			channel: InboundChannel,
+			index: u32,
+		) -> DispatchResult {
+			ensure_root(origin)?;
+
+			let message = match channel {
+				InboundChannel::Downward => <PausedDownwardMessages<T>>::try_mutate(|queue| {
+					ensure!((index as usize) < queue.len(), Error::<T>::MessageNotFound);
+					Ok::<_, DispatchError>(queue.remove(index as usize).msg)
+				})?,
+				InboundChannel::Horizontal(sender) => {
+					<PausedHorizontalMessages<T>>::try_mutate(|queue| {
+						let position = queue
+							.iter()
+							.enumerate()
+							.filter(|(_, (s, _))| *s == sender)
+							.nth(index as usize)
+							.map(|(position, _)| position)
+							.ok_or(Error::<T>::MessageNotFound)?;
+						Ok::<_, DispatchError>(queue.remove(position).1.data)
+					})?
+				}
+			};
+
+			let message_hash = BlakeTwo256::hash_of(&message);
+			Self::deposit_event(Event::PausedMessageDropped(channel, index, message_hash));
+			Ok(())
+		}
+
+		/// Force the message queue chain head of the inbound `channel` to the given `head`.
+		///
+		/// Every block checks that the head, extended by the received messages, matches the head
+		/// of the relay chain. This allows to recover from a diverged head, which would otherwise
+		/// prevent the parachain from producing blocks. The new head is used from the next block
+		/// on, so it needs to be the head of the relay chain as of the last message that will not
+		/// be received by the next block.
+		///
+		/// The dispatch origin for this call must be `Root`.
+		#[pallet::weight((T::WeightInfo::force_set_mqc_head(), DispatchClass::Operational))]
+		pub fn force_set_mqc_head(
+			origin: OriginFor<T>,
+			channel: InboundChannel,
+			head: relay_chain::Hash,
+		) -> DispatchResult {
+			ensure_root(origin)?;
+
+			let old_head = match channel {
+				InboundChannel::Downward => <LastDmqMqcHead<T>>::mutate(|mqc| {
+					sp_std::mem::replace(mqc, MessageQueueChain(head))
+				}),
+				InboundChannel::Horizontal(sender) => <LastHrmpMqcHeads<T>>::mutate(|heads| {
+					heads.insert(sender, MessageQueueChain(head)).unwrap_or_default()
+				}),
+			};
+
+			Self::deposit_event(Event::MqcHeadForced(channel, old_head.head(), head));
+			Ok(())
+		}
+
+		/// Request to open an HRMP channel from this parachain to the `recipient`.
+		///
+		/// The request is sent to the relay chain as an upward message. The channel is opened once
+		/// the `recipient` accepted the request.
+		///
+		/// The dispatch origin for this call must be `HrmpChannelManagerOrigin`.
+		#[pallet::weight((T::WeightInfo::force_open_hrmp_channel_request(), DispatchClass::Operational))]
+		pub fn force_open_hrmp_channel_request(
+			origin: OriginFor<T>,
+			recipient: ParaId,
+			proposed_max_capacity: u32,
+			proposed_max_message_size: u32,
+		) -> DispatchResult {
+			T::HrmpChannelManagerOrigin::ensure_origin(origin)?;
+
+			Self::send_relay_hrmp_call(RelayHrmpCall::InitOpenChannel(
+				recipient,
+				proposed_max_capacity,
+				proposed_max_message_size,
+			))
+		}
+
+		/// Accept the request of the `sender` to open an HRMP channel to this parachain.
+		///
+		/// The dispatch origin for this call must be `HrmpChannelManagerOrigin`.
+		#[pallet::weight((T::WeightInfo::accept_open_channel(), DispatchClass::Operational))]
+		pub fn accept_open_channel(origin: OriginFor<T>, sender: ParaId) -> DispatchResult {
+			T::HrmpChannelManagerOrigin::ensure_origin(origin)?;
+
+			Self::send_relay_hrmp_call(RelayHrmpCall::AcceptOpenChannel(sender))
+		}
+
+		/// Close the HRMP channel between the `sender` and the `recipient`.
+		///
+		/// One of both needs to be this parachain.
+		///
+		/// The dispatch origin for this call must be `HrmpChannelManagerOrigin`.
+		#[pallet::weight((T::WeightInfo::close_channel(), DispatchClass::Operational))]
+		pub fn close_channel(
+			origin: OriginFor<T>,
+			sender: ParaId,
+			recipient: ParaId,
+		) -> DispatchResult {
+			T::HrmpChannelManagerOrigin::ensure_origin(origin)?;
+
+			let para_id = T::SelfParaId::get();
+			ensure!(sender == para_id || recipient == para_id, Error::<T>::NotChannelMember);
+
+			Self::send_relay_hrmp_call(RelayHrmpCall::CloseChannel(
+				relay_chain::v1::HrmpChannelId { sender, recipient },
+			))
+		}
 	}
 
 	#[pallet::event]
@@ -386,14 +936,72 @@ pub mod pallet {
 		ValidationFunctionStored(RelayChainBlockNumber),
 		/// The validation function was applied as of the contained relay chain block number.
 		ValidationFunctionApplied(RelayChainBlockNumber),
+		/// The relay chain aborted the upgrade and the pending validation function was discarded.
+		ValidationFunctionDiscarded,
 		/// An upgrade has been authorized.
 		UpgradeAuthorized(T::Hash),
+		/// The relay parent number advanced by more than `ValidationDataGapThreshold` since the
+		/// last block.
+		/// \[ gap \]
+		ValidationDataGap(RelayChainBlockNumber),
+		/// Buffered upward messages were discarded, because they exceed the maximum upward message
+		/// size of the relay chain.
+		/// \[ count \]
+		UpwardMessagesDiscarded(u32),
+		/// An upward message was not sent, because the relay chain queue of this parachain is
+		/// full. Contains the number of messages and bytes in the queue, including the buffered
+		/// messages. Sending can be retried once the relay chain processed the queue.
+		/// \[ queue_count, queue_size \]
+		UpwardMessageQueueFull(u32, u32),
+		/// An upward message was sent with a fee hint for its delivery.
+		/// \[ message_hash, fee_hint \]
+		UpwardMessageFeeHinted(relay_chain::Hash, UpwardMessageFeeHint),
+		/// The lifecycle of this parachain on the relay chain changed.
+		/// \[ lifecycle \]
+		ParaLifecycleChanged(ParaLifecycle),
+		/// The relay chain started to downgrade or to offboard this parachain.
+		/// \[ lifecycle \]
+		OffboardingStarted(ParaLifecycle),
 		/// Some downward messages have been received and will be processed.
 		/// \[ count \]
 		DownwardMessagesReceived(u32),
 		/// Downward messages were processed using the given weight.
 		/// \[ weight_used, result_mqc_head \]
 		DownwardMessagesProcessed(Weight, relay_chain::Hash),
+		/// Some horizontal messages have been received and will be processed.
+		/// \[ count \]
+		HorizontalMessagesReceived(u32),
+		/// Horizontal messages were processed using the given weight. Contains the updated MQC
+		/// heads of all channels that received messages.
+		/// \[ weight_used, result_mqc_heads \]
+		HorizontalMessagesProcessed(Weight, Vec<(ParaId, relay_chain::Hash)>),
+		/// The weight reserved for processing DMP messages was overridden.
+		/// \[ weight \]
+		ReservedDmpWeightOverrideSet(Option<Weight>),
+		/// The weight reserved for processing XCMP messages was overridden.
+		/// \[ weight \]
+		ReservedXcmpWeightOverrideSet(Option<Weight>),
+		/// The execution of inbound messages was paused or resumed.
+		/// \[ paused \]
+		InboundMessagesPausedSet(bool),
+		/// The queue of the paused downward or horizontal messages was full, so the given number of
+		/// messages was dropped without being executed.
+		/// \[ downward, count \]
+		PausedMessagesOverflowed(bool, u32),
+		/// A paused inbound message was dropped without being executed.
+		/// \[ channel, index, message_hash \]
+		PausedMessageDropped(InboundChannel, u32, relay_chain::Hash),
+		/// The message queue chain head of an inbound channel was forced to a new value.
+		/// \[ channel, old_head, new_head \]
+		MqcHeadForced(InboundChannel, relay_chain::Hash, relay_chain::Hash),
+		/// The downward message queue chain head computed from the received messages doesn't
+		/// match the head of the relay chain. The head of the relay chain was adopted.
+		/// \[ relay_chain_head, computed_head \]
+		DownwardMqcHeadMismatch(relay_chain::Hash, relay_chain::Hash),
+		/// The message queue chain head of an HRMP channel computed from the received messages
+		/// doesn't match the head of the relay chain. The head of the relay chain was adopted.
+		/// \[ sender, relay_chain_head, computed_head \]
+		HorizontalMqcHeadMismatch(ParaId, relay_chain::Hash, relay_chain::Hash),
 	}
 
 	#[pallet::error]
@@ -404,7 +1012,7 @@ pub mod pallet {
 		ProhibitedByPolkadot,
 		/// The supplied validation function has compiled into a blob larger than Polkadot is
 		/// willing to run
-		TooBig,
+		CodeTooLarge,
 		/// The inherent which supplies the validation data did not run this block
 		ValidationDataNotAvailable,
 		/// The inherent which supplies the host configuration did not run this block
@@ -415,6 +1023,14 @@ pub mod pallet {
 		NothingAuthorized,
 		/// The given code upgrade has not been authorized.
 		Unauthorized,
+		/// This parachain is neither the sender nor the recipient of the HRMP channel.
+		NotChannelMember,
+		/// The upward message carrying the HRMP call could not be sent.
+		HrmpCallNotSent,
+		/// There is no paused inbound message with the given index in the channel.
+		MessageNotFound,
+		/// The head data of the block is larger than the relay chain accepts.
+		HeadDataTooLarge,
 	}
 
 	/// We need to store the new validation function for the span between
@@ -422,6 +1038,7 @@ pub mod pallet {
 	/// value, then [`PendingValidationFunction`] must have a real value, and
 	/// together will coordinate the block number where the upgrade will happen.
 	#[pallet::storage]
+	#[pallet::getter(fn pending_relay_chain_block_number)]
 	pub(super) type PendingRelayChainBlockNumber<T: Config> =
 		StorageValue<_, RelayChainBlockNumber>;
 
@@ -437,6 +1054,66 @@ pub mod pallet {
 	#[pallet::getter(fn validation_data)]
 	pub(super) type ValidationData<T: Config> = StorageValue<_, PersistedValidationData>;
 
+	/// The relay chain state of the last block that set its validation data.
+	///
+	/// In contrast to [`ValidationData`], this is not removed at the beginning of a block.
+	#[pallet::storage]
+	#[pallet::getter(fn relay_chain_state)]
+	pub(super) type LastRelayChainState<T: Config> = StorageValue<_, RelayChainState>;
+
+	/// The number of blocks that were built on top of the relay parent of the last block,
+	/// including the last block.
+	#[pallet::storage]
+	pub(super) type BlocksOnRelayParent<T: Config> = StorageValue<_, u32, ValueQuery>;
+
+	/// The ancestors of the current block that are not yet included on the relay chain, ordered
+	/// from the oldest to the newest.
+	///
+	/// The current block is added in `on_finalize`.
+	#[pallet::storage]
+	pub(super) type UnincludedSegment<T: Config> =
+		StorageValue<_, Vec<Ancestor<T::Hash>>, ValueQuery>;
+
+	/// The BABE randomness of the current relay chain epoch as of the relay parent of the last
+	/// block.
+	///
+	/// `None` if the relay chain doesn't provide any randomness.
+	#[pallet::storage]
+	#[pallet::getter(fn relay_epoch_randomness)]
+	pub(super) type RelayEpochRandomness<T: Config> = StorageValue<_, [u8; 32]>;
+
+	/// The number of the relay chain block that started the current relay chain epoch as of the
+	/// relay parent of the last block.
+	///
+	/// `None` if the relay chain doesn't track the start of its epochs.
+	#[pallet::storage]
+	#[pallet::getter(fn relay_epoch_start)]
+	pub(super) type RelayEpochStart<T: Config> = StorageValue<_, relay_chain::BlockNumber>;
+
+	/// The index of the relay chain session as of the relay parent of the last block.
+	///
+	/// This is `None` if the relay chain doesn't provide a session index.
+	#[pallet::storage]
+	#[pallet::getter(fn last_relay_session_index)]
+	pub(super) type LastRelaySessionIndex<T: Config> =
+		StorageValue<_, relay_chain::v1::SessionIndex>;
+
+	/// The lifecycle of this parachain on the relay chain as of the relay parent of the last
+	/// block.
+	///
+	/// This is `None` if the relay chain doesn't provide a lifecycle for this parachain.
+	#[pallet::storage]
+	#[pallet::getter(fn relay_para_lifecycle)]
+	pub(super) type RelayParaLifecycle<T: Config> = StorageValue<_, ParaLifecycle>;
+
+	/// The values of the [`Config::AdditionalRelayStateKeys`] as of the relay parent of the last
+	/// block.
+	///
+	/// Keys without a value in the relay chain state are not part of the map.
+	#[pallet::storage]
+	pub(super) type RelayStateEntries<T: Config> =
+		StorageValue<_, BTreeMap<Vec<u8>, Vec<u8>>, ValueQuery>;
+
 	/// Were the validation data set to notify the relay chain?
 	#[pallet::storage]
 	pub(super) type DidSetValidationCode<T: Config> = StorageValue<_, bool, ValueQuery>;
@@ -485,6 +1162,26 @@ pub mod pallet {
 	pub(super) type PendingUpwardMessages<T: Config> =
 		StorageValue<_, Vec<UpwardMessage>, ValueQuery>;
 
+	/// The number and the total size in bytes of the [`PendingUpwardMessages`].
+	#[pallet::storage]
+	pub(super) type PendingUpwardMessagesSize<T: Config> = StorageValue<_, (u32, u32), ValueQuery>;
+
+	/// The index of the first of the [`PendingUpwardMessages`].
+	///
+	/// The messages are numbered in the order they were buffered, so the message at position `i`
+	/// of the queue has the index `PendingUpwardMessagesFront + i`.
+	#[pallet::storage]
+	pub(super) type PendingUpwardMessagesFront<T: Config> = StorageValue<_, u32, ValueQuery>;
+
+	/// The deposits taken for [`PendingUpwardMessages`] of local senders, keyed by the index of
+	/// the message and the sender.
+	///
+	/// Contains the size of the message the deposit was taken for. There is at most one deposit
+	/// per pending message, so this has at most `max_upward_queue_count` entries.
+	#[pallet::storage]
+	pub(super) type PendingUpwardMessageDeposits<T: Config> =
+		StorageDoubleMap<_, Twox64Concat, u32, Blake2_128Concat, T::AccountId, u32>;
+
 	/// The number of HRMP messages we observed in `on_initialize` and thus used that number for
 	/// announcing the weight of `on_initialize` and `on_finalize`.
 	#[pallet::storage]
@@ -500,6 +1197,38 @@ pub mod pallet {
 	#[pallet::storage]
 	pub(super) type ReservedDmpWeightOverride<T: Config> = StorageValue<_, Weight>;
 
+	/// Whether the execution of inbound messages is paused.
+	///
+	/// While paused, inbound downward and horizontal messages are still received, but are queued
+	/// in [`PausedDownwardMessages`] and [`PausedHorizontalMessages`] instead of being passed to
+	/// the message handlers.
+	#[pallet::storage]
+	pub(super) type InboundMessagesPaused<T: Config> = StorageValue<_, bool, ValueQuery>;
+
+	/// The downward messages that were received while the inbound message execution was paused.
+	///
+	/// Contains at most `MaxPausedMessages` messages.
+	#[pallet::storage]
+	pub(super) type PausedDownwardMessages<T: Config> =
+		StorageValue<_, Vec<InboundDownwardMessage>, ValueQuery>;
+
+	/// The horizontal messages that were received while the inbound message execution was paused,
+	/// together with their sender.
+	///
+	/// Contains at most `MaxPausedMessages` messages.
+	#[pallet::storage]
+	pub(super) type PausedHorizontalMessages<T: Config> =
+		StorageValue<_, Vec<(ParaId, InboundHrmpMessage)>, ValueQuery>;
+
+	/// The horizontal messages that exceeded `MaxHorizontalMessagesPerBlock`, together with their
+	/// sender.
+	///
+	/// These were received before the paused messages. Contains at most
+	/// `MaxDeferredHorizontalMessages` messages.
+	#[pallet::storage]
+	pub(super) type DeferredHorizontalMessages<T: Config> =
+		StorageValue<_, Vec<(ParaId, InboundHrmpMessage)>, ValueQuery>;
+
 	/// The next authorized upgrade, if there is one.
 	#[pallet::storage]
 	pub(super) type AuthorizedUpgrade<T: Config> = StorageValue<_, T::Hash>;
@@ -534,6 +1263,99 @@ impl<T: Config> Pallet<T> {
 		ensure!(actual_hash == required_hash, Error::<T>::Unauthorized);
 		Ok(actual_hash)
 	}
+
+	/// Apply the pending validation function that was scheduled for `apply_block`.
+	fn apply_pending_validation_function(
+		apply_block: RelayChainBlockNumber,
+		relay_parent_number: RelayChainBlockNumber,
+	) {
+		<PendingRelayChainBlockNumber<T>>::kill();
+		let validation_function = <PendingValidationFunction<T>>::take();
+		<LastUpgrade<T>>::put(&apply_block);
+		Self::put_parachain_code(&validation_function);
+		<T::OnSystemEvent as OnSystemEvent>::on_validation_code_applied();
+		Self::deposit_event(Event::ValidationFunctionApplied(relay_parent_number));
+	}
+
+	/// Send the given HRMP `call` to the relay chain.
+	///
+	/// The call is wrapped in an XCM `Transact` that is dispatched with the parachain origin.
+	fn send_relay_hrmp_call(call: RelayHrmpCall) -> DispatchResult {
+		let message = Xcm::Transact {
+			origin_type: OriginKind::Native,
+			require_weight_at_most: T::RelayHrmpCallWeight::get(),
+			call: (T::RelayHrmpPalletIndex::get(), call).encode().into(),
+		};
+
+		Self::send_upward_message(VersionedXcm::<()>::from(message).encode())
+			.map(|_| ())
+			.map_err(|e| {
+				log::debug!(
+					target: "runtime::parachain-system",
+					"Failed to send an HRMP call to the relay chain: {:?}",
+					e,
+				);
+				Error::<T>::HrmpCallNotSent.into()
+			})
+	}
+
+	/// Returns the value of one of the [`Config::AdditionalRelayStateKeys`] as of the relay parent
+	/// of the last block.
+	///
+	/// Returns `None` if the relay chain state has no value for the `key`, if the value can not be
+	/// decoded as `V` or if the `key` isn't one of the [`Config::AdditionalRelayStateKeys`].
+	pub fn relay_state_entry<V: codec::Decode>(key: &[u8]) -> Option<V> {
+		RelayStateEntries::<T>::get()
+			.get(key)
+			.and_then(|raw| V::decode(&mut &raw[..]).ok())
+	}
+
+	/// Returns the [`Config::AdditionalRelayStateKeys`] that need to be part of the relay chain
+	/// state proof.
+	///
+	/// This is meant to be used to implement the `RelayStateKeysApi`.
+	pub fn additional_relay_state_keys() -> Vec<Vec<u8>> {
+		T::AdditionalRelayStateKeys::get()
+	}
+
+	/// Returns the [`CollationInfo`] of the current active block.
+	///
+	/// The given `header` is the header of the built block we are collecting the collation info
+	/// for.
+	///
+	/// This is expected to be used by the
+	/// [`CollectCollationInfo`](cumulus_primitives_core::CollectCollationInfo) runtime api.
+	pub fn collect_collation_info(header: &T::Header) -> CollationInfo {
+		let upward_messages =
+			storage::unhashed::get(well_known_keys::UPWARD_MESSAGES).unwrap_or_default();
+		let horizontal_messages =
+			storage::unhashed::get(well_known_keys::HRMP_OUTBOUND_MESSAGES).unwrap_or_default();
+		let processed_downward_messages =
+			storage::unhashed::get(well_known_keys::PROCESSED_DOWNWARD_MESSAGES)
+				.unwrap_or_default();
+		// If `HRMP_WATERMARK` isn't set, no messages were supplied via the message ingestion
+		// inherent. The runtime checked that there are legitimately no pending messages, so the
+		// watermark can be moved up to the relay parent number.
+		let hrmp_watermark = storage::unhashed::get(well_known_keys::HRMP_WATERMARK)
+			.or_else(|| Self::validation_data().map(|vfp| vfp.relay_parent_number))
+			.unwrap_or_default();
+		let new_validation_code =
+			storage::unhashed::get_raw(NEW_VALIDATION_CODE).map(relay_chain::v1::ValidationCode);
+		let head_data_extension: Option<HeadDataExtension> =
+			storage::unhashed::get(well_known_keys::HEAD_DATA_EXTENSION);
+
+		CollationInfo {
+			upward_messages,
+			horizontal_messages,
+			new_validation_code,
+			processed_downward_messages,
+			hrmp_watermark,
+			head_data: cumulus_primitives_core::build_head_data(
+				header.encode(),
+				head_data_extension.as_ref(),
+			),
+		}
+	}
 }
 
 impl<T: Config> sp_runtime::traits::ValidateUnsigned for Pallet<T> {
@@ -606,9 +1428,35 @@ impl<T: Config> GetChannelInfo for Pallet<T> {
 		let index = channels.binary_search_by_key(&id, |item| item.0).ok()?;
 		Some(channels[index].1.max_message_size as usize)
 	}
+
+	fn get_channel_info(recipient: ParaId) -> Option<ChannelInfo> {
+		let channels = Self::relevant_messaging_state()?.egress_channels;
+		Self::channel_info(&channels, recipient)
+	}
+
+	fn get_ingress_channel_info(sender: ParaId) -> Option<ChannelInfo> {
+		let channels = Self::relevant_messaging_state()?.ingress_channels;
+		Self::channel_info(&channels, sender)
+	}
 }
 
 impl<T: Config> Pallet<T> {
+	/// Returns the [`ChannelInfo`] of the channel with `id` in the given sorted `channels`.
+	fn channel_info(
+		channels: &[(ParaId, cumulus_primitives_core::AbridgedHrmpChannel)],
+		id: ParaId,
+	) -> Option<ChannelInfo> {
+		let index = channels.binary_search_by_key(&id, |item| item.0).ok()?;
+		let channel = &channels[index].1;
+		Some(ChannelInfo {
+			max_capacity: channel.max_capacity,
+			max_total_size: channel.max_total_size,
+			max_message_size: channel.max_message_size,
+			msg_count: channel.msg_count,
+			total_size: channel.total_size,
+		})
+	}
+
 	/// Validate the given [`PersistedValidationData`] against the
 	/// [`ValidationParams`](polkadot_parachain::primitives::ValidationParams).
 	///
@@ -639,8 +1487,12 @@ impl<T: Config> Pallet<T> {
 	/// Checks if the sequence of the messages is valid, dispatches them and communicates the
 	/// number of processed messages to the collator via a storage update.
 	///
+	/// While the inbound message execution is paused, the messages are queued instead of being
+	/// dispatched, or dropped if the queue is full. The queued messages are dispatched before any
+	/// new message once it is resumed.
+	///
 	/// **Panics** if it turns out that after processing all messages the Message Queue Chain
-	///            hash doesn't match the expected.
+	///            hash doesn't match the expected, unless `LenientMqcHeadCheck` is enabled.
 	fn process_inbound_downward_messages(
 		expected_dmq_mqc_head: relay_chain::Hash,
 		downward_messages: Vec<InboundDownwardMessage>,
@@ -651,17 +1503,35 @@ impl<T: Config> Pallet<T> {
 		let mut weight_used = 0;
 		if dm_count != 0 {
 			Self::deposit_event(Event::DownwardMessagesReceived(dm_count));
-			let max_weight = <ReservedDmpWeightOverride<T>>::get()
-				.unwrap_or_else(T::ReservedDmpWeight::get);
+			for m in &downward_messages {
+				dmq_head.extend_downward(m);
+			}
+			<LastDmqMqcHead<T>>::put(&dmq_head);
+		}
 
-			let message_iter = downward_messages
-				.into_iter()
-				.inspect(|m| {
-					dmq_head.extend_downward(m);
-				})
-				.map(|m| (m.sent_at, m.msg));
+		let messages = if <InboundMessagesPaused<T>>::get() {
+			// The messages that don't fit into the queue are dropped, as none may be executed.
+			let overflow = Self::queue_paused_messages::<PausedDownwardMessages<T>, _>(
+				downward_messages,
+			);
+			if !overflow.is_empty() {
+				Self::deposit_event(Event::PausedMessagesOverflowed(true, overflow.len() as u32));
+			}
+			Vec::new()
+		} else if <PausedDownwardMessages<T>>::exists() {
+			let mut messages = <PausedDownwardMessages<T>>::take();
+			messages.extend(downward_messages);
+			messages
+		} else {
+			downward_messages
+		};
+
+		if !messages.is_empty() {
+			let max_weight =
+				<ReservedDmpWeightOverride<T>>::get().unwrap_or_else(T::ReservedDmpWeight::get);
+
+			let message_iter = messages.into_iter().map(|m| (m.sent_at, m.msg));
 			weight_used += T::DmpMessageHandler::handle_dmp_messages(message_iter, max_weight);
-			<LastDmqMqcHead<T>>::put(&dmq_head);
 
 			Self::deposit_event(Event::DownwardMessagesProcessed(weight_used, dmq_head.0));
 		}
@@ -671,7 +1541,21 @@ impl<T: Config> Pallet<T> {
 		//
 		// A mismatch means that at least some of the submitted messages were altered, omitted or
 		// added improperly.
-		assert_eq!(dmq_head.0, expected_dmq_mqc_head);
+		if dmq_head.head() != expected_dmq_mqc_head {
+			if !T::LenientMqcHeadCheck::get() {
+				panic!(
+					"Downward message queue chain head mismatch: relay chain {:?}, computed {:?}",
+					expected_dmq_mqc_head,
+					dmq_head.head(),
+				);
+			}
+
+			Self::deposit_event(Event::DownwardMqcHeadMismatch(
+				expected_dmq_mqc_head,
+				dmq_head.head(),
+			));
+			<LastDmqMqcHead<T>>::put(MessageQueueChain(expected_dmq_mqc_head));
+		}
 
 		// Store the processed_downward_messages here so that it will be accessible from
 		// PVF's `validate_block` wrapper and collation pipeline.
@@ -683,12 +1567,14 @@ impl<T: Config> Pallet<T> {
 	/// Process all inbound horizontal messages relayed by the collator.
 	///
 	/// This is similar to [`process_inbound_downward_messages`], but works on multiple inbound
-	/// channels.
+	/// channels. This also includes the queueing of the messages while the inbound message
+	/// execution is paused.
 	///
 	/// **Panics** if either any of horizontal messages submitted by the collator was sent from
 	///            a para which has no open channel to this parachain or if after processing
 	///            messages across all inbound channels MQCs were obtained which do not
-	///            correspond to the ones found on the relay-chain.
+	///            correspond to the ones found on the relay-chain. The latter is tolerated if
+	///            `LenientMqcHeadCheck` is enabled.
 	fn process_inbound_horizontal_messages(
 		ingress_channels: &[(ParaId, cumulus_primitives_core::AbridgedHrmpChannel)],
 		horizontal_messages: BTreeMap<ParaId, Vec<InboundHrmpMessage>>,
@@ -729,6 +1615,7 @@ impl<T: Config> Pallet<T> {
 				ord => ord,
 			}
 		});
+		T::InboundMessagesOrder::order_horizontal_messages(&mut horizontal_messages);
 
 		let last_mqc_heads = <LastHrmpMqcHeads<T>>::get();
 		let mut running_mqc_heads = BTreeMap::new();
@@ -744,18 +1631,72 @@ impl<T: Config> Pallet<T> {
 				}
 
 				running_mqc_heads
-					.entry(sender)
-					.or_insert_with(|| last_mqc_heads.get(&sender).cloned().unwrap_or_default())
+					.entry(*sender)
+					.or_insert_with(|| last_mqc_heads.get(sender).cloned().unwrap_or_default())
 					.extend_hrmp(horizontal_message);
 			}
 		}
-		let message_iter = horizontal_messages
+		// Only the channels that received messages in this block are part of the event.
+		let updated_mqc_heads = running_mqc_heads
 			.iter()
-			.map(|&(sender, ref message)| (sender, message.sent_at, &message.data[..]));
+			.map(|(sender, mqc)| (*sender, mqc.head()))
+			.collect::<Vec<_>>();
+
+		let hm_count = horizontal_messages.len() as u32;
+		if hm_count != 0 {
+			Self::deposit_event(Event::HorizontalMessagesReceived(hm_count));
+		}
+
+		let messages = if <InboundMessagesPaused<T>>::get() {
+			// The messages that don't fit into the queue are dropped, as none may be executed.
+			let overflow = Self::queue_paused_messages::<PausedHorizontalMessages<T>, _>(
+				horizontal_messages,
+			);
+			if !overflow.is_empty() {
+				Self::deposit_event(Event::PausedMessagesOverflowed(false, overflow.len() as u32));
+			}
+			Vec::new()
+		} else {
+			// The deferred and the paused messages were received before the new ones, so they are
+			// dispatched first.
+			let mut messages = if <DeferredHorizontalMessages<T>>::exists() {
+				<DeferredHorizontalMessages<T>>::take()
+			} else {
+				Vec::new()
+			};
+			if <PausedHorizontalMessages<T>>::exists() {
+				messages.extend(<PausedHorizontalMessages<T>>::take());
+			}
+			messages.extend(horizontal_messages);
 
-		let max_weight =
-			<ReservedXcmpWeightOverride<T>>::get().unwrap_or_else(T::ReservedXcmpWeight::get);
-		let weight_used = T::XcmpMessageHandler::handle_xcmp_messages(message_iter, max_weight);
+			let max_messages = match T::MaxHorizontalMessagesPerBlock::get() {
+				0 => usize::max_value(),
+				max => max as usize,
+			};
+			// Rather exceed the limit than the number of deferred messages, so the messages are
+			// still passed to the handler in the order they were received.
+			let max_messages = max_messages.max(
+				messages.len().saturating_sub(T::MaxDeferredHorizontalMessages::get() as usize),
+			);
+			if messages.len() > max_messages {
+				<DeferredHorizontalMessages<T>>::put(messages.split_off(max_messages));
+			}
+			messages
+		};
+		// Only report the processing if any message was passed to the handler.
+		let processed = !messages.is_empty();
+
+		let weight_used = if processed {
+			let message_iter = messages
+				.iter()
+				.map(|&(sender, ref message)| (sender, message.sent_at, &message.data[..]));
+
+			let max_weight =
+				<ReservedXcmpWeightOverride<T>>::get().unwrap_or_else(T::ReservedXcmpWeight::get);
+			T::XcmpMessageHandler::handle_xcmp_messages(message_iter, max_weight)
+		} else {
+			0
+		};
 
 		// Check that the MQC heads for each channel provided by the relay chain match the MQC
 		// heads we have after processing all incoming messages.
@@ -765,17 +1706,38 @@ impl<T: Config> Pallet<T> {
 		// it won't get into next block's `last_mqc_heads` and thus will be all zeros, which
 		// would corrupt the message queue chain.
 		for &(ref sender, ref channel) in ingress_channels {
-			let cur_head = running_mqc_heads
-				.entry(sender)
-				.or_insert_with(|| last_mqc_heads.get(&sender).cloned().unwrap_or_default())
-				.head();
+			let mqc = running_mqc_heads
+				.entry(*sender)
+				.or_insert_with(|| last_mqc_heads.get(sender).cloned().unwrap_or_default());
+			let cur_head = mqc.head();
 			let target_head = channel.mqc_head.unwrap_or_default();
 
-			assert!(cur_head == target_head);
+			if cur_head != target_head {
+				if !T::LenientMqcHeadCheck::get() {
+					panic!(
+						"Message queue chain head mismatch for the HRMP channel from {:?}: \
+						relay chain {:?}, computed {:?}",
+						sender,
+						target_head,
+						cur_head,
+					);
+				}
+
+				Self::deposit_event(Event::HorizontalMqcHeadMismatch(
+					*sender,
+					target_head,
+					cur_head,
+				));
+				*mqc = MessageQueueChain(target_head);
+			}
 		}
 
 		<LastHrmpMqcHeads<T>>::put(running_mqc_heads);
 
+		if processed {
+			Self::deposit_event(Event::HorizontalMessagesProcessed(weight_used, updated_mqc_heads));
+		}
+
 		// If we processed at least one message, then advance watermark to that location.
 		if let Some(hrmp_watermark) = hrmp_watermark {
 			storage::unhashed::put(well_known_keys::HRMP_WATERMARK, &hrmp_watermark);
@@ -784,6 +1746,28 @@ impl<T: Config> Pallet<T> {
 		weight_used
 	}
 
+	/// Append the given `messages` to the queue of paused messages `Q`, as far as the queue is not
+	/// full.
+	///
+	/// Returns the messages that didn't fit into the queue, see [`Config::MaxPausedMessages`].
+	fn queue_paused_messages<Q, M>(mut messages: Vec<M>) -> Vec<M>
+	where
+		Q: storage::StorageValue<Vec<M>, Query = Vec<M>>,
+		M: codec::FullCodec,
+	{
+		if messages.is_empty() {
+			return messages;
+		}
+
+		let max_paused = T::MaxPausedMessages::get() as usize;
+		Q::mutate(|queue| {
+			let queued = max_paused.saturating_sub(queue.len()).min(messages.len());
+			let overflow = messages.split_off(queued);
+			queue.extend(messages);
+			overflow
+		})
+	}
+
 	/// Put a new validation function into a particular location where polkadot
 	/// monitors for updates. Calling this function notifies polkadot that a new
 	/// upgrade has been scheduled.
@@ -805,6 +1789,52 @@ impl<T: Config> Pallet<T> {
 		<HostConfiguration<T>>::get().map(|cfg| cfg.max_code_size)
 	}
 
+	/// Store the `extension` to be appended to the head data of the current block.
+	///
+	/// The extension is dropped with a warning if its payload is larger than
+	/// [`MAX_HEAD_DATA_EXTENSION_SIZE`] or if the head data would become larger than the relay
+	/// chain accepts. The block is still valid without the extension.
+	fn store_head_data_extension(extension: HeadDataExtension) {
+		let payload_size = extension.payload().len();
+		if payload_size > MAX_HEAD_DATA_EXTENSION_SIZE as usize {
+			log::warn!(
+				target: "runtime::parachain-system",
+				"Dropping the head data extension of {} bytes, at most {} bytes are allowed",
+				payload_size,
+				MAX_HEAD_DATA_EXTENSION_SIZE,
+			);
+			return;
+		}
+
+		let head_data_size = Self::head_data_size_estimate() + extension.encoded_size();
+		if let Some(max_head_data_size) = Self::host_configuration().map(|c| c.max_head_data_size) {
+			if head_data_size > max_head_data_size as usize {
+				log::warn!(
+					target: "runtime::parachain-system",
+					"Dropping the head data extension, the head data would have {} bytes, but the \
+					relay chain only accepts {} bytes",
+					head_data_size,
+					max_head_data_size,
+				);
+				return;
+			}
+		}
+
+		storage::unhashed::put(well_known_keys::HEAD_DATA_EXTENSION, &extension);
+	}
+
+	/// Returns the size of the head data of the current block, based on the digest as of now.
+	fn head_data_size_estimate() -> usize {
+		let header = T::Header::new(
+			frame_system::Pallet::<T>::block_number(),
+			Default::default(),
+			Default::default(),
+			frame_system::Pallet::<T>::parent_hash(),
+			frame_system::Pallet::<T>::digest(),
+		);
+		header.encoded_size()
+	}
+
 	/// Returns if a PVF/runtime upgrade could be signalled at the current block, and if so
 	/// when the new code will take the effect.
 	fn code_upgrade_allowed(
@@ -839,7 +1869,7 @@ impl<T: Config> Pallet<T> {
 			Self::host_configuration().ok_or(Error::<T>::HostConfigurationNotAvailable)?;
 		ensure!(
 			validation_function.len() <= cfg.max_code_size as usize,
-			Error::<T>::TooBig
+			Error::<T>::CodeTooLarge
 		);
 		let apply_block =
 			Self::code_upgrade_allowed(&vfp, &cfg).ok_or(Error::<T>::ProhibitedByPolkadot)?;
@@ -868,6 +1898,412 @@ impl<T: Config> frame_system::SetCode for ParachainSetCode<T> {
 	}
 }
 
+/// An inbound message channel of this parachain.
+#[derive(Clone, Copy, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub enum InboundChannel {
+	/// The downward message queue from the relay chain.
+	Downward,
+	/// The HRMP channel from the given sender.
+	Horizontal(ParaId),
+}
+
+/// The relay chain messaging bandwidth that is left for the messages of this parachain.
+#[derive(Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct MessagingBandwidth {
+	/// The number of upward messages that still fit into the relay chain queue.
+	pub ump_messages: u32,
+	/// The number of bytes of upward messages that still fit into the relay chain queue.
+	pub ump_bytes: u32,
+	/// The number of messages and bytes that still fit into each outbound HRMP channel.
+	///
+	/// The channels are sorted by the recipient para id ascension.
+	pub hrmp_channels: Vec<(ParaId, u32, u32)>,
+}
+
+/// The number of bytes the queued messages occupy in the storage of this pallet.
+///
+/// See [`Pallet::queued_messages_footprint`].
+#[derive(Clone, Default, PartialEq, Eq, RuntimeDebug)]
+pub struct QueuedMessagesFootprint {
+	/// The bytes of the upward messages waiting to be sent to the relay chain.
+	pub upward_bytes: u32,
+	/// The bytes of the downward messages queued while the inbound message execution is paused.
+	pub paused_downward_bytes: u32,
+	/// The bytes of the horizontal messages queued while the inbound message execution is paused.
+	pub paused_horizontal_bytes: u32,
+	/// The bytes of the horizontal messages that exceeded `MaxHorizontalMessagesPerBlock`.
+	pub deferred_horizontal_bytes: u32,
+}
+
+impl QueuedMessagesFootprint {
+	/// The total number of bytes of all queued messages.
+	pub fn total(&self) -> u32 {
+		self.upward_bytes
+			.saturating_add(self.paused_downward_bytes)
+			.saturating_add(self.paused_horizontal_bytes)
+			.saturating_add(self.deferred_horizontal_bytes)
+	}
+}
+
+/// The calls of the relay chain HRMP pallet that can be dispatched by a parachain.
+///
+/// The variants need to match the call indices of the relay chain pallet.
+#[derive(Encode)]
+enum RelayHrmpCall {
+	/// `hrmp_init_open_channel(recipient, proposed_max_capacity, proposed_max_message_size)`
+	#[codec(index = 0)]
+	InitOpenChannel(ParaId, u32, u32),
+	/// `hrmp_accept_open_channel(sender)`
+	#[codec(index = 1)]
+	AcceptOpenChannel(ParaId),
+	/// `hrmp_close_channel(channel_id)`
+	#[codec(index = 2)]
+	CloseChannel(relay_chain::v1::HrmpChannelId),
+}
+
+/// Provides the relay chain state and block number the current parachain block is built on.
+///
+/// Before the validation data of the current block is set, the values of the previous block are
+/// returned.
+pub struct RelaychainDataProvider<T>(sp_std::marker::PhantomData<T>);
+
+impl<T: Config> RelaychainStateProvider for RelaychainDataProvider<T> {
+	fn current_relay_chain_state() -> RelayChainState {
+		Pallet::<T>::relay_chain_state().unwrap_or_default()
+	}
+}
+
+impl<T: Config> BlockNumberProvider for RelaychainDataProvider<T> {
+	type BlockNumber = relay_chain::BlockNumber;
+
+	fn current_block_number() -> relay_chain::BlockNumber {
+		Self::current_relay_chain_state().number
+	}
+}
+
+/// Provides the relay chain host configuration the current parachain block is built on.
+///
+/// This allows other pallets, e.g. XCM weight and fee calculators, to use the actual limits of
+/// the relay chain. Before the validation data of the current block is set, the configuration of
+/// the previous block is returned. `None` is only returned before the first block set its
+/// validation data.
+pub struct RelayHostConfiguration<T>(sp_std::marker::PhantomData<T>);
+
+impl<T: Config> Get<Option<AbridgedHostConfiguration>> for RelayHostConfiguration<T> {
+	fn get() -> Option<AbridgedHostConfiguration> {
+		Pallet::<T>::host_configuration()
+	}
+}
+
+/// Randomness derived from the BABE randomness of the current relay chain epoch.
+///
+/// The randomness of an epoch is known from the start of the epoch on, so the returned block
+/// number is the relay chain block that started the epoch. Relay chains that don't track the
+/// start of their epochs only give an upper bound, so the relay parent number of the last block
+/// is returned for them. The output is `None` if the relay chain doesn't provide any randomness,
+/// as an output only derived from the `subject` would be predictable.
+pub struct RelayChainRandomness<T>(sp_std::marker::PhantomData<T>);
+
+impl<T: Config> Randomness<Option<relay_chain::Hash>, relay_chain::BlockNumber>
+	for RelayChainRandomness<T>
+{
+	fn random(subject: &[u8]) -> (Option<relay_chain::Hash>, relay_chain::BlockNumber) {
+		let output = Pallet::<T>::relay_epoch_randomness()
+			.map(|randomness| BlakeTwo256::hash_of(&(subject, randomness)));
+		let known_since = Pallet::<T>::relay_epoch_start()
+			.unwrap_or_else(RelaychainDataProvider::<T>::current_block_number);
+
+		(output, known_since)
+	}
+}
+
+/// Something that should be informed about system related events.
+///
+/// This includes [`on_validation_data`](Self::on_validation_data), which is called once per
+/// block when the parachain inherent sets the validation data, and
+/// [`on_validation_code_applied`](Self::on_validation_code_applied), which is called when a
+/// pending validation code upgrade is written to the state. The new code is used from the next
+/// block on. [`on_validation_data_gap`](Self::on_validation_data_gap) is called when the
+/// parachain resumes after missing many relay chain blocks, so that per relay chain block
+/// accounting can be compensated.
+#[impl_trait_for_tuples::impl_for_tuples(30)]
+pub trait OnSystemEvent {
+	/// Called once in each block when the validation data is set by the inherent.
+	fn on_validation_data(data: &PersistedValidationData);
+	/// Called when a pending validation code upgrade is applied.
+	fn on_validation_code_applied();
+	/// Called after [`on_validation_data`](Self::on_validation_data) when the relay parent
+	/// number advanced by more than `ValidationDataGapThreshold` since the last block.
+	///
+	/// `gap` is the difference between both relay parent numbers.
+	fn on_validation_data_gap(gap: RelayChainBlockNumber);
+}
+
+/// Something that should be informed about relay chain session changes.
+///
+/// The session index is read from the relay chain state proof of every block, so the hook is
+/// called in the first parachain block whose relay parent is in a new session. It is not called
+/// for the first session index that is observed.
+#[impl_trait_for_tuples::impl_for_tuples(30)]
+pub trait OnRelaySessionChange {
+	/// Called from `set_validation_data` when the relay chain session changed to `new_session`.
+	fn on_relay_session_change(new_session: relay_chain::v1::SessionIndex);
+}
+
+/// Something that takes a deposit from the local sender of a queued message.
+///
+/// The deposit is for the storage the message occupies while it is queued, so it is computed
+/// from the size of the message.
+pub trait QueuedMessageDeposit<AccountId> {
+	/// Take the deposit for a message of `size` bytes from `who`.
+	fn reserve(who: &AccountId, size: u32) -> DispatchResult;
+
+	/// Return the deposit for a message of `size` bytes to `who`.
+	fn unreserve(who: &AccountId, size: u32);
+}
+
+impl<AccountId> QueuedMessageDeposit<AccountId> for () {
+	fn reserve(_: &AccountId, _: u32) -> DispatchResult {
+		Ok(())
+	}
+
+	fn unreserve(_: &AccountId, _: u32) {}
+}
+
+/// A [`QueuedMessageDeposit`] that reserves `DepositPerByte` for every byte of the message.
+pub struct ReserveDepositPerByte<Currency, DepositPerByte>(
+	sp_std::marker::PhantomData<(Currency, DepositPerByte)>,
+);
+
+impl<AccountId, Currency, DepositPerByte> QueuedMessageDeposit<AccountId>
+	for ReserveDepositPerByte<Currency, DepositPerByte>
+where
+	Currency: ReservableCurrency<AccountId>,
+	DepositPerByte: Get<Currency::Balance>,
+{
+	fn reserve(who: &AccountId, size: u32) -> DispatchResult {
+		Currency::reserve(who, DepositPerByte::get().saturating_mul(size.into()))
+	}
+
+	fn unreserve(who: &AccountId, size: u32) {
+		Currency::unreserve(who, DepositPerByte::get().saturating_mul(size.into()));
+	}
+}
+
+/// Something that should be informed when the relay chain starts to downgrade or to offboard
+/// this parachain.
+///
+/// This allows the runtime to stop accepting new business logic and to flush its outbound
+/// messages while the parachain still produces blocks. The hook is called once when the
+/// lifecycle read from the relay chain state proof becomes one of the leaving lifecycles, see
+/// [`ParaLifecycle::is_leaving`].
+#[impl_trait_for_tuples::impl_for_tuples(30)]
+pub trait OnOffboarding {
+	/// Called from `set_validation_data` with the new `lifecycle` of this parachain.
+	fn on_offboarding(lifecycle: ParaLifecycle);
+}
+
+/// Checks the relay parent number of a block against the relay parent number of its parent
+/// block.
+///
+/// The check is done in `set_validation_data` and should panic if the relay parent number is
+/// not acceptable. It is skipped for the first block that sets the validation data.
+///
+/// Provided implementations:
+///
+/// - [`RelayNumberStrictlyIncreases`]: the relay parent number needs to increase by at least one.
+/// - [`RelayNumberMonotonicallyIncreases`]: the relay parent number must not decrease.
+/// - [`AnyRelayNumber`]: any relay parent number is accepted.
+///
+/// None of them restricts the size of the increase, so a parachain that resumes block
+/// production after missing many relay chain blocks is always accepted.
+pub trait CheckAssociatedRelayNumber {
+	/// Check the `current` relay parent number against the `previous` one.
+	fn check_associated_relay_number(
+		current: RelayChainBlockNumber,
+		previous: RelayChainBlockNumber,
+	);
+}
+
+/// Accepts a relay parent number only if it is bigger than the previous one.
+///
+/// This means that two blocks can not be built on the same relay parent.
+pub struct RelayNumberStrictlyIncreases;
+
+impl CheckAssociatedRelayNumber for RelayNumberStrictlyIncreases {
+	fn check_associated_relay_number(
+		current: RelayChainBlockNumber,
+		previous: RelayChainBlockNumber,
+	) {
+		if current <= previous {
+			panic!(
+				"Relay chain block number needs to strictly increase between parachain blocks! \
+				current: {}, previous: {}",
+				current,
+				previous,
+			);
+		}
+	}
+}
+
+/// Accepts a relay parent number if it is equal to or bigger than the previous one.
+pub struct RelayNumberMonotonicallyIncreases;
+
+impl CheckAssociatedRelayNumber for RelayNumberMonotonicallyIncreases {
+	fn check_associated_relay_number(
+		current: RelayChainBlockNumber,
+		previous: RelayChainBlockNumber,
+	) {
+		if current < previous {
+			panic!(
+				"Relay chain block number must not decrease between parachain blocks! \
+				current: {}, previous: {}",
+				current,
+				previous,
+			);
+		}
+	}
+}
+
+/// Accepts any relay parent number.
+pub struct AnyRelayNumber;
+
+impl CheckAssociatedRelayNumber for AnyRelayNumber {
+	fn check_associated_relay_number(_: RelayChainBlockNumber, _: RelayChainBlockNumber) {}
+}
+
+/// The state a [`ConsensusHook`] decides on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsensusHookContext {
+	/// The relay parent number of the block.
+	pub relay_parent_number: RelayChainBlockNumber,
+	/// The BABE slot of the relay parent, if the relay chain uses BABE.
+	pub relay_slot: Option<u64>,
+	/// The number of blocks built on top of this relay parent, including the block itself.
+	pub blocks_on_relay_parent: u32,
+	/// The number of ancestors of the block that are not yet included on the relay chain as of
+	/// the relay parent.
+	pub unincluded_segment_len: u32,
+}
+
+/// Decides if a parachain block may be built.
+///
+/// This is called in `set_validation_data` of every block. A block that is not allowed makes the
+/// inherent panic, so the block can neither be built nor imported.
+///
+/// The implementation for `()` allows every block.
+pub trait ConsensusHook {
+	/// Returns `true` if the block described by `context` may be built.
+	///
+	/// This must not change the storage, as it is also used to check a block without building it.
+	fn can_build_block(context: &ConsensusHookContext) -> bool;
+
+	/// Note that the block described by `context` was allowed by [`Self::can_build_block`].
+	///
+	/// Hooks that track the state of the previous blocks update it here. Does nothing by default.
+	fn on_block_accepted(_context: &ConsensusHookContext) {}
+
+	/// The maximum number of ancestors of a block that are not yet included on the relay chain.
+	///
+	/// The nodes use this to not build blocks that are rejected because of a full unincluded
+	/// segment, see [`Pallet::unincluded_segment_capacity`]. Defaults to no limit.
+	fn unincluded_segment_capacity() -> u32 {
+		u32::max_value()
+	}
+}
+
+impl ConsensusHook for () {
+	fn can_build_block(_: &ConsensusHookContext) -> bool {
+		true
+	}
+}
+
+/// A [`ConsensusHook`] that allows at most `Velocity` blocks per relay parent and at most
+/// `Capacity` blocks that are not yet included on the relay chain, including the block itself.
+///
+/// A `Capacity` of one requires the parent of every block to be included.
+pub struct FixedVelocityConsensusHook<Velocity, Capacity>(
+	sp_std::marker::PhantomData<(Velocity, Capacity)>,
+);
+
+impl<Velocity: Get<u32>, Capacity: Get<u32>> ConsensusHook
+	for FixedVelocityConsensusHook<Velocity, Capacity>
+{
+	fn can_build_block(context: &ConsensusHookContext) -> bool {
+		context.blocks_on_relay_parent <= Velocity::get() &&
+			context.unincluded_segment_len < Capacity::get()
+	}
+
+	fn unincluded_segment_capacity() -> u32 {
+		Capacity::get()
+	}
+}
+
+/// Decides the order in which the inbound messages of a block are passed to the message handlers.
+///
+/// The order only affects the execution of the messages. The weight that is available to each
+/// handler is still given by `ReservedDmpWeight` and `ReservedXcmpWeight`, which define the share
+/// of the block that each kind of message can use.
+///
+/// Provided implementations:
+///
+/// - `()`: the downward messages are processed first, the horizontal messages are processed in
+///   ascending order of `sent_at` and then of the sender's para id.
+/// - [`HorizontalFirst`]: like `()`, but the horizontal messages are processed first.
+/// - [`RoundRobinBySender`]: like `()`, but the horizontal messages are processed one message per
+///   sender at a time.
+pub trait InboundMessagesOrder {
+	/// Returns `true` if the downward messages are processed before the horizontal messages.
+	fn downward_first() -> bool {
+		true
+	}
+
+	/// Order the horizontal messages of a block, given as tuples of the sender and the message.
+	///
+	/// The messages are ordered by `sent_at` and then by the sender when this is called. The
+	/// messages of one sender must stay in the same order, otherwise the block is invalid.
+	fn order_horizontal_messages(_messages: &mut Vec<(ParaId, InboundHrmpMessage)>) {}
+}
+
+impl InboundMessagesOrder for () {}
+
+/// Processes the horizontal messages before the downward messages.
+///
+/// A busy downward message queue doesn't delay the messages of the sibling parachains this way.
+pub struct HorizontalFirst;
+
+impl InboundMessagesOrder for HorizontalFirst {
+	fn downward_first() -> bool {
+		false
+	}
+}
+
+/// Processes the horizontal messages one message per sender at a time, in ascending order of the
+/// sender's para id.
+///
+/// A sender with many messages in its channel doesn't delay the messages of the other senders
+/// this way. The downward messages are processed first.
+pub struct RoundRobinBySender;
+
+impl InboundMessagesOrder for RoundRobinBySender {
+	fn order_horizontal_messages(messages: &mut Vec<(ParaId, InboundHrmpMessage)>) {
+		// The n-th message of every sender is processed in the n-th round.
+		let mut rounds = BTreeMap::<ParaId, u32>::new();
+		let mut keyed = messages
+			.drain(..)
+			.map(|(sender, message)| {
+				let round = rounds.entry(sender).or_insert(0);
+				let key = (*round, sender);
+				*round += 1;
+				(key, (sender, message))
+			})
+			.collect::<Vec<_>>();
+		keyed.sort_by_key(|(key, _)| *key);
+
+		messages.extend(keyed.into_iter().map(|(_, message)| message));
+	}
+}
+
 /// This struct provides ability to extend a message queue chain (MQC) and compute a new head.
 ///
 /// MQC is an instance of a [hash chain] applied to a message queue. Using a hash chain it's
@@ -906,6 +2342,31 @@ impl MessageQueueChain {
 }
 
 impl<T: Config> Pallet<T> {
+	/// Returns `true` if the relay chain is downgrading or offboarding this parachain.
+	///
+	/// The lifecycle is as of the relay parent of the last block. See [`OnOffboarding`].
+	pub fn is_offboarding() -> bool {
+		Self::relay_para_lifecycle().map_or(false, |lifecycle| lifecycle.is_leaving())
+	}
+
+	/// The number of blocks that can be built on top of the block with `block_hash` before the
+	/// unincluded segment is full, if the block with `included_hash` is the last block included
+	/// on the relay chain.
+	///
+	/// This needs to be called on the state of the block with `block_hash`, e.g. by the
+	/// [`UnincludedSegmentApi`](cumulus_primitives_core::UnincludedSegmentApi).
+	pub fn unincluded_segment_capacity(block_hash: T::Hash, included_hash: T::Hash) -> u32 {
+		let mut unincluded_segment = <UnincludedSegment<T>>::get();
+		// The hash of the block itself is only added by the next block.
+		if let Some(last) = unincluded_segment.last_mut() {
+			last.para_head_hash.get_or_insert(block_hash);
+		}
+		unincluded_segment::remove_included(&mut unincluded_segment, &included_hash);
+
+		T::ConsensusHook::unincluded_segment_capacity()
+			.saturating_sub(unincluded_segment.len() as u32)
+	}
+
 	pub fn send_upward_message(message: UpwardMessage) -> Result<u32, MessageSendError> {
 		// Check if the message fits into the relay-chain constraints.
 		//
@@ -919,11 +2380,27 @@ impl<T: Config> Pallet<T> {
 		// may change so that the message is no longer valid.
 		//
 		// However, changing this setting is expected to be rare.
+		let (pending_count, pending_size) = <PendingUpwardMessagesSize<T>>::get();
 		match Self::host_configuration() {
 			Some(cfg) => {
 				if message.len() > cfg.max_upward_message_size as usize {
 					return Err(MessageSendError::TooBig);
 				}
+
+				// Messages that don't fit into the relay chain queue are buffered, but we never
+				// buffer more than what is left of the relay chain queue of this parachain as of
+				// the relay parent. Otherwise, the messages would pile up here while the relay
+				// chain queue is full.
+				let (relay_count, relay_size) = Self::relevant_messaging_state()
+					.map_or((0, 0), |state| state.relay_dispatch_queue_size);
+				let queue_count = relay_count.saturating_add(pending_count);
+				let queue_size = relay_size.saturating_add(pending_size);
+				if queue_count >= cfg.max_upward_queue_count ||
+					queue_size.saturating_add(message.len() as u32) > cfg.max_upward_queue_size
+				{
+					Self::deposit_event(Event::UpwardMessageQueueFull(queue_count, queue_size));
+					return Err(MessageSendError::BandwidthExhausted);
+				}
 			}
 			None => {
 				// This storage field should carry over from the previous block. So if it's None
@@ -937,13 +2414,149 @@ impl<T: Config> Pallet<T> {
 				// Thus fall through here.
 			}
 		};
+		<PendingUpwardMessagesSize<T>>::put((
+			pending_count.saturating_add(1),
+			pending_size.saturating_add(message.len() as u32),
+		));
 		<PendingUpwardMessages<T>>::append(message);
 		Ok(0)
 	}
+
+	/// Send an upward message together with a fee hint.
+	///
+	/// The message is sent like with [`Self::send_upward_message`]. If it was accepted, the hint
+	/// is published with [`Event::UpwardMessageFeeHinted`] for offchain relayers.
+	pub fn send_upward_message_with_fee_hint(
+		message: UpwardMessage,
+		fee_hint: UpwardMessageFeeHint,
+	) -> Result<u32, MessageSendError> {
+		let message_hash = BlakeTwo256::hash_of(&message);
+		let res = Self::send_upward_message(message)?;
+		Self::deposit_event(Event::UpwardMessageFeeHinted(message_hash, fee_hint));
+		Ok(res)
+	}
+
+	/// Send an upward message on behalf of the local account `who`.
+	///
+	/// The message is sent like with [`Self::send_upward_message`], but `QueuedMessageDeposit`
+	/// is taken from `who` while the message is queued in this pallet. Fails with
+	/// [`MessageSendError::Other`] if the deposit can not be taken or if the host configuration
+	/// is not known yet, which bounds the number of queued messages.
+	pub fn send_upward_message_from(
+		who: &T::AccountId,
+		message: UpwardMessage,
+	) -> Result<u32, MessageSendError> {
+		ensure!(Self::host_configuration().is_some(), MessageSendError::Other);
+
+		let size = message.len() as u32;
+		let (pending_count, _) = <PendingUpwardMessagesSize<T>>::get();
+		let index = <PendingUpwardMessagesFront<T>>::get().wrapping_add(pending_count);
+
+		T::QueuedMessageDeposit::reserve(who, size).map_err(|_| MessageSendError::Other)?;
+		match Self::send_upward_message(message) {
+			Ok(res) => {
+				<PendingUpwardMessageDeposits<T>>::insert(index, who, size);
+				Ok(res)
+			}
+			Err(e) => {
+				T::QueuedMessageDeposit::unreserve(who, size);
+				Err(e)
+			}
+		}
+	}
+
+	/// Return the deposits of the upward messages with the `left` indices, which left the queue,
+	/// and renumber the deposits of the `kept` messages.
+	///
+	/// The kept messages are numbered up to `next_index`, the index of the next buffered message.
+	/// They only need to be renumbered if messages between them were discarded.
+	fn settle_upward_message_deposits(next_index: u32, left: Vec<u32>, kept: Vec<u32>) {
+		for index in left {
+			for (who, size) in <PendingUpwardMessageDeposits<T>>::drain_prefix(index) {
+				T::QueuedMessageDeposit::unreserve(&who, size);
+			}
+		}
+
+		let front = next_index.wrapping_sub(kept.len() as u32);
+		// The new indices are never lower than the old ones, so the last message is moved first.
+		for (position, old_index) in kept.into_iter().enumerate().rev() {
+			let new_index = front.wrapping_add(position as u32);
+			if new_index != old_index {
+				for (who, size) in <PendingUpwardMessageDeposits<T>>::drain_prefix(old_index) {
+					<PendingUpwardMessageDeposits<T>>::insert(new_index, who, size);
+				}
+			}
+		}
+		<PendingUpwardMessagesFront<T>>::put(front);
+	}
+
+	/// Returns the number of bytes the queued messages occupy in the storage of this pallet.
+	pub fn queued_messages_footprint() -> QueuedMessagesFootprint {
+		// Only the length of the values is read, the values themselves are not decoded.
+		let encoded_len = |key: [u8; 32]| sp_io::storage::read(&key, &mut [], 0).unwrap_or(0);
+
+		QueuedMessagesFootprint {
+			upward_bytes: encoded_len(<PendingUpwardMessages<T>>::hashed_key()),
+			paused_downward_bytes: encoded_len(<PausedDownwardMessages<T>>::hashed_key()),
+			paused_horizontal_bytes: encoded_len(<PausedHorizontalMessages<T>>::hashed_key()),
+			deferred_horizontal_bytes: encoded_len(<DeferredHorizontalMessages<T>>::hashed_key()),
+		}
+	}
+
+	/// Returns the relay chain messaging bandwidth that is left for this parachain.
+	///
+	/// The bandwidth is based on the relay chain state of the last block and takes the buffered
+	/// upward messages into account. Once the block is finalized, the messages that are sent with
+	/// the block are taken into account as well. Returns `None` if the relay chain state is not
+	/// known yet.
+	pub fn remaining_messaging_bandwidth() -> Option<MessagingBandwidth> {
+		let host_config = Self::host_configuration()?;
+		let mut messaging_state = Self::relevant_messaging_state()?;
+
+		// These are only set between `on_finalize` and the `on_initialize` of the next block.
+		let upward_messages: Vec<UpwardMessage> =
+			storage::unhashed::get(well_known_keys::UPWARD_MESSAGES).unwrap_or_default();
+		let horizontal_messages: Vec<(ParaId, Vec<u8>)> =
+			storage::unhashed::get(well_known_keys::HRMP_OUTBOUND_MESSAGES).unwrap_or_default();
+		UsedBandwidth::from_messages(&upward_messages, &horizontal_messages)
+			.apply_to(&mut messaging_state);
+
+		let (relay_count, relay_size) = messaging_state.relay_dispatch_queue_size;
+		let (pending_count, pending_size) = <PendingUpwardMessagesSize<T>>::get();
+
+		Some(MessagingBandwidth {
+			ump_messages: host_config
+				.max_upward_queue_count
+				.saturating_sub(relay_count)
+				.saturating_sub(pending_count),
+			ump_bytes: host_config
+				.max_upward_queue_size
+				.saturating_sub(relay_size)
+				.saturating_sub(pending_size),
+			hrmp_channels: messaging_state
+				.egress_channels
+				.iter()
+				.map(|(recipient, channel)| {
+					(
+						*recipient,
+						channel.max_capacity.saturating_sub(channel.msg_count),
+						channel.max_total_size.saturating_sub(channel.total_size),
+					)
+				})
+				.collect(),
+		})
+	}
 }
 
 impl<T: Config> UpwardMessageSender for Pallet<T> {
 	fn send_upward_message(message: UpwardMessage) -> Result<u32, MessageSendError> {
 		Self::send_upward_message(message)
 	}
+
+	fn send_upward_message_with_fee_hint(
+		message: UpwardMessage,
+		fee_hint: UpwardMessageFeeHint,
+	) -> Result<u32, MessageSendError> {
+		Self::send_upward_message_with_fee_hint(message, fee_hint)
+	}
 }
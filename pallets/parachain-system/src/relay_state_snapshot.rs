@@ -15,11 +15,16 @@
 // along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
 
 use codec::{Encode, Decode};
-use cumulus_primitives_core::{relay_chain, AbridgedHostConfiguration, AbridgedHrmpChannel, ParaId};
+use cumulus_primitives_core::{
+	relay_chain, relay_well_known_keys, AbridgedHostConfiguration, AbridgedHrmpChannel,
+	ParaId, ParaLifecycle, UpgradeGoAhead,
+};
 use hash_db::{HashDB, EMPTY_PREFIX};
+use polkadot_parachain::primitives::HeadData;
+use relay_chain::v1::SessionIndex;
 use sp_runtime::traits::HashFor;
 use sp_state_machine::{Backend, TrieBackend};
-use sp_trie::StorageProof;
+use sp_trie::{MemoryDB, StorageProof};
 use sp_std::vec::Vec;
 
 /// A snapshot of some messaging related state of relay chain pertaining to the current parachain.
@@ -73,6 +78,22 @@ pub enum Error {
 	HrmpEgressChannelIndex(ReadEntryErr),
 	/// The channel identified by the sender and receiver cannot be extracted.
 	HrmpChannel(ParaId, ParaId, ReadEntryErr),
+	/// The randomness of the current epoch cannot be extracted.
+	CurrentEpochRandomness(ReadEntryErr),
+	/// The start of the current epoch cannot be extracted.
+	CurrentEpochStart(ReadEntryErr),
+	/// The index of the current session cannot be extracted.
+	CurrentSessionIndex(ReadEntryErr),
+	/// The current slot cannot be extracted.
+	CurrentSlot(ReadEntryErr),
+	/// The upgrade go-ahead signal cannot be extracted.
+	UpgradeGoAhead(ReadEntryErr),
+	/// The included para head cannot be extracted.
+	ParaHead(ReadEntryErr),
+	/// The para lifecycle cannot be extracted.
+	ParaLifecycle(ReadEntryErr),
+	/// The entry identified by the key cannot be extracted.
+	Entry(Vec<u8>, ReadEntryErr),
 }
 
 #[derive(Debug)]
@@ -92,6 +113,20 @@ pub enum ReadEntryErr {
 /// a malformed proof), in case the decoding fails, or in case where the value is empty in the relay
 /// chain state and no fallback was provided.
 fn read_entry<T, B>(backend: &B, key: &[u8], fallback: Option<T>) -> Result<T, ReadEntryErr>
+where
+	T: Decode,
+	B: Backend<HashFor<relay_chain::Block>>,
+{
+	read_optional_entry(backend, key)?
+		.or(fallback)
+		.ok_or(ReadEntryErr::Absent)
+}
+
+/// Read an entry given by the key and try to decode it.
+///
+/// In contrast to [`read_entry`], a value that is absent in the relay chain state is not considered
+/// to be an error and `None` is returned. However, the proof still needs to prove the absence.
+fn read_optional_entry<T, B>(backend: &B, key: &[u8]) -> Result<Option<T>, ReadEntryErr>
 where
 	T: Decode,
 	B: Backend<HashFor<relay_chain::Block>>,
@@ -100,99 +135,203 @@ where
 		.storage(key)
 		.map_err(|_| ReadEntryErr::Proof)?
 		.map(|raw_entry| T::decode(&mut &raw_entry[..]).map_err(|_| ReadEntryErr::Decode))
-		.transpose()?
-		.or(fallback)
-		.ok_or(ReadEntryErr::Absent)
+		.transpose()
 }
 
-/// Extract the relay chain state from the given storage proof. This function accepts the `para_id`
-/// of the current parachain and the expected storage root the proof should stem from.
-pub fn extract_from_proof(
+/// A state proof extracted from the relay chain.
+///
+/// This state proof is extracted from the relay chain block we are building on top of.
+pub struct RelayChainStateProof {
 	para_id: ParaId,
-	relay_parent_storage_root: relay_chain::v1::Hash,
-	proof: StorageProof,
-) -> Result<(AbridgedHostConfiguration, MessagingStateSnapshot), Error> {
-	let db = proof.into_memory_db::<HashFor<relay_chain::Block>>();
-	if !db.contains(&relay_parent_storage_root, EMPTY_PREFIX) {
-		return Err(Error::RootMismatch);
+	trie_backend: TrieBackend<MemoryDB<HashFor<relay_chain::Block>>, HashFor<relay_chain::Block>>,
+}
+
+impl RelayChainStateProof {
+	/// Create a new instance of `Self`.
+	///
+	/// Returns an error if the given `relay_parent_storage_root` is not the root of the given
+	/// `proof`.
+	pub fn new(
+		para_id: ParaId,
+		relay_parent_storage_root: relay_chain::v1::Hash,
+		proof: StorageProof,
+	) -> Result<Self, Error> {
+		let db = proof.into_memory_db::<HashFor<relay_chain::Block>>();
+		if !db.contains(&relay_parent_storage_root, EMPTY_PREFIX) {
+			return Err(Error::RootMismatch);
+		}
+		let trie_backend = TrieBackend::new(db, relay_parent_storage_root);
+
+		Ok(Self {
+			para_id,
+			trie_backend,
+		})
 	}
-	let backend = TrieBackend::new(db, relay_parent_storage_root);
-
-	let host_config: AbridgedHostConfiguration = read_entry(
-		&backend,
-		relay_chain::well_known_keys::ACTIVE_CONFIG,
-		None,
-	)
-	.map_err(Error::Config)?;
-
-	let dmq_mqc_head: relay_chain::Hash = read_entry(
-		&backend,
-		&relay_chain::well_known_keys::dmq_mqc_head(para_id),
-		Some(Default::default()),
-	)
-	.map_err(Error::DmqMqcHead)?;
-
-	let relay_dispatch_queue_size: (u32, u32) = read_entry(
-		&backend,
-		&relay_chain::well_known_keys::relay_dispatch_queue_size(para_id),
-		Some((0, 0)),
-	)
-	.map_err(Error::RelayDispatchQueueSize)?;
-
-	let ingress_channel_index: Vec<ParaId> = read_entry(
-		&backend,
-		&relay_chain::well_known_keys::hrmp_ingress_channel_index(para_id),
-		Some(Vec::new()),
-	)
-	.map_err(Error::HrmpIngressChannelIndex)?;
-
-	let egress_channel_index: Vec<ParaId> = read_entry(
-		&backend,
-		&relay_chain::well_known_keys::hrmp_egress_channel_index(para_id),
-		Some(Vec::new()),
-	)
-	.map_err(Error::HrmpEgressChannelIndex)?;
-
-	let mut ingress_channels = Vec::with_capacity(ingress_channel_index.len());
-	for sender in ingress_channel_index {
-		let channel_id = relay_chain::v1::HrmpChannelId {
-			sender,
-			recipient: para_id,
-		};
-		let hrmp_channel: AbridgedHrmpChannel = read_entry(
-			&backend,
-			&relay_chain::well_known_keys::hrmp_channels(channel_id),
-			None,
+
+	/// Read the [`MessagingStateSnapshot`] from the relay chain state proof.
+	///
+	/// Returns an error if anything failed at reading or decoding.
+	pub fn read_messaging_state_snapshot(&self) -> Result<MessagingStateSnapshot, Error> {
+		let dmq_mqc_head: relay_chain::Hash = read_entry(
+			&self.trie_backend,
+			&relay_chain::well_known_keys::dmq_mqc_head(self.para_id),
+			Some(Default::default()),
 		)
-		.map_err(|read_err| Error::HrmpChannel(sender, para_id, read_err))?;
-		ingress_channels.push((sender, hrmp_channel));
-	}
+		.map_err(Error::DmqMqcHead)?;
 
-	let mut egress_channels = Vec::with_capacity(egress_channel_index.len());
-	for recipient in egress_channel_index {
-		let channel_id = relay_chain::v1::HrmpChannelId {
-			sender: para_id,
-			recipient,
-		};
-		let hrmp_channel: AbridgedHrmpChannel = read_entry(
-			&backend,
-			&relay_chain::well_known_keys::hrmp_channels(channel_id),
-			None,
+		let relay_dispatch_queue_size: (u32, u32) = read_entry(
+			&self.trie_backend,
+			&relay_chain::well_known_keys::relay_dispatch_queue_size(self.para_id),
+			Some((0, 0)),
 		)
-		.map_err(|read_err| Error::HrmpChannel(para_id, recipient, read_err))?;
-		egress_channels.push((recipient, hrmp_channel));
-	}
+		.map_err(Error::RelayDispatchQueueSize)?;
+
+		let ingress_channel_index: Vec<ParaId> = read_entry(
+			&self.trie_backend,
+			&relay_chain::well_known_keys::hrmp_ingress_channel_index(self.para_id),
+			Some(Vec::new()),
+		)
+		.map_err(Error::HrmpIngressChannelIndex)?;
 
-	// NOTE that ingress_channels and egress_channels promise to be sorted. We satisfy this property
-	// by relying on the fact that `ingress_channel_index` and `egress_channel_index` are themselves sorted.
+		let egress_channel_index: Vec<ParaId> = read_entry(
+			&self.trie_backend,
+			&relay_chain::well_known_keys::hrmp_egress_channel_index(self.para_id),
+			Some(Vec::new()),
+		)
+		.map_err(Error::HrmpEgressChannelIndex)?;
+
+		let mut ingress_channels = Vec::with_capacity(ingress_channel_index.len());
+		for sender in ingress_channel_index {
+			let channel_id = relay_chain::v1::HrmpChannelId {
+				sender,
+				recipient: self.para_id,
+			};
+			let hrmp_channel: AbridgedHrmpChannel = read_entry(
+				&self.trie_backend,
+				&relay_chain::well_known_keys::hrmp_channels(channel_id),
+				None,
+			)
+			.map_err(|read_err| Error::HrmpChannel(sender, self.para_id, read_err))?;
+			ingress_channels.push((sender, hrmp_channel));
+		}
 
-	Ok((
-		host_config,
-		MessagingStateSnapshot {
+		let mut egress_channels = Vec::with_capacity(egress_channel_index.len());
+		for recipient in egress_channel_index {
+			let channel_id = relay_chain::v1::HrmpChannelId {
+				sender: self.para_id,
+				recipient,
+			};
+			let hrmp_channel: AbridgedHrmpChannel = read_entry(
+				&self.trie_backend,
+				&relay_chain::well_known_keys::hrmp_channels(channel_id),
+				None,
+			)
+			.map_err(|read_err| Error::HrmpChannel(self.para_id, recipient, read_err))?;
+			egress_channels.push((recipient, hrmp_channel));
+		}
+
+		// NOTE that ingress_channels and egress_channels promise to be sorted. We satisfy this
+		// property by relying on the fact that `ingress_channel_index` and `egress_channel_index`
+		// are themselves sorted.
+		Ok(MessagingStateSnapshot {
 			dmq_mqc_head,
 			relay_dispatch_queue_size,
 			ingress_channels,
 			egress_channels,
-		},
-	))
+		})
+	}
+
+	/// Read the [`AbridgedHostConfiguration`] from the relay chain state proof.
+	///
+	/// Returns an error if anything failed at reading or decoding.
+	pub fn read_abridged_host_configuration(&self) -> Result<AbridgedHostConfiguration, Error> {
+		read_entry(&self.trie_backend, relay_chain::well_known_keys::ACTIVE_CONFIG, None)
+			.map_err(Error::Config)
+	}
+
+	/// Read the BABE randomness of the current relay chain epoch from the state proof.
+	///
+	/// Returns `Ok(None)` if the relay chain state doesn't contain any randomness, e.g. because the
+	/// relay chain doesn't use BABE. The proof is required to contain the entry or to prove its
+	/// absence, so that a collator can not withhold the randomness.
+	pub fn read_current_epoch_randomness(&self) -> Result<Option<[u8; 32]>, Error> {
+		read_optional_entry(
+			&self.trie_backend,
+			relay_well_known_keys::CURRENT_EPOCH_RANDOMNESS,
+		)
+		.map_err(Error::CurrentEpochRandomness)
+	}
+
+	/// Read the number of the relay chain block that started the current epoch from the state
+	/// proof.
+	///
+	/// Returns `Ok(None)` if the relay chain doesn't track the start of its epochs. As with the
+	/// randomness, the proof is required to contain the entry or to prove its absence.
+	pub fn read_current_epoch_start(&self) -> Result<Option<relay_chain::BlockNumber>, Error> {
+		read_optional_entry::<(relay_chain::BlockNumber, relay_chain::BlockNumber), _>(
+			&self.trie_backend,
+			relay_well_known_keys::CURRENT_EPOCH_START,
+		)
+		.map(|epoch_start| epoch_start.map(|(_, current)| current))
+		.map_err(Error::CurrentEpochStart)
+	}
+
+	/// Read the index of the current relay chain session from the state proof.
+	///
+	/// Returns `Ok(None)` if the relay chain state doesn't contain a session index. As with the
+	/// randomness, the proof is required to contain the entry or to prove its absence.
+	pub fn read_current_session_index(&self) -> Result<Option<SessionIndex>, Error> {
+		read_optional_entry(&self.trie_backend, relay_well_known_keys::CURRENT_SESSION_INDEX)
+			.map_err(Error::CurrentSessionIndex)
+	}
+
+	/// Read the BABE slot of the relay parent from the state proof.
+	///
+	/// Returns `Ok(None)` if the relay chain doesn't use BABE. As with the randomness, the proof is
+	/// required to contain the entry or to prove its absence.
+	pub fn read_current_slot(&self) -> Result<Option<u64>, Error> {
+		read_optional_entry(&self.trie_backend, relay_well_known_keys::CURRENT_SLOT)
+			.map_err(Error::CurrentSlot)
+	}
+
+	/// Read the [`UpgradeGoAhead`] signal of this parachain from the state proof.
+	///
+	/// Returns `Ok(None)` if the relay chain didn't send any signal.
+	pub fn read_upgrade_go_ahead_signal(&self) -> Result<Option<UpgradeGoAhead>, Error> {
+		read_optional_entry(
+			&self.trie_backend,
+			&relay_well_known_keys::upgrade_go_ahead_signal(self.para_id),
+		)
+		.map_err(Error::UpgradeGoAhead)
+	}
+
+	/// Read the head of this parachain that was last included on the relay chain from the state
+	/// proof.
+	///
+	/// Returns `Ok(None)` if the parachain is not registered on the relay chain.
+	pub fn read_included_para_head(&self) -> Result<Option<HeadData>, Error> {
+		read_optional_entry(&self.trie_backend, &relay_well_known_keys::para_head(self.para_id))
+			.map_err(Error::ParaHead)
+	}
+
+	/// Read the lifecycle of this parachain from the state proof.
+	///
+	/// Returns `Ok(None)` if the parachain is not registered on the relay chain.
+	pub fn read_para_lifecycle(&self) -> Result<Option<ParaLifecycle>, Error> {
+		read_optional_entry(
+			&self.trie_backend,
+			&relay_well_known_keys::para_lifecycle(self.para_id),
+		)
+		.map_err(Error::ParaLifecycle)
+	}
+
+	/// Read the raw value stored under the given `key` from the state proof.
+	///
+	/// Returns `Ok(None)` if there is no value stored under `key` in the relay chain state. The
+	/// proof is required to prove the absence in this case.
+	pub fn read_raw_entry(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		self.trie_backend
+			.storage(key)
+			.map_err(|_| Error::Entry(key.to_vec(), ReadEntryErr::Proof))
+	}
 }
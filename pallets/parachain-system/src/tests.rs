@@ -17,7 +17,8 @@ use super::*;
 
 use codec::Encode;
 use cumulus_primitives_core::{
-	AbridgedHrmpChannel, InboundDownwardMessage, InboundHrmpMessage, PersistedValidationData,
+	extract_relay_parent_info, extract_reserved_pov_size, AbridgedHrmpChannel,
+	InboundDownwardMessage, InboundHrmpMessage, PersistedValidationData,
 	relay_chain::BlockNumber as RelayBlockNumber,
 };
 use cumulus_test_relay_sproof_builder::RelayStateSproofBuilder;
@@ -32,9 +33,12 @@ use frame_support::{
 };
 use frame_system::{InitKind, RawOrigin};
 use hex_literal::hex;
-use relay_chain::v1::HrmpChannelId;
+use relay_chain::v1::{HeadData, HrmpChannelId};
 use sp_core::H256;
-use sp_runtime::{testing::Header, traits::IdentityLookup};
+use sp_runtime::{
+	testing::Header,
+	traits::{Header as _, IdentityLookup},
+};
 use sp_version::RuntimeVersion;
 use std::cell::RefCell;
 
@@ -68,6 +72,12 @@ parameter_types! {
 	pub const ParachainId: ParaId = ParaId::new(200);
 	pub const ReservedXcmpWeight: Weight = 0;
 	pub const ReservedDmpWeight: Weight = 0;
+	pub const ValidationDataGapThreshold: RelayChainBlockNumber = 2_000;
+	pub const BlockVelocity: u32 = 2;
+	pub const UnincludedSegmentCapacity: u32 = 2;
+	pub const RelayHrmpPalletIndex: u8 = 60;
+	pub const RelayHrmpCallWeight: Weight = 1_000;
+	pub const UpgradePovReserve: u32 = 1024;
 }
 impl frame_system::Config for Test {
 	type Origin = Origin;
@@ -96,13 +106,31 @@ impl frame_system::Config for Test {
 }
 impl Config for Test {
 	type Event = Event;
-	type OnValidationData = ();
+	type OnSystemEvent = RecordSystemEvents;
+	type OnRelaySessionChange = RecordSystemEvents;
+	type OnOffboarding = RecordSystemEvents;
 	type SelfParaId = ParachainId;
+	type CheckAssociatedRelayNumber = RelayNumberMonotonicallyIncreases;
+	type ValidationDataGapThreshold = ValidationDataGapThreshold;
+	type ConsensusHook = FixedVelocityConsensusHook<BlockVelocity, UnincludedSegmentCapacity>;
 	type OutboundXcmpMessageSource = FromThreadLocal;
 	type DmpMessageHandler = SaveIntoThreadLocal;
 	type ReservedDmpWeight = ReservedDmpWeight;
 	type XcmpMessageHandler = SaveIntoThreadLocal;
 	type ReservedXcmpWeight = ReservedXcmpWeight;
+	type MaxHorizontalMessagesPerBlock = MaxHorizontalMessagesPerBlock;
+	type MaxPausedMessages = MaxPausedMessages;
+	type MaxDeferredHorizontalMessages = MaxDeferredHorizontalMessages;
+	type LenientMqcHeadCheck = LenientMqcHeadCheck;
+	type InboundMessagesOrder = ();
+	type AdditionalRelayStateKeys = AdditionalRelayStateKeys;
+	type HrmpChannelManagerOrigin = frame_system::EnsureRoot<u64>;
+	type RelayHrmpPalletIndex = RelayHrmpPalletIndex;
+	type RelayHrmpCallWeight = RelayHrmpCallWeight;
+	type UpgradePovReserve = UpgradePovReserve;
+	type HeadDataExtensionProvider = HeadDataExtensionProvider;
+	type QueuedMessageDeposit = RecordDeposits;
+	type WeightInfo = ();
 }
 
 pub struct FromThreadLocal;
@@ -112,6 +140,122 @@ std::thread_local! {
 	static HANDLED_DMP_MESSAGES: RefCell<Vec<(relay_chain::BlockNumber, Vec<u8>)>> = RefCell::new(Vec::new());
 	static HANDLED_XCMP_MESSAGES: RefCell<Vec<(ParaId, relay_chain::BlockNumber, Vec<u8>)>> = RefCell::new(Vec::new());
 	static SENT_MESSAGES: RefCell<Vec<(ParaId, Vec<u8>)>> = RefCell::new(Vec::new());
+	static SYSTEM_EVENTS: RefCell<Vec<SystemEvent>> = RefCell::new(Vec::new());
+	static ADDITIONAL_RELAY_STATE_KEYS: RefCell<Vec<Vec<u8>>> = RefCell::new(Vec::new());
+	static XCMP_MAX_WEIGHT: RefCell<Option<Weight>> = RefCell::new(None);
+	static HEAD_DATA_EXTENSION: RefCell<Option<HeadDataExtension>> = RefCell::new(None);
+	static MAX_HORIZONTAL_MESSAGES: RefCell<u32> = RefCell::new(0);
+	static MAX_PAUSED_MESSAGES: RefCell<u32> = RefCell::new(1_000);
+	static MAX_DEFERRED_MESSAGES: RefCell<u32> = RefCell::new(1_000);
+	static LENIENT_MQC_HEAD_CHECK: RefCell<bool> = RefCell::new(false);
+	static RESERVED_DEPOSITS: RefCell<BTreeMap<u64, u32>> = RefCell::new(BTreeMap::new());
+}
+
+/// Records the reserved deposits per account. Account `0` can't pay any deposit.
+pub struct RecordDeposits;
+
+impl QueuedMessageDeposit<u64> for RecordDeposits {
+	fn reserve(who: &u64, size: u32) -> DispatchResult {
+		if *who == 0 {
+			return Err(DispatchError::Other("no balance"));
+		}
+
+		RESERVED_DEPOSITS.with(|d| *d.borrow_mut().entry(*who).or_default() += size);
+		Ok(())
+	}
+
+	fn unreserve(who: &u64, size: u32) {
+		RESERVED_DEPOSITS.with(|d| *d.borrow_mut().entry(*who).or_default() -= size);
+	}
+}
+
+fn reserved_deposit(who: u64) -> u32 {
+	RESERVED_DEPOSITS.with(|d| d.borrow().get(&who).cloned().unwrap_or_default())
+}
+
+pub struct LenientMqcHeadCheck;
+
+impl Get<bool> for LenientMqcHeadCheck {
+	fn get() -> bool {
+		LENIENT_MQC_HEAD_CHECK.with(|l| *l.borrow())
+	}
+}
+
+pub struct MaxHorizontalMessagesPerBlock;
+
+impl Get<u32> for MaxHorizontalMessagesPerBlock {
+	fn get() -> u32 {
+		MAX_HORIZONTAL_MESSAGES.with(|m| *m.borrow())
+	}
+}
+
+pub struct MaxPausedMessages;
+
+impl Get<u32> for MaxPausedMessages {
+	fn get() -> u32 {
+		MAX_PAUSED_MESSAGES.with(|m| *m.borrow())
+	}
+}
+
+pub struct MaxDeferredHorizontalMessages;
+
+impl Get<u32> for MaxDeferredHorizontalMessages {
+	fn get() -> u32 {
+		MAX_DEFERRED_MESSAGES.with(|m| *m.borrow())
+	}
+}
+
+pub struct HeadDataExtensionProvider;
+
+impl Get<Option<HeadDataExtension>> for HeadDataExtensionProvider {
+	fn get() -> Option<HeadDataExtension> {
+		HEAD_DATA_EXTENSION.with(|e| e.borrow().clone())
+	}
+}
+
+pub struct AdditionalRelayStateKeys;
+
+impl Get<Vec<Vec<u8>>> for AdditionalRelayStateKeys {
+	fn get() -> Vec<Vec<u8>> {
+		ADDITIONAL_RELAY_STATE_KEYS.with(|k| k.borrow().clone())
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum SystemEvent {
+	ValidationData(relay_chain::BlockNumber),
+	ValidationCodeApplied,
+	ValidationDataGap(relay_chain::BlockNumber),
+	RelaySessionChange(relay_chain::v1::SessionIndex),
+	Offboarding(ParaLifecycle),
+}
+
+pub struct RecordSystemEvents;
+
+impl OnSystemEvent for RecordSystemEvents {
+	fn on_validation_data(data: &PersistedValidationData) {
+		SYSTEM_EVENTS.with(|e| e.borrow_mut().push(SystemEvent::ValidationData(data.relay_parent_number)));
+	}
+
+	fn on_validation_code_applied() {
+		SYSTEM_EVENTS.with(|e| e.borrow_mut().push(SystemEvent::ValidationCodeApplied));
+	}
+
+	fn on_validation_data_gap(gap: relay_chain::BlockNumber) {
+		SYSTEM_EVENTS.with(|e| e.borrow_mut().push(SystemEvent::ValidationDataGap(gap)));
+	}
+}
+
+impl OnRelaySessionChange for RecordSystemEvents {
+	fn on_relay_session_change(new_session: relay_chain::v1::SessionIndex) {
+		SYSTEM_EVENTS.with(|e| e.borrow_mut().push(SystemEvent::RelaySessionChange(new_session)));
+	}
+}
+
+impl OnOffboarding for RecordSystemEvents {
+	fn on_offboarding(lifecycle: ParaLifecycle) {
+		SYSTEM_EVENTS.with(|e| e.borrow_mut().push(SystemEvent::Offboarding(lifecycle)));
+	}
 }
 
 fn send_message(
@@ -161,8 +305,9 @@ impl DmpMessageHandler for SaveIntoThreadLocal {
 impl XcmpMessageHandler for SaveIntoThreadLocal {
 	fn handle_xcmp_messages<'a, I: Iterator<Item=(ParaId, RelayBlockNumber, &'a [u8])>>(
 		iter: I,
-		_max_weight: Weight,
+		max_weight: Weight,
 	) -> Weight {
+		XCMP_MAX_WEIGHT.with(|w| *w.borrow_mut() = Some(max_weight));
 		HANDLED_XCMP_MESSAGES.with(|m| {
 			for (sender, sent_at, message) in iter {
 				m.borrow_mut().push((sender, sent_at, message.to_vec()));
@@ -174,7 +319,7 @@ impl XcmpMessageHandler for SaveIntoThreadLocal {
 
 // This function basically just builds a genesis storage key/value store according to
 // our desired mockup.
-fn new_test_ext() -> sp_io::TestExternalities {
+pub(crate) fn new_test_ext() -> sp_io::TestExternalities {
 	HANDLED_DMP_MESSAGES.with(|m| m.borrow_mut().clear());
 	HANDLED_XCMP_MESSAGES.with(|m| m.borrow_mut().clear());
 
@@ -300,6 +445,7 @@ impl BlockTests {
 	fn run(&mut self) {
 		self.ran = true;
 		wasm_ext().execute_with(|| {
+			let mut parent_header: Option<Header> = None;
 			for BlockTest {
 				n,
 				within_block,
@@ -316,13 +462,16 @@ impl BlockTests {
 				// begin initialization
 				System::initialize(
 					&n,
-					&Default::default(),
+					&parent_header.as_ref().map(|h| h.hash()).unwrap_or_default(),
 					&Default::default(),
 					InitKind::Full,
 				);
 
 				// now mess with the storage the way validate_block does
 				let mut sproof_builder = RelayStateSproofBuilder::default();
+				// By default, the parent block is included on the relay chain.
+				sproof_builder.included_para_head =
+					parent_header.as_ref().map(|h| HeadData(h.encode()));
 				if let Some(ref hook) = self.relay_sproof_builder_hook {
 					hook(self, *n as RelayChainBlockNumber, &mut sproof_builder);
 				}
@@ -379,7 +528,7 @@ impl BlockTests {
 				}
 
 				// clean up
-				System::finalize();
+				parent_header = Some(System::finalize());
 				if let Some(after_block) = after_block {
 					after_block();
 				}
@@ -404,6 +553,106 @@ fn block_tests_run_on_drop() {
 	});
 }
 
+#[test]
+#[should_panic(expected = "Relay chain block number must not decrease")]
+fn relay_parent_number_must_not_decrease() {
+	BlockTests::new().add(124, || {}).add(123, || {});
+}
+
+#[test]
+fn blocks_on_the_same_relay_parent_are_counted() {
+	BlockTests::new()
+		.add(1, || assert_eq!(BlocksOnRelayParent::<Test>::get(), 1))
+		.add(1, || assert_eq!(BlocksOnRelayParent::<Test>::get(), 2))
+		.add(2, || assert_eq!(BlocksOnRelayParent::<Test>::get(), 1));
+}
+
+#[test]
+#[should_panic(expected = "Block is not allowed by the consensus hook")]
+fn block_velocity_is_enforced() {
+	BlockTests::new().add(1, || {}).add(1, || {}).add(1, || {});
+}
+
+#[test]
+fn unincluded_segment_tracks_used_bandwidth() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| {
+			if relay_block_num > 1 {
+				// The parent is not included.
+				sproof.included_para_head = None;
+			}
+		})
+		.add(1, || {
+			assert!(UnincludedSegment::<Test>::get().is_empty());
+			ParachainSystem::send_upward_message(b"Mr F was here".to_vec()).unwrap();
+		})
+		.add(2, || {
+			let segment = UnincludedSegment::<Test>::get();
+			assert_eq!(segment.len(), 1);
+			assert_eq!(segment[0].relay_parent_number, 1);
+			assert_eq!(segment[0].used_bandwidth.ump_msg_count, 1);
+			assert!(segment[0].para_head_hash.is_some());
+
+			// The relay chain doesn't know about the message of the parent yet.
+			assert_eq!(
+				ParachainSystem::relevant_messaging_state()
+					.unwrap()
+					.relay_dispatch_queue_size,
+				(1, 13),
+			);
+		});
+}
+
+#[test]
+#[should_panic(expected = "Block is not allowed by the consensus hook")]
+fn unincluded_segment_capacity_is_enforced() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| {
+			if relay_block_num > 1 {
+				sproof.included_para_head = None;
+			}
+		})
+		.add(1, || {})
+		.add(2, || {})
+		.add(3, || {});
+}
+
+#[test]
+fn unincluded_segment_capacity_is_reported() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| {
+			if relay_block_num > 1 {
+				sproof.included_para_head = None;
+			}
+		})
+		.add(1, || {})
+		.add(2, || {
+			let parent = UnincludedSegment::<Test>::get()[0].para_head_hash.unwrap();
+			let unknown = Default::default();
+
+			// The parent takes one place of the segment, until it is included.
+			assert_eq!(ParachainSystem::unincluded_segment_capacity(unknown, unknown), 1);
+			assert_eq!(ParachainSystem::unincluded_segment_capacity(unknown, parent), 2);
+		});
+}
+
+#[test]
+fn relay_number_checks() {
+	RelayNumberStrictlyIncreases::check_associated_relay_number(2, 1);
+	RelayNumberMonotonicallyIncreases::check_associated_relay_number(2, 1);
+	RelayNumberMonotonicallyIncreases::check_associated_relay_number(1, 1);
+	AnyRelayNumber::check_associated_relay_number(1, 2);
+
+	assert!(std::panic::catch_unwind(|| {
+		RelayNumberStrictlyIncreases::check_associated_relay_number(1, 1)
+	})
+	.is_err());
+	assert!(std::panic::catch_unwind(|| {
+		RelayNumberMonotonicallyIncreases::check_associated_relay_number(1, 2)
+	})
+	.is_err());
+}
+
 #[test]
 fn events() {
 	BlockTests::new()
@@ -440,7 +689,7 @@ fn events() {
 }
 
 #[test]
-fn non_overlapping() {
+fn on_system_event_is_called() {
 	BlockTests::new()
 		.with_relay_sproof_builder(|_, _, builder| {
 			builder.host_config.validation_upgrade_delay = 1000;
@@ -451,126 +700,942 @@ fn non_overlapping() {
 				Default::default()
 			));
 		})
-		.add(234, || {
-			assert_eq!(
-				System::set_code(RawOrigin::Root.into(), Default::default()),
-				Err(Error::<Test>::OverlappingUpgrades.into()),
-			)
-		});
+		.add_with_post_test(
+			1234,
+			|| {},
+			|| {
+				SYSTEM_EVENTS.with(|e| {
+					assert_eq!(
+						&*e.borrow(),
+						&[
+							SystemEvent::ValidationData(123),
+							SystemEvent::ValidationCodeApplied,
+							SystemEvent::ValidationData(1234),
+						],
+					);
+				});
+			},
+		);
 }
 
 #[test]
-fn manipulates_storage() {
+fn validation_data_gap_is_detected() {
 	BlockTests::new()
-		.add(123, || {
-			assert!(
-				!<PendingValidationFunction<Test>>::exists(),
-				"validation function must not exist yet"
-			);
-			assert_ok!(System::set_code(
-				RawOrigin::Root.into(),
-				Default::default()
-			));
-			assert!(
-				<PendingValidationFunction<Test>>::exists(),
-				"validation function must now exist"
-			);
-		})
+		.add(1, || {})
 		.add_with_post_test(
-			1234,
+			2_001,
 			|| {},
 			|| {
-				assert!(
-					!<PendingValidationFunction<Test>>::exists(),
-					"validation function must have been unset"
-				);
+				assert!(!System::events().iter().any(|r| matches!(
+					r.event,
+					Event::parachain_system(crate::Event::ValidationDataGap(_))
+				)));
+			},
+		)
+		.add_with_post_test(
+			4_002,
+			|| {},
+			|| {
+				assert!(System::events().iter().any(|r| r.event ==
+					Event::parachain_system(crate::Event::ValidationDataGap(2_001))));
+				SYSTEM_EVENTS.with(|e| {
+					assert_eq!(
+						&*e.borrow(),
+						&[
+							SystemEvent::ValidationData(1),
+							SystemEvent::ValidationData(2_001),
+							SystemEvent::ValidationData(4_002),
+							SystemEvent::ValidationDataGap(2_001),
+						],
+					);
+				});
 			},
 		);
 }
 
 #[test]
-fn checks_size() {
+fn relay_chain_state_is_provided() {
 	BlockTests::new()
-		.with_relay_sproof_builder(|_, _, builder| {
-			builder.host_config.max_code_size = 8;
-		})
 		.add(123, || {
+			assert_eq!(RelaychainDataProvider::<Test>::current_block_number(), 123);
+		})
+		.add(124, || {
+			let state = RelaychainDataProvider::<Test>::current_relay_chain_state();
+			assert_eq!(state.number, 124);
 			assert_eq!(
-				System::set_code(RawOrigin::Root.into(), vec![0; 64]),
-				Err(Error::<Test>::TooBig.into()),
+				state.state_root,
+				ValidationData::<Test>::get().unwrap().relay_parent_storage_root,
 			);
 		});
 }
 
 #[test]
-fn send_upward_message_num_per_candidate() {
+fn relay_chain_randomness_is_provided() {
 	BlockTests::new()
-		.with_relay_sproof_builder(|_, _, sproof| {
-			sproof.host_config.max_upward_message_num_per_candidate = 1;
-			sproof.relay_dispatch_queue_size = None;
+		.with_relay_sproof_builder(|_, relay_block_num, builder| {
+			if relay_block_num >= 2 {
+				builder.current_epoch_randomness = Some([relay_block_num as u8; 32]);
+			}
+			if relay_block_num == 3 {
+				builder.current_epoch_start = Some((1, 2));
+			}
+		})
+		.add(1, || {
+			assert_eq!(ParachainSystem::relay_epoch_randomness(), None);
+			assert_eq!(RelayChainRandomness::<Test>::random(b"first"), (None, 1));
+		})
+		.add(2, || {
+			assert_eq!(ParachainSystem::relay_epoch_randomness(), Some([2; 32]));
+
+			let (first, number) = RelayChainRandomness::<Test>::random(b"first");
+			let (second, _) = RelayChainRandomness::<Test>::random(b"second");
+			assert_eq!(number, 2);
+			assert_ne!(first, second);
+			assert_eq!(first, Some(BlakeTwo256::hash_of(&(&b"first"[..], [2u8; 32]))));
+		})
+		.add(3, || {
+			assert_eq!(ParachainSystem::relay_epoch_start(), Some(2));
+
+			let (output, number) = RelayChainRandomness::<Test>::random(b"first");
+			assert_eq!(number, 2);
+			assert_eq!(output, Some(BlakeTwo256::hash_of(&(&b"first"[..], [3u8; 32]))));
+		});
+}
+
+#[test]
+fn relay_session_change_is_detected() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, builder| {
+			builder.current_session_index = match relay_block_num {
+				1 => None,
+				2 | 3 => Some(5),
+				_ => Some(6),
+			};
 		})
+		.add(1, || {})
+		.add(2, || assert_eq!(ParachainSystem::last_relay_session_index(), Some(5)))
+		.add(3, || {})
 		.add_with_post_test(
-			1,
+			4,
+			|| {},
 			|| {
-				ParachainSystem::send_upward_message(b"Mr F was here".to_vec()).unwrap();
-				ParachainSystem::send_upward_message(b"message 2".to_vec()).unwrap();
+				assert_eq!(ParachainSystem::last_relay_session_index(), Some(6));
+				SYSTEM_EVENTS.with(|e| {
+					let changes = e
+						.borrow()
+						.iter()
+						.filter(|e| matches!(e, SystemEvent::RelaySessionChange(_)))
+						.count();
+					assert_eq!(changes, 1);
+					assert_eq!(e.borrow().last(), Some(&SystemEvent::RelaySessionChange(6)));
+				});
 			},
+		);
+}
+
+#[test]
+fn offboarding_is_detected() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, builder| {
+			builder.para_lifecycle = match relay_block_num {
+				1 => Some(ParaLifecycle::Parachain),
+				_ => Some(ParaLifecycle::OffboardingParachain),
+			};
+		})
+		.add(1, || {
+			assert_eq!(ParachainSystem::relay_para_lifecycle(), Some(ParaLifecycle::Parachain));
+			assert!(!ParachainSystem::is_offboarding());
+		})
+		.add(2, || {
+			assert!(ParachainSystem::is_offboarding());
+			assert!(System::events().iter().any(|r| r.event ==
+				Event::parachain_system(crate::Event::OffboardingStarted(
+					ParaLifecycle::OffboardingParachain,
+				))));
+		})
+		.add_with_post_test(
+			3,
+			|| {},
 			|| {
-				let v: Option<Vec<Vec<u8>>> =
-					storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
-				assert_eq!(v, Some(vec![b"Mr F was here".to_vec()]),);
+				// The hook is only called once.
+				SYSTEM_EVENTS.with(|e| {
+					let offboardings = e
+						.borrow()
+						.iter()
+						.filter(|e| matches!(e, SystemEvent::Offboarding(_)))
+						.cloned()
+						.collect::<Vec<_>>();
+					assert_eq!(
+						offboardings,
+						vec![SystemEvent::Offboarding(ParaLifecycle::OffboardingParachain)],
+					);
+				});
 			},
-		)
+		);
+}
+
+#[test]
+fn relay_host_configuration_is_provided() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, builder| {
+			builder.host_config.max_upward_queue_count = relay_block_num;
+		})
+		.add(1, || {
+			let host_config = RelayHostConfiguration::<Test>::get().unwrap();
+			assert_eq!(host_config.max_upward_queue_count, 1);
+		})
 		.add_with_post_test(
 			2,
-			|| { /* do nothing within block */ },
 			|| {
-				let v: Option<Vec<Vec<u8>>> =
-					storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
-				assert_eq!(v, Some(vec![b"message 2".to_vec()]),);
+				let host_config = RelayHostConfiguration::<Test>::get().unwrap();
+				assert_eq!(host_config.max_upward_queue_count, 2);
+			},
+			|| {
+				// The configuration stays available after the block is finalized.
+				let host_config = RelayHostConfiguration::<Test>::get().unwrap();
+				assert_eq!(host_config.max_upward_queue_count, 2);
 			},
 		);
 }
 
 #[test]
-fn send_upward_message_relay_bottleneck() {
+fn additional_relay_state_entries_are_provided() {
+	ADDITIONAL_RELAY_STATE_KEYS.with(|k| {
+		*k.borrow_mut() = vec![b"relay_key".to_vec(), b"absent_key".to_vec()];
+	});
+
 	BlockTests::new()
-		.with_relay_sproof_builder(|_, relay_block_num, sproof| {
-			sproof.host_config.max_upward_message_num_per_candidate = 2;
-			sproof.host_config.max_upward_queue_count = 5;
+		.with_relay_sproof_builder(|_, relay_block_num, builder| {
+			builder
+				.additional_key_values
+				.push((b"relay_key".to_vec(), relay_block_num.encode()));
+			builder.additional_absent_keys.push(b"absent_key".to_vec());
+		})
+		.add(1, || {
+			assert_eq!(ParachainSystem::relay_state_entry::<u32>(b"relay_key"), Some(1));
+			assert_eq!(ParachainSystem::relay_state_entry::<u32>(b"absent_key"), None);
+		})
+		.add(2, || {
+			assert_eq!(ParachainSystem::relay_state_entry::<u32>(b"relay_key"), Some(2));
+		});
+}
 
-			match relay_block_num {
-				1 => sproof.relay_dispatch_queue_size = Some((5, 0)),
-				2 => sproof.relay_dispatch_queue_size = Some((4, 0)),
-				_ => unreachable!(),
-			}
+#[test]
+fn upgrade_go_ahead_signal_is_respected() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, builder| {
+			builder.host_config.validation_upgrade_delay = 1000;
+			builder.upgrade_go_ahead = match relay_block_num {
+				200 => Some(UpgradeGoAhead::Abort),
+				400 => Some(UpgradeGoAhead::GoAhead),
+				_ => None,
+			};
+		})
+		.add(100, || {
+			assert_ok!(System::set_code(RawOrigin::Root.into(), Default::default()));
+		})
+		.add_with_post_test(
+			200,
+			|| {},
+			|| {
+				assert_eq!(ParachainSystem::pending_relay_chain_block_number(), None);
+				assert_eq!(
+					System::events()[0].event,
+					Event::parachain_system(crate::Event::ValidationFunctionDiscarded.into())
+				);
+			},
+		)
+		.add(300, || {
+			assert_ok!(System::set_code(RawOrigin::Root.into(), Default::default()));
+			assert_eq!(ParachainSystem::pending_relay_chain_block_number(), Some(1300));
 		})
+		.add_with_post_test(
+			400,
+			|| {},
+			|| {
+				assert_eq!(ParachainSystem::pending_relay_chain_block_number(), None);
+				assert_eq!(
+					System::events()[0].event,
+					Event::parachain_system(crate::Event::ValidationFunctionApplied(400).into())
+				);
+			},
+		);
+}
+
+#[test]
+fn pov_is_reserved_while_upgrade_is_authorized() {
+	BlockTests::new()
 		.add_with_post_test(
 			1,
 			|| {
-				ParachainSystem::send_upward_message(vec![0u8; 8]).unwrap();
+				assert_ok!(ParachainSystem::authorize_upgrade(
+					RawOrigin::Root.into(),
+					Default::default(),
+				));
 			},
 			|| {
-				// The message won't be sent because there is already one message in queue.
-				let v: Option<Vec<Vec<u8>>> =
-					storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
-				assert_eq!(v, Some(vec![]),);
+				let digest = System::digest();
+				assert_eq!(extract_reserved_pov_size(&digest), 1024);
 			},
 		)
 		.add_with_post_test(
 			2,
-			|| { /* do nothing within block */ },
 			|| {
-				let v: Option<Vec<Vec<u8>>> =
-					storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
-				assert_eq!(v, Some(vec![vec![0u8; 8]]),);
+				AuthorizedUpgrade::<Test>::kill();
+			},
+			|| {
+				let digest = System::digest();
+				assert_eq!(extract_reserved_pov_size(&digest), 0);
 			},
 		);
 }
 
 #[test]
-fn send_hrmp_message_buffer_channel_close() {
-	BlockTests::new()
+fn relay_parent_info_is_deposited() {
+	BlockTests::new().add_with_post_test(
+		123,
+		|| {},
+		|| {
+			let digest = System::digest();
+			let vfp = ValidationData::<Test>::get().unwrap();
+			assert_eq!(
+				extract_relay_parent_info(&digest),
+				Some((123, vfp.relay_parent_storage_root)),
+			);
+		},
+	);
+}
+
+#[test]
+fn reserved_weight_can_be_overridden() {
+	BlockTests::new()
+		.add(1, || {
+			assert_eq!(XCMP_MAX_WEIGHT.with(|w| *w.borrow()), Some(ReservedXcmpWeight::get()));
+
+			assert_ok!(ParachainSystem::set_reserved_xcmp_weight_override(
+				RawOrigin::Root.into(),
+				Some(1_000),
+			));
+			assert_ok!(ParachainSystem::set_reserved_dmp_weight_override(
+				RawOrigin::Root.into(),
+				Some(2_000),
+			));
+			assert_eq!(ReservedDmpWeightOverride::<Test>::get(), Some(2_000));
+		})
+		.add(2, || {
+			assert_eq!(XCMP_MAX_WEIGHT.with(|w| *w.borrow()), Some(1_000));
+
+			assert!(ParachainSystem::set_reserved_xcmp_weight_override(
+				RawOrigin::Signed(1).into(),
+				None,
+			)
+			.is_err());
+			assert_ok!(ParachainSystem::set_reserved_xcmp_weight_override(
+				RawOrigin::Root.into(),
+				None,
+			));
+		})
+		.add(3, || {
+			assert_eq!(XCMP_MAX_WEIGHT.with(|w| *w.borrow()), Some(ReservedXcmpWeight::get()));
+		});
+}
+
+#[test]
+fn non_overlapping() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, _, builder| {
+			builder.host_config.validation_upgrade_delay = 1000;
+		})
+		.add(123, || {
+			assert_ok!(System::set_code(
+				RawOrigin::Root.into(),
+				Default::default()
+			));
+		})
+		.add(234, || {
+			assert_eq!(
+				System::set_code(RawOrigin::Root.into(), Default::default()),
+				Err(Error::<Test>::OverlappingUpgrades.into()),
+			)
+		});
+}
+
+#[test]
+fn manipulates_storage() {
+	BlockTests::new()
+		.add(123, || {
+			assert!(
+				!<PendingValidationFunction<Test>>::exists(),
+				"validation function must not exist yet"
+			);
+			assert_ok!(System::set_code(
+				RawOrigin::Root.into(),
+				Default::default()
+			));
+			assert!(
+				<PendingValidationFunction<Test>>::exists(),
+				"validation function must now exist"
+			);
+		})
+		.add_with_post_test(
+			1234,
+			|| {},
+			|| {
+				assert!(
+					!<PendingValidationFunction<Test>>::exists(),
+					"validation function must have been unset"
+				);
+			},
+		);
+}
+
+#[test]
+fn checks_size() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, _, builder| {
+			builder.host_config.max_code_size = 8;
+		})
+		.add(123, || {
+			assert_eq!(
+				System::set_code(RawOrigin::Root.into(), vec![0; 64]),
+				Err(Error::<Test>::CodeTooLarge.into()),
+			);
+		});
+}
+
+#[test]
+fn checks_head_data_size() {
+	new_test_ext().execute_with(|| {
+		let mut sproof_builder = RelayStateSproofBuilder::default();
+		sproof_builder.host_config.max_head_data_size = 8;
+		let (relay_parent_storage_root, relay_chain_state) =
+			sproof_builder.into_state_root_and_proof();
+		let data = ParachainInherentData {
+			validation_data: PersistedValidationData {
+				relay_parent_number: 1,
+				relay_parent_storage_root,
+				..Default::default()
+			},
+			relay_chain_state,
+			downward_messages: Default::default(),
+			horizontal_messages: Default::default(),
+		};
+
+		System::initialize(&1, &Default::default(), &Default::default(), InitKind::Full);
+		assert_eq!(
+			ParachainSystem::set_validation_data(RawOrigin::None.into(), data)
+				.map_err(|e| e.error),
+			Err(Error::<Test>::HeadDataTooLarge.into()),
+		);
+	});
+}
+
+#[test]
+fn send_upward_message_num_per_candidate() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, _, sproof| {
+			sproof.host_config.max_upward_message_num_per_candidate = 1;
+			sproof.relay_dispatch_queue_size = None;
+		})
+		.add_with_post_test(
+			1,
+			|| {
+				ParachainSystem::send_upward_message(b"Mr F was here".to_vec()).unwrap();
+				ParachainSystem::send_upward_message(b"message 2".to_vec()).unwrap();
+			},
+			|| {
+				let v: Option<Vec<Vec<u8>>> =
+					storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
+				assert_eq!(v, Some(vec![b"Mr F was here".to_vec()]),);
+			},
+		)
+		.add_with_post_test(
+			2,
+			|| { /* do nothing within block */ },
+			|| {
+				let v: Option<Vec<Vec<u8>>> =
+					storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
+				assert_eq!(v, Some(vec![b"message 2".to_vec()]),);
+			},
+		);
+}
+
+#[test]
+fn collect_collation_info_works() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, _, sproof| {
+			sproof.relay_dispatch_queue_size = None;
+		})
+		.add_with_post_test(
+			1,
+			|| {
+				ParachainSystem::send_upward_message(b"Mr F was here".to_vec()).unwrap();
+			},
+			|| {
+				let header = Header::new(
+					1,
+					Default::default(),
+					Default::default(),
+					Default::default(),
+					Default::default(),
+				);
+				let info = ParachainSystem::collect_collation_info(&header);
+
+				assert_eq!(info.upward_messages, vec![b"Mr F was here".to_vec()]);
+				assert!(info.horizontal_messages.is_empty());
+				assert_eq!(info.new_validation_code, None);
+				assert_eq!(info.processed_downward_messages, 0);
+				// No horizontal messages were processed, so the watermark is the relay parent.
+				assert_eq!(info.hrmp_watermark, 1);
+				assert_eq!(info.head_data, HeadData(header.encode()));
+			},
+		);
+}
+
+#[test]
+fn head_data_extension_is_appended() {
+	let extension = HeadDataExtension::V1(vec![1; 32]);
+	HEAD_DATA_EXTENSION.with(|e| *e.borrow_mut() = Some(extension.clone()));
+
+	BlockTests::new()
+		.add_with_post_test(
+			1,
+			|| {},
+			|| {
+				let header = Header::new(
+					1,
+					Default::default(),
+					Default::default(),
+					Default::default(),
+					Default::default(),
+				);
+				let info = ParachainSystem::collect_collation_info(&header);
+
+				let mut expected = header.encode();
+				expected.extend(extension.encode());
+				assert_eq!(info.head_data, HeadData(expected));
+				assert_eq!(
+					cumulus_primitives_core::decode_head_data::<Header>(&info.head_data.0).unwrap(),
+					(header, Some(extension.clone())),
+				);
+			},
+		);
+}
+
+#[test]
+fn oversized_head_data_extension_is_dropped() {
+	HEAD_DATA_EXTENSION.with(|e| {
+		*e.borrow_mut() =
+			Some(HeadDataExtension::V1(vec![1; MAX_HEAD_DATA_EXTENSION_SIZE as usize + 1]))
+	});
+
+	BlockTests::new().add_with_post_test(
+		1,
+		|| {},
+		|| assert!(!storage::unhashed::exists(well_known_keys::HEAD_DATA_EXTENSION)),
+	);
+}
+
+#[test]
+fn head_data_extension_that_does_not_fit_is_dropped() {
+	HEAD_DATA_EXTENSION.with(|e| *e.borrow_mut() = Some(HeadDataExtension::V1(vec![1; 128])));
+
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, _, builder| {
+			// The header fits, but not together with the extension.
+			builder.host_config.max_head_data_size = 200;
+		})
+		.add_with_post_test(
+			1,
+			|| {},
+			|| assert!(!storage::unhashed::exists(well_known_keys::HEAD_DATA_EXTENSION)),
+		);
+}
+
+#[test]
+fn send_upward_message_discards_messages_that_do_not_fit_anymore() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| {
+			sproof.relay_dispatch_queue_size = None;
+			match relay_block_num {
+				1 => {
+					sproof.host_config.max_upward_message_num_per_candidate = 0;
+				}
+				2 => {
+					sproof.host_config.max_upward_message_num_per_candidate = 2;
+					sproof.host_config.max_upward_message_size = 10;
+				}
+				_ => unreachable!(),
+			}
+		})
+		.add_with_post_test(
+			1,
+			|| {
+				ParachainSystem::send_upward_message(b"Mr F was here".to_vec()).unwrap();
+				ParachainSystem::send_upward_message(b"message 2".to_vec()).unwrap();
+			},
+			|| {
+				let v: Option<Vec<Vec<u8>>> =
+					storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
+				assert_eq!(v, Some(vec![]));
+			},
+		)
+		.add_with_post_test(
+			2,
+			|| { /* do nothing within block */ },
+			|| {
+				let v: Option<Vec<Vec<u8>>> =
+					storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
+				assert_eq!(v, Some(vec![b"message 2".to_vec()]));
+				assert!(System::events().iter().any(|r| r.event ==
+					Event::parachain_system(crate::Event::UpwardMessagesDiscarded(1))));
+				assert_eq!(PendingUpwardMessagesSize::<Test>::get(), (0, 0));
+			},
+		);
+}
+
+#[test]
+fn hrmp_channel_calls_are_sent_upwards() {
+	BlockTests::new()
+		.add_with_post_test(
+			1,
+			|| {
+				assert_eq!(
+					ParachainSystem::close_channel(RawOrigin::Root.into(), 300.into(), 400.into()),
+					Err(Error::<Test>::NotChannelMember.into()),
+				);
+				assert!(
+					ParachainSystem::accept_open_channel(RawOrigin::Signed(1).into(), 300.into())
+						.is_err()
+				);
+				assert_ok!(ParachainSystem::close_channel(
+					RawOrigin::Root.into(),
+					ParachainId::get(),
+					300.into(),
+				));
+			},
+			|| {
+				let expected = VersionedXcm::<()>::from(Xcm::Transact {
+					origin_type: OriginKind::Native,
+					require_weight_at_most: 1_000,
+					call: (
+						60u8,
+						2u8,
+						relay_chain::v1::HrmpChannelId {
+							sender: ParachainId::get(),
+							recipient: 300.into(),
+						},
+					)
+						.encode()
+						.into(),
+				})
+				.encode();
+
+				let v: Option<Vec<Vec<u8>>> =
+					storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
+				assert_eq!(v, Some(vec![expected]));
+			},
+		);
+}
+
+#[test]
+fn send_upward_message_bandwidth_exhausted() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, _, sproof| {
+			sproof.host_config.max_upward_message_num_per_candidate = 1;
+			sproof.host_config.max_upward_queue_count = 2;
+			sproof.relay_dispatch_queue_size = Some((1, 8));
+		})
+		.add(1, || {
+			let bandwidth = ParachainSystem::remaining_messaging_bandwidth().unwrap();
+			assert_eq!((bandwidth.ump_messages, bandwidth.ump_bytes), (1, 1016));
+
+			// The relay chain queue already contains one message.
+			ParachainSystem::send_upward_message(vec![0u8; 8]).unwrap();
+			assert_eq!(
+				ParachainSystem::send_upward_message(vec![1u8; 8]),
+				Err(MessageSendError::BandwidthExhausted),
+			);
+			assert!(System::events().iter().any(|r| r.event ==
+				Event::parachain_system(crate::Event::UpwardMessageQueueFull(2, 16))));
+
+			let bandwidth = ParachainSystem::remaining_messaging_bandwidth().unwrap();
+			assert_eq!((bandwidth.ump_messages, bandwidth.ump_bytes), (0, 1008));
+		})
+		.add(2, || {
+			// The message was sent in the last block, so there is room for another one.
+			ParachainSystem::send_upward_message(vec![1u8; 8]).unwrap();
+			assert_eq!(
+				ParachainSystem::send_upward_message(vec![2u8; 8]),
+				Err(MessageSendError::BandwidthExhausted),
+			);
+		});
+}
+
+#[test]
+fn remaining_messaging_bandwidth_includes_the_messages_of_the_block() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, _, sproof| {
+			sproof.para_id = ParaId::from(200);
+			sproof.host_config.max_upward_message_num_per_candidate = 1;
+			sproof.host_config.max_upward_queue_count = 2;
+			sproof.hrmp_egress_channel_index = Some(vec![ParaId::from(300)]);
+			sproof.hrmp_channels.insert(
+				HrmpChannelId {
+					sender: ParaId::from(200),
+					recipient: ParaId::from(300),
+				},
+				AbridgedHrmpChannel {
+					max_capacity: 2,
+					msg_count: 0,
+					max_total_size: 1024,
+					max_message_size: 8,
+					total_size: 0,
+					mqc_head: Default::default(),
+				},
+			);
+		})
+		.add_with_post_test(
+			1,
+			|| {
+				ParachainSystem::send_upward_message(vec![0u8; 8]).unwrap();
+				send_message(ParaId::from(300), vec![1u8; 4]);
+
+				let bandwidth = ParachainSystem::remaining_messaging_bandwidth().unwrap();
+				assert_eq!((bandwidth.ump_messages, bandwidth.ump_bytes), (1, 1016));
+				assert_eq!(bandwidth.hrmp_channels, vec![(ParaId::from(300), 2, 1024)]);
+			},
+			|| {
+				// The messages left the buffers, but they are not part of the relay chain state.
+				let bandwidth = ParachainSystem::remaining_messaging_bandwidth().unwrap();
+				assert_eq!((bandwidth.ump_messages, bandwidth.ump_bytes), (1, 1016));
+				assert_eq!(bandwidth.hrmp_channels, vec![(ParaId::from(300), 1, 1020)]);
+			},
+		);
+}
+
+#[test]
+fn send_upward_message_relay_bottleneck() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| {
+			sproof.host_config.max_upward_message_num_per_candidate = 2;
+			sproof.host_config.max_upward_queue_count = 5;
+
+			match relay_block_num {
+				1 => sproof.relay_dispatch_queue_size = Some((5, 0)),
+				2 => sproof.relay_dispatch_queue_size = Some((4, 0)),
+				_ => unreachable!(),
+			}
+		})
+		.add_with_post_test(
+			1,
+			|| {
+				// The message isn't accepted, because the relay chain queue is full.
+				assert_eq!(
+					ParachainSystem::send_upward_message(vec![0u8; 8]),
+					Err(MessageSendError::BandwidthExhausted),
+				);
+			},
+			|| {
+				let v: Option<Vec<Vec<u8>>> =
+					storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
+				assert_eq!(v, Some(vec![]),);
+				assert_eq!(PendingUpwardMessagesSize::<Test>::get(), (0, 0));
+			},
+		)
+		.add_with_post_test(
+			2,
+			|| {
+				ParachainSystem::send_upward_message(vec![0u8; 8]).unwrap();
+			},
+			|| {
+				let v: Option<Vec<Vec<u8>>> =
+					storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
+				assert_eq!(v, Some(vec![vec![0u8; 8]]),);
+			},
+		);
+}
+
+#[test]
+fn send_upward_message_from_takes_deposit() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, _, sproof| {
+			sproof.host_config.max_upward_message_num_per_candidate = 1;
+		})
+		.add_with_post_test(
+			1,
+			|| {
+				assert_eq!(
+					ParachainSystem::send_upward_message_from(&0, vec![0u8; 8]),
+					Err(MessageSendError::Other),
+				);
+				ParachainSystem::send_upward_message_from(&1, vec![1u8; 8]).unwrap();
+				ParachainSystem::send_upward_message_from(&2, vec![1u8; 8]).unwrap();
+				assert_eq!(reserved_deposit(1), 8);
+				assert_eq!(reserved_deposit(2), 8);
+			},
+			|| {
+				// Only the first message was sent, so only its deposit is returned.
+				assert_eq!(reserved_deposit(1), 0);
+				assert_eq!(reserved_deposit(2), 8);
+				assert_eq!(
+					PendingUpwardMessageDeposits::<Test>::iter().collect::<Vec<_>>(),
+					vec![(1, 2, 8)],
+				);
+			},
+		)
+		.add_with_post_test(
+			2,
+			|| {},
+			|| {
+				assert_eq!(reserved_deposit(2), 0);
+				assert_eq!(PendingUpwardMessageDeposits::<Test>::iter().count(), 0);
+			},
+		);
+}
+
+#[test]
+fn upward_message_deposits_follow_their_messages() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| match relay_block_num {
+			1 => sproof.host_config.max_upward_message_num_per_candidate = 0,
+			_ => {
+				sproof.host_config.max_upward_message_num_per_candidate = 1;
+				sproof.host_config.max_upward_message_size = 8;
+			}
+		})
+		.add_with_post_test(
+			1,
+			|| {
+				// The messages of `1` and `2` are equal, the one of `3` will be discarded.
+				ParachainSystem::send_upward_message_from(&1, vec![1u8; 8]).unwrap();
+				ParachainSystem::send_upward_message_from(&2, vec![1u8; 8]).unwrap();
+				ParachainSystem::send_upward_message_from(&3, vec![3u8; 16]).unwrap();
+			},
+			|| {
+				// Nothing can be sent with the block.
+				assert_eq!(reserved_deposit(1), 8);
+				assert_eq!(reserved_deposit(2), 8);
+				assert_eq!(reserved_deposit(3), 16);
+			},
+		)
+		.add_with_post_test(
+			2,
+			|| {},
+			|| {
+				// The first message was sent and the last one was discarded.
+				assert_eq!(reserved_deposit(1), 0);
+				assert_eq!(reserved_deposit(2), 8);
+				assert_eq!(reserved_deposit(3), 0);
+				assert_eq!(
+					PendingUpwardMessageDeposits::<Test>::iter().collect::<Vec<_>>(),
+					vec![(2, 2, 8)],
+				);
+			},
+		)
+		.add_with_post_test(3, || {}, || assert_eq!(reserved_deposit(2), 0));
+}
+
+#[test]
+fn queued_messages_footprint_is_tracked() {
+	BlockTests::new().add(1, || {
+		assert_eq!(ParachainSystem::queued_messages_footprint().total(), 0);
+
+		ParachainSystem::send_upward_message(vec![0u8; 8]).unwrap();
+		PausedDownwardMessages::<Test>::put(vec![InboundDownwardMessage {
+			sent_at: 1,
+			msg: vec![0u8; 4],
+		}]);
+
+		let footprint = ParachainSystem::queued_messages_footprint();
+		assert_eq!(footprint.upward_bytes, vec![vec![0u8; 8]].encode().len() as u32);
+		assert_eq!(
+			footprint.paused_downward_bytes,
+			PausedDownwardMessages::<Test>::get().encode().len() as u32,
+		);
+		assert_eq!(footprint.paused_horizontal_bytes, 0);
+		assert_eq!(footprint.deferred_horizontal_bytes, 0);
+	});
+}
+
+#[test]
+fn send_upward_message_with_fee_hint() {
+	let fee_hint = UpwardMessageFeeHint {
+		max_fee: 1_000,
+		priority: cumulus_primitives_core::UpwardMessagePriority::Urgent,
+	};
+
+	BlockTests::new().add_with_post_test(
+		1,
+		move || {
+			ParachainSystem::send_upward_message_with_fee_hint(vec![0u8; 8], fee_hint).unwrap();
+			assert!(System::events().iter().any(|r| r.event ==
+				Event::parachain_system(crate::Event::UpwardMessageFeeHinted(
+					BlakeTwo256::hash_of(&vec![0u8; 8]),
+					fee_hint,
+				))));
+		},
+		|| {
+			let v: Option<Vec<Vec<u8>>> = storage::unhashed::get(well_known_keys::UPWARD_MESSAGES);
+			assert_eq!(v, Some(vec![vec![0u8; 8]]));
+		},
+	);
+}
+
+#[test]
+fn hrmp_channel_info_is_exposed() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, _, sproof| {
+			sproof.para_id = ParaId::from(200);
+			sproof.hrmp_egress_channel_index = Some(vec![ParaId::from(300)]);
+			sproof.hrmp_channels.insert(
+				HrmpChannelId {
+					sender: ParaId::from(200),
+					recipient: ParaId::from(300),
+				},
+				AbridgedHrmpChannel {
+					max_capacity: 10,
+					msg_count: 2,
+					max_total_size: 1024,
+					max_message_size: 8,
+					total_size: 12,
+					mqc_head: Default::default(),
+				},
+			);
+			let ingress = sproof.upsert_inbound_channel(ParaId::from(400));
+			ingress.max_capacity = 5;
+			ingress.max_message_size = 16;
+		})
+		.add(1, || {
+			assert_eq!(
+				ParachainSystem::get_channel_info(ParaId::from(300)),
+				Some(ChannelInfo {
+					max_capacity: 10,
+					max_total_size: 1024,
+					max_message_size: 8,
+					msg_count: 2,
+					total_size: 12,
+				}),
+			);
+			assert_eq!(ParachainSystem::get_channel_info(ParaId::from(400)), None);
+
+			assert_eq!(
+				ParachainSystem::get_ingress_channel_info(ParaId::from(400)),
+				Some(ChannelInfo {
+					max_capacity: 5,
+					max_total_size: 0,
+					max_message_size: 16,
+					msg_count: 0,
+					total_size: 0,
+				}),
+			);
+			assert_eq!(ParachainSystem::get_ingress_channel_info(ParaId::from(300)), None);
+		});
+}
+
+#[test]
+fn send_hrmp_message_buffer_channel_close() {
+	BlockTests::new()
 		.with_relay_sproof_builder(|_, relay_block_num, sproof| {
 			//
 			// Base case setup
@@ -716,29 +1781,282 @@ fn message_queue_chain() {
 }
 
 #[test]
-fn receive_dmp() {
+fn receive_dmp() {
+	lazy_static::lazy_static! {
+		static ref MSG: InboundDownwardMessage = InboundDownwardMessage {
+			sent_at: 1,
+			msg: b"down".to_vec(),
+		};
+	}
+
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| match relay_block_num {
+			1 => {
+				sproof.dmq_mqc_head =
+					Some(MessageQueueChain::default().extend_downward(&MSG).head());
+			}
+			_ => unreachable!(),
+		})
+		.with_inherent_data(|_, relay_block_num, data| match relay_block_num {
+			1 => {
+				data.downward_messages.push(MSG.clone());
+			}
+			_ => unreachable!(),
+		})
+		.add(1, || {
+			HANDLED_DMP_MESSAGES.with(|m| {
+				let mut m = m.borrow_mut();
+				assert_eq!(&*m, &[(MSG.sent_at, MSG.msg.clone())]);
+				m.clear();
+			});
+		});
+}
+
+#[test]
+#[should_panic(expected = "Downward message queue chain head mismatch")]
+fn downward_mqc_head_mismatch_is_rejected() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, _, sproof| {
+			sproof.dmq_mqc_head = Some(relay_chain::Hash::repeat_byte(1));
+		})
+		.with_inherent_data(|_, _, data| {
+			data.downward_messages.push(InboundDownwardMessage {
+				sent_at: 1,
+				msg: b"down".to_vec(),
+			});
+		})
+		.add(1, || {});
+}
+
+#[test]
+#[should_panic(expected = "Message queue chain head mismatch for the HRMP channel")]
+fn horizontal_mqc_head_mismatch_is_rejected() {
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, _, sproof| {
+			sproof.upsert_inbound_channel(ParaId::from(300)).mqc_head =
+				Some(relay_chain::Hash::repeat_byte(1));
+		})
+		.with_inherent_data(|_, _, data| {
+			data.horizontal_messages.insert(
+				ParaId::from(300),
+				vec![InboundHrmpMessage {
+					sent_at: 1,
+					data: b"horizontal".to_vec(),
+				}],
+			);
+		})
+		.add(1, || {});
+}
+
+#[test]
+fn lenient_mqc_head_check_adopts_relay_chain_heads() {
+	LENIENT_MQC_HEAD_CHECK.with(|l| *l.borrow_mut() = true);
+	let dmq_head = relay_chain::Hash::repeat_byte(1);
+	let hrmp_head = relay_chain::Hash::repeat_byte(2);
+
+	BlockTests::new()
+		.with_relay_sproof_builder(move |_, _, sproof| {
+			sproof.dmq_mqc_head = Some(dmq_head);
+			sproof.upsert_inbound_channel(ParaId::from(300)).mqc_head = Some(hrmp_head);
+		})
+		.with_inherent_data(|_, _, data| {
+			data.downward_messages.push(InboundDownwardMessage {
+				sent_at: 1,
+				msg: b"down".to_vec(),
+			});
+			data.horizontal_messages.insert(
+				ParaId::from(300),
+				vec![InboundHrmpMessage {
+					sent_at: 1,
+					data: b"horizontal".to_vec(),
+				}],
+			);
+		})
+		.add(1, move || {
+			let computed_dmq_head = MessageQueueChain::default()
+				.extend_downward(&InboundDownwardMessage {
+					sent_at: 1,
+					msg: b"down".to_vec(),
+				})
+				.head();
+			let computed_hrmp_head = MessageQueueChain::default()
+				.extend_hrmp(&InboundHrmpMessage {
+					sent_at: 1,
+					data: b"horizontal".to_vec(),
+				})
+				.head();
+
+			assert!(System::events().iter().any(|r| r.event ==
+				Event::parachain_system(crate::Event::DownwardMqcHeadMismatch(
+					dmq_head,
+					computed_dmq_head,
+				))));
+			assert!(System::events().iter().any(|r| r.event ==
+				Event::parachain_system(crate::Event::HorizontalMqcHeadMismatch(
+					ParaId::from(300),
+					hrmp_head,
+					computed_hrmp_head,
+				))));
+
+			// The messages are still passed to the handlers.
+			HANDLED_DMP_MESSAGES.with(|m| assert_eq!(m.borrow().len(), 1));
+			HANDLED_XCMP_MESSAGES.with(|m| assert_eq!(m.borrow().len(), 1));
+
+			assert_eq!(LastDmqMqcHead::<Test>::get().head(), dmq_head);
+			assert_eq!(
+				LastHrmpMqcHeads::<Test>::get().get(&ParaId::from(300)).map(|mqc| mqc.head()),
+				Some(hrmp_head),
+			);
+		});
+}
+
+#[test]
+fn receive_dmp_while_paused() {
 	lazy_static::lazy_static! {
 		static ref MSG: InboundDownwardMessage = InboundDownwardMessage {
-			sent_at: 1,
+			sent_at: 2,
 			msg: b"down".to_vec(),
 		};
 	}
 
 	BlockTests::new()
 		.with_relay_sproof_builder(|_, relay_block_num, sproof| match relay_block_num {
-			1 => {
+			1 => {}
+			2 | 3 => {
 				sproof.dmq_mqc_head =
 					Some(MessageQueueChain::default().extend_downward(&MSG).head());
 			}
 			_ => unreachable!(),
 		})
 		.with_inherent_data(|_, relay_block_num, data| match relay_block_num {
-			1 => {
+			2 => {
 				data.downward_messages.push(MSG.clone());
 			}
+			1 | 3 => {}
 			_ => unreachable!(),
 		})
 		.add(1, || {
+			assert_ok!(ParachainSystem::set_inbound_messages_paused(RawOrigin::Root.into(), true));
+		})
+		.add(2, || {
+			HANDLED_DMP_MESSAGES.with(|m| assert!(m.borrow().is_empty()));
+			assert_eq!(PausedDownwardMessages::<Test>::get(), vec![MSG.clone()]);
+
+			assert_ok!(
+				ParachainSystem::set_inbound_messages_paused(RawOrigin::Root.into(), false)
+			);
+		})
+		.add(3, || {
+			HANDLED_DMP_MESSAGES.with(|m| {
+				let mut m = m.borrow_mut();
+				assert_eq!(&*m, &[(MSG.sent_at, MSG.msg.clone())]);
+				m.clear();
+			});
+			assert!(!PausedDownwardMessages::<Test>::exists());
+		});
+}
+
+#[test]
+fn drop_paused_message_works() {
+	BlockTests::new().add(1, || {
+		let message = |sent_at| InboundHrmpMessage {
+			sent_at,
+			data: vec![sent_at as u8],
+		};
+		PausedDownwardMessages::<Test>::put(vec![InboundDownwardMessage {
+			sent_at: 1,
+			msg: b"down".to_vec(),
+		}]);
+		PausedHorizontalMessages::<Test>::put(vec![
+			(ParaId::from(300), message(1)),
+			(ParaId::from(400), message(2)),
+			(ParaId::from(300), message(3)),
+		]);
+
+		assert!(ParachainSystem::drop_paused_message(
+			RawOrigin::Signed(1).into(),
+			InboundChannel::Downward,
+			0,
+		)
+		.is_err());
+		assert!(ParachainSystem::drop_paused_message(
+			RawOrigin::Root.into(),
+			InboundChannel::Downward,
+			1,
+		)
+		.is_err());
+		assert_ok!(ParachainSystem::drop_paused_message(
+			RawOrigin::Root.into(),
+			InboundChannel::Downward,
+			0,
+		));
+		assert!(PausedDownwardMessages::<Test>::get().is_empty());
+
+		// The index is the position among the messages of the sender.
+		assert_ok!(ParachainSystem::drop_paused_message(
+			RawOrigin::Root.into(),
+			InboundChannel::Horizontal(ParaId::from(300)),
+			1,
+		));
+		assert_eq!(
+			PausedHorizontalMessages::<Test>::get(),
+			vec![(ParaId::from(300), message(1)), (ParaId::from(400), message(2))],
+		);
+		assert!(ParachainSystem::drop_paused_message(
+			RawOrigin::Root.into(),
+			InboundChannel::Horizontal(ParaId::from(400)),
+			1,
+		)
+		.is_err());
+
+		assert!(System::events().iter().any(|r| r.event ==
+			Event::parachain_system(crate::Event::PausedMessageDropped(
+				InboundChannel::Horizontal(ParaId::from(300)),
+				1,
+				BlakeTwo256::hash_of(&vec![3u8]),
+			))));
+	});
+}
+
+#[test]
+fn force_set_mqc_head_works() {
+	lazy_static::lazy_static! {
+		static ref MSG: InboundDownwardMessage = InboundDownwardMessage {
+			sent_at: 2,
+			msg: b"down".to_vec(),
+		};
+	}
+	let forced_head = relay_chain::Hash::repeat_byte(1);
+
+	BlockTests::new()
+		.with_relay_sproof_builder(move |_, relay_block_num, sproof| match relay_block_num {
+			1 => {}
+			// The relay chain continues the chain from the forced head.
+			2 => {
+				sproof.dmq_mqc_head =
+					Some(MessageQueueChain(forced_head).extend_downward(&MSG).head());
+			}
+			_ => unreachable!(),
+		})
+		.with_inherent_data(|_, relay_block_num, data| match relay_block_num {
+			1 => {}
+			2 => data.downward_messages.push(MSG.clone()),
+			_ => unreachable!(),
+		})
+		.add(1, move || {
+			assert_ok!(ParachainSystem::force_set_mqc_head(
+				RawOrigin::Root.into(),
+				InboundChannel::Downward,
+				forced_head,
+			));
+			assert!(System::events().iter().any(|r| r.event ==
+				Event::parachain_system(crate::Event::MqcHeadForced(
+					InboundChannel::Downward,
+					Default::default(),
+					forced_head,
+				))));
+		})
+		.add(2, || {
 			HANDLED_DMP_MESSAGES.with(|m| {
 				let mut m = m.borrow_mut();
 				assert_eq!(&*m, &[(MSG.sent_at, MSG.msg.clone())]);
@@ -846,6 +2164,338 @@ fn receive_hrmp() {
 		.add(3, || {});
 }
 
+#[test]
+fn receive_hrmp_deposits_events() {
+	lazy_static::lazy_static! {
+		static ref MSG: InboundHrmpMessage = InboundHrmpMessage {
+			sent_at: 1,
+			data: b"1".to_vec(),
+		};
+	}
+
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| match relay_block_num {
+			1 => {
+				sproof.upsert_inbound_channel(ParaId::from(300)).mqc_head =
+					Some(MessageQueueChain::default().extend_hrmp(&MSG).head());
+			}
+			_ => unreachable!(),
+		})
+		.with_inherent_data(|_, relay_block_num, data| match relay_block_num {
+			1 => {
+				data.horizontal_messages.insert(ParaId::from(300), vec![MSG.clone()]);
+			}
+			_ => unreachable!(),
+		})
+		.add(1, || {
+			let events = System::events().into_iter().map(|r| r.event).collect::<Vec<_>>();
+			assert_eq!(
+				events,
+				vec![
+					Event::parachain_system(crate::Event::HorizontalMessagesReceived(1)),
+					Event::parachain_system(crate::Event::HorizontalMessagesProcessed(
+						0,
+						vec![(
+							ParaId::from(300),
+							MessageQueueChain::default().extend_hrmp(&MSG).head(),
+						)],
+					)),
+				],
+			);
+		});
+}
+
+#[test]
+fn receive_hrmp_while_paused() {
+	lazy_static::lazy_static! {
+		static ref MSG: InboundHrmpMessage = InboundHrmpMessage {
+			sent_at: 2,
+			data: b"1".to_vec(),
+		};
+	}
+
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| match relay_block_num {
+			1 => {}
+			2 | 3 => {
+				sproof.upsert_inbound_channel(ParaId::from(300)).mqc_head =
+					Some(MessageQueueChain::default().extend_hrmp(&MSG).head());
+			}
+			_ => unreachable!(),
+		})
+		.with_inherent_data(|_, relay_block_num, data| match relay_block_num {
+			2 => {
+				data.horizontal_messages.insert(ParaId::from(300), vec![MSG.clone()]);
+			}
+			1 | 3 => {}
+			_ => unreachable!(),
+		})
+		.add(1, || {
+			assert_ok!(ParachainSystem::set_inbound_messages_paused(RawOrigin::Root.into(), true));
+		})
+		.add_with_post_test(
+			2,
+			|| {
+				HANDLED_XCMP_MESSAGES.with(|m| assert!(m.borrow().is_empty()));
+				assert_eq!(
+					PausedHorizontalMessages::<Test>::get(),
+					vec![(ParaId::from(300), MSG.clone())],
+				);
+
+				assert_ok!(
+					ParachainSystem::set_inbound_messages_paused(RawOrigin::Root.into(), false)
+				);
+			},
+			|| {
+				// The messages are received, so the watermark advances.
+				let v: Option<relay_chain::BlockNumber> =
+					storage::unhashed::get(well_known_keys::HRMP_WATERMARK);
+				assert_eq!(v, Some(2));
+			},
+		)
+		.add(3, || {
+			HANDLED_XCMP_MESSAGES.with(|m| {
+				let mut m = m.borrow_mut();
+				assert_eq!(&*m, &[(ParaId::from(300), 2, b"1".to_vec())]);
+				m.clear();
+			});
+			assert!(!PausedHorizontalMessages::<Test>::exists());
+		});
+}
+
+#[test]
+fn horizontal_messages_per_block_are_limited() {
+	lazy_static::lazy_static! {
+		static ref MSGS: Vec<InboundHrmpMessage> = (1..=3u8)
+			.map(|i| InboundHrmpMessage {
+				sent_at: 2,
+				data: vec![i],
+			})
+			.collect();
+	}
+	MAX_HORIZONTAL_MESSAGES.with(|m| *m.borrow_mut() = 2);
+
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| match relay_block_num {
+			1 => {}
+			2 | 3 => {
+				let mut mqc = MessageQueueChain::default();
+				for msg in MSGS.iter() {
+					mqc.extend_hrmp(msg);
+				}
+				sproof.upsert_inbound_channel(ParaId::from(300)).mqc_head = Some(mqc.head());
+			}
+			_ => unreachable!(),
+		})
+		.with_inherent_data(|_, relay_block_num, data| match relay_block_num {
+			2 => {
+				data.horizontal_messages.insert(ParaId::from(300), MSGS.clone());
+			}
+			1 | 3 => {}
+			_ => unreachable!(),
+		})
+		.add(1, || {})
+		.add(2, || {
+			HANDLED_XCMP_MESSAGES.with(|m| {
+				let mut m = m.borrow_mut();
+				assert_eq!(
+					&*m,
+					&[(ParaId::from(300), 2, vec![1]), (ParaId::from(300), 2, vec![2])],
+				);
+				m.clear();
+			});
+			assert_eq!(
+				DeferredHorizontalMessages::<Test>::get(),
+				vec![(ParaId::from(300), MSGS[2].clone())],
+			);
+
+			// Deferred messages are not paused messages, so they can't be dropped.
+			assert!(ParachainSystem::drop_paused_message(
+				RawOrigin::Root.into(),
+				InboundChannel::Horizontal(ParaId::from(300)),
+				0,
+			)
+			.is_err());
+		})
+		.add(3, || {
+			HANDLED_XCMP_MESSAGES.with(|m| {
+				let mut m = m.borrow_mut();
+				assert_eq!(&*m, &[(ParaId::from(300), 2, vec![3])]);
+				m.clear();
+			});
+			assert!(!DeferredHorizontalMessages::<Test>::exists());
+		});
+}
+
+#[test]
+fn deferred_horizontal_messages_are_limited() {
+	lazy_static::lazy_static! {
+		static ref MSGS: Vec<InboundHrmpMessage> = (1..=4u8)
+			.map(|i| InboundHrmpMessage {
+				sent_at: 2,
+				data: vec![i],
+			})
+			.collect();
+	}
+	MAX_HORIZONTAL_MESSAGES.with(|m| *m.borrow_mut() = 1);
+	MAX_DEFERRED_MESSAGES.with(|m| *m.borrow_mut() = 2);
+
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| match relay_block_num {
+			1 => {}
+			2 => {
+				let mut mqc = MessageQueueChain::default();
+				for msg in MSGS.iter() {
+					mqc.extend_hrmp(msg);
+				}
+				sproof.upsert_inbound_channel(ParaId::from(300)).mqc_head = Some(mqc.head());
+			}
+			_ => unreachable!(),
+		})
+		.with_inherent_data(|_, relay_block_num, data| match relay_block_num {
+			2 => {
+				data.horizontal_messages.insert(ParaId::from(300), MSGS.clone());
+			}
+			1 => {}
+			_ => unreachable!(),
+		})
+		.add(1, || {})
+		.add(2, || {
+			// Only two messages can be deferred, so two are passed to the handler in order.
+			HANDLED_XCMP_MESSAGES.with(|m| {
+				let mut m = m.borrow_mut();
+				assert_eq!(
+					&*m,
+					&[(ParaId::from(300), 2, vec![1]), (ParaId::from(300), 2, vec![2])],
+				);
+				m.clear();
+			});
+			assert_eq!(
+				DeferredHorizontalMessages::<Test>::get(),
+				vec![(ParaId::from(300), MSGS[2].clone()), (ParaId::from(300), MSGS[3].clone())],
+			);
+		});
+}
+
+#[test]
+fn paused_messages_that_overflow_are_dropped() {
+	lazy_static::lazy_static! {
+		static ref MSGS: Vec<InboundHrmpMessage> = (1..=3u8)
+			.map(|i| InboundHrmpMessage {
+				sent_at: 2,
+				data: vec![i],
+			})
+			.collect();
+	}
+	MAX_PAUSED_MESSAGES.with(|m| *m.borrow_mut() = 2);
+
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| match relay_block_num {
+			1 => {}
+			2 => {
+				let mut mqc = MessageQueueChain::default();
+				for msg in MSGS.iter() {
+					mqc.extend_hrmp(msg);
+				}
+				sproof.upsert_inbound_channel(ParaId::from(300)).mqc_head = Some(mqc.head());
+			}
+			_ => unreachable!(),
+		})
+		.with_inherent_data(|_, relay_block_num, data| match relay_block_num {
+			2 => {
+				data.horizontal_messages.insert(ParaId::from(300), MSGS.clone());
+			}
+			1 => {}
+			_ => unreachable!(),
+		})
+		.add(1, || {
+			assert_ok!(ParachainSystem::set_inbound_messages_paused(RawOrigin::Root.into(), true));
+		})
+		.add(2, || {
+			// The queue is full after two messages, so the last one is dropped.
+			HANDLED_XCMP_MESSAGES.with(|m| assert!(m.borrow().is_empty()));
+			assert_eq!(
+				PausedHorizontalMessages::<Test>::get(),
+				vec![(ParaId::from(300), MSGS[0].clone()), (ParaId::from(300), MSGS[1].clone())],
+			);
+			assert!(System::events().iter().any(|r| r.event ==
+				Event::parachain_system(crate::Event::PausedMessagesOverflowed(false, 1))));
+		});
+}
+
+#[test]
+fn paused_downward_messages_that_overflow_are_dropped() {
+	lazy_static::lazy_static! {
+		static ref MSGS: Vec<InboundDownwardMessage> = (1..=2u8)
+			.map(|i| InboundDownwardMessage {
+				sent_at: 2,
+				msg: vec![i],
+			})
+			.collect();
+	}
+	MAX_PAUSED_MESSAGES.with(|m| *m.borrow_mut() = 1);
+
+	BlockTests::new()
+		.with_relay_sproof_builder(|_, relay_block_num, sproof| match relay_block_num {
+			1 => {}
+			2 => {
+				let mut mqc = MessageQueueChain::default();
+				for msg in MSGS.iter() {
+					mqc.extend_downward(msg);
+				}
+				sproof.dmq_mqc_head = Some(mqc.head());
+			}
+			_ => unreachable!(),
+		})
+		.with_inherent_data(|_, relay_block_num, data| match relay_block_num {
+			2 => {
+				data.downward_messages.extend(MSGS.iter().cloned());
+			}
+			1 => {}
+			_ => unreachable!(),
+		})
+		.add(1, || {
+			assert_ok!(ParachainSystem::set_inbound_messages_paused(RawOrigin::Root.into(), true));
+		})
+		.add(2, || {
+			HANDLED_DMP_MESSAGES.with(|m| assert!(m.borrow().is_empty()));
+			assert_eq!(PausedDownwardMessages::<Test>::get(), vec![MSGS[0].clone()]);
+			assert!(System::events().iter().any(|r| r.event ==
+				Event::parachain_system(crate::Event::PausedMessagesOverflowed(true, 1))));
+		});
+}
+
+#[test]
+fn round_robin_by_sender_interleaves_senders() {
+	let msg = |sent_at, data: &[u8]| InboundHrmpMessage {
+		sent_at,
+		data: data.to_vec(),
+	};
+	let mut messages = vec![
+		(ParaId::from(200), msg(1, b"a1")),
+		(ParaId::from(200), msg(1, b"a2")),
+		(ParaId::from(200), msg(1, b"a3")),
+		(ParaId::from(300), msg(1, b"b1")),
+		(ParaId::from(200), msg(2, b"a4")),
+		(ParaId::from(300), msg(2, b"b2")),
+	];
+
+	RoundRobinBySender::order_horizontal_messages(&mut messages);
+
+	let order = messages.into_iter().map(|(_, m)| m.data).collect::<Vec<_>>();
+	assert_eq!(
+		order,
+		vec![
+			b"a1".to_vec(),
+			b"b1".to_vec(),
+			b"a2".to_vec(),
+			b"b2".to_vec(),
+			b"a3".to_vec(),
+			b"a4".to_vec(),
+		],
+	);
+}
+
 #[test]
 fn receive_hrmp_empty_channel() {
 	BlockTests::new()
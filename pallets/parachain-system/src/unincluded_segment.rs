@@ -0,0 +1,135 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The unincluded segment is the chain of ancestors of the current block that are not yet
+//! included on the relay chain.
+//!
+//! The relay chain state as of the relay parent doesn't know about the messages sent by these
+//! ancestors. So, their used bandwidth is tracked to not exceed the limits of the relay chain
+//! when the ancestors get included.
+
+use super::relay_state_snapshot::MessagingStateSnapshot;
+use codec::{Decode, Encode};
+use cumulus_primitives_core::{ParaId, UpwardMessage};
+use polkadot_parachain::primitives::RelayChainBlockNumber;
+use sp_runtime::RuntimeDebug;
+use sp_std::{collections::btree_map::BTreeMap, vec::Vec};
+
+/// The bandwidth used by the messages sent in one HRMP channel.
+#[derive(Clone, Default, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct HrmpChannelUpdate {
+	/// The number of messages sent.
+	pub msg_count: u32,
+	/// The total size in bytes of the messages sent.
+	pub total_bytes: u32,
+}
+
+/// The messaging bandwidth used by one or more blocks.
+#[derive(Clone, Default, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct UsedBandwidth {
+	/// The number of upward messages sent.
+	pub ump_msg_count: u32,
+	/// The total size in bytes of the upward messages sent.
+	pub ump_total_bytes: u32,
+	/// The bandwidth used per outbound HRMP channel, by recipient.
+	pub hrmp_outgoing: BTreeMap<ParaId, HrmpChannelUpdate>,
+}
+
+impl UsedBandwidth {
+	/// The bandwidth used by sending the given upward and horizontal messages.
+	///
+	/// The horizontal messages are given as tuples of the recipient and the message.
+	pub fn from_messages(upward: &[UpwardMessage], horizontal: &[(ParaId, Vec<u8>)]) -> Self {
+		let mut hrmp_outgoing = BTreeMap::<ParaId, HrmpChannelUpdate>::new();
+		for (recipient, data) in horizontal {
+			let update = hrmp_outgoing.entry(*recipient).or_default();
+			update.msg_count = update.msg_count.saturating_add(1);
+			update.total_bytes = update.total_bytes.saturating_add(data.len() as u32);
+		}
+
+		Self {
+			ump_msg_count: upward.len() as u32,
+			ump_total_bytes: upward.iter().map(|msg| msg.len() as u32).sum(),
+			hrmp_outgoing,
+		}
+	}
+
+	/// Add the bandwidth used by `other`.
+	pub fn append(&mut self, other: &Self) {
+		self.ump_msg_count = self.ump_msg_count.saturating_add(other.ump_msg_count);
+		self.ump_total_bytes = self.ump_total_bytes.saturating_add(other.ump_total_bytes);
+
+		for (recipient, other) in &other.hrmp_outgoing {
+			let update = self.hrmp_outgoing.entry(*recipient).or_default();
+			update.msg_count = update.msg_count.saturating_add(other.msg_count);
+			update.total_bytes = update.total_bytes.saturating_add(other.total_bytes);
+		}
+	}
+
+	/// Add this bandwidth to the resource usage of the given messaging state.
+	///
+	/// The relay chain didn't see these messages yet, so they are not part of the state.
+	pub fn apply_to(&self, messaging_state: &mut MessagingStateSnapshot) {
+		let (count, size) = &mut messaging_state.relay_dispatch_queue_size;
+		*count = count.saturating_add(self.ump_msg_count);
+		*size = size.saturating_add(self.ump_total_bytes);
+
+		for (recipient, update) in &self.hrmp_outgoing {
+			if let Ok(index) = messaging_state
+				.egress_channels
+				.binary_search_by_key(recipient, |item| item.0)
+			{
+				let channel = &mut messaging_state.egress_channels[index].1;
+				channel.msg_count = channel.msg_count.saturating_add(update.msg_count);
+				channel.total_size = channel.total_size.saturating_add(update.total_bytes);
+			}
+		}
+	}
+}
+
+/// An ancestor of the current block that is not yet included on the relay chain.
+#[derive(Clone, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct Ancestor<H> {
+	/// The bandwidth used by the ancestor.
+	pub used_bandwidth: UsedBandwidth,
+	/// The hash of the ancestor.
+	///
+	/// `None` for the parent of the current block until the validation data is set, because the
+	/// hash of a block is only known after it was finalized.
+	pub para_head_hash: Option<H>,
+	/// The relay parent number of the ancestor.
+	pub relay_parent_number: RelayChainBlockNumber,
+}
+
+/// Remove all ancestors up to and including the one with the hash of the `included` head.
+///
+/// Nothing is removed if `included` is not part of the segment.
+pub fn remove_included<H: PartialEq>(segment: &mut Vec<Ancestor<H>>, included: &H) {
+	if let Some(index) = segment
+		.iter()
+		.position(|ancestor| ancestor.para_head_hash.as_ref() == Some(included))
+	{
+		segment.drain(..=index);
+	}
+}
+
+/// The total bandwidth used by all ancestors of the segment.
+pub fn total_used_bandwidth<H>(segment: &[Ancestor<H>]) -> UsedBandwidth {
+	segment.iter().fold(UsedBandwidth::default(), |mut total, ancestor| {
+		total.append(&ancestor.used_bandwidth);
+		total
+	})
+}
@@ -24,18 +24,17 @@ use sp_std::prelude::*;
 
 use hash_db::{HashDB, EMPTY_PREFIX};
 
-use polkadot_parachain::primitives::{
-	HeadData, ValidationCode, ValidationParams, ValidationResult,
-};
+use polkadot_parachain::primitives::{ValidationCode, ValidationParams, ValidationResult};
 
 use codec::{Decode, Encode};
 
 use cumulus_primitives_core::{
+	build_head_data,
 	well_known_keys::{
-		HRMP_OUTBOUND_MESSAGES, HRMP_WATERMARK, NEW_VALIDATION_CODE, PROCESSED_DOWNWARD_MESSAGES,
-		UPWARD_MESSAGES,
+		HEAD_DATA_EXTENSION, HRMP_OUTBOUND_MESSAGES, HRMP_WATERMARK, NEW_VALIDATION_CODE,
+		PROCESSED_DOWNWARD_MESSAGES, UPWARD_MESSAGES,
 	},
-	OutboundHrmpMessage, UpwardMessage,
+	HeadDataExtension, OutboundHrmpMessage, UpwardMessage,
 };
 use sp_core::storage::ChildInfo;
 use sp_externalities::{set_and_run_with_externalities, Externalities};
@@ -68,7 +67,7 @@ pub fn validate_block<B: BlockT, E: ExecuteBlock<B>, PSC: crate::Config>(
 
 	let (header, extrinsics, storage_proof) = block_data.deconstruct();
 
-	let head_data = HeadData(header.encode());
+	let encoded_header = header.encode();
 
 	let block = B::new(header, extrinsics);
 	assert!(
@@ -165,6 +164,12 @@ pub fn validate_block<B: BlockT, E: ExecuteBlock<B>, PSC: crate::Config>(
 		None => Vec::new(),
 	};
 
+	let head_data_extension = overlay.storage(HEAD_DATA_EXTENSION).flatten().map(|v| {
+		HeadDataExtension::decode(&mut &v[..])
+			.expect("Head data extension is not correctly encoded in the storage")
+	});
+	let head_data = build_head_data(encoded_header, head_data_extension.as_ref());
+
 	let hrmp_watermark = overlay
 		.storage(HRMP_WATERMARK)
 		.flatten()
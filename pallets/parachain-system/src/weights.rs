@@ -0,0 +1,85 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The weights of the dispatchables and of the message processing of this pallet.
+//!
+//! The functions are benchmarked in [`crate::benchmarking`].
+
+use frame_support::weights::Weight;
+
+/// The weight functions needed by this pallet.
+pub trait WeightInfo {
+	fn authorize_upgrade() -> Weight;
+	fn enact_authorized_upgrade(c: u32) -> Weight;
+	fn set_reserved_dmp_weight_override() -> Weight;
+	fn set_reserved_xcmp_weight_override() -> Weight;
+	fn set_inbound_messages_paused() -> Weight;
+	fn drop_paused_message() -> Weight;
+	fn force_set_mqc_head() -> Weight;
+	fn force_open_hrmp_channel_request() -> Weight;
+	fn accept_open_channel() -> Weight;
+	fn close_channel() -> Weight;
+	/// The weight of receiving `n` downward messages in `set_validation_data`.
+	///
+	/// This doesn't include the weight of the `DmpMessageHandler`, which reports its own weight.
+	fn receive_downward_messages(n: u32) -> Weight;
+	/// The weight of receiving `n` horizontal messages in `set_validation_data`.
+	///
+	/// This doesn't include the weight of the `XcmpMessageHandler`, which reports its own weight.
+	fn receive_horizontal_messages(n: u32) -> Weight;
+}
+
+/// The weights that were used before the pallet was benchmarked.
+///
+/// The receiving of messages is not accounted for.
+impl WeightInfo for () {
+	fn authorize_upgrade() -> Weight {
+		1_000_000
+	}
+	fn enact_authorized_upgrade(_c: u32) -> Weight {
+		1_000_000
+	}
+	fn set_reserved_dmp_weight_override() -> Weight {
+		1_000
+	}
+	fn set_reserved_xcmp_weight_override() -> Weight {
+		1_000
+	}
+	fn set_inbound_messages_paused() -> Weight {
+		1_000
+	}
+	fn drop_paused_message() -> Weight {
+		1_000_000
+	}
+	fn force_set_mqc_head() -> Weight {
+		1_000
+	}
+	fn force_open_hrmp_channel_request() -> Weight {
+		1_000_000
+	}
+	fn accept_open_channel() -> Weight {
+		1_000_000
+	}
+	fn close_channel() -> Weight {
+		1_000_000
+	}
+	fn receive_downward_messages(_n: u32) -> Weight {
+		0
+	}
+	fn receive_horizontal_messages(_n: u32) -> Weight {
+		0
+	}
+}
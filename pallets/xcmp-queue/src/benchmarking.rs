@@ -0,0 +1,259 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Benchmarks of the XCMP queue pallet.
+//!
+//! The inbound messages are benchmarked without executing them, as the weight of the execution
+//! is determined by the `XcmExecutor`. The receiving of pages is measured while the execution is
+//! suspended, and the processing of the XCMs of a page while they are deferred.
+
+use super::*;
+use frame_benchmarking::{benchmarks, impl_benchmark_test_suite};
+use xcm::v0::OriginKind;
+
+/// The sibling used in the benchmarks.
+const SIBLING: u32 = 2000;
+
+/// The maximum size of the messages used in the benchmarks.
+const MAX_MESSAGE_SIZE: u32 = 64 * 1024;
+
+/// The maximum number of messages, pages or channels used in the benchmarks.
+const MAX_MESSAGES: u32 = 100;
+
+/// The size of the messages used in the benchmarks that don't vary it.
+const MESSAGE_SIZE: u32 = 1024;
+
+/// An encoded XCM with a call of `s` bytes.
+fn encoded_xcm<T: Config>(s: u32) -> Vec<u8> {
+	let xcm = Xcm::<T::Call>::Transact {
+		origin_type: OriginKind::Native,
+		require_weight_at_most: 0,
+		call: vec![0; s as usize].into(),
+	};
+	VersionedXcm::<T::Call>::from(xcm).encode()
+}
+
+/// A page of `m` XCMs with calls of `s` bytes.
+fn xcm_page<T: Config>(m: u32, s: u32) -> Vec<u8> {
+	let mut page = XcmpMessageFormat::ConcatenatedVersionedXcm.encode();
+	for _ in 0..m {
+		page.extend_from_slice(&encoded_xcm::<T>(s));
+	}
+	page
+}
+
+benchmarks! {
+	update_suspend_threshold {
+		let origin = T::ControllerOrigin::successful_origin();
+	}: _<T::Origin>(origin, 3)
+	verify {
+		assert_eq!(QueueConfig::<T>::get().suspend_threshold, 3);
+	}
+
+	update_resume_threshold {
+		let origin = T::ControllerOrigin::successful_origin();
+	}: _<T::Origin>(origin, 0)
+	verify {
+		assert_eq!(QueueConfig::<T>::get().resume_threshold, 0);
+	}
+
+	update_drop_threshold {
+		let origin = T::ControllerOrigin::successful_origin();
+	}: _<T::Origin>(origin, 10)
+	verify {
+		assert_eq!(QueueConfig::<T>::get().drop_threshold, 10);
+	}
+
+	update_queue_config {
+		let origin = T::ControllerOrigin::successful_origin();
+		let config = QueueConfigData { threshold_weight: 1_000, ..Default::default() };
+	}: _<T::Origin>(origin, config)
+	verify {
+		assert_eq!(QueueConfig::<T>::get(), config);
+	}
+
+	suspend_xcm_execution {
+		let origin = T::ControllerOrigin::successful_origin();
+	}: _<T::Origin>(origin)
+	verify {
+		assert!(QueueSuspended::<T>::get());
+	}
+
+	resume_xcm_execution {
+		let origin = T::ControllerOrigin::successful_origin();
+		QueueSuspended::<T>::put(true);
+	}: _<T::Origin>(origin)
+	verify {
+		assert!(!QueueSuspended::<T>::get());
+	}
+
+	pause_channel {
+		let origin = T::ControllerOrigin::successful_origin();
+	}: _<T::Origin>(origin, SIBLING.into())
+	verify {
+		assert!(PausedChannels::<T>::get().contains(&SIBLING.into()));
+	}
+
+	resume_channel {
+		let origin = T::ControllerOrigin::successful_origin();
+		PausedChannels::<T>::mutate(|paused| paused.insert(SIBLING.into()));
+	}: _<T::Origin>(origin, SIBLING.into())
+	verify {
+		assert!(PausedChannels::<T>::get().is_empty());
+	}
+
+	set_deferral_period {
+		let origin = T::ControllerOrigin::successful_origin();
+		let period = T::BlockNumber::from(10u32);
+	}: _<T::Origin>(origin, SIBLING.into(), period)
+	verify {
+		assert_eq!(DeferralPeriods::<T>::get().get(&SIBLING.into()), Some(&period));
+	}
+
+	cancel_deferred_xcm {
+		let origin = T::ControllerOrigin::successful_origin();
+		let index = Pallet::<T>::defer_xcm(
+			SIBLING.into(),
+			1,
+			10u32.into(),
+			encoded_xcm::<T>(MESSAGE_SIZE),
+		);
+	}: _<T::Origin>(origin, SIBLING.into(), index)
+	verify {
+		assert!(!DeferredXcms::<T>::contains_key(ParaId::from(SIBLING), index));
+	}
+
+	set_inbound_channel_priority {
+		let origin = T::ControllerOrigin::successful_origin();
+	}: _<T::Origin>(origin, SIBLING.into(), 1)
+	verify {
+		assert_eq!(InboundChannelPriorities::<T>::get().get(&SIBLING.into()), Some(&1));
+	}
+
+	set_outbound_rate_limit {
+		let origin = T::ControllerOrigin::successful_origin();
+		let limit = OutboundRateLimit {
+			max_messages_per_block: 10,
+			max_bytes_per_block: 10_000,
+			max_overflow_messages: 100,
+		};
+	}: _<T::Origin>(origin, SIBLING.into(), Some(limit))
+	verify {
+		assert_eq!(OutboundRateLimits::<T>::get().get(&SIBLING.into()), Some(&limit));
+	}
+
+	enqueue_fragment {
+		let s in 1 .. MAX_MESSAGE_SIZE / 2;
+		// A page that can't take the fragment, so a new page is created.
+		let recipient = ParaId::from(SIBLING);
+		OutboundXcmpStatus::<T>::put(vec![(recipient, OutboundStatus::Ok, false, 1, 2)]);
+		let mut page = XcmpMessageFormat::ConcatenatedEncodedBlob.encode();
+		page.resize(MAX_MESSAGE_SIZE as usize, 0);
+		OutboundXcmpMessages::<T>::insert(recipient, 1, page);
+		let data = vec![0u8; s as usize].encode();
+	}: {
+		Pallet::<T>::enqueue_fragment(
+			recipient,
+			XcmpMessageFormat::ConcatenatedEncodedBlob,
+			&data,
+			MAX_MESSAGE_SIZE as usize,
+		);
+	}
+	verify {
+		assert!(OutboundXcmpMessages::<T>::contains_key(recipient, 2));
+	}
+
+	receive_xcmp_pages {
+		let n in 1 .. MAX_MESSAGES;
+		QueueSuspended::<T>::put(true);
+		let pages = (0..n)
+			.map(|i| (ParaId::from(SIBLING + i), 1, xcm_page::<T>(1, MESSAGE_SIZE)))
+			.collect::<Vec<_>>();
+	}: {
+		Pallet::<T>::handle_xcmp_messages(
+			pages.iter().map(|(sender, sent_at, page)| (*sender, *sent_at, &page[..])),
+			Weight::max_value(),
+		);
+	}
+	verify {
+		assert_eq!(InboundXcmpStatus::<T>::get().len(), n as usize);
+	}
+
+	defer_xcms {
+		let m in 1 .. MAX_MESSAGES;
+		let sender = ParaId::from(SIBLING);
+		let mut periods = BTreeMap::new();
+		periods.insert(sender, T::BlockNumber::from(10u32));
+		DeferralPeriods::<T>::put(periods);
+		let format = XcmpMessageFormat::ConcatenatedVersionedXcm;
+		let mut page = &xcm_page::<T>(m, MESSAGE_SIZE)[..];
+		let _ = XcmpMessageFormat::decode(&mut page);
+		InboundXcmpMessages::<T>::insert(sender, 1, page);
+	}: {
+		Pallet::<T>::process_xcmp_message(
+			sender,
+			(1, format),
+			Weight::max_value(),
+			Weight::max_value(),
+		);
+	}
+	verify {
+		assert_eq!(DeferredIndices::<T>::get().get(&sender), Some(&(0, m as DeferredIndex)));
+	}
+
+	stash_overweight {
+		let s in 1 .. MAX_MESSAGE_SIZE;
+		let xcm = encoded_xcm::<T>(s);
+	}: {
+		Pallet::<T>::stash_overweight(SIBLING.into(), 1, xcm);
+	}
+	verify {
+		assert!(Overweight::<T>::contains_key(0));
+	}
+
+	process_suspend_signal {
+		let n in 1 .. MAX_MESSAGES;
+		// The signalling channel is the last one to be found.
+		let status = (0..n)
+			.map(|i| (ParaId::from(SIBLING + i), OutboundStatus::Ok, false, 0, 0))
+			.collect::<Vec<_>>();
+		OutboundXcmpStatus::<T>::put(status);
+		let sender = ParaId::from(SIBLING + n - 1);
+		let page = (XcmpMessageFormat::Signals, ChannelSignal::Suspend).encode();
+	}: {
+		Pallet::<T>::handle_xcmp_messages(
+			sp_std::iter::once((sender, 1, &page[..])),
+			Weight::max_value(),
+		);
+	}
+	verify {
+		assert_eq!(OutboundXcmpStatus::<T>::get()[n as usize - 1].1, OutboundStatus::Suspended);
+	}
+
+	service_overweight {
+		// Everything but the execution, which is charged by the `weight_limit`.
+		Overweight::<T>::insert(0, (ParaId::from(SIBLING), 1, encoded_xcm::<T>(MESSAGE_SIZE)));
+	}: {
+		let (_, _, data) = Overweight::<T>::get(0).unwrap();
+		let _ = VersionedXcm::<T::Call>::decode(&mut &data[..]);
+		Overweight::<T>::remove(0);
+	}
+	verify {
+		assert!(!Overweight::<T>::contains_key(0));
+	}
+}
+
+impl_benchmark_test_suite!(Pallet, crate::mock::new_test_ext(), crate::mock::Test);
@@ -28,21 +28,165 @@
 use codec::{Decode, Encode};
 use cumulus_primitives_core::{
 	relay_chain::BlockNumber as RelayBlockNumber, ChannelStatus, GetChannelInfo, MessageSendError,
-	ParaId, XcmpMessageHandler, XcmpMessageSource,
+	ParaId, XcmpChannelQueueStatus, XcmpMessageHandler, XcmpMessageSource, XcmpQueueStatus,
+};
+use frame_support::{
+	dispatch::DispatchResult,
+	ensure,
+	traits::{EnsureOrigin, ExistenceRequirement, Get, OnUnbalanced, WithdrawReasons},
+	weights::{constants::WEIGHT_PER_MILLIS, Weight},
 };
-use frame_support::weights::Weight;
 use rand_chacha::{
 	rand_core::{RngCore, SeedableRng},
 	ChaChaRng,
 };
-use sp_runtime::{traits::Hash, RuntimeDebug};
-use sp_std::{convert::TryFrom, prelude::*};
+use sp_runtime::{
+	traits::{Hash, SaturatedConversion, Saturating, Zero},
+	FixedPointNumber, FixedU128, RuntimeDebug,
+};
+use sp_std::{
+	cmp,
+	collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+	convert::TryFrom,
+	marker::PhantomData,
+	prelude::*,
+};
 use xcm::{
 	v0::{Error as XcmError, ExecuteXcm, Junction, MultiLocation, Outcome, SendXcm, Xcm},
 	VersionedXcm,
 };
+use xcm_executor::traits::Convert;
+
+pub mod weights;
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking;
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod tests;
 
 pub use pallet::*;
+pub use weights::WeightInfo;
+
+/// Index used to identify overweight XCMs.
+pub type OverweightIndex = u64;
+
+/// Index used to identify the deferred XCMs of a sender.
+pub type DeferredIndex = u64;
+
+/// The version of an XCM.
+pub type XcmVersion = u32;
+
+/// Returns the version of the given XCM.
+pub fn xcm_version_of<Call>(xcm: &VersionedXcm<Call>) -> XcmVersion {
+	match xcm {
+		VersionedXcm::V0(_) => 0,
+	}
+}
+
+/// The constants of the delivery fee factor of the outbound channels.
+pub mod delivery_fee_constants {
+	use super::FixedU128;
+
+	/// The queue of a channel is congested once it has more than the suspend threshold divided by
+	/// this factor in pages.
+	pub const THRESHOLD_FACTOR: u32 = 2;
+	/// The factor by which the delivery fee factor is multiplied for every message sent to a
+	/// congested channel, and divided for every page sent from an uncongested one.
+	pub fn exponential_fee_base() -> FixedU128 {
+		FixedU128::saturating_from_rational(105, 100)
+	}
+	/// The additional growth of the delivery fee factor per KiB of a message sent to a congested
+	/// channel.
+	pub fn message_size_fee_base() -> FixedU128 {
+		FixedU128::saturating_from_rational(1, 1000)
+	}
+}
+
+/// Something that tracks the delivery fee factor of the channels to sibling parachains.
+pub trait FeeTracker {
+	/// Returns the delivery fee factor of the channel to `para`.
+	fn get_fee_factor(para: ParaId) -> FixedU128;
+}
+
+/// The price of delivering a message to a sibling parachain.
+pub trait PriceForSiblingDelivery {
+	/// Returns the price of delivering a message of `message_size` bytes to `para`.
+	fn price_for_sibling_delivery(para: ParaId, message_size: usize) -> u128;
+}
+
+impl PriceForSiblingDelivery for () {
+	fn price_for_sibling_delivery(_: ParaId, _: usize) -> u128 {
+		0
+	}
+}
+
+/// A delivery price of `BaseFee + ByteFee * message_size`, multiplied by the delivery fee factor
+/// that `F` tracks for the destination.
+pub struct ExponentialPrice<BaseFee, ByteFee, F>(PhantomData<(BaseFee, ByteFee, F)>);
+
+impl<BaseFee: Get<u128>, ByteFee: Get<u128>, F: FeeTracker> PriceForSiblingDelivery
+	for ExponentialPrice<BaseFee, ByteFee, F>
+{
+	fn price_for_sibling_delivery(para: ParaId, message_size: usize) -> u128 {
+		let price = BaseFee::get()
+			.saturating_add(ByteFee::get().saturating_mul(message_size as u128));
+		F::get_fee_factor(para).saturating_mul_int(price)
+	}
+}
+
+/// An XCM router that charges the delivery fee of the XCMs for sibling parachains before passing
+/// them on to `Router`.
+///
+/// Only the XCMs that are relayed from a local account, as `pallet-xcm` sends them, are charged.
+/// The account, as converted by `Converter`, pays the price of `Price` in `Currency` and
+/// the fee is handed to `OnCharge`. The fee is refunded if `Router` fails to send the XCM. All
+/// other XCMs are passed on without a fee.
+pub struct ChargeSiblingDeliveryFee<Router, Price, Converter, AccountId, Currency, OnCharge>(
+	PhantomData<(Router, Price, Converter, AccountId, Currency, OnCharge)>,
+);
+
+impl<Router, Price, Converter, AccountId, Currency, OnCharge> SendXcm
+	for ChargeSiblingDeliveryFee<Router, Price, Converter, AccountId, Currency, OnCharge>
+where
+	Router: SendXcm,
+	Price: PriceForSiblingDelivery,
+	Converter: Convert<MultiLocation, AccountId>,
+	AccountId: Clone,
+	Currency: frame_support::traits::Currency<AccountId>,
+	OnCharge: OnUnbalanced<Currency::NegativeImbalance>,
+{
+	fn send_xcm(dest: MultiLocation, msg: Xcm<()>) -> Result<(), XcmError> {
+		let (para, who) = match (&dest, &msg) {
+			(
+				MultiLocation::X2(Junction::Parent, Junction::Parachain(id)),
+				Xcm::RelayedFrom { who, .. },
+			) => (ParaId::from(*id), who),
+			_ => return Router::send_xcm(dest, msg),
+		};
+		let payer = Converter::convert_ref(who).map_err(|()| XcmError::BadOrigin)?;
+		let message_size = VersionedXcm::<()>::from(msg.clone()).encoded_size();
+		let price = Price::price_for_sibling_delivery(para, message_size);
+		let fee = Currency::withdraw(
+			&payer,
+			price.saturated_into(),
+			WithdrawReasons::FEE,
+			ExistenceRequirement::KeepAlive,
+		)
+		.map_err(|_| XcmError::TooExpensive)?;
+
+		match Router::send_xcm(dest, msg) {
+			Ok(()) => {
+				OnCharge::on_unbalanced(fee);
+				Ok(())
+			}
+			Err(e) => {
+				let _ = Currency::resolve_creating(&payer, fee);
+				Err(e)
+			}
+		}
+	}
+}
 
 #[frame_support::pallet]
 pub mod pallet {
@@ -63,6 +207,15 @@ pub mod pallet {
 
 		/// Information on the avaialble XCMP channels.
 		type ChannelInfo: GetChannelInfo;
+
+		/// Origin which is allowed to execute overweight messages.
+		type ExecuteOverweightOrigin: EnsureOrigin<Self::Origin>;
+
+		/// Origin which is allowed to change the `QueueConfig`.
+		type ControllerOrigin: EnsureOrigin<Self::Origin>;
+
+		/// Weight info for functions and calls.
+		type WeightInfo: WeightInfo;
 	}
 
 	impl Default for QueueConfigData {
@@ -73,24 +226,307 @@ pub mod pallet {
 				resume_threshold: 1,
 				threshold_weight: 100_000,
 				weight_restrict_decay: 2,
+				inherent_weight_limit: Weight::max_value(),
+				idle_weight_limit: Weight::max_value(),
+				// 10 ms of execution time maximum by default
+				xcmp_max_individual_weight: 10 * WEIGHT_PER_MILLIS,
+				duplicate_window: 0,
 			}
 		}
 	}
 
 	#[pallet::hooks]
 	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
-		fn on_idle(_now: T::BlockNumber, max_weight: Weight) -> Weight {
-			// on_idle processes additional messages with any remaining block weight.
-			Self::service_xcmp_queue(max_weight)
+		fn on_initialize(_n: T::BlockNumber) -> Weight {
+			// The outbound rate limits are per block, so the fragments that exceeded them in the
+			// previous blocks are placed on the queue first.
+			Self::release_outbound_overflow()
+		}
+
+		fn on_idle(now: T::BlockNumber, max_weight: Weight) -> Weight {
+			// on_idle processes additional messages with any remaining block weight, up to the
+			// configured limit. The deferred XCMs that are due are executed first, as they were
+			// received earlier.
+			let limit = max_weight.min(<QueueConfig<T>>::get().idle_weight_limit);
+			let weight_used = Self::service_deferred_xcms(now, limit);
+			weight_used.saturating_add(Self::service_xcmp_queue(limit.saturating_sub(weight_used)))
 		}
 	}
 
 	#[pallet::call]
-	impl<T: Config> Pallet<T> {}
+	impl<T: Config> Pallet<T> {
+		/// Service a single overweight XCM.
+		///
+		/// - `origin`: Must pass `ExecuteOverweightOrigin`.
+		/// - `index`: The index of the overweight XCM to service.
+		/// - `weight_limit`: The amount of weight that XCM execution may take.
+		///
+		/// Errors:
+		/// - `BadOverweightIndex`: XCM under `index` is not found in the `Overweight` storage map.
+		/// - `BadXcm`: XCM under `index` cannot be properly decoded into a valid XCM format.
+		/// - `WeightOverLimit`: XCM execution may use greater `weight_limit`.
+		///
+		/// Events:
+		/// - `OverweightServiced`: On success.
+		#[pallet::weight(weight_limit.saturating_add(T::WeightInfo::service_overweight()))]
+		pub fn service_overweight(
+			origin: OriginFor<T>,
+			index: OverweightIndex,
+			weight_limit: Weight,
+		) -> DispatchResultWithPostInfo {
+			T::ExecuteOverweightOrigin::ensure_origin(origin)?;
+
+			let (sender, sent_at, data) =
+				Overweight::<T>::get(index).ok_or(Error::<T>::BadOverweightIndex)?;
+			let xcm = VersionedXcm::<T::Call>::decode(&mut &data[..])
+				.map_err(|_| Error::<T>::BadXcm)?;
+			let used = Self::handle_xcm_message(sender, sent_at, xcm, weight_limit)
+				.map_err(|_| Error::<T>::WeightOverLimit)?;
+			Overweight::<T>::remove(index);
+			Self::deposit_event(Event::OverweightServiced(index, used));
+			Ok(Some(used.saturating_add(T::WeightInfo::service_overweight())).into())
+		}
+
+		/// Overwrite the number of pages of messages which must be in the queue for the other side
+		/// to be told to suspend their sending.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		/// - `new`: Desired value for `QueueConfigData.suspend_threshold`.
+		///
+		/// Errors:
+		/// - `BadQueueConfig`: The thresholds would be inconsistent, see `update_resume_threshold`.
+		#[pallet::weight((T::WeightInfo::update_suspend_threshold(), DispatchClass::Operational))]
+		pub fn update_suspend_threshold(origin: OriginFor<T>, new: u32) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			Self::mutate_queue_config(|data| data.suspend_threshold = new)
+		}
+
+		/// Overwrite the number of pages of messages which the queue must be reduced to before it
+		/// signals that message sending may recommence after it has been suspended.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		/// - `new`: Desired value for `QueueConfigData.resume_threshold`.
+		///
+		/// Errors:
+		/// - `BadQueueConfig`: The resume threshold must be below the suspend threshold, which
+		///   must not be above the drop threshold.
+		#[pallet::weight((T::WeightInfo::update_resume_threshold(), DispatchClass::Operational))]
+		pub fn update_resume_threshold(origin: OriginFor<T>, new: u32) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			Self::mutate_queue_config(|data| data.resume_threshold = new)
+		}
+
+		/// Overwrite the number of pages of messages which must be in the queue after which the
+		/// oldest pages of the channel are dropped to make room for new ones.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		/// - `new`: Desired value for `QueueConfigData.drop_threshold`.
+		///
+		/// Errors:
+		/// - `BadQueueConfig`: The drop threshold must not be below the suspend threshold.
+		#[pallet::weight((T::WeightInfo::update_drop_threshold(), DispatchClass::Operational))]
+		pub fn update_drop_threshold(origin: OriginFor<T>, new: u32) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			Self::mutate_queue_config(|data| data.drop_threshold = new)
+		}
+
+		/// Overwrite the whole `QueueConfig`.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		/// - `new`: The new queue configuration.
+		///
+		/// Errors:
+		/// - `BadQueueConfig`: The thresholds of `new` are inconsistent.
+		#[pallet::weight((T::WeightInfo::update_queue_config(), DispatchClass::Operational))]
+		pub fn update_queue_config(origin: OriginFor<T>, new: QueueConfigData) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			Self::mutate_queue_config(|data| *data = new)
+		}
+
+		/// Suspend the execution of all inbound XCMP messages.
+		///
+		/// The messages are still received and queued, and channel signals are still handled.
+		/// This is meant as an emergency stop.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		///
+		/// Events:
+		/// - `XcmExecutionSuspended`: On success.
+		#[pallet::weight((T::WeightInfo::suspend_xcm_execution(), DispatchClass::Operational))]
+		pub fn suspend_xcm_execution(origin: OriginFor<T>) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			<QueueSuspended<T>>::put(true);
+			Self::deposit_event(Event::XcmExecutionSuspended);
+			Ok(())
+		}
+
+		/// Resume the execution of inbound XCMP messages after `suspend_xcm_execution`.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		///
+		/// Events:
+		/// - `XcmExecutionResumed`: On success.
+		#[pallet::weight((T::WeightInfo::resume_xcm_execution(), DispatchClass::Operational))]
+		pub fn resume_xcm_execution(origin: OriginFor<T>) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			<QueueSuspended<T>>::kill();
+			Self::deposit_event(Event::XcmExecutionResumed);
+			Ok(())
+		}
+
+		/// Set the number of blocks the XCMs of `sender` are deferred before they are executed.
+		///
+		/// While they are deferred, the XCMs are stored in `DeferredXcms` and can be cancelled
+		/// with `cancel_deferred_xcm`. A period of `0` removes the deferral.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		/// - `sender`: The sender of the inbound channel.
+		/// - `period`: The number of blocks an XCM is deferred after it was received.
+		///
+		/// Events:
+		/// - `DeferralPeriodSet`: On success, with `None` if the deferral was removed.
+		#[pallet::weight((T::WeightInfo::set_deferral_period(), DispatchClass::Operational))]
+		pub fn set_deferral_period(
+			origin: OriginFor<T>,
+			sender: ParaId,
+			period: T::BlockNumber,
+		) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			<DeferralPeriods<T>>::mutate(|periods| {
+				if period.is_zero() {
+					periods.remove(&sender);
+				} else {
+					periods.insert(sender, period);
+				}
+			});
+			let period = if period.is_zero() { None } else { Some(period) };
+			Self::deposit_event(Event::DeferralPeriodSet(sender, period));
+			Ok(())
+		}
+
+		/// Cancel a deferred XCM, so that it is never executed.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		/// - `sender`: The sender of the deferred XCM.
+		/// - `index`: The index of the deferred XCM among the deferred XCMs of `sender`.
+		///
+		/// Errors:
+		/// - `BadDeferredIndex`: XCM under `index` is not found in the `DeferredXcms` storage map.
+		///
+		/// Events:
+		/// - `DeferredXcmCancelled`: On success.
+		#[pallet::weight((T::WeightInfo::cancel_deferred_xcm(), DispatchClass::Operational))]
+		pub fn cancel_deferred_xcm(
+			origin: OriginFor<T>,
+			sender: ParaId,
+			index: DeferredIndex,
+		) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			<DeferredXcms<T>>::take(sender, index).ok_or(Error::<T>::BadDeferredIndex)?;
+			Self::deposit_event(Event::DeferredXcmCancelled(sender, index));
+			Ok(())
+		}
+
+		/// Set the priority with which the inbound messages of `sender` are serviced.
+		///
+		/// Channels with a higher priority are serviced before channels with a lower priority.
+		/// Channels of the same priority are serviced in a random order. A priority of `0`, the
+		/// default, removes the channel from `InboundChannelPriorities`.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		/// - `sender`: The sender of the inbound channel.
+		/// - `priority`: The new priority of the channel.
+		///
+		/// Events:
+		/// - `ChannelPrioritySet`: On success.
+		#[pallet::weight((
+			T::WeightInfo::set_inbound_channel_priority(),
+			DispatchClass::Operational,
+		))]
+		pub fn set_inbound_channel_priority(
+			origin: OriginFor<T>,
+			sender: ParaId,
+			priority: u32,
+		) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			<InboundChannelPriorities<T>>::mutate(|priorities| {
+				if priority == 0 {
+					priorities.remove(&sender);
+				} else {
+					priorities.insert(sender, priority);
+				}
+			});
+			Self::deposit_event(Event::ChannelPrioritySet(sender, priority));
+			Ok(())
+		}
+
+		/// Pause the execution of the inbound messages of `sender`.
+		///
+		/// The messages are still received and queued, and channel signals are still handled.
+		/// This also holds back the deferred XCMs of `sender`.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		/// - `sender`: The sender of the inbound channel.
+		///
+		/// Events:
+		/// - `ChannelPaused`: On success.
+		#[pallet::weight((T::WeightInfo::pause_channel(), DispatchClass::Operational))]
+		pub fn pause_channel(origin: OriginFor<T>, sender: ParaId) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			<PausedChannels<T>>::mutate(|paused| paused.insert(sender));
+			Self::deposit_event(Event::ChannelPaused(sender));
+			Ok(())
+		}
+
+		/// Resume the execution of the inbound messages of `sender`.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		/// - `sender`: The sender of the inbound channel.
+		///
+		/// Events:
+		/// - `ChannelResumed`: On success.
+		#[pallet::weight((T::WeightInfo::resume_channel(), DispatchClass::Operational))]
+		pub fn resume_channel(origin: OriginFor<T>, sender: ParaId) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			<PausedChannels<T>>::mutate(|paused| paused.remove(&sender));
+			Self::deposit_event(Event::ChannelResumed(sender));
+			Ok(())
+		}
+
+		/// Set the rate limit of the outbound messages to `dest`.
+		///
+		/// The messages that exceed the limit in a block are kept aside, and are placed on the
+		/// outbound queue in the following blocks as the limit allows. `None` removes the limit.
+		///
+		/// - `origin`: Must pass `ControllerOrigin`.
+		/// - `dest`: The recipient of the outbound channel.
+		/// - `limit`: The new rate limit of the channel.
+		#[pallet::weight((T::WeightInfo::set_outbound_rate_limit(), DispatchClass::Operational))]
+		pub fn set_outbound_rate_limit(
+			origin: OriginFor<T>,
+			dest: ParaId,
+			limit: Option<OutboundRateLimit>,
+		) -> DispatchResult {
+			T::ControllerOrigin::ensure_origin(origin)?;
+			<OutboundRateLimits<T>>::mutate(|limits| match limit {
+				Some(limit) => limits.insert(dest, limit),
+				None => limits.remove(&dest),
+			});
+			if limit.is_none() {
+				// The usage is only tracked for the rate limited channels.
+				<OutboundRateUsage<T>>::mutate(|usage| usage.remove(&dest));
+			}
+			Ok(())
+		}
+	}
 
 	#[pallet::event]
 	#[pallet::generate_deposit(pub(super) fn deposit_event)]
-	#[pallet::metadata(Option<T::Hash> = "Option<Hash>")]
+	#[pallet::metadata(
+		Option<T::Hash> = "Option<Hash>",
+		T::Hash = "Hash",
+		T::BlockNumber = "BlockNumber"
+	)]
 	pub enum Event<T: Config> {
 		/// Some XCM was executed ok.
 		Success(Option<T::Hash>),
@@ -104,6 +540,55 @@ pub mod pallet {
 		UpwardMessageSent(Option<T::Hash>),
 		/// An HRMP message was sent to a sibling parachain.
 		XcmpMessageSent(Option<T::Hash>),
+		/// An XCM exceeded the individual message weight budget and was placed in the
+		/// overweight queue.
+		/// \[ sender, sent_at, index, required \]
+		OverweightEnqueued(ParaId, RelayBlockNumber, OverweightIndex, Weight),
+		/// An XCM from the overweight queue was executed with the given actual weight used.
+		/// \[ index, used \]
+		OverweightServiced(OverweightIndex, Weight),
+		/// The execution of inbound XCMP messages was suspended.
+		XcmExecutionSuspended,
+		/// The execution of inbound XCMP messages was resumed.
+		XcmExecutionResumed,
+		/// The execution of the inbound messages of a sender was paused.
+		/// \[ sender \]
+		ChannelPaused(ParaId),
+		/// The execution of the inbound messages of a sender was resumed.
+		/// \[ sender \]
+		ChannelResumed(ParaId),
+		/// An XCM of a sender with a deferral period was deferred until the given block.
+		/// \[ sender, sent_at, index, execute_after \]
+		XcmDeferred(ParaId, RelayBlockNumber, DeferredIndex, T::BlockNumber),
+		/// A deferred XCM was cancelled.
+		/// \[ sender, index \]
+		DeferredXcmCancelled(ParaId, DeferredIndex),
+		/// An inbound XCM was processed with the given weight used and outcome.
+		/// \[ hash, sender, sent_at, weight_used, outcome \]
+		XcmProcessed(T::Hash, ParaId, RelayBlockNumber, Weight, XcmProcessingOutcome),
+		/// An outbound message was placed on the queue of the channel to the given destination.
+		/// \[ destination, hash \]
+		XcmpMessageQueued(ParaId, T::Hash),
+		/// An XCM of a higher version than before was handled from the given sibling.
+		/// \[ sibling, version \]
+		XcmVersionUpdated(ParaId, XcmVersion),
+		/// An outbound XCM was downgraded to the highest version the recipient is known to handle.
+		/// \[ recipient, from, to \]
+		XcmDowngraded(ParaId, XcmVersion, XcmVersion),
+		/// An inbound XCM was skipped, as it is a duplicate of a recently processed XCM of the
+		/// same sender.
+		/// \[ sender, sent_at, hash \]
+		DuplicateSkipped(ParaId, RelayBlockNumber, T::Hash),
+		/// The oldest inbound pages of a channel were dropped, as the channel reached the drop
+		/// threshold.
+		/// \[ sender, count \]
+		InboundPagesDropped(ParaId, u32),
+		/// The priority with which the inbound messages of a sender are serviced was set.
+		/// \[ sender, priority \]
+		ChannelPrioritySet(ParaId, u32),
+		/// The deferral period of a sender was set, or removed if `None`.
+		/// \[ sender, period \]
+		DeferralPeriodSet(ParaId, Option<T::BlockNumber>),
 	}
 
 	#[pallet::error]
@@ -114,6 +599,14 @@ pub mod pallet {
 		BadXcmOrigin,
 		/// Bad XCM data.
 		BadXcm,
+		/// Bad overweight index.
+		BadOverweightIndex,
+		/// Provided weight is possibly not enough to execute the message.
+		WeightOverLimit,
+		/// The queue configuration is inconsistent.
+		BadQueueConfig,
+		/// Bad deferred index.
+		BadDeferredIndex,
 	}
 
 	/// Status of the inbound XCMP channels.
@@ -156,6 +649,40 @@ pub mod pallet {
 	pub(super) type OutboundXcmpMessages<T: Config> =
 		StorageDoubleMap<_, Blake2_128Concat, ParaId, Twox64Concat, u16, Vec<u8>, ValueQuery>;
 
+	/// The hashes of the most recently processed XCMs per sender, oldest first.
+	///
+	/// At most `QueueConfigData.duplicate_window` hashes are kept per sender. The hashes of a
+	/// sender are trimmed to the current window whenever its messages are processed, and removed
+	/// when the window is `0` or the channel to the sender is closed.
+	#[pallet::storage]
+	pub(super) type RecentXcmHashes<T: Config> =
+		StorageMap<_, Blake2_128Concat, ParaId, Vec<T::Hash>, ValueQuery>;
+
+	/// The highest XCM version that was handled from a sibling, and thus is known to be
+	/// supported by it.
+	#[pallet::storage]
+	pub(super) type ChannelXcmVersions<T: Config> =
+		StorageMap<_, Blake2_128Concat, ParaId, XcmVersion>;
+
+	/// The rate limits of the outbound channels, by recipient.
+	///
+	/// See `set_outbound_rate_limit`.
+	#[pallet::storage]
+	pub(super) type OutboundRateLimits<T: Config> =
+		StorageValue<_, BTreeMap<ParaId, OutboundRateLimit>, ValueQuery>;
+
+	/// The number and the total size in bytes of the messages placed on the outbound queues of
+	/// the rate limited channels in this block, by recipient.
+	#[pallet::storage]
+	pub(super) type OutboundRateUsage<T: Config> =
+		StorageValue<_, BTreeMap<ParaId, (u32, u32)>, ValueQuery>;
+
+	/// The outbound messages that exceeded the rate limit of their channel, in the order they
+	/// were sent, with the format of the page they go into.
+	#[pallet::storage]
+	pub(super) type OutboundOverflow<T: Config> =
+		StorageMap<_, Blake2_128Concat, ParaId, Vec<(XcmpMessageFormat, Vec<u8>)>, ValueQuery>;
+
 	/// Any signal messages waiting to be sent.
 	#[pallet::storage]
 	pub(super) type SignalMessages<T: Config> =
@@ -164,6 +691,80 @@ pub mod pallet {
 	/// The configuration which controls the dynamics of the outbound queue.
 	#[pallet::storage]
 	pub(super) type QueueConfig<T: Config> = StorageValue<_, QueueConfigData, ValueQuery>;
+
+	/// The messages that exceeded max individual message weight budget.
+	///
+	/// These message stay in this storage map until they are manually dispatched via
+	/// `service_overweight`.
+	#[pallet::storage]
+	pub(super) type Overweight<T: Config> =
+		StorageMap<_, Twox64Concat, OverweightIndex, (ParaId, RelayBlockNumber, Vec<u8>)>;
+
+	/// Whether the execution of inbound XCMP messages is suspended.
+	///
+	/// See `suspend_xcm_execution`.
+	#[pallet::storage]
+	pub(super) type QueueSuspended<T: Config> = StorageValue<_, bool, ValueQuery>;
+
+	/// The senders whose inbound messages are not executed.
+	///
+	/// See `pause_channel`.
+	#[pallet::storage]
+	pub(super) type PausedChannels<T: Config> = StorageValue<_, BTreeSet<ParaId>, ValueQuery>;
+
+	/// The number of blocks the XCMs of a sender are deferred before they are executed.
+	///
+	/// See `set_deferral_period`.
+	#[pallet::storage]
+	pub(super) type DeferralPeriods<T: Config> =
+		StorageValue<_, BTreeMap<ParaId, T::BlockNumber>, ValueQuery>;
+
+	/// The deferred XCMs by sender and index, with the relay block they were sent at and the
+	/// block after which they may be executed.
+	#[pallet::storage]
+	pub(super) type DeferredXcms<T: Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		ParaId,
+		Twox64Concat,
+		DeferredIndex,
+		(RelayBlockNumber, T::BlockNumber, Vec<u8>),
+	>;
+
+	/// The index of the oldest deferred XCM that may still be in `DeferredXcms` and the next free
+	/// index, by sender.
+	#[pallet::storage]
+	pub(super) type DeferredIndices<T: Config> =
+		StorageValue<_, BTreeMap<ParaId, (DeferredIndex, DeferredIndex)>, ValueQuery>;
+
+	/// The servicing priorities of the inbound channels by sender, if they are not `0`.
+	///
+	/// See `set_inbound_channel_priority`.
+	#[pallet::storage]
+	pub(super) type InboundChannelPriorities<T: Config> =
+		StorageValue<_, BTreeMap<ParaId, u32>, ValueQuery>;
+
+	/// The number of overweight messages ever recorded in `Overweight`. Also doubles as the next
+	/// available free overweight index.
+	#[pallet::storage]
+	pub(super) type OverweightCount<T: Config> = StorageValue<_, OverweightIndex, ValueQuery>;
+
+	/// The number of inbound pages ever dropped because their channel reached the drop threshold.
+	#[pallet::storage]
+	pub(super) type DroppedInboundPages<T: Config> = StorageValue<_, u64, ValueQuery>;
+
+	#[pallet::type_value]
+	pub fn InitialFactor() -> FixedU128 {
+		FixedU128::from_inner(FixedU128::accuracy())
+	}
+
+	/// The factor to multiply the base delivery fee by, per destination.
+	///
+	/// It grows exponentially while the outbound queue of the channel is congested and decays
+	/// back to `1` while the queue drains.
+	#[pallet::storage]
+	pub(super) type DeliveryFeeFactor<T: Config> =
+		StorageMap<_, Twox64Concat, ParaId, FixedU128, ValueQuery, InitialFactor>;
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Encode, Decode, RuntimeDebug)]
@@ -182,18 +783,82 @@ pub enum OutboundStatus {
 pub struct QueueConfigData {
 	/// The number of pages of messages which must be in the queue for the other side to be told to
 	/// suspend their sending.
-	suspend_threshold: u32,
-	/// The number of pages of messages which must be in the queue after which we drop any further
-	/// messages from the channel.
-	drop_threshold: u32,
+	pub suspend_threshold: u32,
+	/// The number of pages of messages which must be in the queue after which we drop the oldest
+	/// pages of the channel to make room for new ones.
+	pub drop_threshold: u32,
 	/// The number of pages of messages which the queue must be reduced to before it signals that
 	/// message sending may recommence after it has been suspended.
-	resume_threshold: u32,
+	pub resume_threshold: u32,
 	// The amount of remaining weight under which we stop processing messages.
-	threshold_weight: Weight,
+	pub threshold_weight: Weight,
 	/// The speed to which the available weight approaches the maximum weight. A lower number
 	/// results in a faster progression. A value of 1 makes the entire weight available initially.
-	weight_restrict_decay: Weight,
+	pub weight_restrict_decay: Weight,
+	/// The maximum weight used per block to execute messages when they are received, i.e. in the
+	/// validation data inherent. This is a hard limit on top of the weight that is reserved by
+	/// `parachain-system` for XCMP. The remaining messages are deferred to `on_idle` or to the
+	/// next blocks.
+	pub inherent_weight_limit: Weight,
+	/// The maximum weight used per block to execute messages in `on_idle`. This is a soft limit,
+	/// as `on_idle` only gets the weight that was left over by the block.
+	pub idle_weight_limit: Weight,
+	/// The maximum amount of weight any individual message may consume. Messages above this
+	/// weight go into the overweight queue and may only be serviced explicitly by the
+	/// `ExecuteOverweightOrigin`.
+	pub xcmp_max_individual_weight: Weight,
+	/// The number of the most recently processed XCMs per channel, within which exact duplicates
+	/// are skipped. A value of 0 disables the duplicate suppression.
+	pub duplicate_window: u32,
+}
+
+impl QueueConfigData {
+	/// Returns `true` if the thresholds are consistent, i.e. a suspended channel can be resumed
+	/// and a channel is suspended before its messages are dropped.
+	fn has_valid_thresholds(&self) -> bool {
+		self.resume_threshold < self.suspend_threshold &&
+			self.suspend_threshold <= self.drop_threshold
+	}
+}
+
+/// The maximum number and total size of the messages that may be placed on the outbound queue
+/// of a channel per block.
+#[derive(Copy, Clone, Eq, PartialEq, Encode, Decode, RuntimeDebug)]
+pub struct OutboundRateLimit {
+	/// The maximum number of messages per block.
+	pub max_messages_per_block: u32,
+	/// The maximum total size in bytes of the messages per block.
+	///
+	/// A message that is bigger than this on its own is still queued in a block in which no
+	/// other message was queued.
+	pub max_bytes_per_block: u32,
+	/// The maximum number of messages that may wait for the limit. Further messages are rejected
+	/// with `MessageSendError::QueueFull`.
+	pub max_overflow_messages: u32,
+}
+
+impl OutboundRateLimit {
+	/// Returns `true` if a message of `size` bytes may be queued in a block in which `usage`
+	/// messages and bytes were queued already.
+	fn allows(&self, (messages, bytes): (u32, u32), size: usize) -> bool {
+		messages < self.max_messages_per_block &&
+			(messages == 0 || bytes.saturating_add(size as u32) <= self.max_bytes_per_block)
+	}
+}
+
+/// The outcome of processing an inbound XCM, as reported by `Event::XcmProcessed`.
+#[derive(Clone, Eq, PartialEq, Encode, Decode, RuntimeDebug)]
+pub enum XcmProcessingOutcome {
+	/// The XCM was executed completely.
+	Complete,
+	/// The XCM was executed up to the given error.
+	Incomplete(XcmError),
+	/// The XCM could not be executed.
+	Error(XcmError),
+	/// The XCM was deferred with the given index, see `Event::XcmDeferred`.
+	Deferred(DeferredIndex),
+	/// The XCM was placed in the overweight queue with the given index.
+	Overweight(OverweightIndex),
 }
 
 #[derive(PartialEq, Eq, Copy, Clone, Encode, Decode)]
@@ -215,6 +880,15 @@ pub enum XcmpMessageFormat {
 }
 
 impl<T: Config> Pallet<T> {
+	/// Change the `QueueConfig` with `f`, as long as the result is consistent.
+	fn mutate_queue_config(f: impl FnOnce(&mut QueueConfigData)) -> DispatchResult {
+		<QueueConfig<T>>::try_mutate(|data| {
+			f(data);
+			ensure!(data.has_valid_thresholds(), Error::<T>::BadQueueConfig);
+			Ok(())
+		})
+	}
+
 	/// Place a message `fragment` on the outgoing XCMP queue for `recipient`.
 	///
 	/// Format is the type of aggregate message that the `fragment` may be safely encoded and
@@ -235,22 +909,88 @@ impl<T: Config> Pallet<T> {
 	/// length prefixed and can thus decode each fragment from the aggregate stream. With this,
 	/// we can concatenate them into a single aggregate blob without needing to be concerned
 	/// about encoding fragment boundaries.
+	///
+	/// Returns `MessageSendError::ExceedsMaxMessageSize` if the fragment doesn't fit into a page of
+	/// the channel's max message size.
+	///
+	/// If the channel has an `OutboundRateLimit` that the fragment exceeds, it is placed on the
+	/// queue in one of the following blocks by `release_outbound_overflow` instead, or rejected
+	/// with `MessageSendError::QueueFull` if too many fragments wait for the limit already.
+	///
+	/// Returns the index of the page the fragment was placed on. A fragment that waits for the
+	/// rate limit is placed behind the current pages, so the index of the last page is returned.
 	fn send_fragment<Fragment: Encode>(
 		recipient: ParaId,
 		format: XcmpMessageFormat,
 		fragment: Fragment,
 	) -> Result<u32, MessageSendError> {
 		let data = fragment.encode();
+		let hash = T::Hashing::hash(&data);
 
 		// Optimization note: `max_message_size` could potentially be stored in
 		// `OutboundXcmpMessages` once known; that way it's only accessed when a new page is needed.
 
 		let max_message_size =
 			T::ChannelInfo::get_channel_max(recipient).ok_or(MessageSendError::NoChannel)?;
-		if data.len() > max_message_size {
-			return Err(MessageSendError::TooBig);
+		// Every page starts with the encoded format, so a fragment never fits into a page if it
+		// doesn't fit next to the format.
+		let size = format.encoded_size().saturating_add(data.len());
+		if size > max_message_size {
+			return Err(MessageSendError::ExceedsMaxMessageSize {
+				size: size as u32,
+				max: max_message_size as u32,
+			});
 		}
 
+		let result = match <OutboundRateLimits<T>>::get().get(&recipient) {
+			Some(limit) => {
+				let mut usage = <OutboundRateUsage<T>>::get();
+				let used = usage.entry(recipient).or_default();
+				// Once messages overflow, all further messages have to overflow as well to keep
+				// the order.
+				if <OutboundOverflow<T>>::contains_key(recipient) ||
+					!limit.allows(*used, data.len())
+				{
+					log::debug!("Outbound rate limit of {:?} reached.", recipient);
+					<OutboundOverflow<T>>::try_mutate(recipient, |overflow| {
+						if overflow.len() >= limit.max_overflow_messages as usize {
+							return Err(MessageSendError::QueueFull);
+						}
+						overflow.push((format, data));
+						Ok(())
+					})?;
+					Self::deposit_event(Event::XcmpMessageQueued(recipient, hash));
+					return Ok(Self::outbound_page_count(recipient).saturating_sub(1));
+				}
+				used.0 = used.0.saturating_add(1);
+				used.1 = used.1.saturating_add(data.len() as u32);
+				<OutboundRateUsage<T>>::put(usage);
+				Self::enqueue_fragment(recipient, format, &data, max_message_size)
+			}
+			None => Self::enqueue_fragment(recipient, format, &data, max_message_size),
+		};
+		Self::deposit_event(Event::XcmpMessageQueued(recipient, hash));
+		Ok(result)
+	}
+
+	/// The number of pages in the outbound queue of the channel to `recipient`.
+	fn outbound_page_count(recipient: ParaId) -> u32 {
+		<OutboundXcmpStatus<T>>::get()
+			.iter()
+			.find(|item| item.0 == recipient)
+			.map_or(0, |item| (item.4 - item.3) as u32)
+	}
+
+	/// Place the encoded `data` of a fragment on a page of the outbound queue for `recipient`,
+	/// which has the given `max_message_size`, and return the index of the page in the queue.
+	///
+	/// The fragment must fit into a page next to the format.
+	fn enqueue_fragment(
+		recipient: ParaId,
+		format: XcmpMessageFormat,
+		data: &[u8],
+		max_message_size: usize,
+	) -> u32 {
 		let mut s = <OutboundXcmpStatus<T>>::get();
 		let index = s
 			.iter()
@@ -271,8 +1011,14 @@ impl<T: Config> Pallet<T> {
 				s.extend_from_slice(&data[..]);
 				return true;
 			});
+
+		let number_of_pages = (s[index].4 - s[index].3) as u32 + if appended { 0 } else { 1 };
+		if number_of_pages > Self::delivery_fee_threshold() {
+			Self::increase_fee_factor(recipient, data.len());
+		}
+
 		if appended {
-			Ok((s[index].4 - s[index].3 - 1) as u32)
+			(s[index].4 - s[index].3 - 1) as u32
 		} else {
 			// Need to add a new page.
 			let page_index = s[index].4;
@@ -282,10 +1028,223 @@ impl<T: Config> Pallet<T> {
 			<OutboundXcmpMessages<T>>::insert(recipient, page_index, new_page);
 			let r = (s[index].4 - s[index].3 - 1) as u32;
 			<OutboundXcmpStatus<T>>::put(s);
-			Ok(r)
+			r
+		}
+	}
+
+	/// Place the overflowed outbound messages on their queues, as far as the rate limits of their
+	/// channels allow in this block.
+	fn release_outbound_overflow() -> Weight {
+		let limits = <OutboundRateLimits<T>>::get();
+		let destinations = <OutboundOverflow<T>>::iter().map(|(dest, _)| dest).collect::<Vec<_>>();
+		// The usage is only tracked, and thus only has to be reset, if there is a limit.
+		if destinations.is_empty() {
+			if limits.is_empty() {
+				return T::DbWeight::get().reads(2);
+			}
+			<OutboundRateUsage<T>>::kill();
+			return T::DbWeight::get().reads_writes(2, 1);
+		}
+
+		let mut usage = BTreeMap::new();
+		let mut weight = T::DbWeight::get().reads(2);
+		for dest in destinations {
+			let mut overflow = <OutboundOverflow<T>>::take(dest);
+			weight = weight.saturating_add(T::DbWeight::get().reads_writes(2, 1));
+			let max_message_size = match T::ChannelInfo::get_channel_max(dest) {
+				Some(max_message_size) => max_message_size,
+				None => {
+					log::warn!("Dropping the overflowed messages to closed channel {:?}.", dest);
+					continue;
+				}
+			};
+
+			let mut used = (0u32, 0u32);
+			let mut released = 0;
+			for (format, data) in &overflow {
+				if limits.get(&dest).map_or(false, |limit| !limit.allows(used, data.len())) {
+					break;
+				}
+				if format.encoded_size().saturating_add(data.len()) <= max_message_size {
+					Self::enqueue_fragment(dest, *format, data, max_message_size);
+					weight =
+						weight.saturating_add(T::WeightInfo::enqueue_fragment(data.len() as u32));
+				} else {
+					log::warn!("Dropping an overflowed message that exceeds the channel maximum.");
+				}
+				used.0 = used.0.saturating_add(1);
+				used.1 = used.1.saturating_add(data.len() as u32);
+				released += 1;
+			}
+			overflow.drain(..released);
+			if !overflow.is_empty() {
+				<OutboundOverflow<T>>::insert(dest, overflow);
+			}
+			if limits.contains_key(&dest) {
+				usage.insert(dest, used);
+			}
+		}
+		if !limits.is_empty() {
+			<OutboundRateUsage<T>>::put(usage);
+			weight = weight.saturating_add(T::DbWeight::get().writes(1));
+		}
+
+		weight
+	}
+
+	/// The number of outbound pages of a channel above which the delivery fee factor grows.
+	fn delivery_fee_threshold() -> u32 {
+		<QueueConfig<T>>::get().suspend_threshold / delivery_fee_constants::THRESHOLD_FACTOR
+	}
+
+	/// Raise the delivery fee factor of the channel to `dest` for a message of `message_size`
+	/// bytes.
+	fn increase_fee_factor(dest: ParaId, message_size: usize) {
+		let message_size_factor = FixedU128::saturating_from_integer((message_size / 1024) as u128)
+			.saturating_mul(delivery_fee_constants::message_size_fee_base());
+		<DeliveryFeeFactor<T>>::mutate(dest, |factor| {
+			*factor = factor.saturating_mul(
+				delivery_fee_constants::exponential_fee_base().saturating_add(message_size_factor),
+			);
+		});
+	}
+
+	/// Lower the delivery fee factor of the channel to `dest`, down to the initial factor.
+	fn decrease_fee_factor(dest: ParaId) {
+		if !<DeliveryFeeFactor<T>>::contains_key(dest) {
+			return;
+		}
+		let factor =
+			<DeliveryFeeFactor<T>>::get(dest) / delivery_fee_constants::exponential_fee_base();
+		if factor <= InitialFactor::get() {
+			<DeliveryFeeFactor<T>>::remove(dest);
+		} else {
+			<DeliveryFeeFactor<T>>::insert(dest, factor);
+		}
+	}
+
+	/// The depths and the status of the inbound and outbound queues, for the `XcmpQueueApi`.
+	pub fn queue_status() -> XcmpQueueStatus {
+		let mut channels = BTreeMap::<ParaId, XcmpChannelQueueStatus>::new();
+		let mut total_queued_bytes = 0u64;
+
+		for (sender, status, pages) in <InboundXcmpStatus<T>>::get() {
+			let channel = channels.entry(sender).or_insert_with(|| XcmpChannelQueueStatus {
+				para_id: sender,
+				..Default::default()
+			});
+			channel.inbound_suspended = status == InboundStatus::Suspended;
+			for (sent_at, _) in pages {
+				let len = <InboundXcmpMessages<T>>::decode_len(sender, sent_at).unwrap_or(0);
+				channel.inbound_pages = channel.inbound_pages.saturating_add(1);
+				channel.inbound_bytes = channel.inbound_bytes.saturating_add(len as u32);
+				total_queued_bytes = total_queued_bytes.saturating_add(len as u64);
+			}
+		}
+
+		for (recipient, status, _, begin, end) in <OutboundXcmpStatus<T>>::get() {
+			let channel = channels.entry(recipient).or_insert_with(|| XcmpChannelQueueStatus {
+				para_id: recipient,
+				..Default::default()
+			});
+			channel.outbound_suspended = status == OutboundStatus::Suspended;
+			for index in begin..end {
+				let len = <OutboundXcmpMessages<T>>::decode_len(recipient, index).unwrap_or(0);
+				channel.outbound_pages = channel.outbound_pages.saturating_add(1);
+				channel.outbound_bytes = channel.outbound_bytes.saturating_add(len as u32);
+				total_queued_bytes = total_queued_bytes.saturating_add(len as u64);
+			}
+		}
+
+		XcmpQueueStatus {
+			channels: channels.into_iter().map(|(_, channel)| channel).collect(),
+			total_queued_bytes,
+			execution_suspended: <QueueSuspended<T>>::get(),
 		}
 	}
 
+	/// Split the aggregate message `page` into pages of at most `max_size` bytes.
+	///
+	/// The fragments keep their order. Fragments that don't fit into a page of `max_size` bytes on
+	/// their own, and everything after a fragment that can't be decoded, are dropped.
+	fn split_page(page: &[u8], max_size: usize) -> Vec<Vec<u8>> {
+		let mut remaining_fragments = page;
+		let format = match XcmpMessageFormat::decode(&mut remaining_fragments) {
+			Ok(format) => format,
+			Err(_) => {
+				log::warn!("Dropping an outbound XCMP page with an unknown format.");
+				return Vec::new();
+			}
+		};
+		let prefix = format.encode();
+
+		let mut pages = Vec::new();
+		let mut current = prefix.clone();
+		while !remaining_fragments.is_empty() {
+			let last_remaining_fragments = remaining_fragments;
+			let decoded = match format {
+				XcmpMessageFormat::ConcatenatedVersionedXcm =>
+					VersionedXcm::<()>::decode(&mut remaining_fragments).is_ok(),
+				XcmpMessageFormat::ConcatenatedEncodedBlob =>
+					<Vec<u8>>::decode(&mut remaining_fragments).is_ok(),
+				XcmpMessageFormat::Signals => false,
+			};
+			if !decoded {
+				log::warn!("Dropping the undecodable rest of an outbound XCMP page.");
+				break;
+			}
+
+			let fragment_len = last_remaining_fragments.len() - remaining_fragments.len();
+			let fragment = &last_remaining_fragments[..fragment_len];
+			if prefix.len() + fragment.len() > max_size {
+				log::warn!("Dropping an outbound XCMP fragment larger than the channel maximum.");
+				continue;
+			}
+			if current.len() + fragment.len() > max_size {
+				pages.push(sp_std::mem::replace(&mut current, prefix.clone()));
+			}
+			current.extend_from_slice(fragment);
+		}
+		if current.len() > prefix.len() {
+			pages.push(current);
+		}
+
+		pages
+	}
+
+	/// Replace the outbound page `begin` of the channel to `recipient` with pages that are at
+	/// most `max_size` bytes in size. The pages after it are moved accordingly.
+	///
+	/// Returns the new index of the end of the queue.
+	fn repage_outbound(recipient: ParaId, begin: u16, end: u16, max_size: usize) -> u16 {
+		let page = <OutboundXcmpMessages<T>>::take(recipient, begin);
+		let mut pages = Self::split_page(&page, max_size);
+		// The queue can't grow beyond the last index; the fragments that don't fit are dropped.
+		let max_pages = (u16::max_value() - end) as usize + 1;
+		if pages.len() > max_pages {
+			log::warn!("Dropping outbound XCMP pages that exceed the queue capacity.");
+			pages.truncate(max_pages);
+		}
+		let new_end = end + pages.len() as u16 - 1;
+
+		if new_end > end {
+			for i in (begin + 1..end).rev() {
+				let moved = <OutboundXcmpMessages<T>>::take(recipient, i);
+				<OutboundXcmpMessages<T>>::insert(recipient, i + new_end - end, moved);
+			}
+		} else if new_end < end {
+			for i in begin + 1..end {
+				let moved = <OutboundXcmpMessages<T>>::take(recipient, i);
+				<OutboundXcmpMessages<T>>::insert(recipient, i - 1, moved);
+			}
+		}
+		for (i, page) in pages.into_iter().enumerate() {
+			<OutboundXcmpMessages<T>>::insert(recipient, begin + i as u16, page);
+		}
+
+		new_end
+	}
+
 	/// Sends a signal to the `dest` chain over XCMP. This is guaranteed to be dispatched on this
 	/// block.
 	fn send_signal(dest: ParaId, signal: ChannelSignal) -> Result<(), ()> {
@@ -347,38 +1306,221 @@ impl<T: Config> Pallet<T> {
 		Err(false)
 	}
 
+	/// Execute an inbound XCM.
+	///
+	/// `Event::XcmProcessed` is deposited unless the XCM needs more weight than `max_weight`, in
+	/// which case the caller decides what happens to it.
 	fn handle_xcm_message(
 		sender: ParaId,
-		_sent_at: RelayBlockNumber,
+		sent_at: RelayBlockNumber,
 		xcm: VersionedXcm<T::Call>,
 		max_weight: Weight,
 	) -> Result<Weight, XcmError> {
 		let hash = Encode::using_encoded(&xcm, T::Hashing::hash);
 		log::debug!("Processing XCMP-XCM: {:?}", &hash);
-		let (result, event) = match Xcm::<T::Call>::try_from(xcm) {
+		let version = xcm_version_of(&xcm);
+		let (result, event, outcome) = match Xcm::<T::Call>::try_from(xcm) {
 			Ok(xcm) => {
 				let location = (Junction::Parent, Junction::Parachain(sender.into()));
 				match T::XcmExecutor::execute_xcm(location.into(), xcm, max_weight) {
-					Outcome::Error(e) => (Err(e.clone()), Event::Fail(Some(hash), e)),
-					Outcome::Complete(w) => (Ok(w), Event::Success(Some(hash))),
+					Outcome::Error(e) => (
+						Err(e.clone()),
+						Event::Fail(Some(hash), e.clone()),
+						(0, XcmProcessingOutcome::Error(e)),
+					),
+					Outcome::Complete(w) => (
+						Ok(w),
+						Event::Success(Some(hash)),
+						(w, XcmProcessingOutcome::Complete),
+					),
 					// As far as the caller is concerned, this was dispatched without error, so
 					// we just report the weight used.
-					Outcome::Incomplete(w, e) => (Ok(w), Event::Fail(Some(hash), e)),
+					Outcome::Incomplete(w, e) => (
+						Ok(w),
+						Event::Fail(Some(hash), e.clone()),
+						(w, XcmProcessingOutcome::Incomplete(e)),
+					),
 				}
 			}
 			Err(()) => (
 				Err(XcmError::UnhandledXcmVersion),
 				Event::BadVersion(Some(hash)),
+				(0, XcmProcessingOutcome::Error(XcmError::UnhandledXcmVersion)),
 			),
 		};
 		Self::deposit_event(event);
+		if result.is_ok() {
+			Self::note_xcm_version(sender, version);
+		}
+		match result {
+			Err(XcmError::WeightLimitReached(_)) | Err(XcmError::TooMuchWeightRequired) => {}
+			_ => {
+				let (weight_used, outcome) = outcome;
+				Self::deposit_event(Event::XcmProcessed(
+					hash,
+					sender,
+					sent_at,
+					weight_used,
+					outcome,
+				));
+			}
+		}
 		result
 	}
 
+	/// Record that `sibling` handles XCMs of `version`.
+	fn note_xcm_version(sibling: ParaId, version: XcmVersion) {
+		if <ChannelXcmVersions<T>>::get(sibling).map_or(true, |known| known < version) {
+			<ChannelXcmVersions<T>>::insert(sibling, version);
+			Self::deposit_event(Event::XcmVersionUpdated(sibling, version));
+		}
+	}
+
+	/// Convert `xcm` into the highest version that `recipient` is known to handle.
+	///
+	/// The XCM is sent in the current version if the version of the recipient is not known.
+	fn versioned_xcm_for(recipient: ParaId, xcm: Xcm<()>) -> Result<VersionedXcm<()>, XcmError> {
+		let xcm = VersionedXcm::<()>::from(xcm);
+		let current = xcm_version_of(&xcm);
+		match <ChannelXcmVersions<T>>::get(recipient) {
+			Some(version) if version < current => {
+				let downgraded = match version {
+					0 => Xcm::<()>::try_from(xcm).map(VersionedXcm::V0),
+					_ => Err(()),
+				}
+				.map_err(|()| XcmError::UnhandledXcmVersion)?;
+				Self::deposit_event(Event::XcmDowngraded(recipient, current, version));
+				Ok(downgraded)
+			}
+			_ => Ok(xcm),
+		}
+	}
+
+	/// Put an overweight XCM into the `Overweight` storage map and return its index.
+	fn stash_overweight(
+		sender: ParaId,
+		sent_at: RelayBlockNumber,
+		xcm: Vec<u8>,
+	) -> OverweightIndex {
+		let index = <OverweightCount<T>>::mutate(|count| {
+			let index = *count;
+			*count += 1;
+			index
+		});
+		<Overweight<T>>::insert(index, (sender, sent_at, xcm));
+		index
+	}
+
+	/// Put an XCM into the `DeferredXcms` storage map and return its index.
+	fn defer_xcm(
+		sender: ParaId,
+		sent_at: RelayBlockNumber,
+		execute_after: T::BlockNumber,
+		xcm: Vec<u8>,
+	) -> DeferredIndex {
+		let index = <DeferredIndices<T>>::mutate(|indices| {
+			let (_, end) = indices.entry(sender).or_default();
+			let index = *end;
+			*end += 1;
+			index
+		});
+		<DeferredXcms<T>>::insert(sender, index, (sent_at, execute_after, xcm));
+		index
+	}
+
+	/// Execute the deferred XCMs that may be executed at block `now`, using up to `max_weight`
+	/// including the weight of the storage accesses.
+	///
+	/// The XCMs of a sender are executed in the order they were deferred, so an XCM that is not
+	/// due yet also holds back the XCMs of the same sender that were deferred after it. The XCMs
+	/// of paused channels are not read at all.
+	fn service_deferred_xcms(now: T::BlockNumber, max_weight: Weight) -> Weight {
+		let db_weight = T::DbWeight::get();
+		if <QueueSuspended<T>>::get() {
+			return db_weight.reads(1);
+		}
+
+		let mut indices = <DeferredIndices<T>>::get();
+		let mut weight_used = db_weight.reads(2);
+		if indices.values().all(|(begin, end)| begin == end) {
+			return weight_used;
+		}
+
+		let max_individual_weight = <QueueConfig<T>>::get().xcmp_max_individual_weight;
+		let paused = <PausedChannels<T>>::get();
+		weight_used = weight_used.saturating_add(db_weight.reads_writes(2, 1));
+		// Reading and removing an XCM, which is also enough to skip a cancelled one.
+		let access_weight = db_weight.reads_writes(1, 1);
+		'senders: for (&sender, (begin, end)) in indices.iter_mut() {
+			if paused.contains(&sender) {
+				continue;
+			}
+			while begin < end {
+				if weight_used.saturating_add(access_weight) > max_weight {
+					break 'senders;
+				}
+				weight_used = weight_used.saturating_add(db_weight.reads(1));
+				let (sent_at, execute_after, data) = match <DeferredXcms<T>>::get(sender, *begin) {
+					Some(deferred) => deferred,
+					// The XCM was cancelled.
+					None => {
+						*begin += 1;
+						continue;
+					}
+				};
+				if execute_after > now {
+					break;
+				}
+
+				if let Ok(xcm) = VersionedXcm::<T::Call>::decode(&mut &data[..]) {
+					let weight =
+						max_weight.saturating_sub(weight_used).saturating_sub(db_weight.writes(1));
+					match Self::handle_xcm_message(sender, sent_at, xcm, weight) {
+						Ok(used) => weight_used = weight_used.saturating_add(used),
+						Err(XcmError::WeightLimitReached(required))
+							if required > max_individual_weight =>
+						{
+							let hash = T::Hashing::hash(&data);
+							let size = data.len() as u32;
+							let index = Self::stash_overweight(sender, sent_at, data);
+							weight_used = weight_used
+								.saturating_add(T::WeightInfo::stash_overweight(size));
+							Self::deposit_event(Event::OverweightEnqueued(
+								sender, sent_at, index, required,
+							));
+							Self::deposit_event(Event::XcmProcessed(
+								hash,
+								sender,
+								sent_at,
+								0,
+								XcmProcessingOutcome::Overweight(index),
+							));
+						}
+						Err(XcmError::WeightLimitReached(_)) |
+						Err(XcmError::TooMuchWeightRequired) => {
+							// Try again with the weight of the next block.
+							break;
+						}
+						Err(_) => {
+							// Message looks invalid; don't attempt to retry
+						}
+					}
+				}
+				<DeferredXcms<T>>::remove(sender, *begin);
+				weight_used = weight_used.saturating_add(db_weight.writes(1));
+				*begin += 1;
+			}
+		}
+
+		<DeferredIndices<T>>::put(indices);
+		weight_used
+	}
+
 	fn process_xcmp_message(
 		sender: ParaId,
 		(sent_at, format): (RelayBlockNumber, XcmpMessageFormat),
 		max_weight: Weight,
+		max_individual_weight: Weight,
 	) -> (Weight, bool) {
 		let data = <InboundXcmpMessages<T>>::get(sender, sent_at);
 		let mut last_remaining_fragments;
@@ -386,33 +1528,133 @@ impl<T: Config> Pallet<T> {
 		let mut weight_used = 0;
 		match format {
 			XcmpMessageFormat::ConcatenatedVersionedXcm => {
+				let now = frame_system::Pallet::<T>::block_number();
+				let execute_after = <DeferralPeriods<T>>::get()
+					.get(&sender)
+					.map(|period| now.saturating_add(*period));
+				let duplicate_window = <QueueConfig<T>>::get().duplicate_window as usize;
+				let mut recent_hashes = <RecentXcmHashes<T>>::get(sender);
+				// The window may have shrunk since the hashes were kept.
+				let stale = recent_hashes.len().saturating_sub(duplicate_window);
+				recent_hashes.drain(..stale);
+				let initial_recent_hashes_len = recent_hashes.len();
+				// The number of XCMs that were taken off the page.
+				let mut handled = 0u32;
 				while !remaining_fragments.is_empty() {
 					last_remaining_fragments = remaining_fragments;
 					if let Ok(xcm) = VersionedXcm::<T::Call>::decode(&mut remaining_fragments) {
-						let weight = max_weight - weight_used;
+						handled = handled.saturating_add(1);
+						if duplicate_window > 0 {
+							let msg_len = last_remaining_fragments
+								.len()
+								.saturating_sub(remaining_fragments.len());
+							let hash = T::Hashing::hash(&last_remaining_fragments[..msg_len]);
+							if recent_hashes.contains(&hash) {
+								Self::deposit_event(Event::DuplicateSkipped(sender, sent_at, hash));
+								continue;
+							}
+							// The hash is removed again if the XCM is left for later.
+							recent_hashes.push(hash);
+						}
+
+						if let Some(execute_after) = execute_after {
+							// The sender is on the defer list; the XCM is executed after its
+							// deferral period, unless it is cancelled until then.
+							let msg_len = last_remaining_fragments
+								.len()
+								.saturating_sub(remaining_fragments.len());
+							let deferred_xcm = last_remaining_fragments[..msg_len].to_vec();
+							let hash = T::Hashing::hash(&deferred_xcm);
+							let index =
+								Self::defer_xcm(sender, sent_at, execute_after, deferred_xcm);
+							Self::deposit_event(Event::XcmDeferred(
+								sender,
+								sent_at,
+								index,
+								execute_after,
+							));
+							Self::deposit_event(Event::XcmProcessed(
+								hash,
+								sender,
+								sent_at,
+								0,
+								XcmProcessingOutcome::Deferred(index),
+							));
+							continue;
+						}
+
+						let weight = max_weight.saturating_sub(weight_used);
 						match Self::handle_xcm_message(sender, sent_at, xcm, weight) {
 							Ok(used) => weight_used = weight_used.saturating_add(used),
+							Err(XcmError::WeightLimitReached(required))
+								if required > max_individual_weight =>
+							{
+								// Overweight; the XCM is parked in the overweight queue and the
+								// following XCMs of the channel are processed as usual.
+								let msg_len = last_remaining_fragments
+									.len()
+									.saturating_sub(remaining_fragments.len());
+								let overweight_xcm = last_remaining_fragments[..msg_len].to_vec();
+								let hash = T::Hashing::hash(&overweight_xcm);
+								let index =
+									Self::stash_overweight(sender, sent_at, overweight_xcm);
+								let stash_weight = T::WeightInfo::stash_overweight(msg_len as u32);
+								weight_used = weight_used.saturating_add(stash_weight);
+								Self::deposit_event(Event::OverweightEnqueued(
+									sender, sent_at, index, required,
+								));
+								Self::deposit_event(Event::XcmProcessed(
+									hash,
+									sender,
+									sent_at,
+									0,
+									XcmProcessingOutcome::Overweight(index),
+								));
+							}
+							Err(XcmError::WeightLimitReached(_)) |
 							Err(XcmError::TooMuchWeightRequired) => {
 								// That message didn't get processed this time because of being
 								// too heavy. We leave it around for next time and bail.
 								remaining_fragments = last_remaining_fragments;
+								handled -= 1;
+								if duplicate_window > 0 {
+									recent_hashes.pop();
+								}
 								break;
 							}
 							Err(_) => {
-								// Message looks invalid; don't attempt to retry
+								// Message looks invalid; don't attempt to retry. It failed before
+								// it was executed, so it didn't use any of the weight.
 							}
 						}
 					} else {
-						debug_assert!(false, "Invalid incoming XCMP message data");
+						// The rest of the page can't be decoded and is dropped. Decoding failed
+						// without using any weight, so the weight stays available for the other
+						// messages.
+						log::warn!("Dropping invalid incoming XCMP message data from {:?}", sender);
 						remaining_fragments = &b""[..];
 					}
 				}
+				if stale > 0 || recent_hashes.len() != initial_recent_hashes_len {
+					let excess = recent_hashes.len().saturating_sub(duplicate_window);
+					recent_hashes.drain(..excess);
+					if recent_hashes.is_empty() {
+						<RecentXcmHashes<T>>::remove(sender);
+					} else {
+						<RecentXcmHashes<T>>::insert(sender, recent_hashes);
+					}
+				}
+				// Only charged when the page made progress, so that a page which is left for
+				// later doesn't look like it did.
+				if handled > 0 {
+					weight_used = weight_used.saturating_add(T::WeightInfo::defer_xcms(handled));
+				}
 			}
 			XcmpMessageFormat::ConcatenatedEncodedBlob => {
 				while !remaining_fragments.is_empty() {
 					last_remaining_fragments = remaining_fragments;
 					if let Ok(blob) = <Vec<u8>>::decode(&mut remaining_fragments) {
-						let weight = max_weight - weight_used;
+						let weight = max_weight.saturating_sub(weight_used);
 						match Self::handle_blob_message(sender, sent_at, blob, weight) {
 							Ok(used) => weight_used = weight_used.saturating_add(used),
 							Err(true) => {
@@ -426,7 +1668,7 @@ impl<T: Config> Pallet<T> {
 							}
 						}
 					} else {
-						debug_assert!(false, "Invalid incoming blob message data");
+						log::warn!("Dropping invalid incoming blob message data from {:?}", sender);
 						remaining_fragments = &b""[..];
 					}
 				}
@@ -450,7 +1692,8 @@ impl<T: Config> Pallet<T> {
 	///
 	/// Channels are first shuffled and then processed in this random one page at a time, order over
 	/// and over until either `max_weight` is exhausted or no channel has messages that can be
-	/// processed any more.
+	/// processed any more. Channels with a higher priority in `InboundChannelPriorities` are
+	/// placed before the channels with a lower priority in this order.
 	///
 	/// There are two obvious "modes" that we could apportion `max_weight`: one would be to attempt
 	/// to spend it all on the first channel's first page, then use the leftover (if any) for the
@@ -473,6 +1716,10 @@ impl<T: Config> Pallet<T> {
 	/// for the second &c. though empirical and or practical factors may give rise to adjusting it
 	/// further.
 	fn service_xcmp_queue(max_weight: Weight) -> Weight {
+		if <QueueSuspended<T>>::get() {
+			return 0;
+		}
+
 		let mut status = <InboundXcmpStatus<T>>::get(); // <- sorted.
 		if status.len() == 0 {
 			return 0;
@@ -482,10 +1729,24 @@ impl<T: Config> Pallet<T> {
 			resume_threshold,
 			threshold_weight,
 			weight_restrict_decay,
+			xcmp_max_individual_weight,
 			..
 		} = <QueueConfig<T>>::get();
 
 		let mut shuffled = Self::create_shuffle(status.len());
+		// The messages of paused channels are kept, but not executed.
+		let paused = <PausedChannels<T>>::get();
+		if !paused.is_empty() {
+			shuffled.retain(|&index| !paused.contains(&status[index].0));
+		}
+		// Channels with a higher priority are serviced first. The sort is stable, so channels of
+		// the same priority keep their random order.
+		let priorities = <InboundChannelPriorities<T>>::get();
+		if !priorities.is_empty() {
+			shuffled.sort_by_key(|&index| {
+				cmp::Reverse(priorities.get(&status[index].0).cloned().unwrap_or_default())
+			});
+		}
 		let mut weight_used = 0;
 		let mut weight_available = 0;
 
@@ -528,14 +1789,18 @@ impl<T: Config> Pallet<T> {
 			} else {
 				// Process up to one block's worth for now.
 				let weight_remaining = weight_available.saturating_sub(weight_used);
-				let (weight_processed, is_empty) =
-					Self::process_xcmp_message(sender, status[index].2[0], weight_remaining);
+				let (weight_processed, is_empty) = Self::process_xcmp_message(
+					sender,
+					status[index].2[0],
+					weight_remaining,
+					xcmp_max_individual_weight,
+				);
 				if is_empty {
 					status[index].2.remove(0);
 				}
 				weight_processed
 			};
-			weight_used += weight_processed;
+			weight_used = weight_used.saturating_add(weight_processed);
 
 			if status[index].2.len() as u32 <= resume_threshold
 				&& status[index].1 == InboundStatus::Suspended
@@ -552,7 +1817,8 @@ impl<T: Config> Pallet<T> {
 			// If there are more and we're making progress, we process them after we've given the
 			// other channels a look in. If we've still not unlocked all weight, then we set them
 			// up for processing a second time anyway.
-			if !status[index].2.is_empty() && weight_processed > 0 || weight_available != max_weight
+			if !status[index].2.is_empty() &&
+				(weight_processed > 0 || weight_available != max_weight)
 			{
 				if shuffle_index + 1 == shuffled.len() {
 					// Only this queue left. Just run around this loop once more.
@@ -570,7 +1836,8 @@ impl<T: Config> Pallet<T> {
 		weight_used
 	}
 
-	fn suspend_channel(target: ParaId) {
+	/// Suspend the outbound channel to `target` and return the number of outbound channels.
+	fn suspend_outbound_channel(target: ParaId) -> u32 {
 		<OutboundXcmpStatus<T>>::mutate(|s| {
 			if let Some(index) = s.iter().position(|item| item.0 == target) {
 				let ok = s[index].1 == OutboundStatus::Ok;
@@ -579,10 +1846,12 @@ impl<T: Config> Pallet<T> {
 			} else {
 				s.push((target, OutboundStatus::Suspended, false, 0, 0));
 			}
-		});
+			s.len() as u32
+		})
 	}
 
-	fn resume_channel(target: ParaId) {
+	/// Resume the outbound channel to `target` and return the number of outbound channels.
+	fn resume_outbound_channel(target: ParaId) -> u32 {
 		<OutboundXcmpStatus<T>>::mutate(|s| {
 			if let Some(index) = s.iter().position(|item| item.0 == target) {
 				let suspended = s[index].1 == OutboundStatus::Suspended;
@@ -601,7 +1870,8 @@ impl<T: Config> Pallet<T> {
 					"WARNING: Attempt to resume channel that was not suspended."
 				);
 			}
-		});
+			s.len() as u32
+		})
 	}
 }
 
@@ -611,14 +1881,22 @@ impl<T: Config> XcmpMessageHandler for Pallet<T> {
 		max_weight: Weight,
 	) -> Weight {
 		let mut status = <InboundXcmpStatus<T>>::get();
+		let mut dropped = BTreeMap::<ParaId, u32>::new();
 
 		let QueueConfigData {
 			suspend_threshold,
 			drop_threshold,
+			inherent_weight_limit,
 			..
 		} = <QueueConfig<T>>::get();
 
+		// The signals are acted on before any other page is handled, regardless of the order of
+		// the pages, so that the backpressure of the siblings takes effect right away.
+		let mut page_count = 0u32;
+		let mut signal_weight: Weight = 0;
+		let mut pages = Vec::new();
 		for (sender, sent_at, data) in iter {
+			page_count = page_count.saturating_add(1);
 			// Figure out the message format.
 			let mut data_ref = data;
 			let format = match XcmpMessageFormat::decode(&mut data_ref) {
@@ -634,40 +1912,48 @@ impl<T: Config> XcmpMessageHandler for Pallet<T> {
 			if format == XcmpMessageFormat::Signals {
 				while !data_ref.is_empty() {
 					use ChannelSignal::*;
-					match ChannelSignal::decode(&mut data_ref) {
-						Ok(Suspend) => Self::suspend_channel(sender),
-						Ok(Resume) => Self::resume_channel(sender),
+					let channels = match ChannelSignal::decode(&mut data_ref) {
+						Ok(Suspend) => Self::suspend_outbound_channel(sender),
+						Ok(Resume) => Self::resume_outbound_channel(sender),
 						Err(_) => break,
-					}
+					};
+					// Resuming a channel takes the same work as suspending it.
+					signal_weight = signal_weight
+						.saturating_add(T::WeightInfo::process_suspend_signal(channels));
 				}
 			} else {
-				// Record the fact we received it.
-				match status.binary_search_by_key(&sender, |item| item.0) {
-					Ok(i) => {
-						let count = status[i].2.len();
-						if count as u32 >= suspend_threshold && status[i].1 == InboundStatus::Ok {
-							status[i].1 = InboundStatus::Suspended;
-							let r = Self::send_signal(sender, ChannelSignal::Suspend);
-							if r.is_err() {
-								log::warn!(
-									"Attempt to suspend channel failed. Messages may be dropped."
-								);
-							}
-						}
-						if (count as u32) < drop_threshold {
-							status[i].2.push((sent_at, format));
-						} else {
-							debug_assert!(
-								false,
-								"XCMP channel queue full. Silently dropping message"
+				pages.push((sender, sent_at, format, data_ref));
+			}
+		}
+
+		for (sender, sent_at, format, data_ref) in pages {
+			// Record the fact we received it.
+			match status.binary_search_by_key(&sender, |item| item.0) {
+				Ok(i) => {
+					let count = status[i].2.len();
+					if count as u32 >= suspend_threshold && status[i].1 == InboundStatus::Ok {
+						status[i].1 = InboundStatus::Suspended;
+						let r = Self::send_signal(sender, ChannelSignal::Suspend);
+						if r.is_err() {
+							log::warn!(
+								"Attempt to suspend channel failed. Messages may be dropped."
 							);
 						}
 					}
-					Err(_) => status.push((sender, InboundStatus::Ok, vec![(sent_at, format)])),
+					if count as u32 >= drop_threshold {
+						// Make room for the new page by dropping the oldest ones.
+						let excess = (count + 1).saturating_sub(drop_threshold as usize);
+						for (old_sent_at, _) in status[i].2.drain(..excess.min(count)) {
+							<InboundXcmpMessages<T>>::remove(sender, old_sent_at);
+						}
+						*dropped.entry(sender).or_default() += excess.min(count) as u32;
+					}
+					status[i].2.push((sent_at, format));
 				}
-				// Queue the payload for later execution.
-				<InboundXcmpMessages<T>>::insert(sender, sent_at, data_ref);
+				Err(_) => status.push((sender, InboundStatus::Ok, vec![(sent_at, format)])),
 			}
+			// Queue the payload for later execution.
+			<InboundXcmpMessages<T>>::insert(sender, sent_at, data_ref);
 
 			// Optimization note; it would make sense to execute messages immediately if
 			// `status.is_empty()` here.
@@ -675,7 +1961,22 @@ impl<T: Config> XcmpMessageHandler for Pallet<T> {
 		status.sort();
 		<InboundXcmpStatus<T>>::put(status);
 
-		Self::service_xcmp_queue(max_weight)
+		for (sender, count) in dropped {
+			log::warn!("XCMP channel queue of {:?} full. Dropped {} oldest pages.", sender, count);
+			<DroppedInboundPages<T>>::mutate(|total| *total = total.saturating_add(count as u64));
+			Self::deposit_event(Event::InboundPagesDropped(sender, count));
+		}
+
+		let receive_weight =
+			T::WeightInfo::receive_xcmp_pages(page_count).saturating_add(signal_weight);
+		let limit = max_weight.min(inherent_weight_limit).saturating_sub(receive_weight);
+		receive_weight.saturating_add(Self::service_xcmp_queue(limit))
+	}
+}
+
+impl<T: Config> FeeTracker for Pallet<T> {
+	fn get_fee_factor(para: ParaId) -> FixedU128 {
+		<DeliveryFeeFactor<T>>::get(para)
 	}
 }
 
@@ -707,6 +2008,8 @@ impl<T: Config> XcmpMessageSource for Pallet<T> {
 					if signalling {
 						<SignalMessages<T>>::remove(para_id);
 					}
+					<DeliveryFeeFactor<T>>::remove(para_id);
+					<RecentXcmHashes<T>>::remove(para_id);
 					*status = (para_id, OutboundStatus::Ok, false, 0, 0);
 					continue;
 				}
@@ -716,7 +2019,7 @@ impl<T: Config> XcmpMessageSource for Pallet<T> {
 
 			let page = if signalling {
 				let page = <SignalMessages<T>>::get(para_id);
-				if page.len() < max_size_now {
+				if page.len() <= max_size_now {
 					<SignalMessages<T>>::remove(para_id);
 					signalling = false;
 					page
@@ -724,10 +2027,25 @@ impl<T: Config> XcmpMessageSource for Pallet<T> {
 					continue;
 				}
 			} else if end > begin {
-				let page = <OutboundXcmpMessages<T>>::get(para_id, begin);
-				if page.len() < max_size_now {
+				let mut page = <OutboundXcmpMessages<T>>::get(para_id, begin);
+				if page.len() > max_size_ever {
+					// The channel's max message size has shrunk since the page was built, so it
+					// is split into pages that fit.
+					end = Self::repage_outbound(para_id, begin, end, max_size_ever);
+					if begin == end {
+						*status = (para_id, outbound_status, signalling, 0, 0);
+						continue;
+					}
+					// The new end must be kept even if the page can't be sent right now.
+					*status = (para_id, outbound_status, signalling, begin, end);
+					page = <OutboundXcmpMessages<T>>::get(para_id, begin);
+				}
+				if page.len() <= max_size_now {
 					<OutboundXcmpMessages<T>>::remove(para_id, begin);
 					begin += 1;
+					if ((end - begin) as u32) < Self::delivery_fee_threshold() {
+						Self::decrease_fee_factor(para_id);
+					}
 					page
 				} else {
 					continue;
@@ -741,10 +2059,9 @@ impl<T: Config> XcmpMessageSource for Pallet<T> {
 			}
 
 			if page.len() > max_size_ever {
-				// TODO: #274 This means that the channel's max message size has changed since
-				//   the message was sent. We should parse it and split into smaller mesasges but
-				//   since it's so unlikely then for now we just drop it.
-				log::warn!("WARNING: oversize message in queue. silently dropping.");
+				// Only signal pages can get here, as outbound pages are split above. Signals are
+				// tiny, so this is only possible with a nonsensical channel configuration.
+				log::warn!("WARNING: oversize signal message in queue. silently dropping.");
 			} else {
 				result.push((para_id, page));
 			}
@@ -768,9 +2085,10 @@ impl<T: Config> XcmpMessageSource for Pallet<T> {
 
 		// old_status_len must be >= status.len() since we never add anything to status.
 		let pruned = old_statuses_len - statuses.len();
-		// removing an item from status implies a message being sent, so the result messages must
-		// be no less than the pruned channels.
-		statuses.rotate_left(result.len() - pruned);
+		// removing an item from status usually implies a message being sent, so the result
+		// messages are no less than the pruned channels. Channels that were closed or whose pages
+		// were dropped entirely are pruned without sending a message, though.
+		statuses.rotate_left(result.len().saturating_sub(pruned));
 
 		<OutboundXcmpStatus<T>>::put(statuses);
 
@@ -784,14 +2102,17 @@ impl<T: Config> SendXcm for Pallet<T> {
 		match &dest {
 			// An HRMP message for a sibling parachain.
 			MultiLocation::X2(Junction::Parent, Junction::Parachain(id)) => {
-				let msg = VersionedXcm::<()>::from(msg);
+				let msg = Self::versioned_xcm_for((*id).into(), msg)?;
 				let hash = T::Hashing::hash_of(&msg);
 				Self::send_fragment(
 					(*id).into(),
 					XcmpMessageFormat::ConcatenatedVersionedXcm,
 					msg,
 				)
-				.map_err(|e| XcmError::SendFailed(<&'static str>::from(e)))?;
+				.map_err(|e| {
+					log::debug!("Failed to send an XCM to {:?}: {:?}", id, e);
+					XcmError::SendFailed(<&'static str>::from(e))
+				})?;
 				Self::deposit_event(Event::XcmpMessageSent(Some(hash)));
 				Ok(())
 			}
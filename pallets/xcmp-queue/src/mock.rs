@@ -0,0 +1,281 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::*;
+use crate as xcmp_queue;
+
+use cumulus_primitives_core::ChannelInfo;
+use frame_support::{parameter_types, traits::GenesisBuild};
+use sp_core::H256;
+use sp_runtime::{
+	testing::Header,
+	traits::{BlakeTwo256, IdentityLookup},
+};
+use std::cell::RefCell;
+use xcm::v0::OriginKind;
+
+type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
+type Block = frame_system::mocking::MockBlock<Test>;
+
+frame_support::construct_runtime!(
+	pub enum Test where
+		Block = Block,
+		NodeBlock = Block,
+		UncheckedExtrinsic = UncheckedExtrinsic,
+	{
+		System: frame_system::{Pallet, Call, Config, Storage, Event<T>},
+		Balances: pallet_balances::{Pallet, Call, Storage, Config<T>, Event<T>},
+		XcmpQueue: xcmp_queue::{Pallet, Call, Storage, Event<T>},
+	}
+);
+
+parameter_types! {
+	pub const BlockHashCount: u64 = 250;
+	pub const ExistentialDeposit: u64 = 1;
+}
+
+pub type AccountId = u64;
+
+/// The account that is endowed at genesis.
+pub const ALICE: AccountId = 1;
+
+impl frame_system::Config for Test {
+	type Origin = Origin;
+	type Call = Call;
+	type Index = u64;
+	type BlockNumber = u64;
+	type Hash = H256;
+	type Hashing = BlakeTwo256;
+	type AccountId = AccountId;
+	type Lookup = IdentityLookup<Self::AccountId>;
+	type Header = Header;
+	type Event = Event;
+	type BlockHashCount = BlockHashCount;
+	type BlockLength = ();
+	type BlockWeights = ();
+	type Version = ();
+	type PalletInfo = PalletInfo;
+	type AccountData = pallet_balances::AccountData<u64>;
+	type OnNewAccount = ();
+	type OnKilledAccount = ();
+	type DbWeight = ();
+	type BaseCallFilter = ();
+	type SystemWeightInfo = ();
+	type SS58Prefix = ();
+	type OnSetCode = ();
+}
+
+impl pallet_balances::Config for Test {
+	type Balance = u64;
+	type Event = Event;
+	type DustRemoval = ();
+	type ExistentialDeposit = ExistentialDeposit;
+	type AccountStore = System;
+	type WeightInfo = ();
+	type MaxLocks = ();
+}
+
+thread_local! {
+	/// The `(max_size_now, max_size_ever)` of the open outbound channels.
+	pub static CHANNELS: RefCell<BTreeMap<ParaId, (usize, usize)>> = RefCell::new(BTreeMap::new());
+	/// The senders and weights of the executed `Transact`s.
+	pub static EXECUTED: RefCell<Vec<(ParaId, Weight)>> = RefCell::new(Vec::new());
+}
+
+/// Open the outbound channel to `recipient`, or change its max message sizes.
+pub fn set_channel(recipient: u32, max_size_now: usize, max_size_ever: usize) {
+	CHANNELS.with(|c| c.borrow_mut().insert(recipient.into(), (max_size_now, max_size_ever)));
+}
+
+/// Close the outbound channel to `recipient`.
+pub fn close_channel(recipient: u32) {
+	CHANNELS.with(|c| c.borrow_mut().remove(&recipient.into()));
+}
+
+/// Returns the senders and weights of the `Transact`s executed since the last call.
+pub fn take_executed() -> Vec<(ParaId, Weight)> {
+	EXECUTED.with(|e| sp_std::mem::take(&mut *e.borrow_mut()))
+}
+
+pub struct MockChannelInfo;
+impl GetChannelInfo for MockChannelInfo {
+	fn get_channel_status(id: ParaId) -> ChannelStatus {
+		CHANNELS.with(|c| match c.borrow().get(&id) {
+			Some(&(now, ever)) => ChannelStatus::Ready(now, ever),
+			None => ChannelStatus::Closed,
+		})
+	}
+
+	fn get_channel_max(id: ParaId) -> Option<usize> {
+		CHANNELS.with(|c| c.borrow().get(&id).map(|&(_, ever)| ever))
+	}
+
+	fn get_channel_info(_recipient: ParaId) -> Option<ChannelInfo> {
+		None
+	}
+
+	fn get_ingress_channel_info(_sender: ParaId) -> Option<ChannelInfo> {
+		None
+	}
+}
+
+/// Executes a `Transact` with its `require_weight_at_most` as the weight. Every other XCM fails
+/// without using any weight.
+pub struct MockExec;
+impl ExecuteXcm<Call> for MockExec {
+	fn execute_xcm_in_credit(
+		origin: MultiLocation,
+		message: Xcm<Call>,
+		weight_limit: Weight,
+		_credit: Weight,
+	) -> Outcome {
+		let sender = match origin {
+			MultiLocation::X2(Junction::Parent, Junction::Parachain(id)) => ParaId::from(id),
+			_ => return Outcome::Error(XcmError::BadOrigin),
+		};
+		match message {
+			Xcm::Transact { require_weight_at_most, .. } => {
+				if require_weight_at_most <= weight_limit {
+					EXECUTED.with(|e| e.borrow_mut().push((sender, require_weight_at_most)));
+					Outcome::Complete(require_weight_at_most)
+				} else {
+					Outcome::Error(XcmError::WeightLimitReached(require_weight_at_most))
+				}
+			}
+			_ => Outcome::Error(XcmError::Unimplemented),
+		}
+	}
+}
+
+/// Converts an `AccountIndex64` junction into the account of that index.
+pub struct AccountIndexToAccountId;
+impl Convert<MultiLocation, AccountId> for AccountIndexToAccountId {
+	fn convert(location: MultiLocation) -> Result<AccountId, MultiLocation> {
+		match location {
+			MultiLocation::X1(Junction::AccountIndex64 { index, .. }) => Ok(index),
+			location => Err(location),
+		}
+	}
+}
+
+/// No weight for the queue itself, so the tests only see the weight of the executed XCMs.
+pub struct NoWeight;
+impl weights::WeightInfo for NoWeight {
+	fn update_suspend_threshold() -> Weight {
+		0
+	}
+	fn update_resume_threshold() -> Weight {
+		0
+	}
+	fn update_drop_threshold() -> Weight {
+		0
+	}
+	fn update_queue_config() -> Weight {
+		0
+	}
+	fn suspend_xcm_execution() -> Weight {
+		0
+	}
+	fn resume_xcm_execution() -> Weight {
+		0
+	}
+	fn pause_channel() -> Weight {
+		0
+	}
+	fn resume_channel() -> Weight {
+		0
+	}
+	fn set_deferral_period() -> Weight {
+		0
+	}
+	fn cancel_deferred_xcm() -> Weight {
+		0
+	}
+	fn set_inbound_channel_priority() -> Weight {
+		0
+	}
+	fn set_outbound_rate_limit() -> Weight {
+		0
+	}
+	fn enqueue_fragment(_s: u32) -> Weight {
+		0
+	}
+	fn receive_xcmp_pages(_n: u32) -> Weight {
+		0
+	}
+	fn defer_xcms(_m: u32) -> Weight {
+		0
+	}
+	fn stash_overweight(_s: u32) -> Weight {
+		0
+	}
+	fn process_suspend_signal(_n: u32) -> Weight {
+		0
+	}
+	fn service_overweight() -> Weight {
+		0
+	}
+}
+
+impl Config for Test {
+	type Event = Event;
+	type XcmExecutor = MockExec;
+	type ChannelInfo = MockChannelInfo;
+	type ExecuteOverweightOrigin = frame_system::EnsureRoot<AccountId>;
+	type ControllerOrigin = frame_system::EnsureRoot<AccountId>;
+	type WeightInfo = NoWeight;
+}
+
+/// An XCM that the `MockExec` executes with the given `weight`.
+pub fn transact(weight: Weight) -> Xcm<Call> {
+	Xcm::Transact {
+		origin_type: OriginKind::Native,
+		require_weight_at_most: weight,
+		call: vec![].into(),
+	}
+}
+
+/// An inbound page of the given XCMs.
+pub fn xcm_page(xcms: &[Xcm<Call>]) -> Vec<u8> {
+	let mut page = XcmpMessageFormat::ConcatenatedVersionedXcm.encode();
+	for xcm in xcms {
+		page.extend_from_slice(&VersionedXcm::<Call>::from(xcm.clone()).encode());
+	}
+	page
+}
+
+/// Hand the inbound `pages` of `(sender, sent_at, page)` to the XCMP queue.
+pub fn receive(pages: &[(u32, RelayBlockNumber, Vec<u8>)], max_weight: Weight) -> Weight {
+	XcmpQueue::handle_xcmp_messages(
+		pages.iter().map(|(sender, sent_at, page)| ((*sender).into(), *sent_at, &page[..])),
+		max_weight,
+	)
+}
+
+pub fn new_test_ext() -> sp_io::TestExternalities {
+	CHANNELS.with(|c| c.borrow_mut().clear());
+	EXECUTED.with(|e| e.borrow_mut().clear());
+
+	let mut storage = frame_system::GenesisConfig::default().build_storage::<Test>().unwrap();
+	pallet_balances::GenesisConfig::<Test> { balances: vec![(ALICE, 100_000)] }
+		.assimilate_storage(&mut storage)
+		.unwrap();
+
+	let mut ext: sp_io::TestExternalities = storage.into();
+	// Events are not deposited in the genesis block.
+	ext.execute_with(|| System::set_block_number(1));
+	ext
+}
@@ -0,0 +1,719 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::*;
+use crate::mock::*;
+
+use frame_support::{
+	assert_err, assert_noop, assert_ok, parameter_types,
+	traits::{OnIdle, OnInitialize},
+};
+use sp_runtime::{traits::BlakeTwo256, DispatchError::BadOrigin};
+use xcm::v0::{NetworkId, OriginKind};
+
+/// The events of the XCMP queue, in the order they were deposited.
+fn events() -> Vec<crate::Event<Test>> {
+	System::events()
+		.into_iter()
+		.filter_map(|r| match r.event {
+			Event::XcmpQueue(e) => Some(e),
+			_ => None,
+		})
+		.collect()
+}
+
+/// An outbound page of the given blobs.
+fn blob_page(blobs: &[Vec<u8>]) -> Vec<u8> {
+	let mut page = XcmpMessageFormat::ConcatenatedEncodedBlob.encode();
+	for blob in blobs {
+		page.extend_from_slice(&blob.encode());
+	}
+	page
+}
+
+fn send_blob(recipient: u32, blob: Vec<u8>) -> Result<u32, MessageSendError> {
+	XcmpQueue::send_blob_message(recipient.into(), blob)
+}
+
+#[test]
+fn inherent_weight_limit_leaves_messages_to_on_idle() {
+	new_test_ext().execute_with(|| {
+		QueueConfig::<Test>::mutate(|config| config.inherent_weight_limit = 3_000_000);
+
+		let page = xcm_page(&[transact(2_000_000), transact(2_000_001)]);
+		assert_eq!(receive(&[(1000, 1, page)], 10_000_000), 2_000_000);
+		assert_eq!(take_executed(), vec![(1000.into(), 2_000_000)]);
+		assert!(InboundXcmpMessages::<Test>::contains_key(ParaId::from(1000), 1));
+
+		assert_eq!(XcmpQueue::on_idle(1, 10_000_000), 2_000_001);
+		assert_eq!(take_executed(), vec![(1000.into(), 2_000_001)]);
+		assert!(InboundXcmpStatus::<Test>::get().is_empty());
+	});
+}
+
+#[test]
+fn overweight_xcms_are_parked_and_serviced() {
+	new_test_ext().execute_with(|| {
+		QueueConfig::<Test>::mutate(|config| config.xcmp_max_individual_weight = 5_000_000);
+
+		let overweight = transact(10_000_000);
+		let page = xcm_page(&[overweight.clone(), transact(1_000_000)]);
+		// The overweight XCM doesn't hold back the XCM after it.
+		assert_eq!(receive(&[(1000, 1, page)], 8_000_000), 1_000_000);
+		assert_eq!(take_executed(), vec![(1000.into(), 1_000_000)]);
+		let data = VersionedXcm::<Call>::from(overweight).encode();
+		assert_eq!(Overweight::<Test>::get(0), Some((1000.into(), 1, data)));
+		let enqueued = crate::Event::OverweightEnqueued(1000.into(), 1, 0, 10_000_000);
+		assert!(events().contains(&enqueued));
+
+		assert_noop!(XcmpQueue::service_overweight(Origin::signed(1), 0, 10_000_000), BadOrigin);
+		assert_noop!(
+			XcmpQueue::service_overweight(Origin::root(), 1, 10_000_000),
+			Error::<Test>::BadOverweightIndex,
+		);
+		assert_err!(
+			XcmpQueue::service_overweight(Origin::root(), 0, 9_999_999),
+			Error::<Test>::WeightOverLimit,
+		);
+		assert!(Overweight::<Test>::contains_key(0));
+
+		assert_ok!(XcmpQueue::service_overweight(Origin::root(), 0, 10_000_000));
+		assert_eq!(take_executed(), vec![(1000.into(), 10_000_000)]);
+		assert!(!Overweight::<Test>::contains_key(0));
+		assert!(events().contains(&crate::Event::OverweightServiced(0, 10_000_000)));
+	});
+}
+
+#[test]
+fn thresholds_must_stay_consistent() {
+	new_test_ext().execute_with(|| {
+		// The default thresholds are 1 to resume, 2 to suspend and 5 to drop.
+		assert_noop!(
+			XcmpQueue::update_resume_threshold(Origin::root(), 2),
+			Error::<Test>::BadQueueConfig,
+		);
+		assert_noop!(
+			XcmpQueue::update_suspend_threshold(Origin::root(), 6),
+			Error::<Test>::BadQueueConfig,
+		);
+		assert_noop!(
+			XcmpQueue::update_drop_threshold(Origin::root(), 1),
+			Error::<Test>::BadQueueConfig,
+		);
+		assert_noop!(XcmpQueue::update_suspend_threshold(Origin::signed(1), 3), BadOrigin);
+
+		assert_ok!(XcmpQueue::update_suspend_threshold(Origin::root(), 3));
+		assert_ok!(XcmpQueue::update_resume_threshold(Origin::root(), 2));
+		let config = QueueConfig::<Test>::get();
+		assert_eq!(
+			(config.resume_threshold, config.suspend_threshold, config.drop_threshold),
+			(2, 3, 5),
+		);
+	});
+}
+
+#[test]
+fn channels_are_suspended_and_resumed_at_the_thresholds() {
+	new_test_ext().execute_with(|| {
+		QueueSuspended::<Test>::put(true);
+		let pages = (1..=3)
+			.map(|sent_at| (1000, sent_at, xcm_page(&[transact(1_000_000)])))
+			.collect::<Vec<_>>();
+		receive(&pages, 10_000_000);
+		assert_eq!(InboundXcmpStatus::<Test>::get()[0].1, InboundStatus::Suspended);
+		assert_eq!(
+			SignalMessages::<Test>::get(ParaId::from(1000)),
+			(XcmpMessageFormat::Signals, ChannelSignal::Suspend).encode(),
+		);
+
+		QueueSuspended::<Test>::kill();
+		XcmpQueue::on_idle(1, 10_000_000);
+		assert_eq!(take_executed().len(), 3);
+		assert!(InboundXcmpStatus::<Test>::get().is_empty());
+		assert_eq!(
+			SignalMessages::<Test>::get(ParaId::from(1000)),
+			(XcmpMessageFormat::Signals, ChannelSignal::Suspend, ChannelSignal::Resume).encode(),
+		);
+	});
+}
+
+#[test]
+fn channels_with_a_higher_priority_are_serviced_first() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(XcmpQueue::set_inbound_channel_priority(Origin::root(), 2000.into(), 1));
+		assert!(events().contains(&crate::Event::ChannelPrioritySet(2000.into(), 1)));
+
+		// There is only enough weight for one of the pages.
+		let page = xcm_page(&[transact(6_000_000)]);
+		receive(&[(1000, 1, page.clone()), (2000, 1, page)], 9_000_000);
+		assert_eq!(take_executed(), vec![(2000.into(), 6_000_000)]);
+
+		XcmpQueue::on_idle(1, 9_000_000);
+		assert_eq!(take_executed(), vec![(1000.into(), 6_000_000)]);
+
+		assert_ok!(XcmpQueue::set_inbound_channel_priority(Origin::root(), 2000.into(), 0));
+		assert!(InboundChannelPriorities::<Test>::get().is_empty());
+		assert!(events().contains(&crate::Event::ChannelPrioritySet(2000.into(), 0)));
+	});
+}
+
+#[test]
+fn queue_config_can_be_updated_by_the_controller() {
+	new_test_ext().execute_with(|| {
+		let config = QueueConfigData { threshold_weight: 1_000, ..Default::default() };
+		assert_noop!(XcmpQueue::update_queue_config(Origin::signed(1), config), BadOrigin);
+		assert_noop!(
+			XcmpQueue::update_queue_config(
+				Origin::root(),
+				QueueConfigData { resume_threshold: 3, ..config },
+			),
+			Error::<Test>::BadQueueConfig,
+		);
+		assert_ok!(XcmpQueue::update_queue_config(Origin::root(), config));
+		assert_eq!(QueueConfig::<Test>::get(), config);
+	});
+}
+
+#[test]
+fn execution_can_be_suspended_and_resumed() {
+	new_test_ext().execute_with(|| {
+		assert_noop!(XcmpQueue::suspend_xcm_execution(Origin::signed(1)), BadOrigin);
+		assert_ok!(XcmpQueue::suspend_xcm_execution(Origin::root()));
+
+		// The messages are queued, but not executed.
+		assert_eq!(receive(&[(1000, 1, xcm_page(&[transact(1_000_000)]))], 10_000_000), 0);
+		assert_eq!(XcmpQueue::on_idle(1, 10_000_000), 0);
+		assert!(take_executed().is_empty());
+
+		assert_ok!(XcmpQueue::resume_xcm_execution(Origin::root()));
+		assert_eq!(XcmpQueue::on_idle(1, 10_000_000), 1_000_000);
+		assert_eq!(take_executed(), vec![(1000.into(), 1_000_000)]);
+
+		let events = events();
+		assert!(events.contains(&crate::Event::XcmExecutionSuspended));
+		assert!(events.contains(&crate::Event::XcmExecutionResumed));
+	});
+}
+
+#[test]
+fn xcms_of_deferred_senders_are_executed_after_the_period() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(XcmpQueue::set_deferral_period(Origin::root(), 1000.into(), 2));
+		assert!(events().contains(&crate::Event::DeferralPeriodSet(1000.into(), Some(2))));
+
+		let page = xcm_page(&[transact(1_000_000), transact(2_000_000)]);
+		assert_eq!(receive(&[(1000, 1, page)], 10_000_000), 0);
+		assert!(take_executed().is_empty());
+		assert!(events().contains(&crate::Event::XcmDeferred(1000.into(), 1, 0, 3)));
+		assert!(events().contains(&crate::Event::XcmDeferred(1000.into(), 1, 1, 3)));
+
+		assert_ok!(XcmpQueue::cancel_deferred_xcm(Origin::root(), 1000.into(), 0));
+		assert_noop!(
+			XcmpQueue::cancel_deferred_xcm(Origin::root(), 1000.into(), 0),
+			Error::<Test>::BadDeferredIndex,
+		);
+		assert!(events().contains(&crate::Event::DeferredXcmCancelled(1000.into(), 0)));
+
+		assert_eq!(XcmpQueue::on_idle(2, 10_000_000), 0);
+		assert_eq!(XcmpQueue::on_idle(3, 10_000_000), 2_000_000);
+		assert_eq!(take_executed(), vec![(1000.into(), 2_000_000)]);
+		assert_eq!(DeferredIndices::<Test>::get().get(&ParaId::from(1000)), Some(&(2, 2)));
+
+		assert_ok!(XcmpQueue::set_deferral_period(Origin::root(), 1000.into(), 0));
+		assert!(DeferralPeriods::<Test>::get().is_empty());
+		assert!(events().contains(&crate::Event::DeferralPeriodSet(1000.into(), None)));
+	});
+}
+
+#[test]
+fn deferred_xcms_of_paused_senders_do_not_hold_back_the_others() {
+	new_test_ext().execute_with(|| {
+		for sender in &[1000, 2000] {
+			assert_ok!(XcmpQueue::set_deferral_period(Origin::root(), (*sender).into(), 1));
+		}
+		let page = xcm_page(&[transact(1_000_000)]);
+		receive(&[(1000, 1, page.clone()), (2000, 1, page)], 10_000_000);
+		assert_ok!(XcmpQueue::pause_channel(Origin::root(), 1000.into()));
+
+		assert_eq!(XcmpQueue::on_idle(2, 10_000_000), 1_000_000);
+		assert_eq!(take_executed(), vec![(2000.into(), 1_000_000)]);
+		assert!(DeferredXcms::<Test>::contains_key(ParaId::from(1000), 0));
+
+		assert_ok!(XcmpQueue::resume_channel(Origin::root(), 1000.into()));
+		assert_eq!(XcmpQueue::on_idle(3, 10_000_000), 1_000_000);
+		assert_eq!(take_executed(), vec![(1000.into(), 1_000_000)]);
+	});
+}
+
+#[test]
+fn outbound_pages_are_split_when_the_max_message_size_shrinks() {
+	new_test_ext().execute_with(|| {
+		set_channel(2000, 100, 100);
+		// Two blobs of 41 bytes each fit into a page.
+		let blobs = (0..4).map(|i| vec![i; 40]).collect::<Vec<_>>();
+		for blob in &blobs {
+			assert_ok!(send_blob(2000, blob.clone()));
+		}
+		assert_eq!(
+			OutboundXcmpStatus::<Test>::get(),
+			vec![(2000.into(), OutboundStatus::Ok, false, 0, 2)],
+		);
+
+		// The first page is split into two and the second page is moved behind them.
+		set_channel(2000, 50, 50);
+		assert_eq!(
+			XcmpQueue::take_outbound_messages(10),
+			vec![(2000.into(), blob_page(&blobs[..1]))],
+		);
+		assert_eq!(
+			OutboundXcmpStatus::<Test>::get(),
+			vec![(2000.into(), OutboundStatus::Ok, false, 1, 3)],
+		);
+		assert_eq!(
+			OutboundXcmpMessages::<Test>::get(ParaId::from(2000), 1),
+			blob_page(&blobs[1..2]),
+		);
+		assert_eq!(
+			OutboundXcmpMessages::<Test>::get(ParaId::from(2000), 2),
+			blob_page(&blobs[2..]),
+		);
+
+		for blob in &blobs[1..] {
+			assert_eq!(
+				XcmpQueue::take_outbound_messages(10),
+				vec![(2000.into(), blob_page(&[blob.clone()]))],
+			);
+		}
+		assert!(OutboundXcmpStatus::<Test>::get().is_empty());
+	});
+}
+
+#[test]
+fn fragments_above_the_new_max_message_size_are_dropped() {
+	new_test_ext().execute_with(|| {
+		set_channel(2000, 100, 100);
+		let (big_a, big_b, small) = (vec![1; 60], vec![2; 60], vec![3; 10]);
+		for blob in &[big_a, big_b.clone(), small.clone()] {
+			assert_ok!(send_blob(2000, blob.clone()));
+		}
+
+		// Nothing is left of the first page, so the second page is moved to its index. It is
+		// still too big, so it is split the next time.
+		set_channel(2000, 40, 40);
+		assert!(XcmpQueue::take_outbound_messages(10).is_empty());
+		assert_eq!(
+			OutboundXcmpStatus::<Test>::get(),
+			vec![(2000.into(), OutboundStatus::Ok, false, 0, 1)],
+		);
+		assert_eq!(
+			OutboundXcmpMessages::<Test>::get(ParaId::from(2000), 0),
+			blob_page(&[big_b, small.clone()]),
+		);
+
+		assert_eq!(XcmpQueue::take_outbound_messages(10), vec![(2000.into(), blob_page(&[small]))]);
+		assert!(OutboundXcmpStatus::<Test>::get().is_empty());
+	});
+}
+
+#[test]
+fn sent_and_processed_xcms_are_traceable_by_hash() {
+	new_test_ext().execute_with(|| {
+		set_channel(2000, 1000, 1000);
+		let xcm = Xcm::<()>::Transact {
+			origin_type: OriginKind::Native,
+			require_weight_at_most: 1_000,
+			call: vec![].into(),
+		};
+		let hash = BlakeTwo256::hash_of(&VersionedXcm::<()>::from(xcm.clone()));
+		let dest = MultiLocation::X2(Junction::Parent, Junction::Parachain(2000));
+		assert_ok!(XcmpQueue::send_xcm(dest, xcm));
+		assert!(events().contains(&crate::Event::XcmpMessageQueued(2000.into(), hash)));
+		assert!(events().contains(&crate::Event::XcmpMessageSent(Some(hash))));
+
+		let inbound = transact(1_000_000);
+		let hash = BlakeTwo256::hash_of(&VersionedXcm::<Call>::from(inbound.clone()));
+		receive(&[(1000, 1, xcm_page(&[inbound]))], 10_000_000);
+		assert!(events().contains(&crate::Event::Success(Some(hash))));
+		assert!(events().contains(&crate::Event::XcmProcessed(
+			hash,
+			1000.into(),
+			1,
+			1_000_000,
+			XcmProcessingOutcome::Complete,
+		)));
+	});
+}
+
+#[test]
+fn queue_status_reports_the_queued_pages() {
+	new_test_ext().execute_with(|| {
+		set_channel(2000, 1000, 1000);
+		assert_ok!(XcmpQueue::suspend_xcm_execution(Origin::root()));
+		let page = xcm_page(&[transact(1_000_000)]);
+		receive(&[(1000, 1, page.clone()), (1000, 2, page.clone())], 10_000_000);
+		assert_ok!(send_blob(2000, vec![0; 10]));
+
+		// The inbound pages are stored without their format.
+		let inbound_bytes = 2 * (page.len() as u32 - 1);
+		let outbound_bytes = blob_page(&[vec![0; 10]]).len() as u32;
+		assert_eq!(
+			XcmpQueue::queue_status(),
+			XcmpQueueStatus {
+				channels: vec![
+					XcmpChannelQueueStatus {
+						para_id: 1000.into(),
+						inbound_pages: 2,
+						inbound_bytes,
+						..Default::default()
+					},
+					XcmpChannelQueueStatus {
+						para_id: 2000.into(),
+						outbound_pages: 1,
+						outbound_bytes,
+						..Default::default()
+					},
+				],
+				total_queued_bytes: (inbound_bytes + outbound_bytes) as u64,
+				execution_suspended: true,
+			},
+		);
+	});
+}
+
+parameter_types! {
+	pub const BaseDeliveryFee: u128 = 1_000;
+	pub const ByteDeliveryFee: u128 = 10;
+}
+
+type Price = ExponentialPrice<BaseDeliveryFee, ByteDeliveryFee, XcmpQueue>;
+type Router =
+	ChargeSiblingDeliveryFee<XcmpQueue, Price, AccountIndexToAccountId, AccountId, Balances, ()>;
+
+#[test]
+fn delivery_fee_factor_follows_the_congestion() {
+	new_test_ext().execute_with(|| {
+		set_channel(2000, 100, 100);
+		let factor = || DeliveryFeeFactor::<Test>::get(ParaId::from(2000));
+
+		// The factor grows once the channel has more pages than half the suspend threshold.
+		assert_ok!(send_blob(2000, vec![0; 90]));
+		assert_eq!(Price::price_for_sibling_delivery(2000.into(), 100), 2_000);
+		assert_ok!(send_blob(2000, vec![1; 90]));
+		assert_eq!(factor(), FixedU128::saturating_from_rational(105, 100));
+		assert_eq!(Price::price_for_sibling_delivery(2000.into(), 100), 2_100);
+
+		XcmpQueue::take_outbound_messages(10);
+		assert_eq!(factor(), FixedU128::saturating_from_rational(105, 100));
+		XcmpQueue::take_outbound_messages(10);
+		assert_eq!(factor(), InitialFactor::get());
+		assert!(!DeliveryFeeFactor::<Test>::contains_key(ParaId::from(2000)));
+	});
+}
+
+#[test]
+fn delivery_fees_of_relayed_xcms_are_charged() {
+	new_test_ext().execute_with(|| {
+		let sibling = |id| MultiLocation::X2(Junction::Parent, Junction::Parachain(id));
+		let message = Xcm::<()>::Transact {
+			origin_type: OriginKind::SovereignAccount,
+			require_weight_at_most: 1_000,
+			call: vec![1, 2, 3].into(),
+		};
+		let alice =
+			MultiLocation::X1(Junction::AccountIndex64 { network: NetworkId::Any, index: ALICE });
+		let relayed = Xcm::RelayedFrom { who: alice, message: Box::new(message.clone()) };
+		let size = VersionedXcm::<()>::from(relayed.clone()).encoded_size() as u64;
+		let balance = 100_000 - 1_000 - 10 * size;
+		set_channel(2000, 1_000, 1_000);
+
+		assert_ok!(Router::send_xcm(sibling(2000), relayed.clone()));
+		assert_eq!(Balances::free_balance(ALICE), balance);
+
+		// The fee is refunded if the XCM is not sent.
+		assert!(Router::send_xcm(sibling(3000), relayed.clone()).is_err());
+		assert_eq!(Balances::free_balance(ALICE), balance);
+
+		// XCMs that are not relayed from an account are not charged.
+		assert_ok!(Router::send_xcm(sibling(2000), message));
+		assert_eq!(Balances::free_balance(ALICE), balance);
+		assert_eq!(
+			events().into_iter().filter(|e| matches!(e, crate::Event::XcmpMessageSent(_))).count(),
+			2,
+		);
+	});
+}
+
+#[test]
+fn oldest_inbound_pages_are_dropped_at_the_drop_threshold() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(XcmpQueue::suspend_xcm_execution(Origin::root()));
+		let pages = (1..=7u32)
+			.map(|sent_at| (1000, sent_at, xcm_page(&[transact(sent_at as Weight)])))
+			.collect::<Vec<_>>();
+		receive(&pages, 10_000_000);
+
+		let sent_at = InboundXcmpStatus::<Test>::get()[0]
+			.2
+			.iter()
+			.map(|(sent_at, _)| *sent_at)
+			.collect::<Vec<_>>();
+		assert_eq!(sent_at, vec![3, 4, 5, 6, 7]);
+		assert!(!InboundXcmpMessages::<Test>::contains_key(ParaId::from(1000), 1));
+		assert!(!InboundXcmpMessages::<Test>::contains_key(ParaId::from(1000), 2));
+		assert!(InboundXcmpMessages::<Test>::contains_key(ParaId::from(1000), 3));
+		assert_eq!(DroppedInboundPages::<Test>::get(), 2);
+		assert!(events().contains(&crate::Event::InboundPagesDropped(1000.into(), 2)));
+	});
+}
+
+#[test]
+fn fragments_above_the_max_message_size_are_rejected() {
+	new_test_ext().execute_with(|| {
+		set_channel(2000, 50, 50);
+		// The 49 encoded bytes fill a page together with the format.
+		assert_ok!(send_blob(2000, vec![0; 48]));
+		assert_eq!(
+			send_blob(2000, vec![0; 49]),
+			Err(MessageSendError::ExceedsMaxMessageSize { size: 51, max: 50 }),
+		);
+		assert_eq!(send_blob(3000, vec![0; 1]), Err(MessageSendError::NoChannel));
+	});
+}
+
+#[test]
+fn rate_limited_messages_overflow_in_order() {
+	new_test_ext().execute_with(|| {
+		set_channel(2000, 1000, 1000);
+		let limit = OutboundRateLimit {
+			max_messages_per_block: 1,
+			max_bytes_per_block: 1_000,
+			max_overflow_messages: 2,
+		};
+		assert_ok!(XcmpQueue::set_outbound_rate_limit(Origin::root(), 2000.into(), Some(limit)));
+
+		let blobs = (1..=4).map(|i| vec![i; 10]).collect::<Vec<_>>();
+		for blob in &blobs[..3] {
+			assert_ok!(send_blob(2000, blob.clone()));
+		}
+		let page = || OutboundXcmpMessages::<Test>::get(ParaId::from(2000), 0);
+		assert_eq!(page(), blob_page(&blobs[..1]));
+		assert_eq!(OutboundOverflow::<Test>::get(ParaId::from(2000)).len(), 2);
+
+		// One overflowed message is released per block, and new messages queue up behind them.
+		XcmpQueue::on_initialize(2);
+		assert_ok!(send_blob(2000, blobs[3].clone()));
+		assert_eq!(page(), blob_page(&blobs[..2]));
+		XcmpQueue::on_initialize(3);
+		XcmpQueue::on_initialize(4);
+		assert!(!OutboundOverflow::<Test>::contains_key(ParaId::from(2000)));
+
+		assert_eq!(XcmpQueue::take_outbound_messages(10), vec![(2000.into(), blob_page(&blobs))]);
+	});
+}
+
+#[test]
+fn messages_beyond_the_max_overflow_are_rejected() {
+	new_test_ext().execute_with(|| {
+		set_channel(2000, 1000, 1000);
+		let limit = OutboundRateLimit {
+			max_messages_per_block: 1,
+			max_bytes_per_block: 1_000,
+			max_overflow_messages: 1,
+		};
+		assert_ok!(XcmpQueue::set_outbound_rate_limit(Origin::root(), 2000.into(), Some(limit)));
+
+		// The overflowed message goes behind the last page.
+		assert_eq!(send_blob(2000, vec![1; 10]), Ok(0));
+		assert_eq!(send_blob(2000, vec![2; 10]), Ok(0));
+		assert_eq!(send_blob(2000, vec![3; 10]), Err(MessageSendError::QueueFull));
+		assert_eq!(OutboundOverflow::<Test>::get(ParaId::from(2000)).len(), 1);
+
+		XcmpQueue::on_initialize(2);
+		assert_eq!(send_blob(2000, vec![3; 10]), Ok(0));
+	});
+}
+
+#[test]
+fn rate_usage_is_only_tracked_for_limited_channels() {
+	new_test_ext().execute_with(|| {
+		set_channel(2000, 1000, 1000);
+		assert_ok!(send_blob(2000, vec![1; 10]));
+		XcmpQueue::on_initialize(2);
+		assert!(OutboundRateUsage::<Test>::get().is_empty());
+
+		let limit = OutboundRateLimit {
+			max_messages_per_block: 10,
+			max_bytes_per_block: 1_000,
+			max_overflow_messages: 10,
+		};
+		assert_ok!(XcmpQueue::set_outbound_rate_limit(Origin::root(), 2000.into(), Some(limit)));
+		assert_ok!(send_blob(2000, vec![2; 10]));
+		assert_eq!(OutboundRateUsage::<Test>::get().get(&ParaId::from(2000)), Some(&(1, 11)));
+
+		// The usage of a channel is forgotten with its limit.
+		assert_ok!(XcmpQueue::set_outbound_rate_limit(Origin::root(), 2000.into(), None));
+		assert!(OutboundRateUsage::<Test>::get().is_empty());
+	});
+}
+
+#[test]
+fn xcm_versions_of_the_senders_are_tracked() {
+	new_test_ext().execute_with(|| {
+		assert_eq!(ChannelXcmVersions::<Test>::get(ParaId::from(1000)), None);
+		let page = xcm_page(&[transact(1_000_000)]);
+		receive(&[(1000, 1, page.clone())], 10_000_000);
+		assert_eq!(ChannelXcmVersions::<Test>::get(ParaId::from(1000)), Some(0));
+		receive(&[(1000, 2, page)], 10_000_000);
+		let updates = events()
+			.into_iter()
+			.filter(|e| matches!(e, crate::Event::XcmVersionUpdated(..)))
+			.count();
+		assert_eq!(updates, 1);
+
+		let xcm = Xcm::<()>::WithdrawAsset { assets: vec![], effects: vec![] };
+		assert_eq!(
+			XcmpQueue::versioned_xcm_for(1000.into(), xcm.clone()),
+			Ok(VersionedXcm::V0(xcm)),
+		);
+	});
+}
+
+#[test]
+fn signals_are_handled_before_the_other_pages() {
+	new_test_ext().execute_with(|| {
+		set_channel(2000, 1000, 1000);
+		assert_ok!(send_blob(2000, vec![0; 10]));
+
+		// The suspend signal comes after a page of XCMs of the same sender.
+		let suspend = (XcmpMessageFormat::Signals, ChannelSignal::Suspend).encode();
+		let page = xcm_page(&[transact(1_000_000)]);
+		receive(&[(2000, 1, page), (2000, 2, suspend)], 10_000_000);
+		assert_eq!(take_executed(), vec![(2000.into(), 1_000_000)]);
+		assert!(!InboundXcmpMessages::<Test>::contains_key(ParaId::from(2000), 2));
+		assert_eq!(
+			OutboundXcmpStatus::<Test>::get(),
+			vec![(2000.into(), OutboundStatus::Suspended, false, 0, 1)],
+		);
+		assert!(XcmpQueue::take_outbound_messages(10).is_empty());
+
+		let resume = (XcmpMessageFormat::Signals, ChannelSignal::Resume).encode();
+		receive(&[(2000, 3, resume)], 10_000_000);
+		assert_eq!(XcmpQueue::take_outbound_messages(10).len(), 1);
+	});
+}
+
+#[test]
+fn duplicate_xcms_within_the_window_are_skipped() {
+	new_test_ext().execute_with(|| {
+		QueueConfig::<Test>::mutate(|config| config.duplicate_window = 2);
+
+		let duplicate = transact(1_000_000);
+		let hash = BlakeTwo256::hash_of(&VersionedXcm::<Call>::from(duplicate.clone()));
+		receive(&[(1000, 1, xcm_page(&[duplicate.clone(), duplicate.clone()]))], 10_000_000);
+		assert_eq!(take_executed(), vec![(1000.into(), 1_000_000)]);
+		assert!(events().contains(&crate::Event::DuplicateSkipped(1000.into(), 1, hash)));
+
+		// Once two other XCMs were processed, the XCM is no longer a duplicate.
+		receive(&[(1000, 2, xcm_page(&[transact(2_000_000), transact(3_000_000)]))], 10_000_000);
+		assert_eq!(take_executed().len(), 2);
+		receive(&[(1000, 3, xcm_page(&[duplicate]))], 10_000_000);
+		assert_eq!(take_executed(), vec![(1000.into(), 1_000_000)]);
+	});
+}
+
+#[test]
+fn recent_xcm_hashes_follow_the_duplicate_window() {
+	new_test_ext().execute_with(|| {
+		QueueConfig::<Test>::mutate(|config| config.duplicate_window = 3);
+		let xcms = [transact(1_000_000), transact(2_000_000), transact(3_000_000)];
+		let hashes = xcms
+			.iter()
+			.map(|xcm| BlakeTwo256::hash_of(&VersionedXcm::<Call>::from(xcm.clone())))
+			.collect::<Vec<_>>();
+		receive(&[(1000, 1, xcm_page(&xcms))], 10_000_000);
+		assert_eq!(take_executed().len(), 3);
+		assert_eq!(RecentXcmHashes::<Test>::get(ParaId::from(1000)), hashes);
+
+		// Once the window shrinks, the oldest hashes are no longer duplicates.
+		QueueConfig::<Test>::mutate(|config| config.duplicate_window = 1);
+		receive(&[(1000, 2, xcm_page(&xcms[1..2]))], 10_000_000);
+		assert_eq!(take_executed(), vec![(1000.into(), 2_000_000)]);
+		assert_eq!(RecentXcmHashes::<Test>::get(ParaId::from(1000)), vec![hashes[1]]);
+
+		// Without a window, no hashes are kept.
+		QueueConfig::<Test>::mutate(|config| config.duplicate_window = 0);
+		receive(&[(1000, 3, xcm_page(&xcms[1..2]))], 10_000_000);
+		assert_eq!(take_executed(), vec![(1000.into(), 2_000_000)]);
+		assert!(!RecentXcmHashes::<Test>::contains_key(ParaId::from(1000)));
+	});
+}
+
+#[test]
+fn recent_xcm_hashes_are_removed_when_the_channel_closes() {
+	new_test_ext().execute_with(|| {
+		QueueConfig::<Test>::mutate(|config| config.duplicate_window = 2);
+		receive(&[(2000, 1, xcm_page(&[transact(1_000_000)]))], 10_000_000);
+		assert!(RecentXcmHashes::<Test>::contains_key(ParaId::from(2000)));
+
+		set_channel(2000, 1000, 1000);
+		assert_ok!(send_blob(2000, vec![1; 10]));
+		close_channel(2000);
+		assert!(XcmpQueue::take_outbound_messages(10).is_empty());
+		assert!(!RecentXcmHashes::<Test>::contains_key(ParaId::from(2000)));
+	});
+}
+
+#[test]
+fn invalid_xcms_use_no_weight() {
+	new_test_ext().execute_with(|| {
+		// The `MockExec` fails to execute anything but a `Transact`.
+		let failing = Xcm::<Call>::WithdrawAsset { assets: vec![], effects: vec![] };
+		let hash = BlakeTwo256::hash_of(&VersionedXcm::<Call>::from(failing.clone()));
+		let mut page = xcm_page(&[failing, transact(1_000_000)]);
+		// Data that can't be decoded.
+		page.push(0xff);
+
+		assert_eq!(receive(&[(1000, 1, page)], 10_000_000), 1_000_000);
+		assert!(!InboundXcmpMessages::<Test>::contains_key(ParaId::from(1000), 1));
+		assert!(events().contains(&crate::Event::XcmProcessed(
+			hash,
+			1000.into(),
+			1,
+			0,
+			XcmProcessingOutcome::Error(XcmError::Unimplemented),
+		)));
+	});
+}
+
+#[test]
+fn paused_channels_are_not_executed() {
+	new_test_ext().execute_with(|| {
+		assert_noop!(XcmpQueue::pause_channel(Origin::signed(1), 1000.into()), BadOrigin);
+		assert_ok!(XcmpQueue::pause_channel(Origin::root(), 1000.into()));
+
+		let page = xcm_page(&[transact(1_000_000)]);
+		assert_eq!(receive(&[(1000, 1, page.clone()), (2000, 1, page)], 10_000_000), 1_000_000);
+		assert_eq!(take_executed(), vec![(2000.into(), 1_000_000)]);
+
+		assert_ok!(XcmpQueue::resume_channel(Origin::root(), 1000.into()));
+		assert_eq!(XcmpQueue::on_idle(1, 10_000_000), 1_000_000);
+		assert_eq!(take_executed(), vec![(1000.into(), 1_000_000)]);
+
+		let events = events();
+		assert!(events.contains(&crate::Event::ChannelPaused(1000.into())));
+		assert!(events.contains(&crate::Event::ChannelResumed(1000.into())));
+	});
+}
@@ -0,0 +1,221 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Cumulus.
+
+// Cumulus is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Cumulus is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The weights of the dispatchables and of the message queueing of this pallet.
+//!
+//! The functions are benchmarked in [`crate::benchmarking`]. The weights of the message queueing
+//! functions are the base for choosing the weights of the `QueueConfig`, e.g. `threshold_weight`.
+
+use frame_support::{
+	traits::Get,
+	weights::{constants::RocksDbWeight, Weight},
+};
+use sp_std::marker::PhantomData;
+
+/// The weight functions needed by this pallet.
+pub trait WeightInfo {
+	fn update_suspend_threshold() -> Weight;
+	fn update_resume_threshold() -> Weight;
+	fn update_drop_threshold() -> Weight;
+	fn update_queue_config() -> Weight;
+	fn suspend_xcm_execution() -> Weight;
+	fn resume_xcm_execution() -> Weight;
+	fn pause_channel() -> Weight;
+	fn resume_channel() -> Weight;
+	fn set_deferral_period() -> Weight;
+	fn cancel_deferred_xcm() -> Weight;
+	fn set_inbound_channel_priority() -> Weight;
+	fn set_outbound_rate_limit() -> Weight;
+	/// The weight of placing an outbound fragment of `s` bytes on a page.
+	fn enqueue_fragment(s: u32) -> Weight;
+	/// The weight of receiving `n` inbound pages of different senders.
+	///
+	/// This doesn't include the weight of executing the messages, which is reported separately.
+	fn receive_xcmp_pages(n: u32) -> Weight;
+	/// The weight of processing an inbound page of `m` XCMs, without executing them.
+	fn defer_xcms(m: u32) -> Weight;
+	/// The weight of placing an XCM of `s` bytes in the overweight queue.
+	fn stash_overweight(s: u32) -> Weight;
+	/// The weight of processing a suspend signal with `n` outbound channels.
+	fn process_suspend_signal(n: u32) -> Weight;
+	/// The weight of servicing an overweight XCM, without executing it.
+	fn service_overweight() -> Weight;
+}
+
+/// The weights of the benchmarks in [`crate::benchmarking`].
+///
+/// These are estimates from the storage accesses of the benchmarks, until they are regenerated
+/// with `benchmark --pallet cumulus_pallet_xcmp_queue` on the reference hardware.
+pub struct SubstrateWeight<T>(PhantomData<T>);
+impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
+	fn update_suspend_threshold() -> Weight {
+		(3_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+	fn update_resume_threshold() -> Weight {
+		(3_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+	fn update_drop_threshold() -> Weight {
+		(3_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+	fn update_queue_config() -> Weight {
+		(4_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+	fn suspend_xcm_execution() -> Weight {
+		(2_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+	fn resume_xcm_execution() -> Weight {
+		(2_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+	fn pause_channel() -> Weight {
+		(4_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+	fn resume_channel() -> Weight {
+		(4_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+	fn set_deferral_period() -> Weight {
+		(4_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+	fn cancel_deferred_xcm() -> Weight {
+		(6_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+	fn set_inbound_channel_priority() -> Weight {
+		(4_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+	fn set_outbound_rate_limit() -> Weight {
+		(5_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(2 as Weight))
+			.saturating_add(T::DbWeight::get().writes(2 as Weight))
+	}
+	fn enqueue_fragment(s: u32) -> Weight {
+		(8_000_000 as Weight)
+			.saturating_add((1_000 as Weight).saturating_mul(s as Weight))
+			.saturating_add(T::DbWeight::get().reads(3 as Weight))
+			.saturating_add(T::DbWeight::get().writes(2 as Weight))
+	}
+	fn receive_xcmp_pages(n: u32) -> Weight {
+		(10_000_000 as Weight)
+			.saturating_add((4_000_000 as Weight).saturating_mul(n as Weight))
+			.saturating_add(T::DbWeight::get().reads(2 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes((1 as Weight).saturating_mul(n as Weight)))
+	}
+	fn defer_xcms(m: u32) -> Weight {
+		(12_000_000 as Weight)
+			.saturating_add((6_000_000 as Weight).saturating_mul(m as Weight))
+			.saturating_add(T::DbWeight::get().reads(4 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes((1 as Weight).saturating_mul(m as Weight)))
+	}
+	fn stash_overweight(s: u32) -> Weight {
+		(5_000_000 as Weight)
+			.saturating_add((1_000 as Weight).saturating_mul(s as Weight))
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(2 as Weight))
+	}
+	fn process_suspend_signal(n: u32) -> Weight {
+		(10_000_000 as Weight)
+			.saturating_add((100_000 as Weight).saturating_mul(n as Weight))
+			.saturating_add(T::DbWeight::get().reads(3 as Weight))
+			.saturating_add(T::DbWeight::get().writes(2 as Weight))
+	}
+	fn service_overweight() -> Weight {
+		(10_000_000 as Weight)
+			.saturating_add(T::DbWeight::get().reads(1 as Weight))
+			.saturating_add(T::DbWeight::get().writes(1 as Weight))
+	}
+}
+
+/// The weights that were used before the pallet was benchmarked.
+impl WeightInfo for () {
+	fn update_suspend_threshold() -> Weight {
+		RocksDbWeight::get().writes(1)
+	}
+	fn update_resume_threshold() -> Weight {
+		RocksDbWeight::get().writes(1)
+	}
+	fn update_drop_threshold() -> Weight {
+		RocksDbWeight::get().writes(1)
+	}
+	fn update_queue_config() -> Weight {
+		RocksDbWeight::get().writes(1)
+	}
+	fn suspend_xcm_execution() -> Weight {
+		RocksDbWeight::get().writes(1)
+	}
+	fn resume_xcm_execution() -> Weight {
+		RocksDbWeight::get().writes(1)
+	}
+	fn pause_channel() -> Weight {
+		RocksDbWeight::get().reads_writes(1, 1)
+	}
+	fn resume_channel() -> Weight {
+		RocksDbWeight::get().reads_writes(1, 1)
+	}
+	fn set_deferral_period() -> Weight {
+		RocksDbWeight::get().reads_writes(1, 1)
+	}
+	fn cancel_deferred_xcm() -> Weight {
+		RocksDbWeight::get().reads_writes(1, 1)
+	}
+	fn set_inbound_channel_priority() -> Weight {
+		RocksDbWeight::get().reads_writes(1, 1)
+	}
+	fn set_outbound_rate_limit() -> Weight {
+		RocksDbWeight::get().reads_writes(2, 2)
+	}
+	fn enqueue_fragment(_s: u32) -> Weight {
+		RocksDbWeight::get().reads_writes(2, 2)
+	}
+	fn receive_xcmp_pages(n: u32) -> Weight {
+		RocksDbWeight::get()
+			.reads_writes(2, 1)
+			.saturating_add(RocksDbWeight::get().writes(n as Weight))
+	}
+	fn defer_xcms(m: u32) -> Weight {
+		RocksDbWeight::get()
+			.reads_writes(4, 1)
+			.saturating_add(RocksDbWeight::get().writes(m as Weight))
+	}
+	fn stash_overweight(_s: u32) -> Weight {
+		RocksDbWeight::get().reads_writes(1, 2)
+	}
+	fn process_suspend_signal(_n: u32) -> Weight {
+		RocksDbWeight::get().reads_writes(1, 1)
+	}
+	fn service_overweight() -> Weight {
+		1_000_000 + RocksDbWeight::get().reads_writes(1, 1)
+	}
+}
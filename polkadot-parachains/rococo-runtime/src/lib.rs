@@ -234,22 +234,69 @@ impl pallet_sudo::Config for Runtime {
 parameter_types! {
 	pub const ReservedXcmpWeight: Weight = MAXIMUM_BLOCK_WEIGHT / 4;
 	pub const ReservedDmpWeight: Weight = MAXIMUM_BLOCK_WEIGHT / 4;
+	pub const RelayHrmpPalletIndex: u8 = 60;
+	pub const RelayHrmpCallWeight: Weight = 1_000_000_000;
+	pub const UpgradePovReserve: u32 = 1024 * 1024;
+	pub const MaxPausedMessages: u32 = 1_000;
+	pub const MaxDeferredHorizontalMessages: u32 = 1_000;
+	pub const ValidationDataGapThreshold: u32 = 100;
+	pub const BlockVelocity: u32 = 1;
+	pub const UnincludedSegmentCapacity: u32 = 1;
 }
 
 impl cumulus_pallet_parachain_system::Config for Runtime {
 	type Event = Event;
-	type OnValidationData = ();
+	type OnSystemEvent = ();
+	type OnRelaySessionChange = ();
+	type OnOffboarding = ();
 	type SelfParaId = parachain_info::Pallet<Runtime>;
+	type CheckAssociatedRelayNumber = cumulus_pallet_parachain_system::RelayNumberStrictlyIncreases;
+	type ValidationDataGapThreshold = ValidationDataGapThreshold;
+	type ConsensusHook = cumulus_pallet_parachain_system::FixedVelocityConsensusHook<
+		BlockVelocity,
+		UnincludedSegmentCapacity,
+	>;
 	type OutboundXcmpMessageSource = XcmpQueue;
 	type DmpMessageHandler = DmpQueue;
 	type ReservedDmpWeight = ReservedDmpWeight;
 	type XcmpMessageHandler = XcmpQueue;
 	type ReservedXcmpWeight = ReservedXcmpWeight;
+	type MaxHorizontalMessagesPerBlock = ();
+	type MaxPausedMessages = MaxPausedMessages;
+	type MaxDeferredHorizontalMessages = MaxDeferredHorizontalMessages;
+	type LenientMqcHeadCheck = ();
+	type InboundMessagesOrder = ();
+	type AdditionalRelayStateKeys = ();
+	type HrmpChannelManagerOrigin = frame_system::EnsureRoot<AccountId>;
+	type RelayHrmpPalletIndex = RelayHrmpPalletIndex;
+	type RelayHrmpCallWeight = RelayHrmpCallWeight;
+	type UpgradePovReserve = UpgradePovReserve;
+	type HeadDataExtensionProvider = ();
+	type QueuedMessageDeposit = ();
+	type WeightInfo = ();
 }
 
 impl parachain_info::Config for Runtime {}
 
-impl cumulus_pallet_aura_ext::Config for Runtime {}
+parameter_types! {
+	pub const EquivocationReportLongevity: u64 = DAYS as u64;
+}
+
+impl cumulus_pallet_aura_ext::Config for Runtime {
+	type Event = Event;
+	type HandleEquivocation = ();
+	type SecondaryAuthorWindow = ();
+	type ReportLongevity = EquivocationReportLongevity;
+	type WeightInfo = cumulus_pallet_aura_ext::weights::SubstrateWeight<Runtime>;
+}
+
+impl<C> frame_system::offchain::SendTransactionTypes<C> for Runtime
+where
+	Call: From<C>,
+{
+	type Extrinsic = UncheckedExtrinsic;
+	type OverarchingCall = Call;
+}
 
 parameter_types! {
 	pub const RocLocation: MultiLocation = X1(Parent);
@@ -349,13 +396,29 @@ pub type LocalOriginToLocation = (
 	SignedToAccountId32<Origin, AccountId, RococoNetwork>,
 );
 
+parameter_types! {
+	pub const BaseDeliveryFee: u128 = 1 * MILLIROC;
+	pub const ByteDeliveryFee: u128 = 1 * MICROROC;
+}
+
+/// The price of delivering an XCM to a sibling chain, which grows while the channel is congested.
+pub type PriceForSiblingDelivery =
+	cumulus_pallet_xcmp_queue::ExponentialPrice<BaseDeliveryFee, ByteDeliveryFee, XcmpQueue>;
+
 /// The means for routing XCM messages which are not for local execution into the right message
 /// queues.
 pub type XcmRouter = (
 	// Two routers - use UMP to communicate with the relay chain:
 	cumulus_primitives_utility::ParentAsUmp<ParachainSystem>,
-	// ..and XCMP to communicate with the sibling chains.
-	XcmpQueue,
+	// ..and XCMP to communicate with the sibling chains, burning the delivery fee of the sender.
+	cumulus_pallet_xcmp_queue::ChargeSiblingDeliveryFee<
+		XcmpQueue,
+		PriceForSiblingDelivery,
+		LocationToAccountId,
+		AccountId,
+		Balances,
+		(),
+	>,
 );
 
 impl pallet_xcm::Config for Runtime {
@@ -379,12 +442,18 @@ impl cumulus_pallet_xcmp_queue::Config for Runtime {
 	type Event = Event;
 	type XcmExecutor = XcmExecutor<XcmConfig>;
 	type ChannelInfo = ParachainSystem;
+	type ExecuteOverweightOrigin = frame_system::EnsureRoot<AccountId>;
+	type ControllerOrigin = frame_system::EnsureRoot<AccountId>;
+	type WeightInfo = cumulus_pallet_xcmp_queue::weights::SubstrateWeight<Runtime>;
 }
 
 impl cumulus_pallet_dmp_queue::Config for Runtime {
 	type Event = Event;
 	type XcmExecutor = XcmExecutor<XcmConfig>;
+	type Weigher = FixedWeightBounds<UnitWeightCost, Call>;
 	type ExecuteOverweightOrigin = frame_system::EnsureRoot<AccountId>;
+	type PriorityMessages = cumulus_pallet_dmp_queue::SuperuserTransacts;
+	type OnInvalidDownwardMessage = ();
 }
 
 impl cumulus_ping::Config for Runtime {
@@ -445,7 +514,7 @@ construct_runtime! {
 		Assets: pallet_assets::{Pallet, Call, Storage, Event<T>} = 31,
 
 		Aura: pallet_aura::{Pallet, Config<T>},
-		AuraExt: cumulus_pallet_aura_ext::{Pallet, Config},
+		AuraExt: cumulus_pallet_aura_ext::{Pallet, Call, Config, Event<T>, ValidateUnsigned},
 
 		// XCM helpers.
 		XcmpQueue: cumulus_pallet_xcmp_queue::{Pallet, Call, Storage, Event<T>} = 50,
@@ -579,6 +648,68 @@ impl_runtime_apis! {
 			Aura::authorities()
 		}
 	}
+
+	impl cumulus_primitives_core::RelayChainStateApi<Block> for Runtime {
+		fn relay_chain_state() -> Option<cumulus_primitives_core::RelayChainState> {
+			ParachainSystem::relay_chain_state()
+		}
+	}
+
+	impl cumulus_primitives_core::UnincludedSegmentApi<Block> for Runtime {
+		fn unincluded_segment_capacity(
+			block_hash: <Block as BlockT>::Hash,
+			included_hash: <Block as BlockT>::Hash,
+		) -> u32 {
+			ParachainSystem::unincluded_segment_capacity(block_hash, included_hash)
+		}
+	}
+
+	impl cumulus_primitives_core::RelayStateKeysApi<Block> for Runtime {
+		fn additional_relay_state_keys() -> Vec<Vec<u8>> {
+			ParachainSystem::additional_relay_state_keys()
+		}
+	}
+
+	impl cumulus_primitives_core::XcmpQueueApi<Block> for Runtime {
+		fn xcmp_queue_status() -> cumulus_primitives_core::XcmpQueueStatus {
+			XcmpQueue::queue_status()
+		}
+	}
+
+	impl cumulus_primitives_core::AuraExtApi<Block, AuraId> for Runtime {
+		fn aura_slot_info() -> cumulus_primitives_core::AuraSlotInfo<AuraId> {
+			AuraExt::slot_info()
+		}
+
+		fn consensus_migration_block() -> Option<BlockNumber> {
+			AuraExt::consensus_migration_block()
+		}
+	}
+
+	impl cumulus_primitives_core::AuraEquivocationApi<Block, AuraId> for Runtime {
+		fn submit_report_equivocation_unsigned_extrinsic(
+			equivocation_proof: cumulus_primitives_core::AuraEquivocationProof<
+				<Block as BlockT>::Header,
+				AuraId,
+			>,
+		) -> Option<()> {
+			AuraExt::submit_unsigned_equivocation_report(equivocation_proof)
+		}
+	}
+
+	impl cumulus_primitives_core::DmpQueueApi<Block> for Runtime {
+		fn dmp_queue_status(max_previews: u32) -> cumulus_primitives_core::DmpQueueStatus {
+			DmpQueue::queue_status(max_previews)
+		}
+	}
+
+	impl cumulus_primitives_core::CollectCollationInfo<Block> for Runtime {
+		fn collect_collation_info(
+			header: &<Block as BlockT>::Header,
+		) -> cumulus_primitives_core::CollationInfo {
+			ParachainSystem::collect_collation_info(header)
+		}
+	}
 }
 
 cumulus_pallet_parachain_system::register_validate_block!(
@@ -155,17 +155,46 @@ impl frame_system::Config for Runtime {
 parameter_types! {
 	// We do anything the parent chain tells us in this runtime.
 	pub const ReservedDmpWeight: Weight = MAXIMUM_BLOCK_WEIGHT / 2;
+	pub const RelayHrmpPalletIndex: u8 = 60;
+	pub const RelayHrmpCallWeight: Weight = 1_000_000_000;
+	pub const UpgradePovReserve: u32 = 1024 * 1024;
+	pub const MaxPausedMessages: u32 = 1_000;
+	pub const MaxDeferredHorizontalMessages: u32 = 1_000;
+	pub const ValidationDataGapThreshold: u32 = 100;
+	pub const BlockVelocity: u32 = 1;
+	pub const UnincludedSegmentCapacity: u32 = 1;
 }
 
 impl cumulus_pallet_parachain_system::Config for Runtime {
 	type Event = Event;
-	type OnValidationData = ();
+	type OnSystemEvent = ();
+	type OnRelaySessionChange = ();
+	type OnOffboarding = ();
 	type SelfParaId = parachain_info::Pallet<Runtime>;
+	type CheckAssociatedRelayNumber = cumulus_pallet_parachain_system::RelayNumberStrictlyIncreases;
+	type ValidationDataGapThreshold = ValidationDataGapThreshold;
+	type ConsensusHook = cumulus_pallet_parachain_system::FixedVelocityConsensusHook<
+		BlockVelocity,
+		UnincludedSegmentCapacity,
+	>;
 	type OutboundXcmpMessageSource = ();
 	type DmpMessageHandler = cumulus_pallet_xcm::UnlimitedDmpExecution<Runtime>;
 	type ReservedDmpWeight = ReservedDmpWeight;
 	type XcmpMessageHandler = ();
 	type ReservedXcmpWeight = ();
+	type MaxHorizontalMessagesPerBlock = ();
+	type MaxPausedMessages = MaxPausedMessages;
+	type MaxDeferredHorizontalMessages = MaxDeferredHorizontalMessages;
+	type LenientMqcHeadCheck = ();
+	type InboundMessagesOrder = ();
+	type AdditionalRelayStateKeys = ();
+	type HrmpChannelManagerOrigin = frame_system::EnsureRoot<AccountId>;
+	type RelayHrmpPalletIndex = RelayHrmpPalletIndex;
+	type RelayHrmpCallWeight = RelayHrmpCallWeight;
+	type UpgradePovReserve = UpgradePovReserve;
+	type HeadDataExtensionProvider = ();
+	type QueuedMessageDeposit = ();
+	type WeightInfo = ();
 }
 
 impl parachain_info::Config for Runtime {}
@@ -360,6 +389,20 @@ impl_runtime_apis! {
 			Vec::new()
 		}
 	}
+
+	impl cumulus_primitives_core::CollectCollationInfo<Block> for Runtime {
+		fn collect_collation_info(
+			header: &<Block as BlockT>::Header,
+		) -> cumulus_primitives_core::CollationInfo {
+			ParachainSystem::collect_collation_info(header)
+		}
+	}
+
+	impl cumulus_primitives_core::RelayStateKeysApi<Block> for Runtime {
+		fn additional_relay_state_keys() -> Vec<Vec<u8>> {
+			ParachainSystem::additional_relay_state_keys()
+		}
+	}
 }
 
 cumulus_pallet_parachain_system::register_validate_block!(Runtime, Executive);
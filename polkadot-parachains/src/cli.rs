@@ -103,6 +103,18 @@ pub struct Cli {
 	#[structopt(flatten)]
 	pub run: cumulus_client_cli::RunCmd,
 
+	/// Back off before building a block on the shell runtime, in steps of this many
+	/// milliseconds.
+	///
+	/// Each collator waits a different number of steps for each relay chain block, so that
+	/// typically only one of them builds a block.
+	#[structopt(long)]
+	pub authoring_backoff_step: Option<u64>,
+
+	/// The number of different backoff steps, usually the expected number of collators.
+	#[structopt(long, default_value = "4")]
+	pub authoring_backoff_positions: u32,
+
 	/// Relaychain arguments
 	#[structopt(raw = true)]
 	pub relaychain_args: Vec<String>,
@@ -19,10 +19,12 @@ use crate::{
 	cli::{Cli, RelayChainCli, Subcommand},
 };
 use codec::Encode;
+use cumulus_client_consensus_relay_chain::AuthoringBackoff;
 use cumulus_client_service::genesis::generate_genesis_block;
 use cumulus_primitives_core::ParaId;
 use log::info;
 use polkadot_parachain::primitives::AccountIdConversion;
+use polkadot_primitives::v1::CollatorPair;
 use sc_cli::{
 	ChainSpec, CliConfiguration, DefaultConfigurationValues, ImportParams, KeystoreParams,
 	NetworkParams, Result, RuntimeVersion, SharedParams, SubstrateCli,
@@ -30,7 +32,7 @@ use sc_cli::{
 use sc_service::config::{BasePath, PrometheusConfig};
 use sp_core::hexdisplay::HexDisplay;
 use sp_runtime::traits::Block as BlockT;
-use std::{io::Write, net::SocketAddr};
+use std::{io::Write, net::SocketAddr, time::Duration};
 
 fn load_spec(
 	id: &str,
@@ -296,7 +298,12 @@ pub fn run() -> Result<()> {
 
 			runner.run_node_until_exit(|config| async move {
 				// TODO
-				let key = sp_core::Pair::generate().0;
+				let key: CollatorPair = sp_core::Pair::generate().0;
+				let authoring_backoff = cli.authoring_backoff_step.map(|step| AuthoringBackoff {
+					collator_id: sp_core::Pair::public(&key).encode(),
+					positions: cli.authoring_backoff_positions,
+					step: Duration::from_millis(step),
+				});
 
 				let para_id =
 					chain_spec::Extensions::try_get(&*config.chain_spec).map(|e| e.para_id);
@@ -335,10 +342,16 @@ pub fn run() -> Result<()> {
 				);
 
 				if use_shell {
-					crate::service::start_shell_node(config, key, polkadot_config, id)
-						.await
-						.map(|r| r.0)
-						.map_err(Into::into)
+					crate::service::start_shell_node(
+						config,
+						key,
+						polkadot_config,
+						id,
+						authoring_backoff,
+					)
+					.await
+					.map(|r| r.0)
+					.map_err(Into::into)
 				} else {
 					crate::service::start_rococo_parachain_node(config, key, polkadot_config, id)
 						.await
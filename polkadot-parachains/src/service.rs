@@ -15,14 +15,19 @@
 // along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
 
 use cumulus_client_consensus_aura::{
-	build_aura_consensus, BuildAuraConsensusParams, SlotProportion,
+	build_aura_consensus, BuildAuraConsensusParams, MigrationConsensus, SlotProportion,
 };
-use cumulus_client_consensus_common::ParachainConsensus;
+use cumulus_client_consensus_common::{
+	CombinedSyncOracle, ParachainConsensus, RelayChainClientConfig, RelayChainHealthHandle,
+	RelayChainHealthOracle, RetryPolicy, DEFAULT_CACHE_SIZE,
+};
+use cumulus_client_consensus_relay_chain::AuthoringBackoff;
 use cumulus_client_network::build_block_announce_validator;
 use cumulus_client_service::{
 	prepare_node_config, start_collator, start_full_node, StartCollatorParams, StartFullNodeParams,
 };
-use cumulus_primitives_core::ParaId;
+use cumulus_primitives_core::{ParaId, RelayStateKeysApi};
+use cumulus_primitives_parachain_inherent::ParachainInherentData;
 use polkadot_primitives::v1::CollatorPair;
 
 use sc_client_api::ExecutorProvider;
@@ -30,11 +35,10 @@ use sc_executor::native_executor_instance;
 use sc_network::NetworkService;
 use sc_service::{Configuration, PartialComponents, Role, TFullBackend, TFullClient, TaskManager};
 use sc_telemetry::{Telemetry, TelemetryHandle, TelemetryWorker, TelemetryWorkerHandle};
-use sp_api::ConstructRuntimeApi;
-use sp_consensus::SlotData;
+use sp_api::{ConstructRuntimeApi, ProvideRuntimeApi};
 use sp_keystore::SyncCryptoStorePtr;
-use sp_runtime::traits::BlakeTwo256;
-use std::sync::Arc;
+use sp_runtime::{generic::BlockId, traits::BlakeTwo256};
+use std::{sync::Arc, time::Duration};
 use substrate_prometheus_endpoint::Registry;
 
 pub use sc_executor::NativeExecutor;
@@ -44,6 +48,10 @@ type Header = sp_runtime::generic::Header<BlockNumber, sp_runtime::traits::Blake
 pub type Block = sp_runtime::generic::Block<Header, sp_runtime::OpaqueExtrinsic>;
 type Hash = sp_core::H256;
 
+/// The maximum age of the last relay chain head before the relay chain backend is considered to
+/// be unhealthy and no blocks are authored anymore.
+const MAX_RELAY_CHAIN_HEAD_AGE: Duration = Duration::from_secs(60);
+
 // Native executor instance.
 native_executor_instance!(
 	pub RococoParachainRuntimeExecutor,
@@ -180,7 +188,8 @@ where
 			Block,
 			StateBackend = sc_client_api::StateBackendFor<TFullBackend<Block>, Block>,
 		> + sp_offchain::OffchainWorkerApi<Block>
-		+ sp_block_builder::BlockBuilder<Block>,
+		+ sp_block_builder::BlockBuilder<Block>
+		+ cumulus_primitives_core::CollectCollationInfo<Block>,
 	sc_client_api::StateBackendFor<TFullBackend<Block>, Block>: sp_api::StateBackend<BlakeTwo256>,
 	Executor: sc_executor::NativeExecutionDispatch + 'static,
 	RB: Fn(
@@ -207,6 +216,7 @@ where
 		Arc<NetworkService<Block, Hash>>,
 		SyncCryptoStorePtr,
 		bool,
+		RelayChainHealthHandle,
 	) -> Result<Box<dyn ParachainConsensus<Block>>, sc_service::Error>,
 {
 	if matches!(parachain_config.role, Role::Light) {
@@ -235,6 +245,7 @@ where
 		id,
 		Box::new(relay_chain_full_node.network.clone()),
 		relay_chain_full_node.backend.clone(),
+		Box::new(()),
 	);
 
 	let force_authoring = parachain_config.force_authoring;
@@ -278,6 +289,19 @@ where
 		Arc::new(move |hash, data| network.announce_block(hash, data))
 	};
 
+	let relay_chain_health = RelayChainHealthHandle::default().with_sync_status({
+		let network = relay_chain_full_node.network.clone();
+		move || network.is_major_syncing()
+	});
+
+	let relay_chain_client_config = RelayChainClientConfig {
+		retry_policy: Some(RetryPolicy::default()),
+		cache_size: Some(DEFAULT_CACHE_SIZE),
+		metrics_registry: prometheus_registry.clone(),
+		health: relay_chain_health.clone(),
+		..Default::default()
+	};
+
 	if validator {
 		let parachain_consensus = build_consensus(
 			client.clone(),
@@ -289,6 +313,7 @@ where
 			network,
 			params.keystore_container.sync_keystore(),
 			force_authoring,
+			relay_chain_health,
 		)?;
 
 		let spawner = task_manager.spawn_handle();
@@ -304,6 +329,7 @@ where
 			spawner,
 			backend,
 			parachain_consensus,
+			relay_chain_client_config,
 		};
 
 		start_collator(params).await?;
@@ -314,6 +340,7 @@ where
 			task_manager: &mut task_manager,
 			para_id: id,
 			polkadot_full_node: relay_chain_full_node,
+			relay_chain_client_config,
 		};
 
 		start_full_node(params)?;
@@ -325,6 +352,10 @@ where
 }
 
 /// Build the import queue for the rococo parachain runtime.
+///
+/// The rococo parachain may take over a chain that launched with the relay-chain consensus, like
+/// the shell runtime. The blocks before the switch to AuRa are verified by the relay-chain
+/// consensus.
 pub fn rococo_parachain_build_import_queue(
 	client: Arc<TFullClient<Block, rococo_parachain_runtime::RuntimeApi, RococoParachainRuntimeExecutor>>,
 	config: &Configuration,
@@ -337,16 +368,25 @@ pub fn rococo_parachain_build_import_queue(
 	>,
 	sc_service::Error,
 > {
-	let slot_duration = cumulus_client_consensus_aura::slot_duration(&*client)?;
-
 	let block_import = cumulus_client_consensus_aura::AuraBlockImport::<
 		_,
 		_,
 		_,
 		sp_consensus_aura::sr25519::AuthorityPair,
 	>::new(client.clone(), client.clone());
+	// Report the authors that seal two different blocks of the same slot.
+	let block_import = cumulus_client_consensus_aura::EquivocationReportingBlockImport::<
+		_,
+		_,
+		sp_consensus_aura::sr25519::AuthorityPair,
+	>::new(block_import, client.clone());
+
+	let fallback_verifier = cumulus_client_consensus_relay_chain::Verifier::new(
+		client.clone(),
+		|_, _| async { Ok::<_, Box<dyn std::error::Error + Send + Sync>>(()) },
+	);
 
-	cumulus_client_consensus_aura::import_queue::<
+	cumulus_client_consensus_aura::migration_import_queue::<
 		sp_consensus_aura::sr25519::AuthorityPair,
 		_,
 		_,
@@ -354,19 +394,32 @@ pub fn rococo_parachain_build_import_queue(
 		_,
 		_,
 		_,
-	>(cumulus_client_consensus_aura::ImportQueueParams {
+	>(cumulus_client_consensus_aura::MigrationImportQueueParams {
 		block_import,
 		client: client.clone(),
-		create_inherent_data_providers: move |_, _| async move {
-			let time = sp_timestamp::InherentDataProvider::from_system_time();
-
-			let slot =
-				sp_consensus_aura::inherents::InherentDataProvider::from_timestamp_and_duration(
-					*time,
-					slot_duration.slot_duration(),
-				);
+		fallback_verifier: Box::new(fallback_verifier),
+		create_inherent_data_providers: {
+			let client = client.clone();
+			move |parent, _| {
+				// The slot duration may be changed by a runtime upgrade of the parent.
+				let slot_duration = cumulus_client_consensus_aura::slot_duration_at::<
+					_,
+					_,
+					sp_consensus_aura::sr25519::AuthorityId,
+				>(&*client, &BlockId::Hash(parent));
+
+				async move {
+					let time = sp_timestamp::InherentDataProvider::from_system_time();
+
+					let slot =
+					sp_consensus_aura::inherents::InherentDataProvider::from_timestamp_and_duration(
+						*time,
+						slot_duration?,
+					);
 
-			Ok((time, slot))
+					Ok((time, slot))
+				}
+			}
 		},
 		registry: config.prometheus_registry().clone(),
 		can_author_with: sp_consensus::CanAuthorWithNativeVersion::new(client.executor().clone()),
@@ -377,6 +430,9 @@ pub fn rococo_parachain_build_import_queue(
 }
 
 /// Start a rococo parachain node.
+///
+/// The blocks are built by AuRa once the chain switched to AuRa, see
+/// [`rococo_parachain_build_import_queue`], and by the relay-chain consensus before.
 pub async fn start_rococo_parachain_node(
 	parachain_config: Configuration,
 	collator_key: CollatorPair,
@@ -400,20 +456,28 @@ pub async fn start_rococo_parachain_node(
 		 transaction_pool,
 		 sync_oracle,
 		 keystore,
-		 force_authoring| {
-			let slot_duration = cumulus_client_consensus_aura::slot_duration(&*client)?;
-
+		 force_authoring,
+		 relay_chain_health| {
 			let proposer_factory = sc_basic_authorship::ProposerFactory::with_proof_recording(
 				task_manager.spawn_handle(),
 				client.clone(),
-				transaction_pool,
+				transaction_pool.clone(),
 				prometheus_registry.clone(),
 				telemetry.clone(),
 			);
+			let fallback_proposer_factory =
+				sc_basic_authorship::ProposerFactory::with_proof_recording(
+					task_manager.spawn_handle(),
+					client.clone(),
+					transaction_pool,
+					prometheus_registry.clone(),
+					telemetry.clone(),
+				);
 
 			let relay_chain_backend = relay_chain_node.backend.clone();
 			let relay_chain_client = relay_chain_node.client.clone();
-			Ok(build_aura_consensus::<
+			let para_client = client.clone();
+			let aura = build_aura_consensus::<
 				sp_consensus_aura::sr25519::AuthorityPair,
 				_,
 				_,
@@ -426,14 +490,25 @@ pub async fn start_rococo_parachain_node(
 				_,
 			>(BuildAuraConsensusParams {
 				proposer_factory,
-				create_inherent_data_providers: move |_, (relay_parent, validation_data)| {
+				create_inherent_data_providers: move |parent, (relay_parent, validation_data)| {
+					let additional_relay_state_keys = para_client
+						.runtime_api()
+						.additional_relay_state_keys(&BlockId::Hash(parent))
+						.unwrap_or_default();
+					// The slot duration may be changed by a runtime upgrade of the parent.
+					let slot_duration = cumulus_client_consensus_aura::slot_duration_at::<
+						_,
+						_,
+						sp_consensus_aura::sr25519::AuthorityId,
+					>(&*para_client, &BlockId::Hash(parent));
 					let parachain_inherent =
-					cumulus_primitives_parachain_inherent::ParachainInherentData::create_at_with_client(
+					cumulus_primitives_parachain_inherent::ParachainInherentData::create_at_with_client_and_extra_keys(
 						relay_parent,
 						&relay_chain_client,
 						&*relay_chain_backend,
 						&validation_data,
 						id,
+						&additional_relay_state_keys,
 					);
 					async move {
 						let time = sp_timestamp::InherentDataProvider::from_system_time();
@@ -441,7 +516,7 @@ pub async fn start_rococo_parachain_node(
 						let slot =
 						sp_consensus_aura::inherents::InherentDataProvider::from_timestamp_and_duration(
 							*time,
-							slot_duration.slot_duration(),
+							slot_duration?,
 						);
 
 						let parachain_inherent = parachain_inherent.ok_or_else(|| {
@@ -457,14 +532,67 @@ pub async fn start_rococo_parachain_node(
 				relay_chain_backend: relay_chain_node.backend.clone(),
 				para_client: client.clone(),
 				backoff_authoring_blocks: Option::<()>::None,
-				sync_oracle,
+				// Don't author on top of a stale relay parent while the relay chain is syncing or
+				// its head streams stopped yielding.
+				sync_oracle: CombinedSyncOracle::new(
+					sync_oracle,
+					CombinedSyncOracle::new(
+						relay_chain_node.network.clone(),
+						RelayChainHealthOracle::new(relay_chain_health, MAX_RELAY_CHAIN_HEAD_AGE),
+					),
+				),
 				keystore,
 				force_authoring,
-				slot_duration,
 				// We got around 500ms for proposing
 				block_proposal_slot_portion: SlotProportion::new(1f32 / 24f32),
 				telemetry,
-			}))
+			});
+
+			let relay_chain_backend = relay_chain_node.backend.clone();
+			let relay_chain_client = relay_chain_node.client.clone();
+			let para_client = client.clone();
+			let fallback = cumulus_client_consensus_relay_chain::build_relay_chain_consensus(
+				cumulus_client_consensus_relay_chain::BuildRelayChainConsensusParams {
+					para_id: id,
+					proposer_factory: fallback_proposer_factory,
+					block_import: client.clone(),
+					relay_chain_client: relay_chain_node.client.clone(),
+					relay_chain_backend: relay_chain_node.backend.clone(),
+					authoring_backoff: None,
+					create_inherent_data_providers: move |parent, (relay_parent, validation_data)| {
+						let additional_relay_state_keys = para_client
+							.runtime_api()
+							.additional_relay_state_keys(&BlockId::Hash(parent))
+							.unwrap_or_default();
+						let parachain_inherent =
+							ParachainInherentData::create_at_with_client_and_extra_keys(
+								relay_parent,
+								&relay_chain_client,
+								&*relay_chain_backend,
+								&validation_data,
+								id,
+								&additional_relay_state_keys,
+							);
+						async move {
+							parachain_inherent.ok_or_else(|| {
+								Box::<dyn std::error::Error + Send + Sync>::from(
+									"Failed to create parachain inherent",
+								)
+							})
+						}
+					},
+				},
+			);
+
+			let uses_aura = Arc::new(move |parent: Hash| {
+				cumulus_client_consensus_aura::uses_aura_at::<
+					_,
+					_,
+					sp_consensus_aura::sr25519::AuthorityId,
+				>(&*client, &BlockId::Hash(parent))
+			});
+
+			Ok(Box::new(MigrationConsensus::new(uses_aura, aura, fallback)))
 		},
 	)
 	.await
@@ -494,11 +622,14 @@ pub fn shell_build_import_queue(
 }
 
 /// Start a rococo-shell parachain node.
+///
+/// The collator waits for the `authoring_backoff` before building a block, if given.
 pub async fn start_shell_node(
 	parachain_config: Configuration,
 	collator_key: CollatorPair,
 	polkadot_config: Configuration,
 	id: ParaId,
+	authoring_backoff: Option<AuthoringBackoff>,
 ) -> sc_service::error::Result<
 	(TaskManager, Arc<TFullClient<Block, shell_runtime::RuntimeApi, ShellRuntimeExecutor>>)
 > {
@@ -517,6 +648,7 @@ pub async fn start_shell_node(
 		 transaction_pool,
 		 _,
 		 _,
+		 _,
 		 _| {
 			let proposer_factory = sc_basic_authorship::ProposerFactory::with_proof_recording(
 				task_manager.spawn_handle(),
@@ -528,6 +660,7 @@ pub async fn start_shell_node(
 
 			let relay_chain_backend = relay_chain_node.backend.clone();
 			let relay_chain_client = relay_chain_node.client.clone();
+			let para_client = client.clone();
 
 			Ok(
 				cumulus_client_consensus_relay_chain::build_relay_chain_consensus(
@@ -537,16 +670,22 @@ pub async fn start_shell_node(
 						block_import: client.clone(),
 						relay_chain_client: relay_chain_node.client.clone(),
 						relay_chain_backend: relay_chain_node.backend.clone(),
+						authoring_backoff,
 						create_inherent_data_providers:
-							move |_, (relay_parent, validation_data)| {
+							move |parent, (relay_parent, validation_data)| {
+								let additional_relay_state_keys = para_client
+									.runtime_api()
+									.additional_relay_state_keys(&BlockId::Hash(parent))
+									.unwrap_or_default();
 								let parachain_inherent =
-					cumulus_primitives_parachain_inherent::ParachainInherentData::create_at_with_client(
-						relay_parent,
-						&relay_chain_client,
-						&*relay_chain_backend,
-							&validation_data,
-							id,
-					);
+									ParachainInherentData::create_at_with_client_and_extra_keys(
+										relay_parent,
+										&relay_chain_client,
+										&*relay_chain_backend,
+										&validation_data,
+										id,
+										&additional_relay_state_keys,
+									);
 								async move {
 									let parachain_inherent =
 										parachain_inherent.ok_or_else(|| {
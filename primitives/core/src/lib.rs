@@ -19,8 +19,12 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
 use sp_std::prelude::*;
-use codec::{Encode, Decode};
-use sp_runtime::{RuntimeDebug, traits::Block as BlockT};
+use codec::{Codec, Encode, Decode};
+use sp_runtime::{
+	generic::{Digest, DigestItem},
+	traits::{Block as BlockT, NumberFor},
+	ConsensusEngineId, RuntimeDebug,
+};
 use frame_support::weights::Weight;
 
 pub use polkadot_core_primitives::InboundDownwardMessage;
@@ -54,6 +58,15 @@ pub enum MessageSendError {
 	TooBig,
 	/// Some other error.
 	Other,
+	/// The relay chain can not take any more messages for now.
+	BandwidthExhausted,
+	/// The message is bigger than the negotiated max message size of the channel.
+	ExceedsMaxMessageSize {
+		/// The size of the message in bytes, as it would be placed in the channel.
+		size: u32,
+		/// The max message size of the channel in bytes.
+		max: u32,
+	},
 }
 
 impl From<MessageSendError> for &'static str {
@@ -64,11 +77,14 @@ impl From<MessageSendError> for &'static str {
 			NoChannel => "NoChannel",
 			TooBig => "TooBig",
 			Other => "Other",
+			BandwidthExhausted => "BandwidthExhausted",
+			ExceedsMaxMessageSize { .. } => "ExceedsMaxMessageSize",
 		}
 	}
 }
 
 /// Information about an XCMP channel.
+#[derive(Clone, PartialEq, Eq, RuntimeDebug)]
 pub struct ChannelInfo {
 	/// The maximum number of messages that can be pending in the channel at once.
 	pub max_capacity: u32,
@@ -77,7 +93,7 @@ pub struct ChannelInfo {
 	/// The maximum message size that could be put into the channel.
 	pub max_message_size: u32,
 	/// The current number of messages pending in the channel.
-	/// Invariant: should be less or equal to `max_capacity`.s`.
+	/// Invariant: should be less or equal to `max_capacity`.
 	pub msg_count: u32,
 	/// The total size in bytes of all message payloads in the channel.
 	/// Invariant: should be less or equal to `max_total_size`.
@@ -87,6 +103,18 @@ pub struct ChannelInfo {
 pub trait GetChannelInfo {
 	fn get_channel_status(id: ParaId) -> ChannelStatus;
 	fn get_channel_max(id: ParaId) -> Option<usize>;
+
+	/// Returns the information about the outbound channel to the `recipient`, as of the relay
+	/// parent.
+	///
+	/// The usage includes the messages that were sent by the ancestors of the current block, but
+	/// not the messages of the current block. Returns `None` if there is no such channel.
+	fn get_channel_info(recipient: ParaId) -> Option<ChannelInfo>;
+
+	/// Returns the information about the inbound channel from the `sender`, as of the relay parent.
+	///
+	/// Returns `None` if there is no such channel.
+	fn get_ingress_channel_info(sender: ParaId) -> Option<ChannelInfo>;
 }
 
 /// Well known keys for values in the storage.
@@ -115,13 +143,297 @@ pub mod well_known_keys {
 	///
 	/// The value is stored as SCALE encoded `u32`.
 	pub const PROCESSED_DOWNWARD_MESSAGES: &'static [u8] = b":cumulus_processed_downward_messages:";
+
+	/// The storage key for the extension that is appended to the head data of the current block.
+	///
+	/// The value is stored as SCALE encoded [`HeadDataExtension`](super::HeadDataExtension).
+	pub const HEAD_DATA_EXTENSION: &'static [u8] = b":cumulus_head_data_extension:";
+}
+
+/// Relay chain storage keys that are read by Cumulus, but are not part of the Polkadot
+/// `well_known_keys`.
+pub mod relay_well_known_keys {
+	use super::ParaId;
+	use codec::Encode;
+	use sp_io::hashing::twox_64;
+	use sp_std::vec::Vec;
+
+	/// The BABE randomness of the current relay chain epoch, `Babe::Randomness`.
+	///
+	/// The value is stored as SCALE encoded `[u8; 32]`.
+	pub const CURRENT_EPOCH_RANDOMNESS: &'static [u8] = &[
+		0x1c, 0xb6, 0xf3, 0x6e, 0x02, 0x7a, 0xbb, 0x20, 0x91, 0xcf, 0xb5, 0x11, 0x0a, 0xb5, 0x08,
+		0x7f, 0x7a, 0x41, 0x4c, 0xb0, 0x08, 0xe0, 0xe6, 0x1e, 0x46, 0x72, 0x2a, 0xa6, 0x0a, 0xbd,
+		0xd6, 0x72,
+	];
+
+	/// The block numbers when the previous and the current relay chain epoch started,
+	/// `Babe::EpochStart`.
+	///
+	/// The value is stored as SCALE encoded `(BlockNumber, BlockNumber)`.
+	pub const CURRENT_EPOCH_START: &'static [u8] = &[
+		0x1c, 0xb6, 0xf3, 0x6e, 0x02, 0x7a, 0xbb, 0x20, 0x91, 0xcf, 0xb5, 0x11, 0x0a, 0xb5, 0x08,
+		0x7f, 0xe9, 0x0e, 0x2f, 0xbf, 0x2d, 0x79, 0x2c, 0xb3, 0x24, 0xbf, 0xfa, 0x94, 0x27, 0xfe,
+		0x1f, 0x0e,
+	];
+
+	/// The BABE slot of the current relay chain block, `Babe::CurrentSlot`.
+	///
+	/// The value is stored as SCALE encoded `u64`.
+	pub const CURRENT_SLOT: &'static [u8] = &[
+		0x1c, 0xb6, 0xf3, 0x6e, 0x02, 0x7a, 0xbb, 0x20, 0x91, 0xcf, 0xb5, 0x11, 0x0a, 0xb5, 0x08,
+		0x7f, 0x06, 0x15, 0x5b, 0x3c, 0xd9, 0xa8, 0xc9, 0xe5, 0xe9, 0xa2, 0x3f, 0xd5, 0xdc, 0x13,
+		0xa5, 0xed,
+	];
+
+	/// The index of the current relay chain session, `Session::CurrentIndex`.
+	///
+	/// The value is stored as SCALE encoded `SessionIndex`.
+	pub const CURRENT_SESSION_INDEX: &'static [u8] = &[
+		0xce, 0xc5, 0x07, 0x0d, 0x60, 0x9d, 0xd3, 0x49, 0x7f, 0x72, 0xbd, 0xe0, 0x7f, 0xc9, 0x6b,
+		0xa0, 0x72, 0x76, 0x38, 0x00, 0xa3, 0x6a, 0x99, 0xfd, 0xfc, 0x7c, 0x10, 0xf6, 0x41, 0x5f,
+		0x6e, 0xe6,
+	];
+
+	/// The prefix of [`upgrade_go_ahead_signal`].
+	const UPGRADE_GO_AHEAD_SIGNAL_PREFIX: &'static [u8] = &[
+		0xcd, 0x71, 0x0b, 0x30, 0xbd, 0x2e, 0xab, 0x03, 0x52, 0xdd, 0xcc, 0x26, 0x41, 0x7a, 0xa1,
+		0x94, 0x9e, 0x94, 0xc0, 0x40, 0xf5, 0xe7, 0x3d, 0x9b, 0x7a, 0xdd, 0xd6, 0xcb, 0x60, 0x3d,
+		0x15, 0xd3,
+	];
+
+	/// The prefix of [`para_lifecycle`].
+	const PARA_LIFECYCLE_PREFIX: &'static [u8] = &[
+		0xcd, 0x71, 0x0b, 0x30, 0xbd, 0x2e, 0xab, 0x03, 0x52, 0xdd, 0xcc, 0x26, 0x41, 0x7a, 0xa1,
+		0x94, 0x28, 0x1e, 0x0b, 0xfd, 0xe1, 0x7b, 0x36, 0x57, 0x32, 0x08, 0xa0, 0x6c, 0xb5, 0xcf,
+		0xba, 0x6b,
+	];
+
+	/// The prefix of [`para_head`].
+	const PARA_HEAD_PREFIX: &'static [u8] = &[
+		0xcd, 0x71, 0x0b, 0x30, 0xbd, 0x2e, 0xab, 0x03, 0x52, 0xdd, 0xcc, 0x26, 0x41, 0x7a, 0xa1,
+		0x94, 0x1b, 0x3c, 0x25, 0x2f, 0xcb, 0x29, 0xd8, 0x8e, 0xff, 0x4f, 0x3d, 0xe5, 0xde, 0x44,
+		0x76, 0xc3,
+	];
+
+	/// The upgrade go-ahead signal of the given `para_id`, `Paras::UpgradeGoAheadSignal`.
+	///
+	/// The value is stored as SCALE encoded [`UpgradeGoAhead`](super::UpgradeGoAhead).
+	pub fn upgrade_go_ahead_signal(para_id: ParaId) -> Vec<u8> {
+		para_key(UPGRADE_GO_AHEAD_SIGNAL_PREFIX, para_id)
+	}
+
+	/// The lifecycle of the given `para_id`, `Paras::ParaLifecycles`.
+	///
+	/// The value is stored as SCALE encoded [`ParaLifecycle`](super::ParaLifecycle).
+	pub fn para_lifecycle(para_id: ParaId) -> Vec<u8> {
+		para_key(PARA_LIFECYCLE_PREFIX, para_id)
+	}
+
+	/// The head of the given `para_id` that was last included, `Paras::Heads`.
+	///
+	/// The value is stored as SCALE encoded `HeadData`.
+	pub fn para_head(para_id: ParaId) -> Vec<u8> {
+		para_key(PARA_HEAD_PREFIX, para_id)
+	}
+
+	/// The key of a map entry with the given `prefix`, keyed by `para_id` with `Twox64Concat`.
+	fn para_key(prefix: &[u8], para_id: ParaId) -> Vec<u8> {
+		para_id.using_encoded(|para_id: &[u8]| {
+			prefix
+				.iter()
+				.chain(twox_64(para_id).iter())
+				.chain(para_id.iter())
+				.cloned()
+				.collect()
+		})
+	}
+}
+
+/// A signal of the relay chain to apply or to discard the pending validation code upgrade of a
+/// parachain.
+#[derive(Clone, Copy, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub enum UpgradeGoAhead {
+	/// The upgrade was aborted and the pending validation code needs to be discarded.
+	#[codec(index = 0)]
+	Abort,
+	/// The pending validation code needs to be applied.
+	#[codec(index = 1)]
+	GoAhead,
+}
+
+/// The lifecycle of a para on the relay chain.
+///
+/// The variants need to match the `ParaLifecycle` of the relay chain `paras` pallet.
+#[derive(Clone, Copy, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub enum ParaLifecycle {
+	/// The para is new and is onboarding as a parathread or parachain.
+	#[codec(index = 0)]
+	Onboarding,
+	/// The para is a parathread.
+	#[codec(index = 1)]
+	Parathread,
+	/// The para is a parachain.
+	#[codec(index = 2)]
+	Parachain,
+	/// The para is a parathread which is upgrading to a parachain.
+	#[codec(index = 3)]
+	UpgradingParathread,
+	/// The para is a parachain which is downgrading to a parathread.
+	#[codec(index = 4)]
+	DowngradingParachain,
+	/// The parathread is queued to be offboarded.
+	#[codec(index = 5)]
+	OffboardingParathread,
+	/// The parachain is queued to be offboarded.
+	#[codec(index = 6)]
+	OffboardingParachain,
+}
+
+impl ParaLifecycle {
+	/// Returns `true` if the para is about to lose its parachain slot, because it is downgraded
+	/// or offboarded.
+	pub fn is_leaving(&self) -> bool {
+		matches!(
+			self,
+			Self::DowngradingParachain | Self::OffboardingParathread | Self::OffboardingParachain,
+		)
+	}
+}
+
+/// The consensus engine id of the digest items that are deposited by the Cumulus runtime.
+pub const CUMULUS_CONSENSUS_ID: ConsensusEngineId = *b"CMLS";
+
+/// Consensus digest items that are deposited by the Cumulus runtime for the collator.
+#[derive(Clone, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub enum CumulusDigestItem {
+	/// The number of bytes of the PoV the collator should keep free when building the next block.
+	#[codec(index = 0)]
+	ReservePovSize(u32),
+	/// The relay parent the block was built on.
+	///
+	/// The hash of the relay parent block is not known to the runtime, so the relay parent is
+	/// identified by its number and its storage root. The storage root is part of the relay
+	/// parent header. The hash can not be proven with the relay chain state either, as that only
+	/// contains the hashes of the ancestors of the relay parent. Nodes that need the hash look up
+	/// the relay chain header with the given number and compare its storage root.
+	#[codec(index = 1)]
+	RelayParentInfo {
+		/// The number of the relay parent.
+		number: RelayBlockNumber,
+		/// The storage root of the relay parent.
+		storage_root: relay_chain::Hash,
+	},
+}
+
+impl CumulusDigestItem {
+	/// Encode this into a [`DigestItem`].
+	pub fn to_digest_item<Hash>(&self) -> DigestItem<Hash> {
+		DigestItem::Consensus(CUMULUS_CONSENSUS_ID, self.encode())
+	}
+}
+
+/// Extract the number of bytes of the PoV that should be kept free from the given `digest`.
+///
+/// Returns `0` if the digest doesn't contain a [`CumulusDigestItem::ReservePovSize`].
+pub fn extract_reserved_pov_size<Hash>(digest: &Digest<Hash>) -> u32 {
+	digest
+		.logs()
+		.iter()
+		.find_map(|item| match item {
+			DigestItem::Consensus(id, data) if id == &CUMULUS_CONSENSUS_ID => {
+				match CumulusDigestItem::decode(&mut &data[..]) {
+					Ok(CumulusDigestItem::ReservePovSize(size)) => Some(size),
+					_ => None,
+				}
+			}
+			_ => None,
+		})
+		.unwrap_or(0)
+}
+
+/// Extract the number and the storage root of the relay parent from the given `digest`.
+///
+/// Returns `None` if the digest doesn't contain a [`CumulusDigestItem::RelayParentInfo`].
+pub fn extract_relay_parent_info<Hash>(
+	digest: &Digest<Hash>,
+) -> Option<(RelayBlockNumber, relay_chain::Hash)> {
+	digest.logs().iter().find_map(|item| match item {
+		DigestItem::Consensus(id, data) if id == &CUMULUS_CONSENSUS_ID => {
+			match CumulusDigestItem::decode(&mut &data[..]) {
+				Ok(CumulusDigestItem::RelayParentInfo { number, storage_root }) => {
+					Some((number, storage_root))
+				}
+				_ => None,
+			}
+		}
+		_ => None,
+	})
+}
+
+/// The maximum size in bytes of the payload of a [`HeadDataExtension`].
+pub const MAX_HEAD_DATA_EXTENSION_SIZE: u32 = 256;
+
+/// A payload that the runtime appends to the head data of a parachain block.
+///
+/// The head data is the SCALE encoded header, followed by the SCALE encoded extension if there is
+/// one. Decoding a header ignores any trailing bytes, so nodes that don't know about the extension
+/// can still decode the header from the head data. Use [`decode_head_data`] to get both.
+#[derive(Clone, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub enum HeadDataExtension {
+	/// An opaque payload, e.g. the root of an MMR for bridging.
+	///
+	/// The payload must not be larger than [`MAX_HEAD_DATA_EXTENSION_SIZE`].
+	#[codec(index = 0)]
+	V1(Vec<u8>),
+}
+
+impl HeadDataExtension {
+	/// Returns the payload of the extension.
+	pub fn payload(&self) -> &[u8] {
+		match self {
+			Self::V1(payload) => payload,
+		}
+	}
+}
+
+/// Build the head data from the given `encoded_header` and the optional `extension`.
+pub fn build_head_data(
+	mut encoded_header: Vec<u8>,
+	extension: Option<&HeadDataExtension>,
+) -> relay_chain::v1::HeadData {
+	if let Some(extension) = extension {
+		extension.encode_to(&mut encoded_header);
+	}
+
+	relay_chain::v1::HeadData(encoded_header)
+}
+
+/// Decode the header and the optional [`HeadDataExtension`] from the given `head_data`.
+///
+/// Returns an error if there are bytes after the header that are not a valid extension.
+pub fn decode_head_data<H: Decode>(
+	mut head_data: &[u8],
+) -> Result<(H, Option<HeadDataExtension>), codec::Error> {
+	let header = H::decode(&mut head_data)?;
+	let extension = if head_data.is_empty() {
+		None
+	} else {
+		Some(HeadDataExtension::decode(&mut head_data)?)
+	};
+
+	Ok((header, extension))
 }
 
 /// Something that should be called when a downward message is received.
 pub trait DmpMessageHandler {
 	/// Handle some incoming DMP messages (note these are individual XCM messages).
 	///
-	/// Also, process messages up to some `max_weight`.
+	/// Also, process messages up to some `max_weight`. Messages that are not processed within the
+	/// weight limit are not given to the handler again, so they need to be stored by the handler
+	/// to be processed in a later block.
+	///
+	/// Returns the weight used.
 	fn handle_dmp_messages(
 		iter: impl Iterator<Item=(RelayBlockNumber, Vec<u8>)>,
 		max_weight: Weight,
@@ -158,11 +470,43 @@ impl XcmpMessageHandler for () {
 	}
 }
 
+/// How urgently an upward message should be delivered.
+#[derive(Clone, Copy, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub enum UpwardMessagePriority {
+	/// The message can wait until delivery is cheap.
+	Standard,
+	/// The message should be delivered as soon as possible.
+	Urgent,
+}
+
+/// A hint about the fee the sender of an upward message is willing to pay for its delivery.
+///
+/// The hint is not enforced by the relay chain. It is made public for offchain relayers and for
+/// a future fee market of the relay chain.
+#[derive(Clone, Copy, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct UpwardMessageFeeHint {
+	/// The maximum fee in relay chain balance units.
+	pub max_fee: relay_chain::Balance,
+	/// How urgently the message should be delivered.
+	pub priority: UpwardMessagePriority,
+}
+
 /// Something that should be called when sending an upward message.
 pub trait UpwardMessageSender {
 	/// Send the given UMP message; return the expected number of blocks before the message will
 	/// be dispatched or an error if the message cannot be sent.
 	fn send_upward_message(msg: UpwardMessage) -> Result<u32, MessageSendError>;
+
+	/// Send the given UMP message together with a fee hint.
+	///
+	/// By default the hint is ignored and the message is sent with
+	/// [`Self::send_upward_message`].
+	fn send_upward_message_with_fee_hint(
+		msg: UpwardMessage,
+		_fee_hint: UpwardMessageFeeHint,
+	) -> Result<u32, MessageSendError> {
+		Self::send_upward_message(msg)
+	}
 }
 impl UpwardMessageSender for () {
 	fn send_upward_message(_msg: UpwardMessage) -> Result<u32, MessageSendError> {
@@ -209,10 +553,212 @@ pub enum ServiceQuality {
 	Fast,
 }
 
-/// A trait which is called when the validation data is set.
-#[impl_trait_for_tuples::impl_for_tuples(30)]
-pub trait OnValidationData {
-	fn on_validation_data(data: &PersistedValidationData);
+/// The state of the relay chain at the relay parent of a parachain block.
+#[derive(Clone, Default, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct RelayChainState {
+	/// The number of the relay parent.
+	pub number: RelayBlockNumber,
+	/// The storage root of the relay parent.
+	pub state_root: relay_chain::Hash,
+}
+
+/// The queues of one XCMP channel, as seen by the parachain.
+#[derive(Clone, Default, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct XcmpChannelQueueStatus {
+	/// The sibling parachain at the other end of the channel.
+	pub para_id: ParaId,
+	/// The number of inbound pages that are not executed yet.
+	pub inbound_pages: u32,
+	/// The total size in bytes of the inbound pages that are not executed yet.
+	pub inbound_bytes: u32,
+	/// Whether the sibling was told to suspend sending on this channel.
+	pub inbound_suspended: bool,
+	/// The number of outbound pages that are not sent yet.
+	pub outbound_pages: u32,
+	/// The total size in bytes of the outbound pages that are not sent yet.
+	pub outbound_bytes: u32,
+	/// Whether the sibling told us to suspend sending on this channel.
+	pub outbound_suspended: bool,
+}
+
+/// The queues of all XCMP channels, as seen by the parachain.
+#[derive(Clone, Default, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct XcmpQueueStatus {
+	/// The channels with queued messages or a suspended status, ordered by para id.
+	pub channels: Vec<XcmpChannelQueueStatus>,
+	/// The total size in bytes of all queued inbound and outbound pages.
+	pub total_queued_bytes: u64,
+	/// Whether the execution of inbound messages is suspended.
+	pub execution_suspended: bool,
+}
+
+/// A downward message that is queued but not executed yet.
+#[derive(Clone, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct QueuedDownwardMessage {
+	/// The blake2-256 hash of the message, which identifies it in the events of the queue.
+	pub id: [u8; 32],
+	/// The relay chain block number at which the message was sent.
+	pub sent_at: RelayBlockNumber,
+	/// The message as it was received from the relay chain, a SCALE encoded `VersionedXcm`.
+	pub msg: Vec<u8>,
+}
+
+/// The queue of downward messages that are not executed yet, as seen by the parachain.
+#[derive(Clone, Default, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct DmpQueueStatus {
+	/// The number of queued messages.
+	pub message_count: u32,
+	/// The total size in bytes of the queued messages.
+	pub total_bytes: u64,
+	/// The relay chain block number at which the oldest queued message was sent.
+	pub first_sent_at: Option<RelayBlockNumber>,
+	/// The relay chain block number at which the newest queued message was sent.
+	pub last_sent_at: Option<RelayBlockNumber>,
+	/// The oldest queued messages, in the order in which they will be executed.
+	pub previews: Vec<QueuedDownwardMessage>,
+}
+
+/// The AuRa slot information of a parachain block.
+#[derive(Clone, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct AuraSlotInfo<AuthorityId> {
+	/// The AuRa slot of the block.
+	pub slot: u64,
+	/// The slot duration in milliseconds.
+	///
+	/// This may be changed by a runtime upgrade, so it should be queried at the parent of the
+	/// block to build or to verify.
+	pub slot_duration: u64,
+	/// The authorities that are allowed to author the next block.
+	pub authorities: Vec<AuthorityId>,
+	/// The relay parent number from which on the block of a slot may be authored by the
+	/// secondary author of the slot, see [`secondary_aura_author_index`].
+	///
+	/// `None` if only the primary author of a slot may author its block.
+	pub secondary_author_from: Option<RelayBlockNumber>,
+}
+
+/// The index of the secondary AuRa author of the slot with the given primary author.
+///
+/// This is the authority half the authority set away from the primary author, so the secondary
+/// author is not the primary author of one of the next slots. With a single authority, this is
+/// the primary author.
+pub fn secondary_aura_author_index(primary: u32, authorities: usize) -> usize {
+	(primary as usize + authorities / 2) % authorities.max(1)
+}
+
+/// A proof that an AuRa authority sealed two different blocks of the same slot.
+#[derive(Clone, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct AuraEquivocationProof<Header, AuthorityId> {
+	/// The slot of both blocks.
+	pub slot: u64,
+	/// The authority that sealed both blocks.
+	pub offender: AuthorityId,
+	/// The sealed header of the first block.
+	pub first_header: Header,
+	/// The sealed header of the second block.
+	pub second_header: Header,
+}
+
+/// Something that provides the [`RelayChainState`] of the current parachain block.
+pub trait RelaychainStateProvider {
+	/// Returns the relay chain state the current block is built on.
+	///
+	/// Before the validation data of the current block is set, this returns the state of the
+	/// previous block.
+	fn current_relay_chain_state() -> RelayChainState;
+}
+
+/// Information about a collation.
+///
+/// This is everything the collator needs to know about a parachain block, besides the block
+/// itself, to build a collation for it.
+#[derive(Clone, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct CollationInfo {
+	/// Messages destined to be interpreted by the Relay chain itself.
+	pub upward_messages: Vec<UpwardMessage>,
+	/// The horizontal messages sent by the parachain.
+	pub horizontal_messages: Vec<OutboundHrmpMessage>,
+	/// New validation code.
+	pub new_validation_code: Option<relay_chain::v1::ValidationCode>,
+	/// The number of messages processed from the DMQ.
+	pub processed_downward_messages: u32,
+	/// The mark which specifies the block number up to which all inbound HRMP messages are
+	/// processed.
+	pub hrmp_watermark: RelayBlockNumber,
+	/// The head data of the block that corresponds to the collation.
+	///
+	/// This is the encoded header, followed by the [`HeadDataExtension`] if there is one.
+	pub head_data: relay_chain::v1::HeadData,
+}
+
+sp_api::decl_runtime_apis! {
+	/// Runtime api to query the relay chain state a parachain block is built on.
+	pub trait RelayChainStateApi {
+		/// Returns the relay chain state of the last block that set its validation data.
+		///
+		/// Returns `None` if no block has set its validation data yet.
+		fn relay_chain_state() -> Option<RelayChainState>;
+	}
+
+	/// Runtime api to query the additional relay chain storage keys a parachain block reads.
+	pub trait RelayStateKeysApi {
+		/// Returns the relay chain storage keys that need to be part of the relay chain state
+		/// proof of the next block, in addition to the keys that are always part of it.
+		fn additional_relay_state_keys() -> Vec<Vec<u8>>;
+	}
+
+	/// Runtime api to query the state of the XCMP queues.
+	pub trait XcmpQueueApi {
+		/// Returns the depths and the status of the inbound and outbound XCMP queues.
+		fn xcmp_queue_status() -> XcmpQueueStatus;
+	}
+
+	/// Runtime api to query the queue of downward messages.
+	pub trait DmpQueueApi {
+		/// Returns the depth of the queue of downward messages that are not executed yet.
+		///
+		/// Up to `max_previews` of the oldest queued messages are returned as well.
+		fn dmp_queue_status(max_previews: u32) -> DmpQueueStatus;
+	}
+
+	/// Runtime api to query the AuRa slot information of a parachain using `aura-ext`.
+	pub trait AuraExtApi<AuthorityId: Codec> {
+		/// Returns the slot of the current block, the slot duration and the authorities of the
+		/// next block.
+		fn aura_slot_info() -> AuraSlotInfo<AuthorityId>;
+
+		/// Returns the first block of the runtime upgrade that switched to AuRa from another
+		/// consensus, if the chain switched to AuRa.
+		fn consensus_migration_block() -> Option<NumberFor<Block>>;
+	}
+
+	/// Runtime api to report the AuRa authorities of a parachain using `aura-ext` that equivocated.
+	pub trait AuraEquivocationApi<AuthorityId: Codec> {
+		/// Submits an unsigned extrinsic that reports the equivocation of the offender.
+		///
+		/// Returns `None` if the extrinsic couldn't be submitted to the transaction pool.
+		fn submit_report_equivocation_unsigned_extrinsic(
+			equivocation_proof: AuraEquivocationProof<Block::Header, AuthorityId>,
+		) -> Option<()>;
+	}
+
+	/// Runtime api to query the capacity of the unincluded segment of a parachain.
+	pub trait UnincludedSegmentApi {
+		/// Returns the number of blocks that can be built on top of the block with `block_hash`
+		/// before the unincluded segment is full, if the block with `included_hash` is the last
+		/// block included on the relay chain.
+		///
+		/// Must be called on the state of the block with `block_hash`.
+		fn unincluded_segment_capacity(block_hash: Block::Hash, included_hash: Block::Hash) -> u32;
+	}
+
+	/// Runtime api to collect information about a collation.
+	pub trait CollectCollationInfo {
+		/// Collect information about a collation.
+		///
+		/// Must be called on the state of the block that corresponds to the given `header`.
+		fn collect_collation_info(header: &Block::Header) -> CollationInfo;
+	}
 }
 
 /// The parachain block that is created by a collator.
@@ -24,6 +24,10 @@ use cumulus_primitives_core::{
 		v1::{HrmpChannelId, ParachainHost},
 		Block as PBlock, Hash as PHash,
 	},
+	relay_well_known_keys::{
+		para_head, para_lifecycle, upgrade_go_ahead_signal, CURRENT_EPOCH_RANDOMNESS,
+		CURRENT_EPOCH_START, CURRENT_SESSION_INDEX, CURRENT_SLOT,
+	},
 	InboundDownwardMessage, InboundHrmpMessage, ParaId, PersistedValidationData,
 };
 use polkadot_service::{Client, ClientHandle, ExecuteWithClient};
@@ -99,10 +103,13 @@ where
 
 /// Collect the relevant relay chain state in form of a proof for putting it into the validation
 /// data inherent.
+///
+/// The `additional_keys` are included in the proof next to the keys required by Cumulus.
 fn collect_relay_storage_proof(
 	polkadot_backend: &impl Backend<PBlock>,
 	para_id: ParaId,
 	relay_parent: PHash,
+	additional_keys: &[Vec<u8>],
 ) -> Option<sp_state_machine::StorageProof> {
 	use relay_chain::well_known_keys as relay_well_known_keys;
 
@@ -171,6 +178,13 @@ fn collect_relay_storage_proof(
 	relevant_keys.push(relay_well_known_keys::relay_dispatch_queue_size(para_id));
 	relevant_keys.push(relay_well_known_keys::hrmp_ingress_channel_index(para_id));
 	relevant_keys.push(relay_well_known_keys::hrmp_egress_channel_index(para_id));
+	relevant_keys.push(CURRENT_EPOCH_RANDOMNESS.to_vec());
+	relevant_keys.push(CURRENT_EPOCH_START.to_vec());
+	relevant_keys.push(CURRENT_SESSION_INDEX.to_vec());
+	relevant_keys.push(CURRENT_SLOT.to_vec());
+	relevant_keys.push(upgrade_go_ahead_signal(para_id));
+	relevant_keys.push(para_head(para_id));
+	relevant_keys.push(para_lifecycle(para_id));
 	relevant_keys.extend(ingress_channels.into_iter().map(|sender| {
 		relay_well_known_keys::hrmp_channels(HrmpChannelId {
 			sender,
@@ -183,8 +197,48 @@ fn collect_relay_storage_proof(
 			recipient,
 		})
 	}));
+	relevant_keys.extend(additional_keys.iter().cloned());
+
+	prove_read_at(relay_parent_state_backend, relay_parent, relevant_keys)
+}
+
+/// Generate a storage read proof for the given `keys` at the given `relay_parent`.
+///
+/// This can be used to prove relay chain storage entries that are not part of the validation data
+/// inherent. Code that has access to a `RelaychainClient` of `cumulus-client-consensus-common`
+/// should use its `prove_read` instead, so that the wrappers of the client apply.
+///
+/// Returns `None` in case of an error.
+pub fn prove_read(
+	polkadot_backend: &impl Backend<PBlock>,
+	relay_parent: PHash,
+	keys: &[Vec<u8>],
+) -> Option<sp_state_machine::StorageProof> {
+	let relay_parent_state_backend = polkadot_backend
+		.state_at(BlockId::Hash(relay_parent))
+		.map_err(|e| {
+			tracing::error!(
+				target: LOG_TARGET,
+				relay_parent = ?relay_parent,
+				error = ?e,
+				"Cannot obtain the state of the relay chain.",
+			)
+		})
+		.ok()?;
+
+	prove_read_at(relay_parent_state_backend, relay_parent, keys)
+}
 
-	sp_state_machine::prove_read(relay_parent_state_backend, relevant_keys)
+fn prove_read_at<I>(
+	state_backend: impl sp_state_machine::Backend<sp_runtime::traits::BlakeTwo256>,
+	relay_parent: PHash,
+	keys: I,
+) -> Option<sp_state_machine::StorageProof>
+where
+	I: IntoIterator,
+	I::Item: AsRef<[u8]>,
+{
+	sp_state_machine::prove_read(state_backend, keys)
 		.map_err(|e| {
 			tracing::error!(
 				target: LOG_TARGET,
@@ -207,12 +261,40 @@ impl ParachainInherentData {
 		validation_data: &PersistedValidationData,
 		para_id: ParaId,
 	) -> Option<ParachainInherentData>
+	where
+		PClient: ProvideRuntimeApi<PBlock>,
+		PClient::Api: ParachainHost<PBlock>,
+	{
+		Self::create_at_with_extra_keys(
+			relay_parent,
+			polkadot_client,
+			polkadot_backend,
+			validation_data,
+			para_id,
+			&[],
+		)
+	}
+
+	/// Create the [`ParachainInherentData`] at the given `relay_parent`.
+	///
+	/// The relay chain state proof will additionally contain the given `extra_keys`, so that they
+	/// can be read by the runtime.
+	///
+	/// Returns `None` if the creation failed.
+	pub fn create_at_with_extra_keys<PClient>(
+		relay_parent: PHash,
+		polkadot_client: &PClient,
+		polkadot_backend: &impl Backend<PBlock>,
+		validation_data: &PersistedValidationData,
+		para_id: ParaId,
+		extra_keys: &[Vec<u8>],
+	) -> Option<ParachainInherentData>
 	where
 		PClient: ProvideRuntimeApi<PBlock>,
 		PClient::Api: ParachainHost<PBlock>,
 	{
 		let relay_chain_state =
-			collect_relay_storage_proof(polkadot_backend, para_id, relay_parent)?;
+			collect_relay_storage_proof(polkadot_backend, para_id, relay_parent, extra_keys)?;
 		let downward_messages = retrieve_dmq_contents(polkadot_client, para_id, relay_parent)?;
 		let horizontal_messages =
 			retrieve_all_inbound_hrmp_channel_contents(polkadot_client, para_id, relay_parent)?;
@@ -234,12 +316,37 @@ impl ParachainInherentData {
 		relay_chain_backend: &impl Backend<PBlock>,
 		validation_data: &PersistedValidationData,
 		para_id: ParaId,
+	) -> Option<ParachainInherentData> {
+		Self::create_at_with_client_and_extra_keys(
+			relay_parent,
+			polkadot_client,
+			relay_chain_backend,
+			validation_data,
+			para_id,
+			&[],
+		)
+	}
+
+	/// Create the [`ParachainInherentData`] at the given `relay_parent`.
+	///
+	/// The relay chain state proof will additionally contain the given `extra_keys`, so that they
+	/// can be read by the runtime.
+	///
+	/// Returns `None` if the creation failed.
+	pub fn create_at_with_client_and_extra_keys(
+		relay_parent: PHash,
+		polkadot_client: &Client,
+		relay_chain_backend: &impl Backend<PBlock>,
+		validation_data: &PersistedValidationData,
+		para_id: ParaId,
+		extra_keys: &[Vec<u8>],
 	) -> Option<ParachainInherentData> {
 		polkadot_client.execute_with(CreateAtWithClient {
 			relay_chain_backend,
 			validation_data,
 			para_id,
 			relay_parent,
+			extra_keys,
 		})
 	}
 }
@@ -268,6 +375,7 @@ struct CreateAtWithClient<'a, B> {
 	relay_chain_backend: &'a B,
 	validation_data: &'a PersistedValidationData,
 	para_id: ParaId,
+	extra_keys: &'a [Vec<u8>],
 }
 
 impl<'a, B> ExecuteWithClient for CreateAtWithClient<'a, B>
@@ -280,12 +388,13 @@ where
 		self,
 		client: std::sync::Arc<Client>,
 	) -> Self::Output where Client: ProvideRuntimeApi<PBlock>, Client::Api: ParachainHost<PBlock> {
-		ParachainInherentData::create_at(
+		ParachainInherentData::create_at_with_extra_keys(
 			self.relay_parent,
 			&*client,
 			self.relay_chain_backend,
 			self.validation_data,
 			self.para_id,
+			self.extra_keys,
 		)
 	}
 }
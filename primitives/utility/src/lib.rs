@@ -21,26 +21,44 @@
 
 use sp_std::marker::PhantomData;
 use codec::Encode;
-use cumulus_primitives_core::UpwardMessageSender;
+use cumulus_primitives_core::{UpwardMessageFeeHint, UpwardMessageSender};
 use xcm::{VersionedXcm, v0::{Xcm, MultiLocation, Junction, SendXcm, Error as XcmError}};
 
+/// Something that chooses the fee hint of an upward XCM message.
+pub trait UpwardMessageFeeHintFor {
+	/// Returns the fee hint for the given message or `None` to send it without a hint.
+	fn fee_hint(msg: &Xcm<()>) -> Option<UpwardMessageFeeHint>;
+}
+
+impl UpwardMessageFeeHintFor for () {
+	fn fee_hint(_msg: &Xcm<()>) -> Option<UpwardMessageFeeHint> {
+		None
+	}
+}
+
 /// Xcm router which recognises the `Parent` destination and handles it by sending the message into
 /// the given UMP `UpwardMessageSender` implementation. Thus this essentially adapts an
 /// `UpwardMessageSender` trait impl into a `SendXcm` trait impl.
 ///
+/// The fee hint of each message is chosen by `H`. By default, messages are sent without a hint.
+///
 /// NOTE: This is a pretty dumb "just send it" router; we will probably want to introduce queuing
 /// to UMP eventually and when we do, the pallet which implements the queuing will be responsible
 /// for the `SendXcm` implementation.
-pub struct ParentAsUmp<T>(PhantomData<T>);
-impl<T: UpwardMessageSender> SendXcm for ParentAsUmp<T> {
+pub struct ParentAsUmp<T, H = ()>(PhantomData<(T, H)>);
+impl<T: UpwardMessageSender, H: UpwardMessageFeeHintFor> SendXcm for ParentAsUmp<T, H> {
 	fn send_xcm(dest: MultiLocation, msg: Xcm<()>) -> Result<(), XcmError> {
 		match &dest {
 			// An upward message for the relay chain.
 			MultiLocation::X1(Junction::Parent) => {
+				let fee_hint = H::fee_hint(&msg);
 				let data = VersionedXcm::<()>::from(msg).encode();
 
-				T::send_upward_message(data)
-					.map_err(|e| XcmError::SendFailed(e.into()))?;
+				match fee_hint {
+					Some(fee_hint) => T::send_upward_message_with_fee_hint(data, fee_hint),
+					None => T::send_upward_message(data),
+				}
+				.map_err(|e| XcmError::SendFailed(e.into()))?;
 
 				Ok(())
 			}
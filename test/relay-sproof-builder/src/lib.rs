@@ -14,7 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with Cumulus.  If not, see <http://www.gnu.org/licenses/>.
 
-use cumulus_primitives_core::{relay_chain, AbridgedHostConfiguration, AbridgedHrmpChannel, ParaId};
+use cumulus_primitives_core::{
+	relay_chain, relay_well_known_keys, AbridgedHostConfiguration, AbridgedHrmpChannel,
+	ParaId, ParaLifecycle, UpgradeGoAhead,
+};
 use sp_runtime::traits::HashFor;
 use sp_state_machine::MemoryDB;
 use sp_std::collections::btree_map::BTreeMap;
@@ -38,6 +41,21 @@ pub struct RelayStateSproofBuilder {
 	pub hrmp_ingress_channel_index: Option<Vec<ParaId>>,
 	pub hrmp_egress_channel_index: Option<Vec<ParaId>>,
 	pub hrmp_channels: BTreeMap<relay_chain::v1::HrmpChannelId, AbridgedHrmpChannel>,
+	pub current_epoch_randomness: Option<[u8; 32]>,
+	/// The block numbers when the previous and the current epoch started.
+	pub current_epoch_start: Option<(relay_chain::BlockNumber, relay_chain::BlockNumber)>,
+	pub current_session_index: Option<relay_chain::v1::SessionIndex>,
+	/// The BABE slot of the relay chain block.
+	pub current_slot: Option<u64>,
+	pub upgrade_go_ahead: Option<UpgradeGoAhead>,
+	/// The head of the parachain that was last included.
+	pub included_para_head: Option<relay_chain::v1::HeadData>,
+	/// The lifecycle of the parachain.
+	pub para_lifecycle: Option<ParaLifecycle>,
+	/// Additional key-value pairs that are added to the relay chain state and the proof.
+	pub additional_key_values: Vec<(Vec<u8>, Vec<u8>)>,
+	/// Additional keys without a value that are part of the proof to prove their absence.
+	pub additional_absent_keys: Vec<Vec<u8>>,
 }
 
 impl Default for RelayStateSproofBuilder {
@@ -60,6 +78,15 @@ impl Default for RelayStateSproofBuilder {
 			hrmp_ingress_channel_index: None,
 			hrmp_egress_channel_index: None,
 			hrmp_channels: BTreeMap::new(),
+			current_epoch_randomness: None,
+			current_epoch_start: None,
+			current_session_index: None,
+			current_slot: None,
+			upgrade_go_ahead: None,
+			included_para_head: None,
+			para_lifecycle: None,
+			additional_key_values: Vec::new(),
+			additional_absent_keys: Vec::new(),
 		}
 	}
 }
@@ -151,6 +178,67 @@ impl RelayStateSproofBuilder {
 					metadata.encode(),
 				);
 			}
+			if let Some(current_epoch_randomness) = self.current_epoch_randomness {
+				insert(
+					relay_well_known_keys::CURRENT_EPOCH_RANDOMNESS.to_vec(),
+					current_epoch_randomness.encode(),
+				);
+			}
+			if let Some(current_epoch_start) = self.current_epoch_start {
+				insert(
+					relay_well_known_keys::CURRENT_EPOCH_START.to_vec(),
+					current_epoch_start.encode(),
+				);
+			}
+			if let Some(current_session_index) = self.current_session_index {
+				insert(
+					relay_well_known_keys::CURRENT_SESSION_INDEX.to_vec(),
+					current_session_index.encode(),
+				);
+			}
+			if let Some(current_slot) = self.current_slot {
+				insert(relay_well_known_keys::CURRENT_SLOT.to_vec(), current_slot.encode());
+			}
+			if let Some(upgrade_go_ahead) = self.upgrade_go_ahead {
+				insert(
+					relay_well_known_keys::upgrade_go_ahead_signal(self.para_id),
+					upgrade_go_ahead.encode(),
+				);
+			}
+			if let Some(included_para_head) = self.included_para_head {
+				insert(
+					relay_well_known_keys::para_head(self.para_id),
+					included_para_head.encode(),
+				);
+			}
+			if let Some(para_lifecycle) = self.para_lifecycle {
+				insert(
+					relay_well_known_keys::para_lifecycle(self.para_id),
+					para_lifecycle.encode(),
+				);
+			}
+			for (key, value) in self.additional_key_values {
+				insert(key, value);
+			}
+		}
+
+		relevant_keys.extend(self.additional_absent_keys);
+
+		// These entries are always read by the runtime, so their absence needs to be proven.
+		for key in [
+			relay_well_known_keys::CURRENT_EPOCH_RANDOMNESS.to_vec(),
+			relay_well_known_keys::CURRENT_EPOCH_START.to_vec(),
+			relay_well_known_keys::CURRENT_SESSION_INDEX.to_vec(),
+			relay_well_known_keys::CURRENT_SLOT.to_vec(),
+			relay_well_known_keys::upgrade_go_ahead_signal(self.para_id),
+			relay_well_known_keys::para_head(self.para_id),
+			relay_well_known_keys::para_lifecycle(self.para_id),
+		]
+		.iter()
+		{
+			if !relevant_keys.contains(key) {
+				relevant_keys.push(key.clone());
+			}
 		}
 
 		let root = backend.root().clone();
@@ -216,13 +216,31 @@ impl pallet_sudo::Config for Runtime {
 
 impl cumulus_pallet_parachain_system::Config for Runtime {
 	type SelfParaId = ParachainId;
+	type CheckAssociatedRelayNumber = cumulus_pallet_parachain_system::RelayNumberStrictlyIncreases;
+	type ValidationDataGapThreshold = ();
+	type ConsensusHook = ();
 	type Event = Event;
-	type OnValidationData = ();
+	type OnSystemEvent = ();
+	type OnRelaySessionChange = ();
+	type OnOffboarding = ();
 	type OutboundXcmpMessageSource = ();
 	type DmpMessageHandler = ();
 	type ReservedDmpWeight = ();
 	type XcmpMessageHandler = ();
 	type ReservedXcmpWeight = ();
+	type MaxHorizontalMessagesPerBlock = ();
+	type MaxPausedMessages = ();
+	type MaxDeferredHorizontalMessages = ();
+	type LenientMqcHeadCheck = ();
+	type InboundMessagesOrder = ();
+	type AdditionalRelayStateKeys = ();
+	type HrmpChannelManagerOrigin = frame_system::EnsureRoot<AccountId>;
+	type RelayHrmpPalletIndex = ();
+	type RelayHrmpCallWeight = ();
+	type UpgradePovReserve = ();
+	type HeadDataExtensionProvider = ();
+	type QueuedMessageDeposit = ();
+	type WeightInfo = ();
 }
 
 parameter_types! {
@@ -395,6 +413,20 @@ impl_runtime_apis! {
 			UpgradeDetection::get()
 		}
 	}
+
+	impl cumulus_primitives_core::CollectCollationInfo<Block> for Runtime {
+		fn collect_collation_info(
+			header: &<Block as BlockT>::Header,
+		) -> cumulus_primitives_core::CollationInfo {
+			ParachainSystem::collect_collation_info(header)
+		}
+	}
+
+	impl cumulus_primitives_core::RelayStateKeysApi<Block> for Runtime {
+		fn additional_relay_state_keys() -> Vec<Vec<u8>> {
+			ParachainSystem::additional_relay_state_keys()
+		}
+	}
 }
 
 cumulus_pallet_parachain_system::register_validate_block!(Runtime, Executive);
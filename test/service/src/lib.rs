@@ -26,7 +26,8 @@ use cumulus_client_network::BlockAnnounceValidator;
 use cumulus_client_service::{
 	prepare_node_config, start_collator, start_full_node, StartCollatorParams, StartFullNodeParams,
 };
-use cumulus_primitives_core::ParaId;
+use cumulus_primitives_core::{ParaId, RelayStateKeysApi};
+use cumulus_primitives_parachain_inherent::ParachainInherentData;
 use cumulus_test_runtime::{NodeBlock as Block, RuntimeApi};
 use polkadot_primitives::v1::CollatorPair;
 use sc_client_api::execution_extensions::ExecutionStrategies;
@@ -41,6 +42,7 @@ use sc_service::{
 	BasePath, ChainSpec, Configuration, Error as ServiceError, PartialComponents, Role,
 	RpcHandlers, TFullBackend, TFullClient, TaskExecutor, TaskManager,
 };
+use sp_api::ProvideRuntimeApi;
 use sp_arithmetic::traits::SaturatedConversion;
 use sp_blockchain::HeaderBackend;
 use sp_core::{Pair, H256};
@@ -235,19 +237,24 @@ where
 
 		let relay_chain_client = relay_chain_full_node.client.clone();
 		let relay_chain_backend = relay_chain_full_node.backend.clone();
+		let para_client = client.clone();
 
 		let parachain_consensus = cumulus_client_consensus_relay_chain::RelayChainConsensus::new(
 			para_id,
 			proposer_factory,
-			move |_, (relay_parent, validation_data)| {
-				let parachain_inherent =
-					cumulus_primitives_parachain_inherent::ParachainInherentData::create_at(
-						relay_parent,
-						&*relay_chain_client,
-						&*relay_chain_backend,
-						&validation_data,
-						para_id,
-					);
+			move |parent, (relay_parent, validation_data)| {
+				let additional_relay_state_keys = para_client
+					.runtime_api()
+					.additional_relay_state_keys(&generic::BlockId::Hash(parent))
+					.unwrap_or_default();
+				let parachain_inherent = ParachainInherentData::create_at_with_extra_keys(
+					relay_parent,
+					&*relay_chain_client,
+					&*relay_chain_backend,
+					&validation_data,
+					para_id,
+					&additional_relay_state_keys,
+				);
 				async move {
 					let time = sp_timestamp::InherentDataProvider::from_system_time();
 
@@ -276,6 +283,7 @@ where
 			collator_key,
 			parachain_consensus: Box::new(parachain_consensus),
 			relay_chain_full_node,
+			relay_chain_client_config: Default::default(),
 		};
 
 		start_collator(params).await?;
@@ -289,6 +297,7 @@ where
 			task_manager: &mut task_manager,
 			para_id,
 			polkadot_full_node: relay_chain_full_node,
+			relay_chain_client_config: Default::default(),
 		};
 
 		start_full_node(params)?;